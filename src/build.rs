@@ -0,0 +1,294 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    c::C,
+    comptime::Comptime,
+    diagnostic::Diagnostic,
+    grammar::Span,
+    interp::Value,
+    ir::{print_module, Module},
+    native::{LinuxX86, WindowsX86},
+    nbbc::Nbbc,
+    wasm::{Wasm, Wat},
+};
+
+// The build driver: what `#%compilation.output` asks for, written to disk.
+//
+// The annotation is a record from target names to output paths, relative
+// to the project's root. Like import paths, those starting with `/` start
+// at the root too, they're never absolute. Each target runs its own pipeline over a copy of
+// the module the front end produced, so nothing a backend does can leak
+// into another one's output, and a failing target doesn't stop the rest.
+
+/// Turns a whole IR module into the contents of an output file.
+pub trait Backend {
+    fn emit(&self, module: &Module) -> Result<Vec<u8>, String>;
+}
+
+/// Every target name `#%compilation.output` accepts.
+pub const TARGETS: [&str; 7] = ["ir", "wasm", "wat", "nbbc", "linux_x86", "windows_x86", "c"];
+
+/// The backend for a target, if there's one yet.
+pub fn backend(target: &str) -> Option<Box<dyn Backend>> {
+    match target {
+        "ir" => Some(Box::new(IRText)),
+        "wasm" => Some(Box::new(Wasm)),
+        "wat" => Some(Box::new(Wat)),
+        "nbbc" => Some(Box::new(Nbbc)),
+        "linux_x86" => Some(Box::new(LinuxX86)),
+        "windows_x86" => Some(Box::new(WindowsX86)),
+        "c" => Some(Box::new(C)),
+        _ => None,
+    }
+}
+
+/// The `ir` target, the IR as `--emit=ir` prints it.
+struct IRText;
+
+impl Backend for IRText {
+    fn emit(&self, module: &Module) -> Result<Vec<u8>, String> {
+        return Ok(print_module(module).into_bytes());
+    }
+}
+
+/// An entry of `#%compilation.output`.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub name: String,
+    // As written, relative to the root, even with a leading `/`.
+    pub output: String,
+    // The target's field in the annotation.
+    pub span: Option<Span>,
+}
+
+/// How building a target went.
+pub struct Report {
+    pub target: Target,
+    // Where the output was written.
+    pub path: PathBuf,
+    // The size of the output, or why there's none.
+    pub result: Result<usize, String>,
+}
+
+/// The targets `#%compilation.output` lists, by name.
+pub fn targets(comptime: &Comptime) -> Result<Vec<Target>, Diagnostic> {
+    let annotation = comptime
+        .annotations
+        .iter()
+        .rev()
+        .find(|a| a.name == "compilation.output");
+    let annotation = match annotation {
+        None => return Ok(Vec::new()),
+        Some(annotation) => annotation,
+    };
+
+    let usage = "`#%compilation.output` takes a record of paths, like `{ wasm: 'program.wasm' }`.";
+    let fields = match &annotation.value {
+        Value::Record(fields) => fields,
+        _ => return Err(Diagnostic::error(usage, annotation.span)),
+    };
+
+    let mut targets = Vec::new();
+    for (name, value) in fields.iter() {
+        let span = annotation.field_span(name);
+        if !TARGETS.contains(&name.as_str()) {
+            let message = format!("Unknown target '{}'.", name);
+            let note = format!("The targets are {}.", TARGETS.join(", "));
+            return Err(Diagnostic::error(message, span).with_note(None, note));
+        }
+        let output = match value {
+            Value::String(path) => path.to_string(),
+            _ => {
+                let message = format!("The output of `{}` should be a path, not {}.", name, value);
+                return Err(Diagnostic::error(message, span));
+            }
+        };
+        targets.push(Target {
+            name: name.clone(),
+            output,
+            span,
+        });
+    }
+    return Ok(targets);
+}
+
+/// Leaves the `%%test fn`s, and the functions inside them, out of a module to build.
+pub fn strip_tests(module: &mut Module) {
+    let tests = std::mem::take(&mut module.tests);
+    module.functions.retain(|f| {
+        !tests
+            .iter()
+            .any(|t| f.symbol == t.symbol || f.symbol.starts_with(&format!("{}::", t.symbol)))
+    });
+}
+
+/// Builds every target from `module`, writing outputs under `root`, `/` being the root itself.
+pub fn build(module: &Module, targets: &[Target], root: &Path) -> Vec<Report> {
+    return targets
+        .iter()
+        .map(|target| {
+            let path = root.join(target.output.trim_start_matches('/'));
+            let result = build_target(module, target).and_then(|bytes| {
+                write(&path, &bytes)?;
+                Ok(bytes.len())
+            });
+            Report {
+                target: target.clone(),
+                path,
+                result,
+            }
+        })
+        .collect();
+}
+
+/// The output of a single target.
+fn build_target(module: &Module, target: &Target) -> Result<Vec<u8>, String> {
+    let backend = match backend(&target.name) {
+        None => return Err(format!("There's no `{}` backend yet.", target.name)),
+        Some(backend) => backend,
+    };
+    let module = module.clone();
+    return backend.emit(&module);
+}
+
+/// Writes `bytes` to `path`, creating the folders it's in.
+fn write(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Couldn't create '{}': {}.", dir.display(), e))?;
+        }
+    }
+    return fs::write(path, bytes)
+        .map_err(|e| format!("Couldn't write '{}': {}.", path.display(), e));
+}
+
+/// `source` resolved, typed and lowered, before its compile-time code is run.
+/// For tests; panics on a compile error.
+#[cfg(test)]
+pub fn analyzed(source: &str) -> Module {
+    use crate::{
+        file_importer::{MemoryFileSystem, SourceMap},
+        modules,
+    };
+
+    let mut files = MemoryFileSystem::new();
+    files.insert("main.nano", source);
+    let sources = SourceMap::with_fs(Box::new(files));
+    let graph = match modules::load(sources, "main.nano", None) {
+        Ok(graph) => graph,
+        Err(_) => panic!("the source doesn't parse"),
+    };
+    assert!(!graph.has_errors(), "{:?}", graph.diagnostics);
+    let program = modules::analyze(&graph);
+    return program.module.expect("the source doesn't compile");
+}
+
+/// What the front end makes of `source`, tests stripped, as `nnc compile` would build it.
+/// For the backends' tests; panics on a compile error.
+#[cfg(test)]
+pub fn front_end(source: &str) -> Module {
+    use crate::comptime;
+
+    let mut module = analyzed(source);
+    let comptime = comptime::evaluate(&mut module, &comptime::Options::default());
+    assert!(!comptime.has_errors(), "{:?}", comptime.diagnostics);
+    strip_tests(&mut module);
+    return module;
+}
+
+/// What the interpreter prints running `module`, what the backends' output is held to.
+#[cfg(test)]
+pub fn interpreted(module: &Module) -> String {
+    let mut interpreter = crate::interp::Interpreter::new();
+    interpreter.capture_output();
+    interpreter.run(module).expect("the interpreter failed");
+    return interpreter.take_output();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{comptime, diagnostic::line_col};
+
+    /// The targets `source` lists, or why they're refused and the line and column it points at.
+    fn listed(source: &str) -> Result<Vec<Target>, (String, (usize, usize))> {
+        let mut module = analyzed(source);
+        let comptime = comptime::evaluate(&mut module, &comptime::Options::default());
+        return targets(&comptime)
+            .map_err(|d| (d.message, line_col(source, d.span.unwrap().start)));
+    }
+
+    #[test]
+    fn targets_are_listed_with_their_field() {
+        let source =
+            "#%compilation.output {\n    ir: 'out.ir'\n    c: 'native/' + 'out.c'\n}\nprint(1)";
+        let targets = listed(source).unwrap();
+        let fields: Vec<(&str, &str, (usize, usize))> = targets
+            .iter()
+            .map(|t| {
+                (
+                    t.name.as_str(),
+                    t.output.as_str(),
+                    line_col(source, t.span.unwrap().start),
+                )
+            })
+            .collect();
+        assert_eq!(
+            fields,
+            [("c", "native/out.c", (3, 5)), ("ir", "out.ir", (2, 5))]
+        );
+        assert!(listed("print(1)").unwrap().is_empty());
+    }
+
+    #[test]
+    fn unknown_targets_point_at_their_field() {
+        let source = "#%compilation.output {\n    ir: 'out.ir'\n    jvm: 'out.class'\n}";
+        assert_eq!(
+            listed(source).unwrap_err(),
+            ("Unknown target 'jvm'.".to_string(), (3, 5))
+        );
+    }
+
+    #[test]
+    fn outputs_that_arent_paths_point_at_their_field() {
+        let source = "#%compilation.output {\n    wasm: 'out.wasm'\n    ir: 42\n}";
+        assert_eq!(
+            listed(source).unwrap_err(),
+            (
+                "The output of `ir` should be a path, not 42.".to_string(),
+                (3, 5)
+            )
+        );
+    }
+
+    #[test]
+    fn outputs_are_written_under_the_root_even_from_a_slash() {
+        let root = std::env::temp_dir().join(format!("nnc-build-{}", std::process::id()));
+        let source = "#%compilation.output { ir: '/dist/out.ir', wat: 'dist/out.wat' }\nprint(1)";
+        let module = front_end(source);
+        let reports = build(&module, &listed(source).unwrap(), &root);
+
+        let written: Vec<PathBuf> = reports.iter().map(|r| r.path.clone()).collect();
+        assert_eq!(
+            written,
+            [root.join("dist/out.ir"), root.join("dist/out.wat")]
+        );
+        for report in &reports {
+            let size = fs::metadata(&report.path).unwrap().len() as usize;
+            assert_eq!(report.result, Ok(size));
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn tests_are_left_out_of_builds() {
+        let module = front_end(
+            "fn used() -> 1\n%%test fn checks -> (\n    %%t_eq(used(), 1)\n)\nprint(used())",
+        );
+        assert!(module.tests.is_empty());
+        let symbols: Vec<&str> = module.functions.iter().map(|f| f.symbol.as_str()).collect();
+        assert_eq!(symbols, ["used"]);
+    }
+}
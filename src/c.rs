@@ -0,0 +1,1125 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    build::Backend,
+    ir::{type_symbol, Function, IRKind, IRNode, Literal, Module, INTRINSIC_PREFIX},
+    mono::{field_names, monomorphize},
+    types::Type,
+};
+
+// The C backend: one self-contained C99 file, which any C compiler turns
+// into a program, `cc -std=c99 program.c -lm -o program`.
+//
+// The file starts with the runtime, `nano_runtime.h`, which lays values out
+// like the other compiled targets do and has what strings, lists and records
+// need. The module is monomorphized, so every nano value gets a C type of
+// its own, and printing and comparing lists, records and closures is done
+// by functions generated for their exact types. Closures are called through
+// "thunks", like in the wasm backend, which take the closure itself first
+// and unpack its captures. Thunks take and return `nano_value`s, and unit
+// is a `nano_value` too, because a polymorphic `let` makes its closure at
+// one type, with unit for the variables, while its uses call it at others.
+//
+// nano is made of expressions and C of statements, so each expression is
+// compiled to the statements computing it and a C expression for its
+// value. Calls and anything else with effects go in their own temporary,
+// keeping nano's evaluation order. Names are kept where they can be, so
+// the C reads like the nano it comes from.
+
+/// The `c` target.
+pub struct C;
+
+impl Backend for C {
+    fn emit(&self, module: &Module) -> Result<Vec<u8>, String> {
+        return Ok(compile(module)?.into_bytes());
+    }
+}
+
+const RUNTIME: &str = include_str!("nano_runtime.h");
+
+/// The C source of a program running `module`.
+pub fn compile(module: &Module) -> Result<String, String> {
+    let module = monomorphize(module);
+    return Compiler::new(&module).finish();
+}
+
+/// A function whose name is known but whose body is still to be compiled.
+enum Pending<'a> {
+    User(&'a Function),
+    // The function pointer stored in closures of `symbol` at type `ty`.
+    Thunk { symbol: String, ty: Type },
+    // `Display` for values of a type, strings quoted.
+    Write(Type),
+    // `eq_T`, since C's `==` would only compare the pointers to lists and records.
+    Eq(Type),
+    Print(Type),
+}
+
+/// The code of a function being compiled.
+struct Body {
+    code: String,
+    indent: usize,
+    temps: u32,
+    // The C names taken in this function.
+    names: HashSet<String>,
+    // The locals in scope by nano name, searched from the end.
+    scopes: Vec<(String, String)>,
+}
+
+impl Body {
+    fn new() -> Body {
+        Body {
+            code: String::new(),
+            indent: 1,
+            temps: 0,
+            names: HashSet::new(),
+            scopes: Vec::new(),
+        }
+    }
+
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.code.push_str("    ");
+        }
+        self.code.push_str(text);
+        self.code.push('\n');
+    }
+
+    /// A new variable of type `ty`, set to `value`.
+    fn temp(&mut self, ty: &Type, value: &str) -> String {
+        self.temps += 1;
+        let name = format!("t{}", self.temps);
+        self.line(&format!("{} = {};", declare(ty, &name), value));
+        return name;
+    }
+
+    /// The C name for a new local called `name` in nano.
+    fn local(&mut self, name: &str) -> String {
+        let name = unique(&mut self.names, &format!("v_{}", identifier(name)));
+        return name;
+    }
+}
+
+struct Compiler<'a> {
+    module: &'a Module,
+    // Every function's C name, by what it is, then its code once compiled.
+    by_key: HashMap<String, usize>,
+    functions: Vec<(String, Option<(String, String)>)>,
+    pending: Vec<(usize, Pending<'a>)>,
+    // The C names taken outside functions.
+    names: HashSet<String>,
+    strings: HashMap<String, String>,
+    string_order: Vec<(String, String)>,
+    globals: HashMap<String, String>,
+    // The types helpers were made for, numbered for their names.
+    types: Vec<Type>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(module: &'a Module) -> Compiler<'a> {
+        let mut compiler = Compiler {
+            module,
+            by_key: HashMap::new(),
+            functions: Vec::new(),
+            pending: Vec::new(),
+            names: HashSet::new(),
+            strings: HashMap::new(),
+            string_order: Vec::new(),
+            globals: HashMap::new(),
+            types: Vec::new(),
+        };
+        for (symbol, _) in &module.globals {
+            let name = unique(&mut compiler.names, &format!("g_{}", identifier(symbol)));
+            compiler.globals.insert(symbol.clone(), name);
+        }
+        return compiler;
+    }
+
+    /// The name of the function for `key`, to be compiled from `pending` if it's new.
+    fn function(
+        &mut self,
+        key: String,
+        name: &str,
+        pending: impl FnOnce() -> Pending<'a>,
+    ) -> String {
+        if let Some(index) = self.by_key.get(&key) {
+            return self.functions[*index].0.clone();
+        }
+        let name = unique(&mut self.names, name);
+        let index = self.functions.len();
+        self.functions.push((name.clone(), None));
+        self.by_key.insert(key, index);
+        self.pending.push((index, pending()));
+        return name;
+    }
+
+    fn user(&mut self, symbol: &str) -> Result<String, String> {
+        let function = match self.module.function(symbol) {
+            None => return Err(format!("The C backend can't find '{}'.", symbol)),
+            Some(f) => f,
+        };
+        let name = format!("fn_{}", identifier(symbol));
+        return Ok(self.function(symbol.to_string(), &name, || Pending::User(function)));
+    }
+
+    /// The function closures of `symbol` and of type `ty` are called through.
+    fn thunk(&mut self, symbol: &str, ty: &Type) -> String {
+        let key = format!("{}$closure<{}>", symbol, type_symbol(ty));
+        let name = format!("thunk_{}", identifier(symbol));
+        return self.function(key, &name, || Pending::Thunk {
+            symbol: symbol.to_string(),
+            ty: ty.clone(),
+        });
+    }
+
+    /// The helper `kind` for values of type `ty`.
+    fn helper(&mut self, kind: &str, ty: &Type) -> String {
+        let key = format!("{}<{}>", kind, type_symbol(ty));
+        let name = format!("{}_{}", kind, self.type_name(ty));
+        let ty = ty.clone();
+        return self.function(key, &name, || match kind {
+            "write" => Pending::Write(ty),
+            "eq" => Pending::Eq(ty),
+            _ => Pending::Print(ty),
+        });
+    }
+
+    /// `int` for ints and the like, `t0`, `t1`... for the rest.
+    fn type_name(&mut self, ty: &Type) -> String {
+        match ty {
+            Type::Int => "int".to_string(),
+            Type::Float => "float".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::String => "string".to_string(),
+            Type::Unit | Type::Var(_) => "unit".to_string(),
+            _ => {
+                let index = match self.types.iter().position(|t| t == ty) {
+                    Some(index) => index,
+                    None => {
+                        self.types.push(ty.clone());
+                        self.types.len() - 1
+                    }
+                };
+                format!("t{}", index)
+            }
+        }
+    }
+
+    /// The variable holding a string with `text`, made when the program starts.
+    fn string(&mut self, text: &str) -> String {
+        if let Some(name) = self.strings.get(text) {
+            return name.clone();
+        }
+        let name = unique(&mut self.names, &format!("str_{}", self.strings.len()));
+        self.strings.insert(text.to_string(), name.clone());
+        self.string_order.push((name.clone(), text.to_string()));
+        return name;
+    }
+
+    fn finish(mut self) -> Result<String, String> {
+        let mut main = Body::new();
+        for node in &self.module.body {
+            self.discard(&mut main, node)?;
+        }
+        main.line("return 0;");
+
+        while let Some((index, pending)) = self.pending.pop() {
+            let name = self.functions[index].0.clone();
+            let code = match pending {
+                Pending::User(function) => self.user_body(&name, function)?,
+                Pending::Thunk { symbol, ty } => self.thunk_body(&name, &symbol, &ty)?,
+                Pending::Write(ty) => self.write_body(&name, &ty),
+                Pending::Eq(ty) => self.eq_body(&name, &ty),
+                Pending::Print(ty) => self.print_body(&name, &ty),
+            };
+            self.functions[index].1 = Some(code);
+        }
+
+        let mut out = String::new();
+        out.push_str(RUNTIME);
+        out.push('\n');
+        for (name, _) in &self.string_order {
+            out.push_str(&format!("static nano_string *{};\n", name));
+        }
+        for (symbol, ty) in &self.module.globals {
+            out.push_str(&format!("static {};\n", declare(ty, &self.globals[symbol])));
+        }
+        out.push('\n');
+        for (_, code) in &self.functions {
+            // Without the comment on the type.
+            if let Some((signature, _)) = code {
+                let prototype = signature.lines().last().unwrap_or_default();
+                out.push_str(&format!("{};\n", prototype));
+            }
+        }
+        for (_, code) in &self.functions {
+            if let Some((signature, body)) = code {
+                out.push_str(&format!("\n{} {{\n{}}}\n", signature, body));
+            }
+        }
+        out.push_str("\nint main(void) {\n");
+        for (name, text) in &self.string_order {
+            out.push_str(&format!(
+                "    {} = nano_string_new({}, {});\n",
+                name,
+                c_string(text),
+                text.len()
+            ));
+        }
+        out.push_str(&main.code);
+        out.push_str("}\n");
+        return Ok(out);
+    }
+
+    fn user_body(&mut self, name: &str, function: &Function) -> Result<(String, String), String> {
+        let mut b = Body::new();
+        let mut params = Vec::new();
+        for (param, ty) in function.captures.iter().chain(function.params.iter()) {
+            let local = b.local(param);
+            params.push(declare(ty, &local));
+            b.scopes.push((param.clone(), local));
+        }
+        let value = self.expr(&mut b, &function.body)?;
+        b.line(&format!("return {};", value));
+        let signature = signature(&function.ret, name, &params);
+        return Ok((signature, b.code));
+    }
+
+    /// `(closure, args...)`, unpacking the closure's captures before `args`.
+    fn thunk_body(
+        &mut self,
+        name: &str,
+        symbol: &str,
+        ty: &Type,
+    ) -> Result<(String, String), String> {
+        let (params, ret) = match ty {
+            Type::Fn(params, ret) => (params.clone(), ret.as_ref().clone()),
+            _ => (Vec::new(), Type::Unit),
+        };
+        let mut b = Body::new();
+        let mut declarations = vec!["nano_closure *closure".to_string()];
+        let mut args = Vec::new();
+        for (i, ty) in params.iter().enumerate() {
+            let arg = format!("a{}", i);
+            declarations.push(format!("nano_value {}", arg));
+            args.push((unbox(&arg, ty), ty.clone()));
+        }
+        let signature = format!("static nano_value {}({})", name, declarations.join(", "));
+
+        if let Some(intrinsic) = symbol.strip_prefix(INTRINSIC_PREFIX) {
+            b.line("(void)closure;");
+            let (value, _) = self.intrinsic(intrinsic, &args, &ret)?;
+            b.line(&format!("return {};", boxed(&value, &ret)));
+            return Ok((signature, b.code));
+        }
+
+        let function = self.user(symbol)?;
+        let captures = match self.module.function(symbol) {
+            Some(f) => f.captures.clone(),
+            None => Vec::new(),
+        };
+        if captures.is_empty() {
+            b.line("(void)closure;");
+        }
+        let mut values: Vec<String> = captures
+            .iter()
+            .enumerate()
+            .map(|(i, (_, ty))| unbox(&format!("closure->captures[{}]", i), ty))
+            .collect();
+        values.extend(args.into_iter().map(|(arg, _)| arg));
+        let call = format!("{}({})", function, values.join(", "));
+        b.line(&format!("return {};", boxed(&call, &ret)));
+        return Ok((signature, b.code));
+    }
+
+    /// Compiles `node` for its effects only.
+    fn discard(&mut self, b: &mut Body, node: &IRNode) -> Result<(), String> {
+        match &node.kind {
+            IRKind::SymbolCall { .. } | IRKind::Call { .. } => {
+                let (call, effects) = self.call(b, node)?;
+                if effects {
+                    b.line(&format!("{};", call));
+                }
+            }
+            // Like the top-level `fn`s, no closure is needed.
+            IRKind::FnRef { captures, .. } => {
+                for capture in captures {
+                    self.discard(b, capture)?;
+                }
+            }
+            _ => {
+                self.expr(b, node)?;
+            }
+        }
+        return Ok(());
+    }
+
+    /// Compiles `node`, returning a C expression for its value.
+    fn expr(&mut self, b: &mut Body, node: &IRNode) -> Result<String, String> {
+        let value = match &node.kind {
+            IRKind::Literal(literal) => match literal {
+                Literal::Unit => "NANO_UNIT".to_string(),
+                Literal::Int(i) => int_literal(*i),
+                Literal::Float(x) => float_literal(*x),
+                Literal::Bool(x) => x.to_string(),
+                Literal::String(s) => self.string(s),
+            },
+            IRKind::Local(name) => match b.scopes.iter().rev().find(|(n, _)| n == name) {
+                Some((_, local)) => local.clone(),
+                None => return Err(format!("The C backend can't find local '{}'.", name)),
+            },
+            IRKind::Global(symbol) => self.global(symbol)?,
+            IRKind::SetGlobal { symbol, value } => {
+                let global = self.global(symbol)?;
+                let value = self.expr(b, value)?;
+                b.line(&format!("{} = {};", global, value));
+                global
+            }
+            IRKind::Let { name, value } => {
+                let value_ty = value.ty.clone();
+                let value = self.expr(b, value)?;
+                let local = b.local(name);
+                b.line(&format!("{} = {};", declare(&value_ty, &local), value));
+                b.scopes.push((name.clone(), local.clone()));
+                local
+            }
+            IRKind::Block(items) => {
+                let depth = b.scopes.len();
+                let mut value = "NANO_UNIT".to_string();
+                for (i, item) in items.iter().enumerate() {
+                    if i + 1 < items.len() {
+                        self.discard(b, item)?;
+                    } else {
+                        value = self.expr(b, item)?;
+                    }
+                }
+                b.scopes.truncate(depth);
+                value
+            }
+            IRKind::FnRef { symbol, captures } => {
+                let thunk = self.thunk(symbol, &node.ty);
+                let name = symbol.split('<').next().unwrap_or(symbol).to_string();
+                let name = self.string(&name);
+                let mut values = Vec::new();
+                for capture in captures {
+                    values.push((self.expr(b, capture)?, &capture.ty));
+                }
+                let closure = b.temp(
+                    &node.ty,
+                    &format!(
+                        "nano_closure_new((void (*)(void)){}, {}, {})",
+                        thunk,
+                        name,
+                        captures.len()
+                    ),
+                );
+                for (i, (value, ty)) in values.into_iter().enumerate() {
+                    b.line(&format!(
+                        "{}->captures[{}] = {};",
+                        closure,
+                        i,
+                        boxed(&value, ty)
+                    ));
+                }
+                closure
+            }
+            IRKind::SymbolCall { .. } | IRKind::Call { .. } => {
+                let (call, effects) = self.call(b, node)?;
+                match (effects, &node.ty) {
+                    (false, _) => call,
+                    (true, Type::Unit | Type::Var(_)) => {
+                        b.line(&format!("{};", call));
+                        "NANO_UNIT".to_string()
+                    }
+                    (true, ty) => b.temp(ty, &call),
+                }
+            }
+            IRKind::List(items) => {
+                let mut values = Vec::new();
+                for item in items {
+                    values.push((self.expr(b, item)?, &item.ty));
+                }
+                let list = b.temp(&node.ty, &format!("nano_list_new({})", items.len()));
+                for (i, (value, ty)) in values.into_iter().enumerate() {
+                    b.line(&format!("{}->items[{}] = {};", list, i, boxed(&value, ty)));
+                }
+                list
+            }
+            IRKind::Record(fields) => {
+                let keys = field_names(&node.ty, fields.iter().map(|(k, _)| k.clone()));
+                let mut values = Vec::new();
+                for (key, value) in fields {
+                    let slot = match keys.iter().position(|k| k == key) {
+                        None => return Err(format!("The C backend can't find field '{}'.", key)),
+                        Some(slot) => slot,
+                    };
+                    values.push((slot, self.expr(b, value)?, &value.ty));
+                }
+                let record = b.temp(&node.ty, &format!("nano_record_new({})", keys.len()));
+                for (slot, value, ty) in values {
+                    b.line(&format!("{}[{}] = {};", record, slot, boxed(&value, ty)));
+                }
+                record
+            }
+            IRKind::GetField { record, key } => {
+                let keys = field_names(&record.ty, std::iter::empty());
+                let slot = match keys.iter().position(|k| k == key) {
+                    None => return Err(format!("The C backend can't find field '{}'.", key)),
+                    Some(slot) => slot,
+                };
+                let record = self.expr(b, record)?;
+                unbox(&format!("{}[{}]", record, slot), &node.ty)
+            }
+            IRKind::Loop {
+                var,
+                iterable,
+                body,
+                collect,
+            } => self.for_loop(b, var, iterable, body, *collect)?,
+            IRKind::Comptime(value) => self.expr(b, value)?,
+            IRKind::Import { path } => {
+                return Err(format!(
+                    "The C backend can't compile the import of '{}'.",
+                    path
+                ));
+            }
+        };
+        return Ok(value);
+    }
+
+    fn global(&self, symbol: &str) -> Result<String, String> {
+        match self.globals.get(symbol) {
+            Some(global) => Ok(global.clone()),
+            None => Err(format!("The C backend can't find global '{}'.", symbol)),
+        }
+    }
+
+    /// A call, compiled up to the call itself, and whether it has effects.
+    fn call(&mut self, b: &mut Body, node: &IRNode) -> Result<(String, bool), String> {
+        match &node.kind {
+            IRKind::SymbolCall { symbol, args } => {
+                let mut values = Vec::new();
+                for arg in args {
+                    values.push((self.expr(b, arg)?, arg.ty.clone()));
+                }
+                return match symbol.strip_prefix(INTRINSIC_PREFIX) {
+                    Some(name) => self.intrinsic(name, &values, &node.ty),
+                    None => {
+                        let function = self.user(symbol)?;
+                        let values: Vec<String> = values.into_iter().map(|(v, _)| v).collect();
+                        Ok((format!("{}({})", function, values.join(", ")), true))
+                    }
+                };
+            }
+            IRKind::Call { callee, args } => {
+                let closure = self.expr(b, callee)?;
+                let closure = match is_identifier(&closure) {
+                    true => closure,
+                    false => b.temp(&callee.ty, &closure),
+                };
+                let mut values = vec![closure.clone()];
+                for arg in args {
+                    let value = self.expr(b, arg)?;
+                    values.push(boxed(&value, &arg.ty));
+                }
+                let types = vec!["nano_value"; args.len()];
+                let call = format!(
+                    "((nano_value (*)(nano_closure *{})){}->code)({})",
+                    types.iter().map(|t| format!(", {}", t)).collect::<String>(),
+                    closure,
+                    values.join(", ")
+                );
+                return Ok((unbox(&call, &node.ty), true));
+            }
+            _ => return Ok((self.expr(b, node)?, false)),
+        }
+    }
+
+    fn for_loop(
+        &mut self,
+        b: &mut Body,
+        var: &str,
+        iterable: &IRNode,
+        body: &IRNode,
+        collect: bool,
+    ) -> Result<String, String> {
+        let list = self.expr(b, iterable)?;
+        let list = match is_identifier(&list) {
+            true => list,
+            false => b.temp(&iterable.ty, &list),
+        };
+        let results = match collect {
+            true => Some(b.temp(
+                &Type::List(Box::new(body.ty.clone())),
+                &format!("nano_list_new({}->length)", list),
+            )),
+            false => None,
+        };
+        b.temps += 1;
+        let i = format!("t{}", b.temps);
+        b.line(&format!(
+            "for (int64_t {i} = 0; {i} < {}->length; {i}++) {{",
+            list
+        ));
+        b.indent += 1;
+
+        let item = match &iterable.ty {
+            Type::List(item) => item.as_ref().clone(),
+            _ => Type::Unit,
+        };
+        let depth = b.scopes.len();
+        let local = b.local(var);
+        let value = unbox(&format!("{}->items[{}]", list, i), &item);
+        b.line(&format!("{} = {};", declare(&item, &local), value));
+        b.scopes.push((var.to_string(), local));
+        match &results {
+            Some(results) => {
+                let value = self.expr(b, body)?;
+                b.line(&format!(
+                    "{}->items[{}] = {};",
+                    results,
+                    i,
+                    boxed(&value, &body.ty)
+                ));
+            }
+            None => self.discard(b, body)?,
+        }
+        b.scopes.truncate(depth);
+
+        b.indent -= 1;
+        b.line("}");
+        return Ok(results.unwrap_or_else(|| "NANO_UNIT".to_string()));
+    }
+
+    /// An intrinsic applied to `args`, and whether it has effects.
+    fn intrinsic(
+        &mut self,
+        name: &str,
+        args: &[(String, Type)],
+        ret: &Type,
+    ) -> Result<(String, bool), String> {
+        let first = args.first().map(|(_, ty)| ty.clone()).unwrap_or(Type::Unit);
+        let values: Vec<&str> = args.iter().map(|(v, _)| v.as_str()).collect();
+        let (a, b) = match values.as_slice() {
+            [a] => (*a, ""),
+            [a, b] => (*a, *b),
+            _ => ("", ""),
+        };
+        let value = match (name, &first) {
+            ("print", ty) => {
+                let print = self.helper("print", ty);
+                return Ok((format!("{}({})", print, a), true));
+            }
+            ("range", _) => format!("nano_range({}, {})", a, b),
+            ("eq", ty) => self.eq(ty, a, b),
+            ("ne", ty) => format!("!{}", self.eq(ty, a, b)),
+            ("sum", Type::String) => format!("nano_concat({}, {})", a, b),
+            ("sum", Type::Int) => format!("nano_add({}, {})", a, b),
+            ("sub", Type::Int) => format!("nano_sub({}, {})", a, b),
+            ("mul", Type::Int) => format!("nano_mul({}, {})", a, b),
+            // These stop the program when dividing by zero.
+            ("div", Type::Int) => return Ok((format!("nano_div({}, {})", a, b), true)),
+            ("rem", Type::Int) => return Ok((format!("nano_rem({}, {})", a, b), true)),
+            ("sum", Type::Float) => format!("({} + {})", a, b),
+            ("sub", Type::Float) => format!("({} - {})", a, b),
+            ("mul", Type::Float) => format!("({} * {})", a, b),
+            ("div", Type::Float) => format!("({} / {})", a, b),
+            ("rem", Type::Float) => format!("fmod({}, {})", a, b),
+            _ => {
+                let types: Vec<String> = args.iter().map(|(_, t)| t.to_string()).collect();
+                return Err(format!(
+                    "The C backend has no '{}{}' for ({}) -> {}.",
+                    INTRINSIC_PREFIX,
+                    name,
+                    types.join(", "),
+                    ret
+                ));
+            }
+        };
+        return Ok((value, false));
+    }
+
+    /// Whether the values `a` and `b` of type `ty` are equal, as a C expression.
+    fn eq(&mut self, ty: &Type, a: &str, b: &str) -> String {
+        match ty {
+            Type::String => format!("nano_string_eq({}, {})", a, b),
+            Type::Unit | Type::Var(_) => format!("nano_unit_eq({}, {})", a, b),
+            Type::List(_) | Type::Record(_) => {
+                format!("{}({}, {})", self.helper("eq", ty), a, b)
+            }
+            _ => format!("({} == {})", a, b),
+        }
+    }
+
+    /// The statement writing the value `x` of type `ty`, as nano shows it.
+    fn write(&mut self, ty: &Type, x: &str) -> String {
+        let function = match ty {
+            Type::Int => "nano_write_int".to_string(),
+            Type::Float => "nano_write_float".to_string(),
+            Type::Bool => "nano_write_bool".to_string(),
+            Type::String => "nano_write_string".to_string(),
+            Type::Unit | Type::Var(_) => "nano_write_unit".to_string(),
+            _ => self.helper("write", ty),
+        };
+        return format!("{}({});", function, x);
+    }
+
+    /// write_T(x), for lists, records and closures, which have no function in the runtime.
+    fn write_body(&mut self, name: &str, ty: &Type) -> (String, String) {
+        let mut b = Body::new();
+        match ty {
+            Type::List(item) => {
+                b.line("putchar('[');");
+                b.line("for (int64_t i = 0; i < x->length; i++) {");
+                b.indent += 1;
+                b.line("if (i > 0) {");
+                b.line("    fputs(\", \", stdout);");
+                b.line("}");
+                let write = self.write(item, &unbox("x->items[i]", item));
+                b.line(&write);
+                b.indent -= 1;
+                b.line("}");
+                b.line("putchar(']');");
+            }
+            Type::Record(fields) => {
+                if fields.is_empty() {
+                    b.line("(void)x;");
+                }
+                b.line("putchar('{');");
+                for (i, (key, field)) in fields.iter().enumerate() {
+                    let separator = match i {
+                        0 => "",
+                        _ => ", ",
+                    };
+                    let label = c_string(&format!("{}{}: ", separator, key));
+                    b.line(&format!("fputs({}, stdout);", label));
+                    let write = self.write(field, &unbox(&format!("x[{}]", i), field));
+                    b.line(&write);
+                }
+                b.line("putchar('}');");
+            }
+            Type::Fn(_, _) => {
+                b.line("fputs(\"<fn \", stdout);");
+                b.line("nano_out_string(x->name);");
+                b.line("putchar('>');");
+            }
+            _ => {
+                let write = self.write(ty, "x");
+                b.line(&write);
+            }
+        }
+        let signature = format!(
+            "/* {} */\nstatic void {}({})",
+            comment(ty),
+            name,
+            declare(ty, "x")
+        );
+        return (signature, b.code);
+    }
+
+    /// eq_T(a, b), for lists and records.
+    fn eq_body(&mut self, name: &str, ty: &Type) -> (String, String) {
+        let mut b = Body::new();
+        match ty {
+            Type::List(item) => {
+                b.line("if (a->length != b->length) {");
+                b.line("    return false;");
+                b.line("}");
+                b.line("for (int64_t i = 0; i < a->length; i++) {");
+                let eq = self.eq(
+                    item,
+                    &unbox("a->items[i]", item),
+                    &unbox("b->items[i]", item),
+                );
+                b.line(&format!("    if (!{}) {{", eq));
+                b.line("        return false;");
+                b.line("    }");
+                b.line("}");
+            }
+            Type::Record(fields) => {
+                if fields.is_empty() {
+                    b.line("(void)a;");
+                    b.line("(void)b;");
+                }
+                for (i, field) in fields.values().enumerate() {
+                    let (a, c) = (format!("a[{}]", i), format!("b[{}]", i));
+                    let eq = self.eq(field, &unbox(&a, field), &unbox(&c, field));
+                    b.line(&format!("if (!{}) {{", eq));
+                    b.line("    return false;");
+                    b.line("}");
+                }
+            }
+            _ => {}
+        }
+        b.line("return true;");
+        let params = [declare(ty, "a"), declare(ty, "b")];
+        let signature = format!(
+            "/* {} */\n{}",
+            comment(ty),
+            self::signature(&Type::Bool, name, &params)
+        );
+        return (signature, b.code);
+    }
+
+    /// print_T(x): the value and a newline, strings unquoted.
+    fn print_body(&mut self, name: &str, ty: &Type) -> (String, String) {
+        let mut b = Body::new();
+        let write = match ty {
+            Type::String => "nano_out_string(x);".to_string(),
+            ty => self.write(ty, "x"),
+        };
+        b.line(&write);
+        b.line("putchar('\\n');");
+        b.line("return NANO_UNIT;");
+        let signature = signature(&Type::Unit, name, &[declare(ty, "x")]);
+        let signature = match ty {
+            Type::List(_) | Type::Record(_) | Type::Fn(_, _) => {
+                format!("/* {} */\n{}", comment(ty), signature)
+            }
+            _ => signature,
+        };
+        return (signature, b.code);
+    }
+}
+
+/// The C type of nano values of type `ty`.
+fn c_type(ty: &Type) -> String {
+    let name = match ty {
+        Type::Unit | Type::Var(_) => "nano_unit",
+        Type::Int => "int64_t",
+        Type::Float => "double",
+        Type::Bool => "bool",
+        Type::String => "nano_string *",
+        Type::List(_) => "nano_list *",
+        Type::Record(_) => "nano_value *",
+        Type::Fn(_, _) => "nano_closure *",
+    };
+    return name.to_string();
+}
+
+/// `int64_t x`, `nano_string *s`.
+fn declare(ty: &Type, name: &str) -> String {
+    let ty = c_type(ty);
+    match ty.ends_with('*') {
+        true => format!("{}{}", ty, name),
+        false => format!("{} {}", ty, name),
+    }
+}
+
+fn signature(ret: &Type, name: &str, params: &[String]) -> String {
+    let params = match params.is_empty() {
+        true => "void".to_string(),
+        false => params.join(", "),
+    };
+    return format!("static {}({})", declare(ret, name), params);
+}
+
+/// `value` of type `ty` as a `nano_value`, to go in a list, record or closure.
+fn boxed(value: &str, ty: &Type) -> String {
+    let field = match ty {
+        Type::Unit | Type::Var(_) => return value.to_string(),
+        Type::Int => "i",
+        Type::Float => "f",
+        Type::Bool => "b",
+        Type::String | Type::List(_) | Type::Record(_) | Type::Fn(_, _) => "p",
+    };
+    return format!("(nano_value){{.{} = {}}}", field, value);
+}
+
+/// The value of type `ty` in the `nano_value` `slot`.
+fn unbox(slot: &str, ty: &Type) -> String {
+    match ty {
+        Type::Unit | Type::Var(_) => slot.to_string(),
+        Type::Int => format!("{}.i", slot),
+        Type::Float => format!("{}.f", slot),
+        Type::Bool => format!("{}.b", slot),
+        _ => format!("(({}){}.p)", c_type(ty), slot),
+    }
+}
+
+fn int_literal(i: i64) -> String {
+    match i {
+        i64::MIN => "INT64_MIN".to_string(),
+        i if i32::try_from(i).is_ok() => i.to_string(),
+        i => format!("INT64_C({})", i),
+    }
+}
+
+fn float_literal(x: f64) -> String {
+    if x.is_nan() {
+        return "NAN".to_string();
+    }
+    if x.is_infinite() {
+        return match x > 0.0 {
+            true => "INFINITY".to_string(),
+            false => "(-INFINITY)".to_string(),
+        };
+    }
+    // Rust's shortest form reads back as the same double in C.
+    let text = format!("{:?}", x);
+    return match x.is_sign_negative() {
+        true => format!("({})", text),
+        false => text,
+    };
+}
+
+/// A C string literal with the bytes of `text`.
+fn c_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for byte in text.bytes() {
+        match byte {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            // No trigraphs.
+            b'?' => out.push_str("\\?"),
+            b' '..=b'~' => out.push(byte as char),
+            _ => out.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    out.push('"');
+    return out;
+}
+
+/// A type for a comment, which can't hold `*/`.
+fn comment(ty: &Type) -> String {
+    ty.to_string().replace("*/", "* /")
+}
+
+/// `outer::inner` -> `outer_inner`, `id<__nano__::int>` -> `id_int`.
+fn identifier(name: &str) -> String {
+    let name = name.replace(INTRINSIC_PREFIX, "");
+    let mut out = String::new();
+    for c in name.chars() {
+        match c.is_ascii_alphanumeric() {
+            true => out.push(c),
+            false if !out.ends_with('_') => out.push('_'),
+            false => {}
+        }
+    }
+    let out = out.trim_matches('_').to_string();
+    match out.is_empty() {
+        true => "_".to_string(),
+        false => out,
+    }
+}
+
+/// `name`, or `name_2`, `name_3`... if it's taken.
+fn unique(names: &mut HashSet<String>, name: &str) -> String {
+    let mut candidate = name.to_string();
+    let mut n = 1;
+    while names.contains(&candidate) {
+        n += 1;
+        candidate = format!("{}_{}", name, n);
+    }
+    names.insert(candidate.clone());
+    return candidate;
+}
+
+fn is_identifier(text: &str) -> bool {
+    text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+    use crate::build::{front_end, interpreted};
+
+    /// The C `compile` makes of `source`, without the runtime, for diffing codegen.
+    fn generated(source: &str) -> String {
+        let c = compile(&front_end(source)).expect("the backend failed");
+        let program = c.strip_prefix(RUNTIME).expect("the runtime comes first");
+        return program.trim_start().to_string();
+    }
+
+    /// Builds `source`'s C with `cc` and runs it, checking it prints what the interpreter does.
+    fn prints_like_the_interpreter(name: &str, source: &str) {
+        let folder = std::env::temp_dir().join(format!("nnc-c-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        let (c, program) = (folder.join("program.c"), folder.join("program"));
+        let module = front_end(source);
+        std::fs::write(&c, compile(&module).expect("the backend failed")).unwrap();
+        let built = Command::new("cc")
+            .args(["-std=c99", "-Wall", "-Werror"])
+            .arg(&c)
+            .args(["-lm", "-o"])
+            .arg(&program)
+            .status()
+            .expect("couldn't run cc");
+        assert!(built.success());
+        let output = Command::new(&program).output().unwrap();
+        std::fs::remove_dir_all(&folder).unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            interpreted(&module)
+        );
+    }
+
+    #[test]
+    fn functions_loops_and_records_compile_to_this() {
+        let source = "fn square(x) -> x * x
+let xs = select square(n) for n in 0..3
+print(xs)
+print({a: 1.5, b: 's'})";
+        assert_eq!(
+            generated(source),
+            r#"static nano_string *str_0;
+static nano_list *g_xs;
+
+static int64_t fn_square(int64_t v_x);
+static nano_unit print_t0(nano_list *x);
+static nano_unit print_t1(nano_value *x);
+static void write_t1(nano_value *x);
+static void write_t0(nano_list *x);
+
+static int64_t fn_square(int64_t v_x) {
+    return nano_mul(v_x, v_x);
+}
+
+/* [int] */
+static nano_unit print_t0(nano_list *x) {
+    write_t0(x);
+    putchar('\n');
+    return NANO_UNIT;
+}
+
+/* {a: float, b: string} */
+static nano_unit print_t1(nano_value *x) {
+    write_t1(x);
+    putchar('\n');
+    return NANO_UNIT;
+}
+
+/* {a: float, b: string} */
+static void write_t1(nano_value *x) {
+    putchar('{');
+    fputs("a: ", stdout);
+    nano_write_float(x[0].f);
+    fputs(", b: ", stdout);
+    nano_write_string(((nano_string *)x[1].p));
+    putchar('}');
+}
+
+/* [int] */
+static void write_t0(nano_list *x) {
+    putchar('[');
+    for (int64_t i = 0; i < x->length; i++) {
+        if (i > 0) {
+            fputs(", ", stdout);
+        }
+        nano_write_int(x->items[i].i);
+    }
+    putchar(']');
+}
+
+int main(void) {
+    str_0 = nano_string_new("s", 1);
+    nano_list *t1 = nano_range(0, 3);
+    nano_list *t2 = nano_list_new(t1->length);
+    for (int64_t t3 = 0; t3 < t1->length; t3++) {
+        int64_t v_n = t1->items[t3].i;
+        int64_t t4 = fn_square(v_n);
+        t2->items[t3] = (nano_value){.i = t4};
+    }
+    g_xs = t2;
+    print_t0(g_xs);
+    nano_value *t5 = nano_record_new(2);
+    t5[0] = (nano_value){.f = 1.5};
+    t5[1] = (nano_value){.p = str_0};
+    print_t1(t5);
+    return 0;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn closures_compile_to_this() {
+        let source = "fn adder(n) -> (x -> x + n)
+let add2 = adder(2)
+print(add2(40))";
+        assert_eq!(
+            generated(source),
+            r#"static nano_string *str_0;
+static nano_closure *g_add2;
+
+static nano_closure *fn_adder(int64_t v_n);
+static nano_unit print_int(int64_t x);
+static nano_value thunk_adder_lambda(nano_closure *closure, nano_value a0);
+static int64_t fn_adder_lambda(int64_t v_n, int64_t v_x);
+
+static nano_closure *fn_adder(int64_t v_n) {
+    nano_closure *t1 = nano_closure_new((void (*)(void))thunk_adder_lambda, str_0, 1);
+    t1->captures[0] = (nano_value){.i = v_n};
+    return t1;
+}
+
+static nano_unit print_int(int64_t x) {
+    nano_write_int(x);
+    putchar('\n');
+    return NANO_UNIT;
+}
+
+static nano_value thunk_adder_lambda(nano_closure *closure, nano_value a0) {
+    return (nano_value){.i = fn_adder_lambda(closure->captures[0].i, a0.i)};
+}
+
+static int64_t fn_adder_lambda(int64_t v_n, int64_t v_x) {
+    return nano_add(v_x, v_n);
+}
+
+int main(void) {
+    str_0 = nano_string_new("adder::lambda", 13);
+    nano_closure *t1 = fn_adder(2);
+    g_add2 = t1;
+    int64_t t2 = ((nano_value (*)(nano_closure *, nano_value))g_add2->code)(g_add2, (nano_value){.i = 40}).i;
+    print_int(t2);
+    return 0;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn floats_print_like_the_interpreter() {
+        prints_like_the_interpreter(
+            "floats",
+            "print(0.1 + 0.2)
+            print(1.0 / 3.0)
+            print(2.0 / 3.0 * 100000000000000000.0)
+            print(123456789012345680000.0)
+            print(0.000001)
+            print(0.00012)
+            print(0.0001)
+            print(0.0)
+            print(1.0)
+            print(100.0)
+            print(10000000000000000.0)
+            print(9999999999999998.0)
+            print(0.0 - 2.5)
+            print([0.5, 0.1 * 3.0])",
+        );
+    }
+
+    #[test]
+    fn values_print_like_the_interpreter() {
+        prints_like_the_interpreter(
+            "values",
+            "fn id(x) -> x
+            print(id(3))
+            print(id('hi'))
+            print('a' + 'b')
+            print(select n * n for n in 0..5)
+            print({name: 'nano', age: 3, tags: ['a', 'b']})
+            print(7 % 3)
+            let k = 10
+            let addk = x -> x + k
+            print(3 |> addk)
+            fn twice(f, x) -> f(f(x))
+            print(twice((s -> s + '!'), 'hey'))
+            for i in 0..3 -> print(i)",
+        );
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::HashSet;
+
+use crate::{
+    diagnostic::Diagnostic,
+    grammar::Span,
+    interp::{fold_frames, Interpreter, Limits, RuntimeError, Value},
+    ir::{Function, IRKind, IRNode, Literal, Module},
+    types::Type,
+};
+
+// The compile-time evaluator: runs `#%` annotation values and `comptime`
+// expressions while compiling, with the reference interpreter.
+//
+// It's sandboxed: every evaluation shares a fuel budget, so a runaway loop
+// fails the build instead of hanging it, and I/O isn't allowed unless asked
+// for. Only functions are available, top-level `let`s only get a value at
+// run time. Annotation values are kept for the driver and later passes;
+// `comptime` nodes are replaced in place by the IR of their value.
+
+/// How many nodes a whole compilation may evaluate by default.
+pub const DEFAULT_FUEL: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    pub fuel: u64,
+    pub allow_io: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            fuel: DEFAULT_FUEL,
+            allow_io: false,
+        }
+    }
+}
+
+/// An evaluated `#%name value`.
+#[derive(Debug, Clone)]
+pub struct Annotation {
+    pub name: String,
+    // Unit when the annotation has no value.
+    pub value: Value,
+    pub span: Option<Span>,
+    // Where each field is set, when the value is written as a record.
+    pub fields: Vec<(String, Span)>,
+}
+
+impl Annotation {
+    /// Where the field `name` is set, or the whole annotation when that isn't known.
+    pub fn field_span(&self, name: &str) -> Option<Span> {
+        let field = self.fields.iter().rev().find(|(n, _)| n == name);
+        return field.map(|(_, span)| *span).or(self.span);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Comptime {
+    // In source order.
+    pub annotations: Vec<Annotation>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Comptime {
+    /// The value of the annotation called `name`, the last one if it's set more than once.
+    pub fn annotation(&self, name: &str) -> Option<&Value> {
+        self.annotations
+            .iter()
+            .rev()
+            .find(|a| a.name == name)
+            .map(|a| &a.value)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(Diagnostic::is_error)
+    }
+}
+
+/// Evaluates the module's annotations and replaces its `comptime` nodes by their values.
+pub fn evaluate(module: &mut Module, options: &Options) -> Comptime {
+    let mut evaluator = Evaluator {
+        interpreter: Interpreter::with_limits(Limits {
+            fuel: Some(options.fuel),
+            allow_io: options.allow_io,
+        }),
+        fuel: options.fuel,
+        comptime: Comptime::default(),
+        out_of_fuel: false,
+    };
+    evaluator.interpreter.load(module);
+
+    let annotations = module.annotations.clone();
+    for annotation in &annotations {
+        let value = match evaluator.value_of(&annotation.value) {
+            None => continue,
+            Some(value) => value,
+        };
+        evaluator.comptime.annotations.push(Annotation {
+            name: annotation.name.clone(),
+            value,
+            span: annotation.span,
+            fields: annotation.fields.clone(),
+        });
+    }
+
+    // `to_ir` reads the signatures of closures, from before the bodies change.
+    let functions = module.functions.clone();
+    for function in &mut module.functions {
+        evaluator.replace_in(&mut function.body, &functions);
+    }
+    for node in &mut module.body {
+        evaluator.replace_in(node, &functions);
+    }
+
+    return evaluator.comptime;
+}
+
+struct Evaluator {
+    interpreter: Interpreter,
+    fuel: u64,
+    comptime: Comptime,
+    // Reported once, later evaluations would only fail the same way.
+    out_of_fuel: bool,
+}
+
+impl Evaluator {
+    /// Evaluates `node`, reporting why it couldn't be.
+    fn value_of(&mut self, node: &IRNode) -> Option<Value> {
+        if self.out_of_fuel {
+            return None;
+        }
+        if let Some(diagnostic) = runtime_only(node) {
+            self.comptime.diagnostics.push(diagnostic);
+            return None;
+        }
+
+        self.interpreter.enter("<comptime>");
+        return match self.interpreter.eval(node) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.out_of_fuel = self.interpreter.steps() > self.fuel;
+                self.comptime
+                    .diagnostics
+                    .push(self.diagnostic(e, node.span));
+                None
+            }
+        };
+    }
+
+    fn diagnostic(&self, e: RuntimeError, at: Option<Span>) -> Diagnostic {
+        let message = format!("Compile-time evaluation failed: {}", e.message);
+        let mut diagnostic = Diagnostic::error(message, e.span.or(at));
+        if self.out_of_fuel {
+            diagnostic = diagnostic.with_note(
+                None,
+                "The limit can be raised with `--comptime-fuel=<steps>`.",
+            );
+        }
+        let frames = e.trace.iter().filter(|f| f.call_site.is_some());
+        for (frame, repeats) in fold_frames(frames) {
+            let note = match repeats {
+                1 => format!("While calling {}.", frame.symbol),
+                n => format!("While calling {} ({} times).", frame.symbol, n),
+            };
+            diagnostic = diagnostic.with_note(frame.call_site, note);
+        }
+        return diagnostic;
+    }
+
+    fn replace_in(&mut self, node: &mut IRNode, functions: &[Function]) {
+        if let IRKind::Comptime(value) = &node.kind {
+            if let Some(v) = self.value_of(value) {
+                *node = to_ir(&v, &node.ty, node.span, functions);
+            }
+            return;
+        }
+        for child in node.children_mut() {
+            self.replace_in(child, functions);
+        }
+    }
+}
+
+/// Why `node` can't be evaluated at compile time, if it uses something only known at run time.
+fn runtime_only(node: &IRNode) -> Option<Diagnostic> {
+    return find_runtime_only(node, &mut HashSet::new());
+}
+
+fn find_runtime_only(node: &IRNode, bound: &mut HashSet<String>) -> Option<Diagnostic> {
+    let name = match &node.kind {
+        IRKind::Local(name) if !bound.contains(name) => Some(name),
+        IRKind::Global(symbol) => Some(symbol),
+        _ => None,
+    };
+    if let Some(name) = name {
+        let message = format!(
+            "'{}' is only known at run time, it can't be used at compile time.",
+            name
+        );
+        return Some(Diagnostic::error(message, node.span));
+    }
+
+    // Names bound inside the expression itself are fine.
+    match &node.kind {
+        IRKind::Let { name, value } => {
+            let found = find_runtime_only(value, bound);
+            bound.insert(name.clone());
+            return found;
+        }
+        IRKind::Loop {
+            var,
+            iterable,
+            body,
+            ..
+        } => {
+            let found = find_runtime_only(iterable, bound);
+            bound.insert(var.clone());
+            return found.or_else(|| find_runtime_only(body, bound));
+        }
+        _ => node
+            .children()
+            .into_iter()
+            .find_map(|c| find_runtime_only(c, bound)),
+    }
+}
+
+/// The IR building `value` back, typed as `ty`.
+fn to_ir(value: &Value, ty: &Type, span: Option<Span>, functions: &[Function]) -> IRNode {
+    let kind = match value {
+        Value::Unit => IRKind::Literal(Literal::Unit),
+        Value::Int(i) => IRKind::Literal(Literal::Int(*i)),
+        Value::Float(x) => IRKind::Literal(Literal::Float(*x)),
+        Value::Bool(b) => IRKind::Literal(Literal::Bool(*b)),
+        Value::String(s) => IRKind::Literal(Literal::String(s.to_string())),
+        Value::List(items) => {
+            let item_ty = match ty {
+                Type::List(item) => item.as_ref().clone(),
+                _ => Type::Unit,
+            };
+            IRKind::List(
+                items
+                    .iter()
+                    .map(|i| to_ir(i, &item_ty, span, functions))
+                    .collect(),
+            )
+        }
+        Value::Record(fields) => IRKind::Record(
+            fields
+                .iter()
+                .map(|(key, v)| {
+                    let field_ty = match ty {
+                        Type::Record(types) => types.get(key).cloned().unwrap_or(Type::Unit),
+                        _ => Type::Unit,
+                    };
+                    (key.clone(), to_ir(v, &field_ty, span, functions))
+                })
+                .collect(),
+        ),
+        Value::Closure { symbol, captures } => {
+            let types = functions
+                .iter()
+                .find(|f| f.symbol == symbol.as_ref())
+                .map(|f| f.captures.iter().map(|(_, t)| t.clone()).collect())
+                .unwrap_or_else(Vec::new);
+            IRKind::FnRef {
+                symbol: symbol.to_string(),
+                captures: captures
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| {
+                        let ty = types.get(i).cloned().unwrap_or(Type::Unit);
+                        to_ir(c, &ty, span, functions)
+                    })
+                    .collect(),
+            }
+        }
+    };
+
+    return IRNode::new(kind, ty.clone(), span);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::analyzed;
+
+    /// `source` with its compile-time code run, with `fuel` steps.
+    fn evaluated(source: &str, fuel: u64) -> (Module, Comptime) {
+        let mut module = analyzed(source);
+        let options = Options {
+            fuel,
+            allow_io: false,
+        };
+        let comptime = evaluate(&mut module, &options);
+        return (module, comptime);
+    }
+
+    /// Whether `node` or a node in it is one `is` holds for.
+    fn contains(node: &IRNode, is: &dyn Fn(&IRKind) -> bool) -> bool {
+        is(&node.kind) || node.children().into_iter().any(|c| contains(c, is))
+    }
+
+    /// The only diagnostic `source` gets, as its message and notes.
+    fn failure(source: &str, fuel: u64) -> (String, Vec<String>) {
+        let (_, comptime) = evaluated(source, fuel);
+        assert_eq!(comptime.diagnostics.len(), 1, "{:?}", comptime.diagnostics);
+        let diagnostic = comptime.diagnostics[0].clone();
+        let notes = diagnostic.notes.into_iter().map(|(_, n)| n).collect();
+        return (diagnostic.message, notes);
+    }
+
+    #[test]
+    fn comptime_values_are_folded_into_constants() {
+        let source = "fn square(x) -> x * x\nlet k = comptime square(12) + 1\nprint(k)";
+        let (module, comptime) = evaluated(source, DEFAULT_FUEL);
+        assert!(!comptime.has_errors(), "{:?}", comptime.diagnostics);
+        let folded = |k: &IRKind| *k == IRKind::Literal(Literal::Int(145));
+        let unfolded = |k: &IRKind| matches!(k, IRKind::Comptime(_));
+        assert!(module.body.iter().any(|n| contains(n, &folded)));
+        assert!(!module.body.iter().any(|n| contains(n, &unfolded)));
+    }
+
+    #[test]
+    fn annotations_are_evaluated() {
+        let (_, comptime) = evaluated("fn six() -> 6\n#%answer six() * 7", DEFAULT_FUEL);
+        assert!(!comptime.has_errors(), "{:?}", comptime.diagnostics);
+        assert_eq!(comptime.annotation("answer"), Some(&Value::Int(42)));
+    }
+
+    #[test]
+    fn running_out_of_fuel_says_how_to_raise_it() {
+        let (message, notes) = failure("let k = comptime (0..100000000000)", 1000);
+        assert_eq!(
+            message,
+            "Compile-time evaluation failed: Ran out of fuel after 1000 steps."
+        );
+        assert_eq!(
+            notes,
+            ["The limit can be raised with `--comptime-fuel=<steps>`."]
+        );
+    }
+
+    #[test]
+    fn growing_strings_spend_fuel() {
+        let source = "fn d(s) -> s + s
+fn d4(s) -> d(d(d(d(s))))
+fn d16(s) -> d4(d4(d4(d4(s))))
+fn d64(s) -> d16(d16(d16(d16(s))))
+let k = comptime d64('ab')";
+        let (message, _) = failure(source, DEFAULT_FUEL);
+        assert_eq!(
+            message,
+            "Compile-time evaluation failed: Ran out of fuel after 1000000 steps."
+        );
+    }
+
+    #[test]
+    fn recursion_is_folded_in_the_notes() {
+        let (_, notes) = failure(
+            "fn forever(x) -> forever(x + 1)\nlet k = comptime forever(0)",
+            100,
+        );
+        assert_eq!(notes.len(), 3, "{:?}", notes);
+        assert_eq!(
+            notes[0],
+            "The limit can be raised with `--comptime-fuel=<steps>`."
+        );
+        assert!(
+            notes[1].starts_with("While calling forever ("),
+            "{}",
+            notes[1]
+        );
+        assert!(notes[1].ends_with(" times)."), "{}", notes[1]);
+        assert_eq!(notes[2], "While calling forever.");
+    }
+
+    #[test]
+    fn run_time_values_and_io_are_refused() {
+        let (message, _) = failure("let x = 1\nlet k = comptime x + 1", DEFAULT_FUEL);
+        assert!(message.ends_with("is only known at run time, it can't be used at compile time."));
+        let (message, _) = failure("let k = comptime print(1)", DEFAULT_FUEL);
+        assert_eq!(
+            message,
+            "Compile-time evaluation failed: `print` does I/O, which isn't allowed here."
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use colored::Colorize;
+
+use crate::grammar::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+/// Where the text that spans point into lives, for diagnostics about more than one file.
+pub trait Sources {
+    /// The path and text of the file `span` points into, and `span` within that file.
+    fn locate(&self, span: Span) -> Option<(&str, &str, Span)>;
+
+    /// The path of the file an offset is in, and its 1-based line and column there.
+    fn position(&self, offset: usize) -> Option<(&str, usize, usize)> {
+        let (path, source, span) = self.locate(Span::new(offset, offset))?;
+        let (line, column) = line_col(source, span.start);
+        return Some((path, line, column));
+    }
+}
+
+/// A single file, which every span points into.
+pub struct SingleFile<'a> {
+    pub path: &'a str,
+    pub source: &'a str,
+}
+
+impl Sources for SingleFile<'_> {
+    fn locate(&self, span: Span) -> Option<(&str, &str, Span)> {
+        Some((self.path, self.source, span))
+    }
+}
+
+/// A message about the source, pointing at the span it is about.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    // Extra places worth looking at, such as the earlier binding a name shadows.
+    pub notes: Vec<(Option<Span>, String)>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Option<Span>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            span,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Option<Span>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(message, span)
+        }
+    }
+
+    pub fn with_note(mut self, span: Option<Span>, message: impl Into<String>) -> Diagnostic {
+        self.notes.push((span, message.into()));
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Renders the diagnostic with the source lines it points at:
+    ///
+    /// error: Undefined name 'w'.
+    ///  --> index.nano:5:9
+    ///   |
+    /// 5 |     z + w
+    ///   |         ^
+    pub fn render(&self, path: &str, source: &str) -> String {
+        return self.render_with(&SingleFile { path, source });
+    }
+
+    /// Same as `render`, for spans that may point into any of `sources`.
+    pub fn render_with(&self, sources: &dyn Sources) -> String {
+        let title = match self.severity {
+            Severity::Error => "error".red().bold(),
+            Severity::Warning => "warning".yellow().bold(),
+            Severity::Info => "info".cyan().bold(),
+        };
+        let mut out = format!("{}: {}\n", title, self.message.bold());
+
+        if let Some((path, source, span)) = self.span.and_then(|s| sources.locate(s)) {
+            out.push_str(&render_snippet(path, source, span));
+        }
+        for (span, note) in &self.notes {
+            out.push_str(&format!("{}: {}\n", "note".dimmed().bold(), note));
+            if let Some((path, source, span)) = span.and_then(|s| sources.locate(s)) {
+                out.push_str(&render_snippet(path, source, span));
+            }
+        }
+
+        return out;
+    }
+}
+
+/// The 1-based line and column of a byte offset.
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let column = source[line_start..offset].chars().count() + 1;
+    return (line, column);
+}
+
+fn render_snippet(path: &str, source: &str, span: Span) -> String {
+    let (line, column) = line_col(source, span.start);
+    let line_text = source.lines().nth(line - 1).unwrap_or("");
+    let gutter = " ".repeat(line.to_string().len());
+
+    // Spans over several lines are underlined up to the end of the first one.
+    let width = source[span.start.min(source.len())..span.end.min(source.len())]
+        .split('\n')
+        .next()
+        .map(|s| s.chars().count())
+        .unwrap_or(0)
+        .max(1);
+
+    return format!(
+        "{}{} {}:{}:{}\n{} {}\n{} {} {}\n{} {} {}{}\n",
+        gutter,
+        "-->".blue(),
+        path,
+        line,
+        column,
+        gutter,
+        "|".blue(),
+        line.to_string().blue(),
+        "|".blue(),
+        line_text,
+        gutter,
+        "|".blue(),
+        " ".repeat(column - 1),
+        "^".repeat(width).red()
+    );
+}
@@ -0,0 +1,416 @@
+use crate::{native::Object, x86::Data};
+
+// ELF64 relocatable objects, what the `linux_x86` target writes.
+//
+// The file is the header, then the contents of each section, then the
+// section headers:
+//
+//   .text            the code, every function of the program
+//   .rodata          string literals
+//   .bss             the runtime's state and nano's globals
+//   .rela.text       where the code refers to data, as PC32 relocations
+//   .symtab          a local symbol per function, and the global `_start`
+//                    and `nano_main`
+//   .strtab          symbol names
+//   .shstrtab        section names
+//   .note.GNU-stack  empty, so the stack isn't made executable
+//
+// Everything in `.text` refers to other code relatively, so only data
+// needs relocating.
+
+const TEXT: u16 = 1;
+const RODATA: u16 = 2;
+const BSS: u16 = 3;
+const RELA_TEXT: u32 = 4;
+const SYMTAB: u32 = 5;
+const STRTAB: u32 = 6;
+const SHSTRTAB: u16 = 7;
+const SECTIONS: u16 = 9;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+
+const R_X86_64_PC32: u64 = 2;
+
+/// A string table, names being offsets into it.
+struct Strings {
+    bytes: Vec<u8>,
+}
+
+impl Strings {
+    fn new() -> Strings {
+        Strings { bytes: vec![0] }
+    }
+
+    fn add(&mut self, name: &str) -> u32 {
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(name.as_bytes());
+        self.bytes.push(0);
+        return offset;
+    }
+}
+
+struct Section {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entry_size: u64,
+}
+
+/// The bytes of a relocatable object with `object`'s code and data.
+pub fn write(object: &Object) -> Result<Vec<u8>, String> {
+    let mut names = Strings::new();
+    let mut symbols = Vec::new();
+    // The null symbol, then one per section, for relocations.
+    symbols.extend_from_slice(&symbol(0, 0, 0, 0, 0));
+    for index in [TEXT, RODATA, BSS] {
+        symbols.extend_from_slice(&symbol(0, STB_LOCAL << 4 | STT_SECTION, index, 0, 0));
+    }
+    for function in &object.symbols {
+        let name = names.add(&function.name);
+        let info = STB_LOCAL << 4 | STT_FUNC;
+        symbols.extend_from_slice(&symbol(
+            name,
+            info,
+            TEXT,
+            function.offset as u64,
+            function.size as u64,
+        ));
+    }
+    let first_global = (symbols.len() / 24) as u32;
+    for (name, at) in [("_start", object.entry), ("nano_main", object.main)] {
+        let name = names.add(name);
+        let info = STB_GLOBAL << 4 | STT_FUNC;
+        symbols.extend_from_slice(&symbol(name, info, TEXT, at as u64, 0));
+    }
+
+    let mut relocations = Vec::new();
+    for (at, data) in &object.relocations {
+        // The displacement is relative to its own end, 4 bytes on.
+        let (section, offset) = match data {
+            Data::Rodata(offset) => (RODATA, *offset),
+            Data::Bss(offset) => (BSS, *offset),
+            Data::Import(_) => {
+                return Err("ELF objects can't import functions from DLLs.".to_string())
+            }
+        };
+        relocations.extend_from_slice(&(*at as u64).to_le_bytes());
+        relocations.extend_from_slice(&((section as u64) << 32 | R_X86_64_PC32).to_le_bytes());
+        relocations.extend_from_slice(&(offset as i64 - 4).to_le_bytes());
+    }
+
+    let mut section_names = Strings::new();
+    let mut out = vec![0; 64];
+    let mut sections = vec![Section {
+        name: 0,
+        kind: 0,
+        flags: 0,
+        offset: 0,
+        size: 0,
+        link: 0,
+        info: 0,
+        align: 0,
+        entry_size: 0,
+    }];
+    let mut add = |out: &mut Vec<u8>, name: &str, kind, flags, bytes: &[u8], align| {
+        while !(out.len() as u64).is_multiple_of(align) {
+            out.push(0);
+        }
+        sections.push(Section {
+            name: section_names.add(name),
+            kind,
+            flags,
+            offset: out.len() as u64,
+            size: bytes.len() as u64,
+            link: 0,
+            info: 0,
+            align,
+            entry_size: 0,
+        });
+        out.extend_from_slice(bytes);
+    };
+    add(
+        &mut out,
+        ".text",
+        SHT_PROGBITS,
+        SHF_ALLOC | SHF_EXECINSTR,
+        &object.text,
+        16,
+    );
+    add(
+        &mut out,
+        ".rodata",
+        SHT_PROGBITS,
+        SHF_ALLOC,
+        &object.rodata,
+        8,
+    );
+    add(&mut out, ".bss", SHT_NOBITS, SHF_ALLOC | SHF_WRITE, &[], 8);
+    add(
+        &mut out,
+        ".rela.text",
+        SHT_RELA,
+        SHF_INFO_LINK,
+        &relocations,
+        8,
+    );
+    add(&mut out, ".symtab", SHT_SYMTAB, 0, &symbols, 8);
+    add(&mut out, ".strtab", SHT_STRTAB, 0, &names.bytes, 1);
+    let shstrtab = section_names.add(".shstrtab");
+    let note = section_names.add(".note.GNU-stack");
+    sections.push(Section {
+        name: shstrtab,
+        kind: SHT_STRTAB,
+        flags: 0,
+        offset: out.len() as u64,
+        size: section_names.bytes.len() as u64,
+        link: 0,
+        info: 0,
+        align: 1,
+        entry_size: 0,
+    });
+    out.extend_from_slice(&section_names.bytes);
+    sections.push(Section {
+        name: note,
+        kind: SHT_PROGBITS,
+        flags: 0,
+        offset: out.len() as u64,
+        size: 0,
+        link: 0,
+        info: 0,
+        align: 1,
+        entry_size: 0,
+    });
+
+    sections[BSS as usize].size = object.bss as u64;
+    let rela = &mut sections[RELA_TEXT as usize];
+    rela.link = SYMTAB;
+    rela.info = TEXT as u32;
+    rela.entry_size = 24;
+    let symtab = &mut sections[SYMTAB as usize];
+    symtab.link = STRTAB;
+    symtab.info = first_global;
+    symtab.entry_size = 24;
+
+    while !out.len().is_multiple_of(8) {
+        out.push(0);
+    }
+    let section_headers = out.len() as u64;
+    for section in &sections {
+        out.extend_from_slice(&section.name.to_le_bytes());
+        out.extend_from_slice(&section.kind.to_le_bytes());
+        out.extend_from_slice(&section.flags.to_le_bytes());
+        // Not loaded anywhere yet.
+        out.extend_from_slice(&0u64.to_le_bytes());
+        out.extend_from_slice(&section.offset.to_le_bytes());
+        out.extend_from_slice(&section.size.to_le_bytes());
+        out.extend_from_slice(&section.link.to_le_bytes());
+        out.extend_from_slice(&section.info.to_le_bytes());
+        out.extend_from_slice(&section.align.to_le_bytes());
+        out.extend_from_slice(&section.entry_size.to_le_bytes());
+    }
+
+    let mut header = Vec::with_capacity(64);
+    // 64-bit, little-endian, version 1, System V.
+    header.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    header.extend_from_slice(&[0; 8]);
+    // A relocatable file, for x86-64.
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&62u16.to_le_bytes());
+    header.extend_from_slice(&1u32.to_le_bytes());
+    // No entry point nor program headers.
+    header.extend_from_slice(&0u64.to_le_bytes());
+    header.extend_from_slice(&0u64.to_le_bytes());
+    header.extend_from_slice(&section_headers.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&64u16.to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(&64u16.to_le_bytes());
+    header.extend_from_slice(&SECTIONS.to_le_bytes());
+    header.extend_from_slice(&SHSTRTAB.to_le_bytes());
+    out[..64].copy_from_slice(&header);
+    return Ok(out);
+}
+
+/// An `Elf64_Sym`.
+fn symbol(name: u32, info: u8, section: u16, value: u64, size: u64) -> [u8; 24] {
+    let mut bytes = [0; 24];
+    bytes[0..4].copy_from_slice(&name.to_le_bytes());
+    bytes[4] = info;
+    bytes[6..8].copy_from_slice(&section.to_le_bytes());
+    bytes[8..16].copy_from_slice(&value.to_le_bytes());
+    bytes[16..24].copy_from_slice(&size.to_le_bytes());
+    return bytes;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::Symbol;
+    use crate::util::{u16_at, u32_at, u64_at};
+
+    fn c_string(bytes: &[u8], at: usize) -> &str {
+        let end = at + bytes[at..].iter().position(|b| *b == 0).unwrap();
+        std::str::from_utf8(&bytes[at..end]).unwrap()
+    }
+
+    /// Two functions, referring to a string and to a global.
+    fn object() -> Object {
+        Object {
+            text: vec![0xc3, 0x90, 0x48, 0x8d, 0x05, 0, 0, 0, 0, 0xc3],
+            rodata: b"hi\0".to_vec(),
+            bss: 24,
+            relocations: vec![(5, Data::Rodata(0)), (1, Data::Bss(16))],
+            imports: Vec::new(),
+            symbols: vec![
+                Symbol {
+                    name: "start".to_string(),
+                    offset: 0,
+                    size: 2,
+                },
+                Symbol {
+                    name: "main".to_string(),
+                    offset: 2,
+                    size: 8,
+                },
+            ],
+            entry: 0,
+            main: 2,
+        }
+    }
+
+    /// A section header's name, kind, offset and size.
+    fn sections(bytes: &[u8]) -> Vec<(String, u32, u64, u64)> {
+        let headers = u64_at(bytes, 0x28) as usize;
+        let count = u16_at(bytes, 0x3c) as usize;
+        let names = headers + 64 * u16_at(bytes, 0x3e) as usize;
+        let names = u64_at(bytes, names + 0x18) as usize;
+        return (0..count)
+            .map(|i| {
+                let header = headers + 64 * i;
+                let name = c_string(bytes, names + u32_at(bytes, header) as usize);
+                let kind = u32_at(bytes, header + 4);
+                let offset = u64_at(bytes, header + 0x18);
+                let size = u64_at(bytes, header + 0x20);
+                return (name.to_string(), kind, offset, size);
+            })
+            .collect();
+    }
+
+    #[test]
+    fn the_header_describes_an_x86_64_relocatable_object() {
+        let bytes = write(&object()).unwrap();
+
+        assert_eq!(&bytes[..8], &[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+        assert_eq!(u16_at(&bytes, 0x10), 1);
+        assert_eq!(u16_at(&bytes, 0x12), 62);
+        assert_eq!(u64_at(&bytes, 0x18), 0);
+        assert_eq!(u16_at(&bytes, 0x34), 64);
+        assert_eq!(u16_at(&bytes, 0x3a), 64);
+        assert_eq!(u16_at(&bytes, 0x3c), SECTIONS);
+        assert_eq!(
+            u64_at(&bytes, 0x28) as usize + 64 * SECTIONS as usize,
+            bytes.len()
+        );
+    }
+
+    #[test]
+    fn sections_hold_the_code_data_and_relocations() {
+        let object = object();
+        let bytes = write(&object).unwrap();
+        let sections = sections(&bytes);
+
+        let names: Vec<&str> = sections.iter().map(|s| s.0.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "",
+                ".text",
+                ".rodata",
+                ".bss",
+                ".rela.text",
+                ".symtab",
+                ".strtab",
+                ".shstrtab",
+                ".note.GNU-stack"
+            ]
+        );
+        let contents = |i: usize| {
+            let (_, _, offset, size) = sections[i];
+            return &bytes[offset as usize..(offset + size) as usize];
+        };
+        assert_eq!(contents(TEXT as usize), &object.text[..]);
+        assert_eq!(contents(RODATA as usize), &object.rodata[..]);
+        assert_eq!(sections[BSS as usize].1, SHT_NOBITS);
+        assert_eq!(sections[BSS as usize].3, 24);
+
+        // PC32 against the section symbols, minus the 4 bytes of the displacement.
+        let relocations = contents(RELA_TEXT as usize);
+        assert_eq!(relocations.len(), 2 * 24);
+        assert_eq!(u64_at(relocations, 0), 5);
+        assert_eq!(
+            u64_at(relocations, 8),
+            (RODATA as u64) << 32 | R_X86_64_PC32
+        );
+        assert_eq!(u64_at(relocations, 16) as i64, -4);
+        assert_eq!(u64_at(relocations, 24), 1);
+        assert_eq!(u64_at(relocations, 32), (BSS as u64) << 32 | R_X86_64_PC32);
+        assert_eq!(u64_at(relocations, 40) as i64, 12);
+    }
+
+    #[test]
+    fn start_and_nano_main_are_global() {
+        let bytes = write(&object()).unwrap();
+        let sections = sections(&bytes);
+        let symtab = &sections[SYMTAB as usize];
+        let strtab = sections[STRTAB as usize].2 as usize;
+        let first_global = u32_at(
+            &bytes,
+            u64_at(&bytes, 0x28) as usize + 64 * SYMTAB as usize + 0x2c,
+        );
+
+        let symbols: Vec<(&str, u8, u64)> = (0..symtab.3 as usize / 24)
+            .map(|i| {
+                let at = symtab.2 as usize + 24 * i;
+                let name = c_string(&bytes, strtab + u32_at(&bytes, at) as usize);
+                return (name, bytes[at + 4], u64_at(&bytes, at + 8));
+            })
+            .collect();
+        assert_eq!(first_global, 6);
+        assert_eq!(
+            symbols[4..],
+            [
+                ("start", STB_LOCAL << 4 | STT_FUNC, 0),
+                ("main", STB_LOCAL << 4 | STT_FUNC, 2),
+                ("_start", STB_GLOBAL << 4 | STT_FUNC, 0),
+                ("nano_main", STB_GLOBAL << 4 | STT_FUNC, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn imports_are_refused() {
+        let mut object = object();
+        object.relocations.push((5, Data::Import(0)));
+        assert!(write(&object).is_err());
+    }
+}
@@ -1,7 +1,476 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::{diagnostic::Sources, grammar::Span};
+
+// Where source text comes from.
+//
+// A `SourceMap` holds every file a compilation reads, each with an ID, its
+// text and where its lines start. Files are laid end to end, so a single
+// offset (and so a `Span`) tells both the file and the place in it.
+// Files are read through a `FileSystem`: the real one, or an in-memory
+// one for tests and for editors' unsaved buffers.
 
 pub fn import_as_text(path: &str) -> Result<String, std::io::Error> {
     let file_read_result = fs::read_to_string(path);
 
     return file_read_result;
 }
+
+/// Where files are read from.
+pub trait FileSystem {
+    fn read(&self, path: &Path) -> io::Result<String>;
+    /// Every file under `dir`, at any depth.
+    fn walk(&self, dir: &Path) -> Vec<PathBuf>;
+    /// The path a file is known by, the same however it's reached.
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        normalize(path)
+    }
+}
+
+/// The disk.
+pub struct RealFileSystem;
+
+impl FileSystem for RealFileSystem {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn walk(&self, dir: &Path) -> Vec<PathBuf> {
+        let mut out = Vec::new();
+        let dir = match dir.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => dir,
+        };
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                match path.is_dir() {
+                    true => pending.push(path),
+                    false => out.push(path),
+                }
+            }
+        }
+        out.sort();
+        return out;
+    }
+
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        fs::canonicalize(path).unwrap_or_else(|_| normalize(path))
+    }
+}
+
+/// Files kept in memory, in front of another file system, if any.
+#[derive(Default)]
+pub struct MemoryFileSystem {
+    files: BTreeMap<PathBuf, String>,
+    below: Option<Box<dyn FileSystem>>,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> MemoryFileSystem {
+        MemoryFileSystem::default()
+    }
+
+    /// Files not in memory are read from `below`, like unsaved buffers over the disk.
+    pub fn over(below: Box<dyn FileSystem>) -> MemoryFileSystem {
+        MemoryFileSystem {
+            files: BTreeMap::new(),
+            below: Some(below),
+        }
+    }
+
+    /// Adds or replaces a file.
+    pub fn insert(&mut self, path: impl AsRef<Path>, text: impl Into<String>) {
+        self.files.insert(normalize(path.as_ref()), text.into());
+    }
+
+    pub fn remove(&mut self, path: impl AsRef<Path>) {
+        self.files.remove(&normalize(path.as_ref()));
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        if let Some(text) = self.files.get(&normalize(path)) {
+            return Ok(text.clone());
+        }
+        match &self.below {
+            Some(below) => below.read(path),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No such file in memory",
+            )),
+        }
+    }
+
+    fn walk(&self, dir: &Path) -> Vec<PathBuf> {
+        let dir = normalize(dir);
+        let mut out: Vec<PathBuf> = self
+            .files
+            .keys()
+            .filter(|p| p.starts_with(&dir))
+            .cloned()
+            .collect();
+        if let Some(below) = &self.below {
+            for path in below.walk(&dir) {
+                if !out.contains(&path) {
+                    out.push(path);
+                }
+            }
+        }
+        out.sort();
+        return out;
+    }
+
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        let normalized = normalize(path);
+        match (&self.below, self.files.contains_key(&normalized)) {
+            (Some(below), false) => below.canonicalize(path),
+            _ => normalized,
+        }
+    }
+}
+
+/// Drops `.` and folds `..` away, without touching the file system.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if out.file_name().is_some() => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    return out;
+}
+
+pub type FileId = usize;
+
+pub struct SourceFile {
+    pub id: FileId,
+    // As it was asked for, for messages.
+    pub path: PathBuf,
+    pub text: String,
+    // Where the file starts among all the files.
+    pub base: usize,
+    // The offset each line starts at, within the file.
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    fn new(id: FileId, path: PathBuf, text: String, base: usize) -> SourceFile {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        SourceFile {
+            id,
+            path,
+            text,
+            base,
+            line_starts,
+        }
+    }
+
+    /// The 1-based line and column of an offset within the file.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.text.len());
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        let column = self.text[self.line_starts[line]..offset].chars().count() + 1;
+        return (line + 1, column);
+    }
+
+    /// `span`, which is within the file, as a span among all the files.
+    pub fn global(&self, span: Span) -> Span {
+        Span::new(span.start + self.base, span.end + self.base)
+    }
+}
+
+pub struct SourceMap {
+    fs: Box<dyn FileSystem>,
+    files: Vec<SourceFile>,
+    by_path: HashMap<PathBuf, FileId>,
+}
+
+impl Default for SourceMap {
+    fn default() -> Self {
+        SourceMap::new()
+    }
+}
+
+impl SourceMap {
+    /// A source map reading from the disk.
+    pub fn new() -> SourceMap {
+        SourceMap::with_fs(Box::new(RealFileSystem))
+    }
+
+    pub fn with_fs(fs: Box<dyn FileSystem>) -> SourceMap {
+        SourceMap {
+            fs,
+            files: Vec::new(),
+            by_path: HashMap::new(),
+        }
+    }
+
+    pub fn fs(&self) -> &dyn FileSystem {
+        self.fs.as_ref()
+    }
+
+    /// Adds a file whose text was already read. Files are only added once, the
+    /// ID of the earlier one is returned if `path` is the same file.
+    pub fn add(&mut self, path: impl AsRef<Path>, text: String) -> FileId {
+        let path = path.as_ref();
+        let real = self.fs.canonicalize(path);
+        if let Some(id) = self.by_path.get(&real) {
+            return *id;
+        }
+
+        let id = self.push(path, text);
+        self.by_path.insert(real, id);
+        return id;
+    }
+
+    /// Adds a file even if one with the same path is there, like the inputs of
+    /// a REPL session, each of them a file. `find` and `load` don't know of it.
+    pub fn push(&mut self, path: impl AsRef<Path>, text: String) -> FileId {
+        let id = self.files.len();
+        let base = self
+            .files
+            .last()
+            .map(|f| f.base + f.text.len() + 1)
+            .unwrap_or(0);
+        self.files
+            .push(SourceFile::new(id, path.as_ref().to_path_buf(), text, base));
+        return id;
+    }
+
+    /// Reads a file, unless it was read before.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<FileId> {
+        let path = path.as_ref();
+        if let Some(id) = self.find(path) {
+            return Ok(id);
+        }
+        let text = self.fs.read(path)?;
+        return Ok(self.add(path, text));
+    }
+
+    /// The ID of a file already in the map.
+    pub fn find(&self, path: impl AsRef<Path>) -> Option<FileId> {
+        self.by_path
+            .get(&self.fs.canonicalize(path.as_ref()))
+            .copied()
+    }
+
+    pub fn file(&self, id: FileId) -> &SourceFile {
+        &self.files[id]
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    /// The file an offset among all the files is in.
+    pub fn file_at(&self, offset: usize) -> Option<&SourceFile> {
+        let i = self.files.partition_point(|f| f.base <= offset);
+        return self.files.get(i.checked_sub(1)?);
+    }
+
+    /// Every file under `root` matching `pattern`, a path relative to `root`
+    /// where `*` is any part of a name, `?` any one character and `**` any
+    /// number of folders. A leading `/` is the root itself.
+    pub fn expand_glob(&self, root: &Path, pattern: &str) -> Vec<PathBuf> {
+        let pattern = pattern.trim_start_matches('/');
+        let pattern: Vec<&str> = pattern.split('/').filter(|p| !p.is_empty()).collect();
+
+        let root = normalize(root);
+        let mut out = Vec::new();
+        for path in self.fs.walk(&root) {
+            let path = normalize(&path);
+            let relative = match path.strip_prefix(&root) {
+                Ok(relative) => relative,
+                Err(_) => continue,
+            };
+            let parts: Vec<String> = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .collect();
+            let parts: Vec<&str> = parts.iter().map(String::as_str).collect();
+            if glob_matches(&pattern, &parts) {
+                out.push(path);
+            }
+        }
+        return out;
+    }
+}
+
+impl Sources for SourceMap {
+    fn locate(&self, span: Span) -> Option<(&str, &str, Span)> {
+        let file = self.file_at(span.start)?;
+        let path = file.path.to_str().unwrap_or("?");
+        let local = Span::new(span.start - file.base, span.end - file.base);
+        return Some((path, &file.text, local));
+    }
+
+    fn position(&self, offset: usize) -> Option<(&str, usize, usize)> {
+        let file = self.file_at(offset)?;
+        let (line, column) = file.line_col(offset - file.base);
+        return Some((file.path.to_str().unwrap_or("?"), line, column));
+    }
+}
+
+/// Whether the path `parts` match the `pattern` parts, see `SourceMap::expand_glob`.
+fn glob_matches(pattern: &[&str], parts: &[&str]) -> bool {
+    match (pattern.first(), parts.first()) {
+        (None, None) => true,
+        (Some(&"**"), _) => {
+            glob_matches(&pattern[1..], parts)
+                || (!parts.is_empty() && glob_matches(pattern, &parts[1..]))
+        }
+        (Some(p), Some(name)) => name_matches(p, name) && glob_matches(&pattern[1..], &parts[1..]),
+        _ => false,
+    }
+}
+
+/// Whether a single file or folder name matches a pattern with `*` and `?`.
+fn name_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    fn go(p: &[char], n: &[char]) -> bool {
+        match (p.first(), n.first()) {
+            (None, None) => true,
+            (Some('*'), _) => go(&p[1..], n) || (!n.is_empty() && go(p, &n[1..])),
+            (Some('?'), Some(_)) => go(&p[1..], &n[1..]),
+            (Some(a), Some(b)) => a == b && go(&p[1..], &n[1..]),
+            _ => false,
+        }
+    }
+
+    return go(&pattern, &name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A source map over files kept in memory.
+    fn in_memory(files: &[(&str, &str)]) -> SourceMap {
+        let mut fs = MemoryFileSystem::new();
+        for (path, text) in files {
+            fs.insert(path, *text);
+        }
+        return SourceMap::with_fs(Box::new(fs));
+    }
+
+    #[test]
+    fn globs_match_names_and_folders() {
+        let sources = in_memory(&[
+            ("project/main.nano", ""),
+            ("project/lib/list.nano", ""),
+            ("project/lib/deep/tree.nano", ""),
+            ("project/lib/notes.txt", ""),
+            ("project/lib2.nano", ""),
+            ("other/main.nano", ""),
+        ]);
+        let root = Path::new("project");
+        let glob = |pattern| {
+            let paths = sources.expand_glob(root, pattern);
+            return paths
+                .iter()
+                .map(|p| p.to_string_lossy().to_string())
+                .collect::<Vec<_>>();
+        };
+
+        assert_eq!(glob("*.nano"), ["project/lib2.nano", "project/main.nano"]);
+        assert_eq!(glob("/lib/*.nano"), ["project/lib/list.nano"]);
+        assert_eq!(
+            glob("lib/**/*.nano"),
+            ["project/lib/deep/tree.nano", "project/lib/list.nano"]
+        );
+        assert_eq!(glob("lib?.nano"), ["project/lib2.nano"]);
+        assert_eq!(glob("**/*.txt"), ["project/lib/notes.txt"]);
+        assert!(glob("*.rs").is_empty());
+    }
+
+    #[test]
+    fn memory_files_are_read_before_the_ones_below() {
+        let mut below = MemoryFileSystem::new();
+        below.insert("a.nano", "on disk");
+        below.insert("b.nano", "on disk too");
+        let mut fs = MemoryFileSystem::over(Box::new(below));
+        fs.insert("./a.nano", "unsaved");
+
+        assert_eq!(fs.read(Path::new("a.nano")).unwrap(), "unsaved");
+        assert_eq!(fs.read(Path::new("b.nano")).unwrap(), "on disk too");
+        assert!(fs.read(Path::new("c.nano")).is_err());
+        fs.remove("a.nano");
+        assert_eq!(fs.read(Path::new("a.nano")).unwrap(), "on disk");
+    }
+
+    #[test]
+    fn files_are_loaded_once_however_they_are_reached() {
+        let mut sources = in_memory(&[("lib/a.nano", "let a = 1")]);
+        let id = sources.load("lib/a.nano").unwrap();
+        assert_eq!(sources.load("lib/../lib/./a.nano").unwrap(), id);
+        assert_eq!(sources.files().len(), 1);
+        assert!(sources.load("lib/missing.nano").is_err());
+    }
+
+    #[test]
+    fn lines_and_columns_count_from_one() {
+        let mut sources = in_memory(&[("a.nano", "let a = 1\n\nprint('é', a)\n")]);
+        let id = sources.load("a.nano").unwrap();
+        let file = sources.file(id);
+        assert_eq!(file.line_col(0), (1, 1));
+        assert_eq!(file.line_col(4), (1, 5));
+        assert_eq!(file.line_col(10), (2, 1));
+        assert_eq!(file.line_col(11), (3, 1));
+        // Columns count characters, not bytes: the comma is the 11th byte of its line.
+        assert_eq!(file.line_col(21), (3, 10));
+        // Offsets past the end are at the end.
+        assert_eq!(file.line_col(100), (4, 1));
+    }
+
+    #[test]
+    fn offsets_tell_the_file_they_are_in() {
+        let mut sources = in_memory(&[
+            ("a.nano", "let a = 1\n"),
+            ("b.nano", "let b = 2"),
+            ("c.nano", "print(b)\nprint(b)"),
+        ]);
+        let ids: Vec<FileId> = ["a.nano", "b.nano", "c.nano"]
+            .iter()
+            .map(|p| sources.load(p).unwrap())
+            .collect();
+        let (a, b, c) = (
+            sources.file(ids[0]),
+            sources.file(ids[1]),
+            sources.file(ids[2]),
+        );
+        // Files are laid end to end, a byte apart.
+        assert_eq!((a.base, b.base, c.base), (0, 11, 21));
+
+        assert_eq!(sources.file_at(0).unwrap().id, ids[0]);
+        assert_eq!(sources.file_at(10).unwrap().id, ids[0]);
+        assert_eq!(sources.file_at(11).unwrap().id, ids[1]);
+        assert_eq!(sources.file_at(25).unwrap().id, ids[2]);
+        assert_eq!(sources.position(31), Some(("c.nano", 2, 2)));
+        let b_span = sources.file(ids[1]).global(Span::new(4, 5));
+        assert_eq!(
+            sources.locate(b_span),
+            Some(("b.nano", "let b = 2", Span::new(4, 5)))
+        );
+    }
+}
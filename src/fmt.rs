@@ -0,0 +1,813 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{
+    grammar::TokenName,
+    nano_grammar::is_ghost_token,
+    parser::{build_tree, tokenize},
+    sexpr::{print_ast, SExprOptions},
+    tree::{NodeId, NodeKind, Tree},
+};
+
+// The formatter behind `nnc fmt`, a Wadler/Oppen-style pretty printer.
+//
+// The source is parsed into a concrete tree, which keeps every token, and
+// the tree is turned into a document: text, places where a line may break,
+// and groups whose breaks are all taken or all left out. The renderer then
+// keeps a group on one line when the rest of the line fits in `max_col`,
+// and breaks it otherwise, outermost groups first. A bracketed list has two
+// nested groups, so it's laid out in one of three ways:
+//
+//   [0, 1, 2]      [                [
+//                      0, 1, 2          0,
+//                  ]                    1,
+//                                       2,
+//                                   ]
+//
+// Comments aren't part of the grammar, so they're attached to the tokens
+// around them first: a comment after code on the same line stays at the end
+// of that line, and the others go on their own line before the next token.
+// Separators, `,` and `;`, are dropped and written again where needed.
+// Blank lines between things are kept, one at most.
+//
+// As a last check, the output must parse to the same tree, with the same
+// comments, or it's not used.
+
+/// How `nnc fmt` lays code out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Style {
+    // Lines are kept within this many columns, when they can be broken.
+    pub max_col: usize,
+    // One level of indentation, spaces or a tab.
+    pub indent: String,
+    // Whether lists with an item per line end with a comma.
+    pub trailing_commas: bool,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            max_col: 80,
+            indent: "    ".to_string(),
+            trailing_commas: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatError {
+    // The source doesn't tokenize or parse.
+    Parse,
+    // The output would be a different program, which is a bug of the formatter.
+    Changed,
+    // Formatting the output again would change it, also a bug of the formatter.
+    Unstable,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormatError::Parse => write!(f, "The file doesn't parse."),
+            FormatError::Changed => write!(
+                f,
+                "Formatting would change what the file means, so it was left as it is."
+            ),
+            FormatError::Unstable => write!(
+                f,
+                "The formatted file would format differently again, so it was left as it is."
+            ),
+        }
+    }
+}
+
+/// Formats a whole nano file.
+pub fn format(source: &str, style: &Style) -> Result<String, FormatError> {
+    let formatted = layout(source, style)?;
+    if meaning(source) != meaning(&formatted) {
+        return Err(FormatError::Changed);
+    }
+    // Formatting must be idempotent, or `--check` would never pass.
+    if layout(&formatted, style)? != formatted {
+        return Err(FormatError::Unstable);
+    }
+    return Ok(formatted);
+}
+
+/// Lays `source` out once, without checking the result.
+fn layout(source: &str, style: &Style) -> Result<String, FormatError> {
+    let tokens = tokenize(source);
+    let ast = build_tree(source, &tokens, "Program", true, None).map_err(|_| FormatError::Parse)?;
+    let tree = Tree::from_ast(&ast);
+
+    let printer = Printer::new(&tree, style);
+    return Ok(render(&printer.program(), style));
+}
+
+/// The abstract tree of `source` and its comments, what formatting must not change.
+fn meaning(source: &str) -> Option<(String, Vec<String>)> {
+    let tokens = tokenize(source);
+    let ast = build_tree(source, &tokens, "Program", false, None).ok()?;
+    let comments = tokens
+        .iter()
+        .filter(|t| matches!(t.name, TokenName::Comment | TokenName::BlockComment))
+        .map(|t| t.str_content.unwrap_or("").trim_end().to_string())
+        .collect();
+    return Some((print_ast(&ast, &SExprOptions::default()), comments));
+}
+
+/// A document, what the renderer lays out.
+#[derive(Debug, Clone)]
+enum Doc {
+    Text(String),
+    // A space, or a line break when its group is broken.
+    Line,
+    // Nothing, or a line break when its group is broken.
+    SoftLine,
+    // Always a line break.
+    HardLine,
+    // An empty line, when at the start of a line.
+    BlankLine,
+    // A comment on its own line.
+    Comment(String),
+    // A comment at the end of the current line, wherever it ends.
+    Suffix(String),
+    // Text only when its group is broken, like a trailing comma.
+    IfBreak(String),
+    // One more level of indentation for the lines inside.
+    Nest(Vec<Doc>),
+    // Broken or not as a whole, and broken for sure when `forced`.
+    Group(Vec<Doc>, bool),
+    Concat(Vec<Doc>),
+}
+
+fn group(docs: Vec<Doc>) -> Doc {
+    let mut suffixed = false;
+    let forced = docs.iter().any(|d| forces_break(d, &mut suffixed));
+    return Doc::Group(docs, forced);
+}
+
+/// Whether a group with `doc` in it can't be on a single line: it has line
+/// breaks of its own, or code after an end-of-line comment.
+fn forces_break(doc: &Doc, suffixed: &mut bool) -> bool {
+    match doc {
+        Doc::HardLine | Doc::Comment(_) => true,
+        Doc::Text(text) => text.contains('\n') || (*suffixed && !text.is_empty()),
+        Doc::Suffix(_) => {
+            *suffixed = true;
+            false
+        }
+        Doc::Group(_, true) => true,
+        Doc::Nest(docs) | Doc::Group(docs, false) | Doc::Concat(docs) => {
+            docs.iter().any(|d| forces_break(d, suffixed))
+        }
+        Doc::Line | Doc::SoftLine | Doc::BlankLine | Doc::IfBreak(_) => false,
+    }
+}
+
+/// `docs` with `separator` between each two.
+fn join(docs: Vec<Doc>, separator: &[Doc]) -> Vec<Doc> {
+    let mut out = Vec::new();
+    for (i, doc) in docs.into_iter().enumerate() {
+        if i > 0 {
+            out.extend_from_slice(separator);
+        }
+        out.push(doc);
+    }
+    return out;
+}
+
+fn is_opening(name: TokenName) -> bool {
+    matches!(
+        name,
+        TokenName::ParenthesisOpen | TokenName::SqBracketsOpen | TokenName::CrBracketsOpen
+    )
+}
+
+fn is_closing(name: TokenName) -> bool {
+    matches!(
+        name,
+        TokenName::ParenthesisClose | TokenName::SqBracketsClose | TokenName::CrBracketsClose
+    )
+}
+
+/// What's around a token besides code.
+#[derive(Debug, Default)]
+struct Trivia {
+    // Comments on their own lines before the token, and blank lines between them.
+    leading: Vec<Doc>,
+    // Whether there's a blank line right before the token.
+    blank_before: bool,
+    // Comments after the token on its line.
+    trailing: Vec<String>,
+}
+
+struct Printer<'t> {
+    tree: &'t Tree,
+    style: &'t Style,
+    trivia: HashMap<NodeId, Trivia>,
+    // Comments after the last token, on their own lines.
+    end: Vec<Doc>,
+}
+
+impl<'t> Printer<'t> {
+    fn new(tree: &'t Tree, style: &'t Style) -> Printer<'t> {
+        let mut printer = Printer {
+            tree,
+            style,
+            trivia: HashMap::new(),
+            end: Vec::new(),
+        };
+        printer.attach_comments();
+        return printer;
+    }
+
+    /// Gives each comment and blank line to a token, see `Trivia`.
+    fn attach_comments(&mut self) {
+        let tree = self.tree;
+        let mut previous: Option<NodeId> = None;
+        let mut newlines = 0;
+        let mut leading = Vec::new();
+
+        for id in tree.descendants(tree.root()) {
+            let (name, text) = match tree.token(id) {
+                Some(token) => token,
+                None => continue,
+            };
+            // Blank lines are kept between things, not at the start of a list or block.
+            let after_code =
+                previous.is_some_and(|p| !matches!(tree.token(p), Some((n, _)) if is_opening(n)));
+            match name {
+                TokenName::Newline => newlines += 1,
+                TokenName::Whitespace | TokenName::Comma | TokenName::Semicolon => {}
+                TokenName::Comment | TokenName::BlockComment => {
+                    let text = text.trim_end().to_string();
+                    match previous {
+                        Some(p) if newlines == 0 => {
+                            self.trivia.entry(p).or_default().trailing.push(text);
+                        }
+                        _ => {
+                            if newlines >= 2 && (after_code || !leading.is_empty()) {
+                                leading.push(Doc::BlankLine);
+                            }
+                            leading.push(Doc::Comment(text));
+                        }
+                    }
+                    newlines = 0;
+                }
+                _ => {
+                    // Nor at the end of one.
+                    let trivia = self.trivia.entry(id).or_default();
+                    trivia.blank_before =
+                        newlines >= 2 && !is_closing(name) && (after_code || !leading.is_empty());
+                    trivia.leading = std::mem::take(&mut leading);
+                    previous = Some(id);
+                    newlines = 0;
+                }
+            }
+        }
+
+        if newlines >= 2 && !leading.is_empty() {
+            leading.insert(0, Doc::BlankLine);
+        }
+        self.end = leading;
+    }
+
+    /// The code children of a node, in order, without ghost tokens.
+    fn items(&self, id: NodeId) -> Vec<NodeId> {
+        let mut out = Vec::new();
+        for child in self.tree.children(id) {
+            match self.tree.kind(*child) {
+                NodeKind::Token { name, .. } if is_ghost_token(&name) => {}
+                NodeKind::Token { .. } | NodeKind::Node { .. } => out.push(*child),
+                NodeKind::Grouping => out.extend(self.items(*child)),
+                NodeKind::None => {}
+            }
+        }
+        return out;
+    }
+
+    fn is_token(&self, id: NodeId, name: TokenName) -> bool {
+        matches!(self.tree.token(id), Some((n, _)) if n == name)
+    }
+
+    fn is_separator(&self, id: NodeId) -> bool {
+        self.is_token(id, TokenName::Comma) || self.is_token(id, TokenName::Semicolon)
+    }
+
+    fn program(&self) -> Doc {
+        let tree = self.tree;
+        let exprs = tree
+            .children(tree.root())
+            .iter()
+            .find(|c| tree.is(**c, "Exprs"))
+            .copied();
+        let mut docs = match exprs {
+            Some(exprs) => vec![self.statements(&self.items(exprs))],
+            None => Vec::new(),
+        };
+        docs.extend(self.end.iter().cloned());
+        return Doc::Concat(docs);
+    }
+
+    /// A token, with its comments.
+    fn token(&self, id: NodeId) -> Doc {
+        let text = self.tree.text(id).unwrap_or("").to_string();
+        let trivia = match self.trivia.get(&id) {
+            None => return Doc::Text(text),
+            Some(trivia) => trivia,
+        };
+
+        let mut docs = trivia.leading.clone();
+        if trivia.blank_before {
+            docs.push(Doc::BlankLine);
+        }
+        docs.push(Doc::Text(text));
+        docs.extend(trivia.trailing.iter().map(|c| Doc::Suffix(c.clone())));
+        return Doc::Concat(docs);
+    }
+
+    fn item(&self, id: NodeId) -> Doc {
+        match self.tree.kind(id) {
+            NodeKind::Token { .. } => self.token(id),
+            _ => self.node(id),
+        }
+    }
+
+    /// Expressions one per line, like the program or a block's.
+    fn statements(&self, items: &[NodeId]) -> Doc {
+        let docs = items
+            .iter()
+            .filter(|i| !self.is_separator(**i))
+            .map(|i| self.item(*i))
+            .collect();
+        return Doc::Concat(join(docs, &[Doc::HardLine]));
+    }
+
+    /// `items` with `separator` between each two.
+    fn spaced(&self, items: &[NodeId], separator: &str) -> Doc {
+        let docs = items.iter().map(|i| self.item(*i)).collect();
+        return Doc::Concat(join(docs, &[Doc::Text(separator.to_string())]));
+    }
+
+    fn node(&self, id: NodeId) -> Doc {
+        let items = self.items(id);
+        match self.tree.rule(id).unwrap_or("") {
+            "Exprs" => self.statements(&items),
+            "Parens" => self.block(&items),
+            "List" | "Call" | "Assertion" => self.with_brackets(&items, false),
+            "Record" => self.with_brackets(&items, true),
+            "Literal" | "Member" | "Range" | "Name" => self.spaced(&items, ""),
+            "FnDecl" => self.with_brackets(&items, false),
+            "Field" => match items.as_slice() {
+                [key, colon, value] => Doc::Concat(vec![
+                    self.item(*key),
+                    self.item(*colon),
+                    Doc::Text(" ".to_string()),
+                    self.item(*value),
+                ]),
+                _ => self.spaced(&items, " "),
+            },
+            "Pipeline" if items.len() > 1 => {
+                let mut rest = Vec::new();
+                for pair in items[1..].chunks(2) {
+                    rest.push(Doc::Line);
+                    rest.push(self.spaced(pair, " "));
+                }
+                group(vec![self.item(items[0]), Doc::Nest(rest)])
+            }
+            // Keywords and operators, `let x = 1`, `a + b`, `for n in 0..5 -> n`...
+            _ => self.spaced(&items, " "),
+        }
+    }
+
+    /// Code with a bracketed, comma-separated part, like `f(a, b)`, `[a, b]`
+    /// or `fn f(a, b) -> body`. Records have spaces inside their braces.
+    fn with_brackets(&self, items: &[NodeId], spaced: bool) -> Doc {
+        let open = items.iter().position(|i| {
+            self.is_token(*i, TokenName::ParenthesisOpen)
+                || self.is_token(*i, TokenName::SqBracketsOpen)
+                || self.is_token(*i, TokenName::CrBracketsOpen)
+        });
+        let close = items.iter().rposition(|i| {
+            self.is_token(*i, TokenName::ParenthesisClose)
+                || self.is_token(*i, TokenName::SqBracketsClose)
+                || self.is_token(*i, TokenName::CrBracketsClose)
+        });
+        let (open, close) = match (open, close) {
+            (Some(open), Some(close)) if open < close => (open, close),
+            _ => return self.spaced(items, " "),
+        };
+
+        let elements: Vec<Doc> = items[open + 1..close]
+            .iter()
+            .filter(|i| !self.is_separator(**i))
+            .map(|i| self.item(*i))
+            .collect();
+        let bracketed = match elements.is_empty() {
+            true => Doc::Concat(vec![self.item(items[open]), self.item(items[close])]),
+            false => {
+                let line = if spaced { Doc::Line } else { Doc::SoftLine };
+                let mut list = join(elements, &[Doc::Text(",".to_string()), Doc::Line]);
+                if self.style.trailing_commas {
+                    list.push(Doc::IfBreak(",".to_string()));
+                }
+                group(vec![
+                    self.item(items[open]),
+                    Doc::Nest(vec![line.clone(), group(list)]),
+                    line,
+                    self.item(items[close]),
+                ])
+            }
+        };
+
+        // What's before the brackets and after them, `fn name` and `-> body`.
+        let mut docs = Vec::new();
+        if open > 0 {
+            docs.push(self.spaced(&items[..open], " "));
+        }
+        docs.push(bracketed);
+        if close + 1 < items.len() {
+            docs.push(Doc::Text(" ".to_string()));
+            docs.push(self.spaced(&items[close + 1..], " "));
+        }
+        return Doc::Concat(docs);
+    }
+
+    /// `( ... )`: a single expression may stay on the line, more go one per line.
+    fn block(&self, items: &[NodeId]) -> Doc {
+        let (open, inner, close) = match items {
+            [open, inner @ .., close] => (*open, inner, *close),
+            _ => return self.spaced(items, " "),
+        };
+        let exprs: Vec<NodeId> = inner
+            .iter()
+            .filter(|i| !self.is_separator(**i))
+            .copied()
+            .collect();
+
+        return match exprs.as_slice() {
+            [expr] => group(vec![
+                self.item(open),
+                Doc::Nest(vec![Doc::SoftLine, self.item(*expr)]),
+                Doc::SoftLine,
+                self.item(close),
+            ]),
+            _ => Doc::Concat(vec![
+                self.item(open),
+                Doc::Nest(vec![Doc::HardLine, self.statements(&exprs)]),
+                Doc::HardLine,
+                self.item(close),
+            ]),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+/// Lays a document out, see the top of the file.
+fn render(doc: &Doc, style: &Style) -> String {
+    let mut renderer = Renderer {
+        style,
+        out: String::new(),
+        col: 0,
+        line_has_content: false,
+        pending_newline: false,
+        suffix: Vec::new(),
+    };
+    let mut stack: Vec<(usize, Mode, &Doc)> = vec![(0, Mode::Break, doc)];
+
+    while let Some((level, mode, doc)) = stack.pop() {
+        match doc {
+            Doc::Text(text) => renderer.text(text, level),
+            Doc::Line if mode == Mode::Flat && !renderer.pending_newline => {
+                renderer.text(" ", level)
+            }
+            Doc::Line | Doc::SoftLine if mode == Mode::Break => renderer.newline(),
+            Doc::Line => renderer.newline(),
+            Doc::SoftLine => {}
+            Doc::HardLine => renderer.newline(),
+            Doc::BlankLine => renderer.blank_line(),
+            Doc::Comment(text) => {
+                if renderer.line_has_content || renderer.pending_newline {
+                    renderer.newline();
+                }
+                renderer.text(text, level);
+                renderer.pending_newline = true;
+            }
+            Doc::Suffix(text) => renderer.suffix.push(text.clone()),
+            Doc::IfBreak(text) if mode == Mode::Break => renderer.text(text, level),
+            Doc::IfBreak(_) => {}
+            Doc::Nest(docs) => stack.extend(docs.iter().rev().map(|d| (level + 1, mode, d))),
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|d| (level, mode, d))),
+            Doc::Group(docs, forced) => {
+                let fits = mode == Mode::Flat
+                    || (!forced && renderer.fits(docs, level, &stack, style.max_col));
+                let mode = if fits { Mode::Flat } else { Mode::Break };
+                stack.extend(docs.iter().rev().map(|d| (level, mode, d)));
+            }
+        }
+    }
+
+    renderer.newline();
+    return renderer.out;
+}
+
+struct Renderer<'s> {
+    style: &'s Style,
+    out: String,
+    col: usize,
+    // Indentation is only written before the first text of a line.
+    line_has_content: bool,
+    // A comment ended the line, so the next text goes on a new one.
+    pending_newline: bool,
+    // End-of-line comments waiting for the line to end.
+    suffix: Vec<String>,
+}
+
+impl<'s> Renderer<'s> {
+    fn indent_width(&self, level: usize) -> usize {
+        let one: usize = self
+            .style
+            .indent
+            .chars()
+            .map(|c| if c == '\t' { 4 } else { 1 })
+            .sum();
+        return one * level;
+    }
+
+    fn text(&mut self, text: &str, level: usize) {
+        if self.pending_newline {
+            self.newline();
+        }
+        if !self.line_has_content {
+            self.out.push_str(&self.style.indent.repeat(level));
+            self.col = self.indent_width(level);
+            self.line_has_content = true;
+        }
+        self.out.push_str(text);
+        self.col = match text.rfind('\n') {
+            Some(i) => text[i + 1..].chars().count(),
+            None => self.col + text.chars().count(),
+        };
+    }
+
+    fn newline(&mut self) {
+        if !self.suffix.is_empty() {
+            self.out.push(' ');
+            self.out.push_str(&self.suffix.join(" "));
+            self.suffix.clear();
+        }
+        let trimmed = self.out.trim_end_matches([' ', '\t']).len();
+        self.out.truncate(trimmed);
+        if self.line_has_content || self.pending_newline {
+            self.out.push('\n');
+        }
+        self.col = 0;
+        self.line_has_content = false;
+        self.pending_newline = false;
+    }
+
+    fn blank_line(&mut self) {
+        // The current line ends first, whether it holds code or a comment.
+        if self.line_has_content || self.pending_newline {
+            self.newline();
+        }
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    /// Whether `docs` fit on the rest of the line, flat, along with what follows
+    /// them up to the next line break.
+    fn fits(&self, docs: &[Doc], level: usize, rest: &[(usize, Mode, &Doc)], max: usize) -> bool {
+        let col = match self.line_has_content && !self.pending_newline {
+            true => self.col,
+            false => self.indent_width(level),
+        };
+        let mut width = max as isize - col as isize;
+        let mut stack: Vec<(Mode, &Doc)> = docs.iter().rev().map(|d| (Mode::Flat, d)).collect();
+        let mut rest = rest.iter().rev();
+
+        loop {
+            let (mode, doc) = match stack.pop() {
+                Some(next) => next,
+                None => match rest.next() {
+                    Some((_, mode, doc)) => (*mode, *doc),
+                    None => return true,
+                },
+            };
+            match doc {
+                Doc::Text(text) => {
+                    let first_line = text.split('\n').next().unwrap_or("");
+                    width -= first_line.chars().count() as isize;
+                    if text.contains('\n') {
+                        return width >= 0;
+                    }
+                }
+                Doc::IfBreak(text) if mode == Mode::Break => width -= text.chars().count() as isize,
+                Doc::Line if mode == Mode::Flat => width -= 1,
+                Doc::Line | Doc::SoftLine if mode == Mode::Break => return width >= 0,
+                Doc::HardLine | Doc::Comment(_) => return width >= 0,
+                Doc::Line | Doc::SoftLine | Doc::BlankLine | Doc::Suffix(_) | Doc::IfBreak(_) => {}
+                Doc::Nest(docs) | Doc::Concat(docs) => {
+                    stack.extend(docs.iter().rev().map(|d| (mode, d)));
+                }
+                Doc::Group(docs, forced) => {
+                    let mode = if *forced { Mode::Break } else { mode };
+                    stack.extend(docs.iter().rev().map(|d| (mode, d)));
+                }
+            }
+            if width < 0 {
+                return false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each fixture is a messy file and what it formats to, with the default style.
+    const COMMENTS: (&str, &str) = (
+        r#"# A header comment.
+
+# About x.
+let x = 1    # after x
+let y = [  # the list
+    # first
+    1,
+    2, # second
+]
+fn add(a, b) -> ( # opening
+    # inside
+    a + b ;
+)
+print(add(x,2)) # done
+# The end.
+"#,
+        r#"# A header comment.
+
+# About x.
+let x = 1 # after x
+let y = [ # the list
+    # first
+    1,
+    2, # second
+]
+fn add(a, b) -> ( # opening
+    # inside
+    a + b
+)
+print(add(x, 2)) # done
+# The end.
+"#,
+    );
+
+    const BLANK_LINES: (&str, &str) = (
+        r#"
+
+let a = 1
+
+
+
+let b = 2
+fn f(x) -> (
+
+    let y = x
+
+
+    y + 1
+
+)
+
+print(f(a + b))
+
+
+"#,
+        r#"let a = 1
+
+let b = 2
+fn f(x) -> (
+    let y = x
+
+    y + 1
+)
+
+print(f(a + b))
+"#,
+    );
+
+    const NESTED_LISTS: (&str, &str) = (
+        r#"let grid = [[1,2,3],[4,5,6],[7,8,9]]
+let wide = [[1000000, 2000000, 3000000, 4000000], [5000000, 6000000, 7000000, 8000000], [9000000]]
+let deep = [ [ [1, [2, [3, [4]]]] ], [] ]
+let tall = [
+    [1, 2],
+    [3,
+     4]
+]
+print(grid)
+"#,
+        r#"let grid = [[1, 2, 3], [4, 5, 6], [7, 8, 9]]
+let wide = [
+    [1000000, 2000000, 3000000, 4000000],
+    [5000000, 6000000, 7000000, 8000000],
+    [9000000],
+]
+let deep = [[[1, [2, [3, [4]]]]], []]
+let tall = [[1, 2], [3, 4]]
+print(grid)
+"#,
+    );
+
+    const PIPELINES: (&str, &str) = (
+        r#"fn inc(x) -> x + 1
+fn double(x) -> x * 2
+let short = 1 |> inc |> double
+let long = 1 |> inc |> double |> inc |> double |> inc |> double |> inc |> double |> inc |> double
+let spread = 1
+    |> inc
+    |> double
+print(short + long + spread)
+"#,
+        r#"fn inc(x) -> x + 1
+fn double(x) -> x * 2
+let short = 1 |> inc |> double
+let long = 1
+    |> inc
+    |> double
+    |> inc
+    |> double
+    |> inc
+    |> double
+    |> inc
+    |> double
+    |> inc
+    |> double
+let spread = 1 |> inc |> double
+print(short + long + spread)
+"#,
+    );
+
+    const TRAILING_COMMENTS: (&str, &str) = (
+        r#"print(1)
+
+# trailing
+"#,
+        r#"print(1)
+
+# trailing
+"#,
+    );
+
+    const FIXTURES: [(&str, &str); 5] = [
+        COMMENTS,
+        BLANK_LINES,
+        NESTED_LISTS,
+        PIPELINES,
+        TRAILING_COMMENTS,
+    ];
+
+    #[test]
+    fn fixtures_format_as_expected() {
+        for (source, expected) in FIXTURES {
+            assert_eq!(format(source, &Style::default()).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn formatting_twice_changes_nothing() {
+        let styles = [
+            Style::default(),
+            Style {
+                max_col: 30,
+                indent: "\t".to_string(),
+                trailing_commas: false,
+            },
+        ];
+        for style in &styles {
+            for (source, expected) in FIXTURES {
+                let once = layout(source, style).unwrap();
+                assert_eq!(layout(&once, style).unwrap(), once);
+                // Formatted files are left as they are, so `--check` passes on them.
+                assert_eq!(format(&once, style).unwrap(), once);
+                if *style == Style::default() {
+                    assert_eq!(format(expected, style).unwrap(), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unparsable_files_are_refused() {
+        assert_eq!(
+            format("let x = (1", &Style::default()),
+            Err(FormatError::Parse)
+        );
+    }
+}
@@ -1,9 +1,31 @@
 use lazy_regex::*;
 
+/// A range of bytes in the source, `start..end`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// The smallest span covering both spans.
+    pub fn merge(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Token<'a> {
     pub name: TokenName,
     pub str_content: Option<&'a str>,
+    pub span: Span,
 }
 
 #[derive(Debug)]
@@ -12,7 +34,7 @@ pub struct TokenMatcher {
     pub name: TokenName,
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub enum TokenName {
     EOF,
 
@@ -36,6 +58,7 @@ pub enum TokenName {
     BranchAnnotation, // '#%define'
 
     IntLiteral,     // '42', '0xFA', '0b0110_1100'
+    FloatLiteral,   // '0.1', '1_000.5'
     StringLiteral,  // '".*?"'
     BooleanLiteral, // 'true' | 'false' | 'yes' | 'no' | '0b' | '1b'
 
@@ -49,6 +72,7 @@ pub enum TokenName {
     AgBracketsClose,     // '>'
     Reticences,          // '...'
     ExclusiveReticences, // '..'
+    Dot,                 // '.'
 
     Colon, // ':'
 
@@ -73,18 +97,179 @@ pub enum TokenName {
     OpEqSign,             // =
 }
 
-#[derive(Debug)]
+impl TokenName {
+    pub const ALL: &'static [TokenName] = &[
+        TokenName::EOF,
+        TokenName::Indent,
+        TokenName::Newline,
+        TokenName::Whitespace,
+        TokenName::BlockComment,
+        TokenName::Comment,
+        TokenName::Identifier,
+        TokenName::ThinArrow,
+        TokenName::Pipe,
+        TokenName::Semicolon,
+        TokenName::Comma,
+        TokenName::ScopeAnnotation,
+        TokenName::BranchAnnotation,
+        TokenName::IntLiteral,
+        TokenName::FloatLiteral,
+        TokenName::StringLiteral,
+        TokenName::BooleanLiteral,
+        TokenName::ParenthesisOpen,
+        TokenName::ParenthesisClose,
+        TokenName::SqBracketsOpen,
+        TokenName::SqBracketsClose,
+        TokenName::CrBracketsOpen,
+        TokenName::CrBracketsClose,
+        TokenName::AgBracketsOpen,
+        TokenName::AgBracketsClose,
+        TokenName::Reticences,
+        TokenName::ExclusiveReticences,
+        TokenName::Dot,
+        TokenName::Colon,
+        TokenName::OpAddrof,
+        TokenName::OpTypeof,
+        TokenName::OpType,
+        TokenName::OpValue,
+        TokenName::OpIs,
+        TokenName::OpXis,
+        TokenName::OpAnd,
+        TokenName::OpOr,
+        TokenName::OpNot,
+        TokenName::OpPipe,
+        TokenName::OpAmpersand,
+        TokenName::OpPlus,
+        TokenName::OpDash,
+        TokenName::OpAsterisk,
+        TokenName::OpForwardSlash,
+        TokenName::OpDoubleForwardSlash,
+        TokenName::OpPercent,
+        TokenName::OpEqSign,
+    ];
+
+    /// The text of tokens that can only ever be spelled one way.
+    pub fn fixed_text(&self) -> Option<&'static str> {
+        match self {
+            TokenName::ThinArrow => Some("->"),
+            TokenName::Pipe => Some("|>"),
+            TokenName::Semicolon => Some(";"),
+            TokenName::Comma => Some(","),
+            TokenName::ParenthesisOpen => Some("("),
+            TokenName::ParenthesisClose => Some(")"),
+            TokenName::SqBracketsOpen => Some("["),
+            TokenName::SqBracketsClose => Some("]"),
+            TokenName::CrBracketsOpen => Some("{"),
+            TokenName::CrBracketsClose => Some("}"),
+            TokenName::AgBracketsOpen => Some("<"),
+            TokenName::AgBracketsClose => Some(">"),
+            TokenName::Reticences => Some("..."),
+            TokenName::ExclusiveReticences => Some(".."),
+            TokenName::Dot => Some("."),
+            TokenName::Colon => Some(":"),
+            TokenName::OpAddrof => Some("addrof"),
+            TokenName::OpTypeof => Some("typeof"),
+            TokenName::OpType => Some("type"),
+            TokenName::OpValue => Some("value"),
+            TokenName::OpIs => Some("is"),
+            TokenName::OpXis => Some("xis"),
+            TokenName::OpAnd => Some("and"),
+            TokenName::OpOr => Some("or"),
+            TokenName::OpNot => Some("not"),
+            TokenName::OpPipe => Some("|"),
+            TokenName::OpAmpersand => Some("&"),
+            TokenName::OpPlus => Some("+"),
+            TokenName::OpDash => Some("-"),
+            TokenName::OpAsterisk => Some("*"),
+            TokenName::OpForwardSlash => Some("/"),
+            TokenName::OpDoubleForwardSlash => Some("//"),
+            TokenName::OpPercent => Some("%"),
+            TokenName::OpEqSign => Some("="),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct ASTNode<'a> {
     pub matched_with: &'static str,
     pub content: Vec<ASTNodeContent<'a>>,
+    // From the first to the last token the rule consumed, ghost tokens aside.
+    // Unlike the content, it covers punctuation dropped by `Skip`.
+    // None for nodes built after parsing, by actions or folds.
+    pub consumed: Option<Span>,
 }
 
-#[derive(Debug)]
+impl<'a> ASTNode<'a> {
+    /// Returns the first piece of content captured under `label`.
+    pub fn get(&self, label: &str) -> Option<&ASTNodeContent<'a>> {
+        self.content.iter().find_map(|c| match c {
+            ASTNodeContent::Labelled(l, inner) if *l == label => Some(inner.as_ref()),
+            _ => None,
+        })
+    }
+
+    /// Returns every piece of content captured under `label`, in order.
+    pub fn get_all(&self, label: &str) -> Vec<&ASTNodeContent<'a>> {
+        self.content
+            .iter()
+            .filter_map(|c| match c {
+                ASTNodeContent::Labelled(l, inner) if *l == label => Some(inner.as_ref()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The span covering every token the node was parsed from, and every token under it.
+    pub fn span(&self) -> Option<Span> {
+        self.content
+            .iter()
+            .filter_map(|c| c.span())
+            .chain(self.consumed)
+            .reduce(Span::merge)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum ASTNodeContent<'a> {
     None,
     Tok(&'a Token<'a>),
     Grouping(Vec<ASTNodeContent<'a>>),
     Node(ASTNode<'a>),
+    // Content captured by a `ParseRule::Label`, `lhs:Expr`.
+    Labelled(&'static str, Box<ASTNodeContent<'a>>),
+}
+
+impl<'a> ASTNodeContent<'a> {
+    /// Looks through labels and returns the node, if this is one.
+    pub fn as_node(&self) -> Option<&ASTNode<'a>> {
+        match self {
+            ASTNodeContent::Node(n) => Some(n),
+            ASTNodeContent::Labelled(_, inner) => inner.as_node(),
+            _ => None,
+        }
+    }
+
+    /// Looks through labels and returns the token, if this is one.
+    pub fn as_token(&self) -> Option<&'a Token<'a>> {
+        match self {
+            ASTNodeContent::Tok(t) => Some(t),
+            ASTNodeContent::Labelled(_, inner) => inner.as_token(),
+            _ => None,
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ASTNodeContent::None => None,
+            ASTNodeContent::Tok(t) => Some(t.span),
+            ASTNodeContent::Grouping(items) => {
+                items.iter().filter_map(|c| c.span()).reduce(Span::merge)
+            }
+            ASTNodeContent::Node(n) => n.span(),
+            ASTNodeContent::Labelled(_, inner) => inner.span(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -93,20 +278,27 @@ pub struct AST<'a> {
     pub root: ASTNode<'a>,
 }
 
-pub fn get_rule<'a>(
-    list: &'a [(&'static str, &[ParseRule])],
-    key: &str,
-) -> Option<&'a [ParseRule<'a>]> {
-    let r = list.iter().position(|(_k, _v)| *_k == key);
+pub type ParseRuleList<'a> = [(&'static str, &'a [ParseRule<'a>])];
+
+pub fn get_rule<'a>(list: &'a ParseRuleList<'a>, key: &str) -> Option<&'a [ParseRule<'a>]> {
+    let r = list.iter().position(|(_k, _v)| *_k == key)?;
 
-    let (_, v) = match r {
-        None => return None,
-        Some(i) => list[i],
-    };
+    let (_, v) = list[r];
 
     return Some(v);
 }
 
+/// A reduction action, ran on a node right after its rule matched.
+/// It may reshape the node's content or replace the node entirely,
+/// for example folding `1 + 2 - 3` into nested `BinaryExpr` nodes.
+pub type ReduceAction = for<'a> fn(ASTNode<'a>) -> ASTNodeContent<'a>;
+
+pub type ReduceActionList = [(&'static str, ReduceAction)];
+
+pub fn get_action(list: &ReduceActionList, key: &str) -> Option<ReduceAction> {
+    list.iter().find(|(k, _)| *k == key).map(|(_, a)| *a)
+}
+
 #[derive(Debug)]
 pub enum ParseRule<'a> {
     SingleToken(TokenName, Option<&'a str>),
@@ -116,4 +308,32 @@ pub enum ParseRule<'a> {
     Optional(&'a [ParseRule<'a>]),
     Many(&'a [ParseRule<'a>]),
     OptionalMany(&'a [ParseRule<'a>]),
+
+    // Any single token that is neither a ghost token nor EOF.
+    AnyToken,
+    // Any single token whose name is in the set, `'+' | '-'`.
+    TokenSet(&'a [TokenName]),
+    // Succeeds if the fragments would match here, without consuming them.
+    Lookahead(&'a [ParseRule<'a>]),
+    // Succeeds if the fragments would NOT match here, without consuming anything.
+    NotLookahead(&'a [ParseRule<'a>]),
+
+    // A list of `item`s separated by `sep`, `Expr (',' Expr)* ','?`.
+    // Produces one flat Grouping with an entry per item.
+    SeparatedBy {
+        item: &'a [ParseRule<'a>],
+        sep: &'a [ParseRule<'a>],
+        allow_trailing: bool,
+        min: usize,
+    },
+
+    // Captures the content matched by the fragments under a name,
+    // so consumers can say `node.get("lhs")` instead of `content[0]`.
+    Label(&'static str, &'a [ParseRule<'a>]),
+    // Matches the fragments but throws away what they produced,
+    // useful for punctuation such as ';' or ','.
+    Skip(&'a [ParseRule<'a>]),
+    // Matches the fragments and splices their content into the parent,
+    // removing every `Grouping` level in between.
+    Flatten(&'a [ParseRule<'a>]),
 }
@@ -0,0 +1,720 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::rc::Rc;
+
+use colored::Colorize;
+
+use crate::{
+    diagnostic::{Diagnostic, SingleFile, Sources},
+    grammar::Span,
+    ir::{
+        intrinsic as intrinsic_symbol, Function, IRKind, IRNode, Literal, Module, INTRINSIC_PREFIX,
+    },
+};
+
+// The reference interpreter: evaluates the IR directly, no backend needed.
+//
+// Locals live in a flat stack per call, searched from the top, and scopes
+// just truncate it back when they end. Functions are called by symbol;
+// closures are a symbol plus the values of its captures, which are passed
+// before the regular arguments, exactly like `SYMBOL_CALL`s do.
+
+/// Calls nested deeper than this are reported as a stack overflow.
+pub const MAX_CALL_DEPTH: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Unit,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(Rc<str>),
+    List(Rc<Vec<Value>>),
+    Record(Rc<BTreeMap<String, Value>>),
+    Closure {
+        symbol: Rc<str>,
+        captures: Rc<Vec<Value>>,
+    },
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Unit => "unit",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Record(_) => "record",
+            Value::Closure { .. } => "function",
+        }
+    }
+
+    /// How `print` shows a value: like `Display`, but strings without quotes.
+    pub fn to_print_string(&self) -> String {
+        match self {
+            Value::String(s) => s.to_string(),
+            other => other.to_string(),
+        }
+    }
+}
+
+/// Shows values as nano literals, `[1, 2]`, `{a: 'text'}`, `yes`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Unit => write!(f, "()"),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Bool(b) => write!(f, "{}", if *b { "yes" } else { "no" }),
+            Value::String(s) => write!(f, "'{}'", s),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Record(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Closure { symbol, .. } => write!(f, "<fn {}>", symbol),
+        }
+    }
+}
+
+/// A call in progress, for stack traces.
+#[derive(Debug, Clone)]
+pub struct TraceFrame {
+    pub symbol: String,
+    // Where the call was made.
+    pub call_site: Option<Span>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub message: String,
+    pub span: Option<Span>,
+    // Innermost call first.
+    pub trace: Vec<TraceFrame>,
+}
+
+impl RuntimeError {
+    pub fn render(&self, path: &str, source: &str) -> String {
+        return self.render_with(&SingleFile { path, source });
+    }
+
+    /// Renders the error followed by its stack trace, with spans in any of `sources`.
+    pub fn render_with(&self, sources: &dyn Sources) -> String {
+        let mut out = Diagnostic::error(self.message.clone(), self.span).render_with(sources);
+        if !self.trace.is_empty() {
+            out.push_str(&format!("{}\n", "stack trace:".bold()));
+        }
+        for (frame, repeats) in fold_frames(&self.trace) {
+            let mut location = match frame.call_site.and_then(|s| sources.position(s.start)) {
+                None => String::new(),
+                Some((path, line, column)) => format!(", called at {}:{}:{}", path, line, column),
+            };
+            if repeats > 1 {
+                location.push_str(&format!(" ({} times)", repeats));
+            }
+            out.push_str(&format!(
+                "  in {}{}\n",
+                frame.symbol.cyan(),
+                location.dimmed()
+            ));
+        }
+        return out;
+    }
+}
+
+/// Each run of identical frames once, with how often it repeats.
+/// Recursion repeats the same frame over and over, so traces are shown folded.
+pub fn fold_frames<'a, I>(frames: I) -> Vec<(&'a TraceFrame, usize)>
+where
+    I: IntoIterator<Item = &'a TraceFrame>,
+{
+    let mut folded: Vec<(&TraceFrame, usize)> = Vec::new();
+    for frame in frames {
+        match folded.last_mut() {
+            Some((last, repeats))
+                if last.symbol == frame.symbol && last.call_site == frame.call_site =>
+            {
+                *repeats += 1
+            }
+            _ => folded.push((frame, 1)),
+        }
+    }
+    return folded;
+}
+
+struct CallFrame {
+    symbol: String,
+    call_site: Option<Span>,
+    locals: Vec<(String, Value)>,
+}
+
+/// What the interpreter may do, the compile-time evaluator runs it sandboxed.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    // How many nodes may be evaluated in total, no limit when `None`.
+    pub fuel: Option<u64>,
+    // Whether intrinsics doing I/O, like `print`, may run.
+    pub allow_io: bool,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            fuel: None,
+            allow_io: true,
+        }
+    }
+}
+
+pub struct Interpreter {
+    functions: HashMap<String, Rc<Function>>,
+    globals: HashMap<String, Value>,
+    frames: Vec<CallFrame>,
+    limits: Limits,
+    // Nodes evaluated so far, counted against `limits.fuel`.
+    steps: u64,
+    // The `%%t_eq`s evaluated since the last `take_assertions`.
+    assertions: Vec<Assertion>,
+    // What `print` wrote since the last `take_output`, when it's captured.
+    output: Option<String>,
+}
+
+/// An evaluated `%%t_eq(actual, expected)`.
+#[derive(Debug, Clone)]
+pub struct Assertion {
+    pub actual: Value,
+    pub expected: Value,
+    // Where the whole `%%t_eq(...)` is written.
+    pub span: Option<Span>,
+    // The variables `actual` reads, where they're read and what they were.
+    pub locals: Vec<(Option<Span>, Value)>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+/// Runs a module's top-level code, returning its last value.
+pub fn run(module: &Module) -> Result<Value, RuntimeError> {
+    Interpreter::new().run(module)
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter::with_limits(Limits::default())
+    }
+
+    pub fn with_limits(limits: Limits) -> Interpreter {
+        Interpreter {
+            functions: HashMap::new(),
+            globals: HashMap::new(),
+            frames: Vec::new(),
+            limits,
+            steps: 0,
+            assertions: Vec::new(),
+            output: None,
+        }
+    }
+
+    /// How many nodes were evaluated so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Makes a module's functions callable, replacing older ones with the same symbol.
+    pub fn load(&mut self, module: &Module) {
+        for f in &module.functions {
+            self.functions.insert(f.symbol.clone(), Rc::new(f.clone()));
+        }
+    }
+
+    /// Loads a module and runs its top-level code. Globals are kept between runs.
+    pub fn run(&mut self, module: &Module) -> Result<Value, RuntimeError> {
+        self.load(module);
+        self.enter("<main>");
+
+        let mut value = Value::Unit;
+        for node in &module.body {
+            value = self.eval(node)?;
+        }
+        return Ok(value);
+    }
+
+    /// Starts over from an empty stack, with `symbol` as the outermost frame.
+    pub fn enter(&mut self, symbol: &str) {
+        self.frames = vec![CallFrame {
+            symbol: symbol.to_string(),
+            call_site: None,
+            locals: Vec::new(),
+        }];
+    }
+
+    pub fn global(&self, symbol: &str) -> Option<&Value> {
+        self.globals.get(symbol)
+    }
+
+    pub fn set_global(&mut self, symbol: &str, value: Value) {
+        self.globals.insert(symbol.to_string(), value);
+    }
+
+    /// The `%%t_eq`s evaluated so far, oldest first, forgetting them.
+    pub fn take_assertions(&mut self) -> Vec<Assertion> {
+        std::mem::take(&mut self.assertions)
+    }
+
+    /// Makes `print` write to a buffer, read with `take_output`, instead of stdout.
+    pub fn capture_output(&mut self) {
+        self.output.get_or_insert_with(String::new);
+    }
+
+    /// What `print` wrote since the last call, when output is captured.
+    pub fn take_output(&mut self) -> String {
+        self.output.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Calls a function value, like `f(args)` would.
+    pub fn call_value(&mut self, callee: &Value, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        self.call_closure(callee, args, None)
+    }
+
+    fn error(&self, message: impl Into<String>, span: Option<Span>) -> RuntimeError {
+        RuntimeError {
+            message: message.into(),
+            span,
+            trace: self
+                .frames
+                .iter()
+                .rev()
+                .map(|f| TraceFrame {
+                    symbol: f.symbol.clone(),
+                    call_site: f.call_site,
+                })
+                .collect(),
+        }
+    }
+
+    fn locals(&mut self) -> &mut Vec<(String, Value)> {
+        &mut self
+            .frames
+            .last_mut()
+            .expect("there's always a frame")
+            .locals
+    }
+
+    fn eval_all(&mut self, nodes: &[IRNode]) -> Result<Vec<Value>, RuntimeError> {
+        nodes.iter().map(|n| self.eval(n)).collect()
+    }
+
+    /// Counts `steps` more against the fuel, failing at `span` when it runs out.
+    fn spend(&mut self, steps: u64, span: Option<Span>) -> Result<(), RuntimeError> {
+        self.steps = self.steps.saturating_add(steps);
+        if let Some(fuel) = self.limits.fuel {
+            if self.steps > fuel {
+                let message = format!("Ran out of fuel after {} steps.", fuel);
+                return Err(self.error(message, span));
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn eval(&mut self, node: &IRNode) -> Result<Value, RuntimeError> {
+        self.spend(1, node.span)?;
+
+        let value = match &node.kind {
+            IRKind::Literal(literal) => match literal {
+                Literal::Unit => Value::Unit,
+                Literal::Int(i) => Value::Int(*i),
+                Literal::Float(x) => Value::Float(*x),
+                Literal::Bool(b) => Value::Bool(*b),
+                Literal::String(s) => Value::String(s.as_str().into()),
+            },
+            IRKind::Local(name) => {
+                let found = self.locals().iter().rev().find(|(n, _)| n == name);
+                match found {
+                    Some((_, value)) => value.clone(),
+                    None => {
+                        let message = format!("Local '{}' read before being set.", name);
+                        return Err(self.error(message, node.span));
+                    }
+                }
+            }
+            IRKind::Global(symbol) => match self.globals.get(symbol) {
+                Some(value) => value.clone(),
+                None => {
+                    let message = format!("'{}' is used before its `let` ran.", symbol);
+                    return Err(self.error(message, node.span));
+                }
+            },
+            IRKind::FnRef { symbol, captures } => Value::Closure {
+                symbol: symbol.as_str().into(),
+                captures: Rc::new(self.eval_all(captures)?),
+            },
+            IRKind::SymbolCall { symbol, args } if symbol == &intrinsic_symbol("t_eq") => {
+                self.assertion(args, node.span)?
+            }
+            IRKind::SymbolCall { symbol, args } => {
+                let args = self.eval_all(args)?;
+                self.call_symbol(symbol, args, node.span)?
+            }
+            IRKind::Call { callee, args } => {
+                let callee = self.eval(callee)?;
+                let args = self.eval_all(args)?;
+                self.call_closure(&callee, args, node.span)?
+            }
+            IRKind::Let { name, value } => {
+                let value = self.eval(value)?;
+                self.locals().push((name.clone(), value.clone()));
+                value
+            }
+            IRKind::SetGlobal { symbol, value } => {
+                let value = self.eval(value)?;
+                self.globals.insert(symbol.clone(), value.clone());
+                value
+            }
+            IRKind::Block(items) => {
+                let depth = self.locals().len();
+                let mut value = Ok(Value::Unit);
+                for item in items {
+                    value = self.eval(item);
+                    if value.is_err() {
+                        break;
+                    }
+                }
+                self.locals().truncate(depth);
+                value?
+            }
+            IRKind::List(items) => Value::List(Rc::new(self.eval_all(items)?)),
+            IRKind::Record(fields) => {
+                let mut out = BTreeMap::new();
+                for (key, value) in fields {
+                    out.insert(key.clone(), self.eval(value)?);
+                }
+                Value::Record(Rc::new(out))
+            }
+            IRKind::GetField { record, key } => match self.eval(record)? {
+                Value::Record(fields) => match fields.get(key) {
+                    Some(value) => value.clone(),
+                    None => {
+                        let message = format!("No field '{}' in this record.", key);
+                        return Err(self.error(message, node.span));
+                    }
+                },
+                other => {
+                    let message = format!("Can't read '.{}' from a {}.", key, other.type_name());
+                    return Err(self.error(message, node.span));
+                }
+            },
+            IRKind::Loop {
+                var,
+                iterable,
+                body,
+                collect,
+            } => {
+                let items = match self.eval(iterable)? {
+                    Value::List(items) => items,
+                    other => {
+                        let message =
+                            format!("Can't loop over a value of type {}.", other.type_name());
+                        return Err(self.error(message, iterable.span));
+                    }
+                };
+
+                let mut results = Vec::new();
+                for item in items.iter() {
+                    let depth = self.locals().len();
+                    self.locals().push((var.clone(), item.clone()));
+                    let value = self.eval(body);
+                    self.locals().truncate(depth);
+                    if *collect {
+                        results.push(value?);
+                    } else {
+                        value?;
+                    }
+                }
+                match collect {
+                    true => Value::List(Rc::new(results)),
+                    false => Value::Unit,
+                }
+            }
+            IRKind::Import { path } => {
+                let message = format!("Can't run imports yet, '{}' wasn't loaded.", path);
+                return Err(self.error(message, node.span));
+            }
+            // Only reached when nothing evaluated it beforehand, like in the REPL.
+            IRKind::Comptime(value) => self.eval(value)?,
+        };
+
+        return Ok(value);
+    }
+
+    fn call_closure(
+        &mut self,
+        callee: &Value,
+        args: Vec<Value>,
+        span: Option<Span>,
+    ) -> Result<Value, RuntimeError> {
+        match callee {
+            Value::Closure { symbol, captures } => {
+                let mut all = captures.as_ref().clone();
+                all.extend(args);
+                self.call_symbol(symbol, all, span)
+            }
+            other => {
+                let message = format!("Can't call a value of type {}.", other.type_name());
+                Err(self.error(message, span))
+            }
+        }
+    }
+
+    /// `__nano__::t_eq(actual, expected, locals...)`, recorded for `nnc test`.
+    fn assertion(&mut self, args: &[IRNode], span: Option<Span>) -> Result<Value, RuntimeError> {
+        let values = self.eval_all(args)?;
+        let (actual, expected) = match values.as_slice() {
+            [actual, expected, ..] => (actual.clone(), expected.clone()),
+            _ => return Err(self.error("`%%t_eq` takes two values.", None)),
+        };
+        let passed = actual == expected;
+        self.assertions.push(Assertion {
+            actual,
+            expected,
+            span,
+            locals: args.iter().map(|a| a.span).zip(values).skip(2).collect(),
+        });
+        return Ok(Value::Bool(passed));
+    }
+
+    fn call_symbol(
+        &mut self,
+        symbol: &str,
+        args: Vec<Value>,
+        span: Option<Span>,
+    ) -> Result<Value, RuntimeError> {
+        if let Some(name) = symbol.strip_prefix(INTRINSIC_PREFIX) {
+            return self.intrinsic(name, args, span);
+        }
+
+        let function = match self.functions.get(symbol) {
+            Some(f) => f.clone(),
+            None => return Err(self.error(format!("Unknown function '{}'.", symbol), span)),
+        };
+
+        let names = function.captures.iter().chain(function.params.iter());
+        if names.clone().count() != args.len() {
+            let message = format!(
+                "'{}' takes {} arguments but got {}.",
+                symbol,
+                function.params.len(),
+                args.len() - function.captures.len().min(args.len())
+            );
+            return Err(self.error(message, span));
+        }
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            let message = format!("Stack overflow, more than {} calls deep.", MAX_CALL_DEPTH);
+            return Err(self.error(message, span));
+        }
+
+        self.frames.push(CallFrame {
+            symbol: symbol.to_string(),
+            call_site: span,
+            locals: names.map(|(n, _)| n.clone()).zip(args).collect(),
+        });
+        let result = self.eval(&function.body);
+        self.frames.pop();
+        return result;
+    }
+
+    fn intrinsic(
+        &mut self,
+        name: &str,
+        args: Vec<Value>,
+        span: Option<Span>,
+    ) -> Result<Value, RuntimeError> {
+        if let (Some(output), "print", [value], true) = (
+            &mut self.output,
+            name,
+            args.as_slice(),
+            self.limits.allow_io,
+        ) {
+            output.push_str(&value.to_print_string());
+            output.push('\n');
+            return Ok(Value::Unit);
+        }
+        // Paid for before it's allocated, or a single call could take all the memory.
+        self.spend(allocation(name, &args), span)?;
+        return intrinsic(name, &args, self.limits.allow_io).map_err(|m| self.error(m, span));
+    }
+}
+
+/// How many items or bytes the intrinsic `name` allocates, a step each.
+fn allocation(name: &str, args: &[Value]) -> u64 {
+    match (name, args) {
+        ("range", [Value::Int(start), Value::Int(end)]) => {
+            let length = (*end as i128 - *start as i128).max(0);
+            u64::try_from(length).unwrap_or(u64::MAX)
+        }
+        ("sum", [Value::String(a), Value::String(b)]) => (a.len() + b.len()) as u64,
+        _ => 0,
+    }
+}
+
+/// The names of the intrinsics, without `__nano__::`.
+pub const INTRINSICS: [&str; 9] = [
+    "print", "range", "eq", "ne", "sum", "sub", "mul", "div", "rem",
+];
+
+/// Runs the intrinsic `__nano__::name`, shared by everything that runs nano code.
+/// Fails with the error's message.
+pub fn intrinsic(name: &str, args: &[Value], allow_io: bool) -> Result<Value, String> {
+    let value = match (name, args) {
+        ("print", [_]) if !allow_io => {
+            return Err("`print` does I/O, which isn't allowed here.".to_string());
+        }
+        ("print", [value]) => {
+            println!("{}", value.to_print_string());
+            Value::Unit
+        }
+        ("range", [Value::Int(start), Value::Int(end)]) => {
+            Value::List(Rc::new((*start..*end).map(Value::Int).collect()))
+        }
+        ("eq", [a, b]) => Value::Bool(a == b),
+        ("ne", [a, b]) => Value::Bool(a != b),
+        ("sum", [Value::String(a), Value::String(b)]) => {
+            Value::String(format!("{}{}", a, b).into())
+        }
+        (op, [Value::Int(a), Value::Int(b)]) => {
+            let result = match op {
+                "sum" => a.checked_add(*b),
+                "sub" => a.checked_sub(*b),
+                "mul" => a.checked_mul(*b),
+                "div" | "rem" if *b == 0 => return Err("Division by zero.".to_string()),
+                "div" => a.checked_div(*b),
+                "rem" => a.checked_rem(*b),
+                _ => return Err(unknown_intrinsic(name, args)),
+            };
+            match result {
+                Some(i) => Value::Int(i),
+                None => return Err("Integer overflow.".to_string()),
+            }
+        }
+        (op, [Value::Float(a), Value::Float(b)]) => Value::Float(match op {
+            "sum" => a + b,
+            "sub" => a - b,
+            "mul" => a * b,
+            "div" => a / b,
+            "rem" => a % b,
+            _ => return Err(unknown_intrinsic(name, args)),
+        }),
+        _ => return Err(unknown_intrinsic(name, args)),
+    };
+
+    return Ok(value);
+}
+
+fn unknown_intrinsic(name: &str, args: &[Value]) -> String {
+    let types: Vec<&str> = args.iter().map(Value::type_name).collect();
+    return format!(
+        "No intrinsic '{}{}' for ({}).",
+        INTRINSIC_PREFIX,
+        name,
+        types.join(", ")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build::front_end, util::plain};
+
+    /// How running `source` fails, rendered without colors.
+    fn failure(source: &'static str) -> String {
+        // Deep recursion needs a roomy stack, like `nnc run` gives it.
+        let run = move || {
+            let module = front_end(source);
+            let error = run(&module).expect_err("it should fail");
+            return plain(&error.render("main.nano", source));
+        };
+        return std::thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(run)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn runtime_errors_point_at_their_span() {
+        let rendered = failure("let x = 0\nprint(10 / x)");
+        assert!(
+            rendered.starts_with("error: Division by zero.\n --> main.nano:2:7\n"),
+            "{}",
+            rendered
+        );
+        assert!(
+            rendered.ends_with("stack trace:\n  in <main>\n"),
+            "{}",
+            rendered
+        );
+    }
+
+    #[test]
+    fn stack_traces_list_calls_innermost_first() {
+        let rendered = failure("fn inner(x) -> x / 0\nfn outer(x) -> inner(x)\nprint(outer(1))");
+        assert!(
+            rendered.ends_with(
+                "stack trace:
+  in inner, called at main.nano:2:16
+  in outer, called at main.nano:3:7
+  in <main>
+"
+            ),
+            "{}",
+            rendered
+        );
+    }
+
+    #[test]
+    fn recursion_is_folded_in_stack_traces() {
+        let rendered = failure("fn forever(x) -> forever(x + 1)\nforever(0)");
+        let trace = rendered.split("stack trace:\n").nth(1).unwrap();
+        // The stack is full, `<main>` and the first call included.
+        assert_eq!(
+            trace,
+            format!(
+                "  in forever, called at main.nano:1:18 ({} times)\n  in forever, called at main.nano:2:1\n  in <main>\n",
+                MAX_CALL_DEPTH - 2
+            )
+        );
+    }
+
+    #[test]
+    fn values_are_shown_as_literals() {
+        let record = BTreeMap::from([("a".to_string(), Value::String("text".into()))]);
+        let list = Value::List(Rc::new(vec![
+            Value::Int(1),
+            Value::Float(0.5),
+            Value::Bool(true),
+            Value::Record(Rc::new(record)),
+        ]));
+        assert_eq!(list.to_string(), "[1, 0.5, yes, {a: 'text'}]");
+        assert_eq!(Value::String("text".into()).to_print_string(), "text");
+    }
+}
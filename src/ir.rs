@@ -0,0 +1,520 @@
+use crate::{
+    grammar::{Span, TokenName},
+    types::{Type, TypeVar},
+    util::json_string,
+};
+
+// The intermediate representation every backend starts from, as sketched
+// in `examples/ast.nano`:
+//
+// (i SYMBOL_CALL
+//     symbol: "__nano__::sum"
+//     args: [
+//         (i LITERAL type: "__nano__::int" value: 1)
+//         (i LITERAL type: "__nano__::int" value: 2)
+//     ]
+// )
+//
+// Names are resolved: operators are calls to intrinsic symbols, calls to
+// declared functions are `SYMBOL_CALL`s, and every `fn` and lambda is lifted
+// out into `Module::functions`, taking the locals it captures as extra
+// leading parameters. Every node keeps its type and source span.
+
+/// The prefix of the symbols the compiler and the runtime provide.
+pub const INTRINSIC_PREFIX: &str = "__nano__::";
+
+/// `+` -> `__nano__::sum`, the symbol an operator is resolved to.
+pub fn intrinsic(name: &str) -> String {
+    format!("{}{}", INTRINSIC_PREFIX, name)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Unit,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+}
+
+impl Literal {
+    /// Parses the text of a literal token, `0x1F`, `1_000`, `'text'`, `yes`...,
+    /// negated if `negative`. `None` when a number doesn't fit in 64 bits.
+    pub fn parse(name: TokenName, text: &str, negative: bool) -> Option<Literal> {
+        let digits = text.replace('_', "");
+        let literal = match name {
+            TokenName::IntLiteral => {
+                // Wider than an int, so that `-9223372036854775808` fits once negated.
+                let magnitude = if let Some(hex) = digits.strip_prefix("0x") {
+                    i128::from_str_radix(hex, 16)
+                } else if let Some(bin) = digits.strip_prefix("0b") {
+                    i128::from_str_radix(bin, 2)
+                } else {
+                    digits.parse()
+                };
+                let value = match negative {
+                    true => -magnitude.ok()?,
+                    false => magnitude.ok()?,
+                };
+                Literal::Int(i64::try_from(value).ok()?)
+            }
+            TokenName::FloatLiteral => {
+                let value: f64 = digits.parse().ok().filter(|x: &f64| x.is_finite())?;
+                Literal::Float(if negative { -value } else { value })
+            }
+            TokenName::StringLiteral => Literal::String(text[1..text.len() - 1].to_string()),
+            TokenName::BooleanLiteral => Literal::Bool(text == "true" || text == "yes"),
+            _ => Literal::Unit,
+        };
+        return Some(literal);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IRKind {
+    Literal(Literal),
+    // A parameter or a `let` of the current function.
+    Local(String),
+    // A module-level `let` or `import`.
+    Global(String),
+    // A function used as a value, closing over `captures`.
+    FnRef {
+        symbol: String,
+        captures: Vec<IRNode>,
+    },
+    // A call to a function known at compile time, or to an intrinsic.
+    SymbolCall {
+        symbol: String,
+        args: Vec<IRNode>,
+    },
+    // A call to a function value.
+    Call {
+        callee: Box<IRNode>,
+        args: Vec<IRNode>,
+    },
+    Let {
+        name: String,
+        value: Box<IRNode>,
+    },
+    SetGlobal {
+        symbol: String,
+        value: Box<IRNode>,
+    },
+    // A sequence of expressions with its own scope, valued as the last one.
+    Block(Vec<IRNode>),
+    List(Vec<IRNode>),
+    Record(Vec<(String, IRNode)>),
+    // `record.key`
+    GetField {
+        record: Box<IRNode>,
+        key: String,
+    },
+    // `for` runs `body` for each item, `select` (`collect`) also gathers the results in a list.
+    Loop {
+        var: String,
+        iterable: Box<IRNode>,
+        body: Box<IRNode>,
+        collect: bool,
+    },
+    // The value of another module, see `import 'path' as alias`.
+    Import {
+        path: String,
+    },
+    // `comptime value`, replaced by what `value` evaluates to during compilation.
+    Comptime(Box<IRNode>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IRNode {
+    pub kind: IRKind,
+    pub ty: Type,
+    pub span: Option<Span>,
+}
+
+impl IRNode {
+    pub fn new(kind: IRKind, ty: Type, span: Option<Span>) -> IRNode {
+        IRNode { kind, ty, span }
+    }
+
+    pub fn unit(span: Option<Span>) -> IRNode {
+        IRNode::new(IRKind::Literal(Literal::Unit), Type::Unit, span)
+    }
+
+    /// The nodes right under this one, in evaluation order.
+    pub fn children(&self) -> Vec<&IRNode> {
+        match &self.kind {
+            IRKind::Literal(_) | IRKind::Local(_) | IRKind::Global(_) | IRKind::Import { .. } => {
+                Vec::new()
+            }
+            IRKind::FnRef {
+                captures: items, ..
+            }
+            | IRKind::SymbolCall { args: items, .. }
+            | IRKind::Block(items)
+            | IRKind::List(items) => items.iter().collect(),
+            IRKind::Call { callee, args } => std::iter::once(callee.as_ref())
+                .chain(args.iter())
+                .collect(),
+            IRKind::Let { value, .. }
+            | IRKind::SetGlobal { value, .. }
+            | IRKind::GetField { record: value, .. }
+            | IRKind::Comptime(value) => vec![value.as_ref()],
+            IRKind::Record(fields) => fields.iter().map(|(_, v)| v).collect(),
+            IRKind::Loop { iterable, body, .. } => vec![iterable.as_ref(), body.as_ref()],
+        }
+    }
+
+    /// Same as `children`, mutably.
+    pub fn children_mut(&mut self) -> Vec<&mut IRNode> {
+        match &mut self.kind {
+            IRKind::Literal(_) | IRKind::Local(_) | IRKind::Global(_) | IRKind::Import { .. } => {
+                Vec::new()
+            }
+            IRKind::FnRef {
+                captures: items, ..
+            }
+            | IRKind::SymbolCall { args: items, .. }
+            | IRKind::Block(items)
+            | IRKind::List(items) => items.iter_mut().collect(),
+            IRKind::Call { callee, args } => std::iter::once(callee.as_mut())
+                .chain(args.iter_mut())
+                .collect(),
+            IRKind::Let { value, .. }
+            | IRKind::SetGlobal { value, .. }
+            | IRKind::GetField { record: value, .. }
+            | IRKind::Comptime(value) => vec![value.as_mut()],
+            IRKind::Record(fields) => fields.iter_mut().map(|(_, v)| v).collect(),
+            IRKind::Loop { iterable, body, .. } => vec![iterable.as_mut(), body.as_mut()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub symbol: String,
+    // The locals of the enclosing functions this one uses, passed before `params`.
+    pub captures: Vec<(String, Type)>,
+    pub params: Vec<(String, Type)>,
+    pub ret: Type,
+    pub body: IRNode,
+    pub span: Option<Span>,
+}
+
+/// `#%name value`, a setting for the compiler rather than code to run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    // Without the `#%`, like `compilation.output`.
+    pub name: String,
+    // Evaluated at compile time, a unit literal when the annotation has no value.
+    pub value: IRNode,
+    pub span: Option<Span>,
+    // Where each field is set, when the value is written as a record.
+    pub fields: Vec<(String, Span)>,
+}
+
+/// `%%test fn name -> ...`, a function `nnc test` calls with no arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Test {
+    pub symbol: String,
+    pub span: Option<Span>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Module {
+    pub functions: Vec<Function>,
+    pub globals: Vec<(String, Type)>,
+    pub annotations: Vec<Annotation>,
+    // What importers can read through the module's alias, by name.
+    pub exports: Vec<(String, IRNode)>,
+    // The module's top-level code, valued as its last expression.
+    pub body: Vec<IRNode>,
+    // The `%%test fn`s, run by `nnc test` and left out of builds.
+    pub tests: Vec<Test>,
+}
+
+impl Module {
+    pub fn function(&self, symbol: &str) -> Option<&Function> {
+        self.functions.iter().find(|f| f.symbol == symbol)
+    }
+}
+
+/// The name a type is spelled with in the IR, `int` -> `__nano__::int`.
+pub fn type_symbol(ty: &Type) -> String {
+    type_symbol_in(ty, &ty.vars())
+}
+
+fn type_symbol_in(ty: &Type, vars: &[TypeVar]) -> String {
+    match ty {
+        Type::Unit | Type::Int | Type::Float | Type::Bool | Type::String => {
+            intrinsic(&ty.to_string())
+        }
+        other => other.to_string_in(vars),
+    }
+}
+
+/// Lines longer than this are broken, one field per line.
+const MAX_FLAT_WIDTH: usize = 80;
+
+// What the printer lays out: `(i NAME label: value ...)`, `[...]` and plain values.
+enum Form {
+    Node(&'static str, Vec<(&'static str, Form)>),
+    List(Vec<Form>),
+    Atom(String),
+}
+
+impl Form {
+    fn string(s: &str) -> Form {
+        Form::Atom(json_string(s))
+    }
+
+    fn flat(&self) -> String {
+        match self {
+            Form::Node(name, fields) => {
+                let mut s = format!("(i {}", name);
+                for (label, value) in fields {
+                    s.push_str(&format!(" {}: {}", label, value.flat()));
+                }
+                s.push(')');
+                s
+            }
+            Form::List(items) => {
+                let items: Vec<String> = items.iter().map(Form::flat).collect();
+                format!("[{}]", items.join(" "))
+            }
+            Form::Atom(a) => a.clone(),
+        }
+    }
+
+    fn write_pretty(&self, out: &mut String, indent: usize) {
+        let flat = self.flat();
+        if indent + flat.len() <= MAX_FLAT_WIDTH {
+            out.push_str(&flat);
+            return;
+        }
+
+        let pad = " ".repeat(indent);
+        match self {
+            Form::Node(name, fields) => {
+                out.push_str(&format!("(i {}", name));
+                for (label, value) in fields {
+                    out.push_str(&format!("\n{}    {}: ", pad, label));
+                    value.write_pretty(out, indent + 4);
+                }
+                out.push_str(&format!("\n{})", pad));
+            }
+            Form::List(items) => {
+                out.push('[');
+                for item in items {
+                    out.push_str(&format!("\n{}    ", pad));
+                    item.write_pretty(out, indent + 4);
+                }
+                out.push_str(&format!("\n{}]", pad));
+            }
+            Form::Atom(a) => out.push_str(a),
+        }
+    }
+}
+
+fn literal_form(literal: &Literal) -> Form {
+    match literal {
+        Literal::Unit => Form::Atom("_".to_string()),
+        Literal::Int(i) => Form::Atom(i.to_string()),
+        Literal::Float(f) => Form::Atom(format!("{:?}", f)),
+        Literal::Bool(b) => Form::Atom(if *b { "yes" } else { "no" }.to_string()),
+        Literal::String(s) => Form::string(s),
+    }
+}
+
+fn list_form(nodes: &[IRNode]) -> Form {
+    Form::List(nodes.iter().map(node_form).collect())
+}
+
+fn node_form(node: &IRNode) -> Form {
+    let boxed = |n: &IRNode| node_form(n);
+    match &node.kind {
+        IRKind::Literal(literal) => Form::Node(
+            "LITERAL",
+            vec![
+                ("type", Form::string(&type_symbol(&node.ty))),
+                ("value", literal_form(literal)),
+            ],
+        ),
+        IRKind::Local(name) => Form::Node("LOCAL", vec![("name", Form::string(name))]),
+        IRKind::Global(symbol) => Form::Node("GLOBAL", vec![("symbol", Form::string(symbol))]),
+        IRKind::FnRef { symbol, captures } => {
+            let mut fields = vec![("symbol", Form::string(symbol))];
+            if !captures.is_empty() {
+                fields.push(("captures", list_form(captures)));
+            }
+            Form::Node("FN_REF", fields)
+        }
+        IRKind::SymbolCall { symbol, args } => Form::Node(
+            "SYMBOL_CALL",
+            vec![("symbol", Form::string(symbol)), ("args", list_form(args))],
+        ),
+        IRKind::Call { callee, args } => Form::Node(
+            "CALL",
+            vec![("callee", boxed(callee)), ("args", list_form(args))],
+        ),
+        IRKind::Let { name, value } => Form::Node(
+            "LET",
+            vec![("name", Form::string(name)), ("value", boxed(value))],
+        ),
+        IRKind::SetGlobal { symbol, value } => Form::Node(
+            "SET_GLOBAL",
+            vec![("symbol", Form::string(symbol)), ("value", boxed(value))],
+        ),
+        IRKind::Block(items) => Form::Node("BLOCK", vec![("exprs", list_form(items))]),
+        IRKind::List(items) => Form::Node("LIST", vec![("items", list_form(items))]),
+        IRKind::Record(fields) => Form::Node(
+            "RECORD",
+            vec![(
+                "fields",
+                Form::List(
+                    fields
+                        .iter()
+                        .map(|(key, value)| {
+                            Form::Node(
+                                "FIELD",
+                                vec![("key", Form::string(key)), ("value", boxed(value))],
+                            )
+                        })
+                        .collect(),
+                ),
+            )],
+        ),
+        IRKind::GetField { record, key } => Form::Node(
+            "GET_FIELD",
+            vec![("record", boxed(record)), ("key", Form::string(key))],
+        ),
+        IRKind::Loop {
+            var,
+            iterable,
+            body,
+            collect,
+        } => Form::Node(
+            match collect {
+                true => "SELECT",
+                false => "FOR",
+            },
+            vec![
+                ("var", Form::string(var)),
+                ("iterable", boxed(iterable)),
+                ("body", boxed(body)),
+            ],
+        ),
+        IRKind::Import { path } => Form::Node("IMPORT", vec![("path", Form::string(path))]),
+        IRKind::Comptime(value) => Form::Node("COMPTIME", vec![("value", node_form(value))]),
+    }
+}
+
+fn params_form(params: &[(String, Type)], vars: &[TypeVar]) -> Form {
+    Form::List(
+        params
+            .iter()
+            .map(|(name, ty)| {
+                Form::Node(
+                    "PARAM",
+                    vec![
+                        ("name", Form::string(name)),
+                        ("type", Form::string(&type_symbol_in(ty, vars))),
+                    ],
+                )
+            })
+            .collect(),
+    )
+}
+
+fn function_form(function: &Function) -> Form {
+    // Type variables are named once for the whole signature.
+    let signature = Type::Fn(
+        function
+            .captures
+            .iter()
+            .chain(function.params.iter())
+            .map(|(_, ty)| ty.clone())
+            .collect(),
+        Box::new(function.ret.clone()),
+    );
+    let vars = signature.vars();
+
+    let mut fields = vec![("symbol", Form::string(&function.symbol))];
+    if !function.captures.is_empty() {
+        fields.push(("captures", params_form(&function.captures, &vars)));
+    }
+    fields.push(("params", params_form(&function.params, &vars)));
+    fields.push((
+        "returns",
+        Form::string(&type_symbol_in(&function.ret, &vars)),
+    ));
+    fields.push(("body", node_form(&function.body)));
+    return Form::Node("FUNCTION", fields);
+}
+
+/// Prints a single IR node, and everything under it.
+pub fn print_node(node: &IRNode) -> String {
+    let mut out = String::new();
+    node_form(node).write_pretty(&mut out, 0);
+    return out;
+}
+
+/// Prints a whole module: its annotations, its functions, then its top-level code.
+pub fn print_module(module: &Module) -> String {
+    let mut out = String::new();
+    for annotation in &module.annotations {
+        let form = Form::Node(
+            "ANNOTATION",
+            vec![
+                ("name", Form::string(&annotation.name)),
+                ("value", node_form(&annotation.value)),
+            ],
+        );
+        form.write_pretty(&mut out, 0);
+        out.push_str("\n\n");
+    }
+    for function in &module.functions {
+        function_form(function).write_pretty(&mut out, 0);
+        out.push_str("\n\n");
+    }
+    for node in &module.body {
+        node_form(node).write_pretty(&mut out, 0);
+        out.push('\n');
+    }
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn int_literals_must_fit_in_64_bits() {
+        let int = |text, negative| Literal::parse(TokenName::IntLiteral, text, negative);
+
+        assert_eq!(
+            int("9223372036854775807", false),
+            Some(Literal::Int(i64::MAX))
+        );
+        assert_eq!(
+            int("9223372036854775808", true),
+            Some(Literal::Int(i64::MIN))
+        );
+        assert_eq!(int("9223372036854775808", false), None);
+        assert_eq!(int("9223372036854775809", true), None);
+        assert_eq!(int("99999999999999999999999", false), None);
+        assert_eq!(
+            int("0x7FFF_FFFF_FFFF_FFFF", false),
+            Some(Literal::Int(i64::MAX))
+        );
+        assert_eq!(int("0x8000000000000000", false), None);
+        assert_eq!(int("0b101", true), Some(Literal::Int(-5)));
+    }
+
+    #[test]
+    fn float_literals_must_be_finite() {
+        let float = |text: &str| Literal::parse(TokenName::FloatLiteral, text, false);
+
+        assert_eq!(float("1_000.5"), Some(Literal::Float(1000.5)));
+        assert_eq!(float(&format!("{}.0", "9".repeat(400))), None);
+    }
+}
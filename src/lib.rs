@@ -0,0 +1,38 @@
+// Explicit `return`s at the end of functions are the house style.
+#![allow(clippy::needless_return)]
+
+//! `nnc`, the `nano` compiler, as a library.
+//! Everything the CLI does is available here for your metaprogramming needs.
+
+pub mod build;
+pub mod c;
+pub mod comptime;
+pub mod diagnostic;
+pub mod elf;
+pub mod file_importer;
+pub mod fmt;
+pub mod grammar;
+pub mod interp;
+pub mod ir;
+pub mod lower;
+pub mod modules;
+pub mod mono;
+pub mod nano_grammar;
+pub mod native;
+pub mod nbbc;
+pub mod opt;
+pub mod parser;
+pub mod pe;
+pub mod repl;
+pub mod resolve;
+pub mod sexpr;
+pub mod style;
+pub mod testing;
+pub mod trace;
+pub mod tree;
+pub mod types;
+pub mod util;
+pub mod visit;
+pub mod vm;
+pub mod wasm;
+pub mod x86;
@@ -0,0 +1,703 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::{
+    grammar::{Span, TokenName},
+    ir::{intrinsic, Annotation, Function, IRKind, IRNode, Literal, Module, Test},
+    resolve::{BindingId, BindingKind, Resolution},
+    tree::{NodeId, Tree},
+    types::{Type, TypeInfo},
+};
+
+// Lowering: the resolved, typed tree -> IR.
+//
+// Functions are lifted out of the tree before anything else, so that calls
+// can refer to functions declared further down. A function that uses the
+// locals of an enclosing one captures them, and so does every function
+// calling it: `captures` is a fixpoint over the call graph.
+
+pub fn lower(tree: &Tree, resolution: &Resolution, types: &TypeInfo) -> Module {
+    return lower_with_imports(tree, resolution, types, "", &HashMap::new());
+}
+
+/// Same as `lower`, for a module named `name` (its symbols start with `name::`),
+/// knowing the `Module::exports` of the modules behind some `Import` nodes.
+pub fn lower_with_imports(
+    tree: &Tree,
+    resolution: &Resolution,
+    types: &TypeInfo,
+    name: &str,
+    imports: &HashMap<NodeId, Vec<(String, IRNode)>>,
+) -> Module {
+    let prefix = match name {
+        "" => String::new(),
+        name => format!("{}::", name),
+    };
+    let mut lowering = Lowering {
+        tree,
+        resolution,
+        types,
+        imports,
+        prefix: prefix.clone(),
+        module: Module::default(),
+        symbols: HashMap::new(),
+        captures: HashMap::new(),
+        globals: HashMap::new(),
+        frames: Vec::new(),
+    };
+
+    lowering.assign_symbols(tree.root(), &prefix);
+    lowering.find_captures();
+
+    lowering.frames.push(Frame::default());
+    let items = lowering.block_items(tree.root());
+    lowering.module.body = lowering.lower_items(items);
+    lowering.frames.pop();
+
+    lowering.module.exports = lowering.exports();
+    return lowering.module;
+}
+
+#[derive(Default)]
+struct Frame {
+    locals: HashMap<BindingId, String>,
+    taken: HashSet<String>,
+}
+
+struct Lowering<'t> {
+    tree: &'t Tree,
+    resolution: &'t Resolution,
+    types: &'t TypeInfo,
+    imports: &'t HashMap<NodeId, Vec<(String, IRNode)>>,
+    // Put before every symbol of the module, `name::`.
+    prefix: String,
+    module: Module,
+    // The symbol of every `FnDecl` and `Lambda`.
+    symbols: HashMap<NodeId, String>,
+    // The outer locals every function uses, ordered by binding.
+    captures: HashMap<NodeId, Vec<BindingId>>,
+    globals: HashMap<BindingId, String>,
+    frames: Vec<Frame>,
+}
+
+/// Makes `name` unique among `taken`, as `name`, `name#1`, `name#2`...
+pub(crate) fn unique(name: String, taken: &mut HashSet<String>) -> String {
+    let mut candidate = name.clone();
+    let mut n = 0;
+    while taken.contains(&candidate) {
+        n += 1;
+        candidate = format!("{}#{}", name, n);
+    }
+    taken.insert(candidate.clone());
+    return candidate;
+}
+
+impl<'t> Lowering<'t> {
+    fn ty(&self, id: NodeId) -> Type {
+        self.types.type_of(id).cloned().unwrap_or(Type::Unit)
+    }
+
+    fn is_global(&self, binding: BindingId) -> bool {
+        let scope = self.resolution.binding(binding).scope;
+        self.resolution.scopes[scope].node == Some(self.tree.root())
+    }
+
+    /// The `FnDecl` a `Fn` binding was introduced by.
+    fn fn_node(&self, binding: BindingId) -> Option<NodeId> {
+        let b = self.resolution.binding(binding);
+        match b.kind {
+            BindingKind::Fn => b.node.and_then(|n| self.tree.parent(n)),
+            _ => None,
+        }
+    }
+
+    fn assign_symbols(&mut self, id: NodeId, prefix: &str) {
+        let tree = self.tree;
+        let mut prefix = prefix.to_string();
+
+        if tree.is(id, "FnDecl") || tree.is(id, "Lambda") {
+            let name = match tree.get(id, "name").and_then(|n| tree.text(n)) {
+                Some(name) => name.to_string(),
+                None => "lambda".to_string(),
+            };
+            let mut taken: HashSet<String> = self.symbols.values().cloned().collect();
+            let symbol = unique(format!("{}{}", prefix, name), &mut taken);
+            prefix = format!("{}::", symbol);
+            self.symbols.insert(id, symbol);
+        }
+
+        for child in tree.child_nodes(id) {
+            self.assign_symbols(child, &prefix);
+        }
+    }
+
+    fn find_captures(&mut self) {
+        let tree = self.tree;
+        let functions: Vec<NodeId> = self.symbols.keys().copied().collect();
+        let inside = |binding: BindingId, f: NodeId| match self.resolution.binding(binding).node {
+            None => false,
+            Some(n) => n == f || tree.ancestors(n).any(|a| a == f),
+        };
+
+        // The functions each one calls, and the outer locals it uses directly.
+        let mut calls: HashMap<NodeId, Vec<NodeId>> = HashMap::new();
+        let mut captures: HashMap<NodeId, BTreeSet<BindingId>> = HashMap::new();
+        for f in &functions {
+            for n in tree.descendants(*f) {
+                let b = match self.resolution.uses.get(&n) {
+                    None => continue,
+                    Some(b) => *b,
+                };
+                match self.resolution.binding(b).kind {
+                    BindingKind::Fn => calls.entry(*f).or_default().extend(self.fn_node(b)),
+                    BindingKind::Builtin => {}
+                    _ => {
+                        if !self.is_global(b) && !inside(b, *f) {
+                            captures.entry(*f).or_default().insert(b);
+                        }
+                    }
+                }
+            }
+        }
+
+        loop {
+            let mut changed = false;
+            for f in &functions {
+                let inherited: Vec<BindingId> = calls
+                    .get(f)
+                    .into_iter()
+                    .flatten()
+                    .flat_map(|g| captures.get(g).cloned().unwrap_or_default())
+                    .filter(|b| !inside(*b, *f))
+                    .collect();
+                let own = captures.entry(*f).or_default();
+                for b in inherited {
+                    changed |= own.insert(b);
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        self.captures = captures
+            .into_iter()
+            .map(|(f, c)| (f, c.into_iter().collect()))
+            .collect();
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames
+            .last_mut()
+            .expect("lowering always happens inside a frame")
+    }
+
+    /// Gives a binding introduced by `name` a local (or, at the top level, global) name.
+    fn define(&mut self, name: Option<NodeId>) -> String {
+        let binding = match name.and_then(|n| self.resolution.definitions.get(&n)) {
+            None => return "_".to_string(),
+            Some(b) => *b,
+        };
+        let text = self.resolution.binding(binding).name.clone();
+
+        if self.is_global(binding) {
+            let mut taken: HashSet<String> = self.globals.values().cloned().collect();
+            let symbol = unique(format!("{}{}", self.prefix, text), &mut taken);
+            self.globals.insert(binding, symbol.clone());
+            let ty = name.map(|n| self.ty(n)).unwrap_or(Type::Unit);
+            self.module.globals.push((symbol.clone(), ty));
+            return symbol;
+        }
+
+        let frame = self.frame();
+        let local = unique(text, &mut frame.taken);
+        frame.locals.insert(binding, local.clone());
+        return local;
+    }
+
+    /// A read of the variable `binding`, from the current function.
+    fn variable(&mut self, binding: BindingId, use_node: Option<NodeId>) -> IRNode {
+        let b = self.resolution.binding(binding);
+        let ty = use_node
+            .or(b.node)
+            .map(|n| self.ty(n))
+            .unwrap_or(Type::Unit);
+        let span = use_node.and_then(|n| self.tree.span(n));
+
+        if let Some(symbol) = self.globals.get(&binding) {
+            return IRNode::new(IRKind::Global(symbol.clone()), ty, span);
+        }
+        let local = self
+            .frame()
+            .locals
+            .get(&binding)
+            .cloned()
+            .unwrap_or_else(|| b.name.clone());
+        return IRNode::new(IRKind::Local(local), ty, span);
+    }
+
+    /// The values a function closes over, read from the current function.
+    fn capture_args(&mut self, f: NodeId) -> Vec<IRNode> {
+        let captures = self.captures.get(&f).cloned().unwrap_or_default();
+        captures
+            .into_iter()
+            .map(|b| self.variable(b, None))
+            .collect()
+    }
+
+    /// Lifts a `FnDecl` or `Lambda` into `Module::functions`.
+    fn lift(&mut self, f: NodeId, params: Vec<NodeId>, body: Option<NodeId>) {
+        let mut frame = Frame::default();
+        let mut captures = Vec::new();
+        for b in self.captures.get(&f).cloned().unwrap_or_default() {
+            let binding = self.resolution.binding(b);
+            let local = unique(binding.name.clone(), &mut frame.taken);
+            frame.locals.insert(b, local.clone());
+            captures.push((
+                local,
+                binding.node.map(|n| self.ty(n)).unwrap_or(Type::Unit),
+            ));
+        }
+        self.frames.push(frame);
+
+        let params = params
+            .into_iter()
+            .map(|p| (self.define(Some(p)), self.ty(p)))
+            .collect();
+        let body = match body {
+            None => IRNode::unit(None),
+            Some(b) => self.lower(b),
+        };
+        self.frames.pop();
+
+        let function = Function {
+            symbol: self.symbols[&f].clone(),
+            captures,
+            params,
+            ret: body.ty.clone(),
+            body,
+            span: self.tree.span(f),
+        };
+        self.module.functions.push(function);
+    }
+
+    /// How importers reach each export: a `FN_REF` for `fn`s, a `GLOBAL` for `let`s.
+    fn exports(&mut self) -> Vec<(String, IRNode)> {
+        let mut out = Vec::new();
+        for b in self.resolution.exports(self.tree) {
+            let binding = self.resolution.binding(b);
+            let name = binding.name.clone();
+            let ty = binding.node.map(|n| self.ty(n)).unwrap_or(Type::Unit);
+            let node = match self.fn_node(b) {
+                Some(f) => self.fn_ref(f, ty, None),
+                None => self.variable(b, None),
+            };
+            out.push((name, node));
+        }
+        return out;
+    }
+
+    /// The exports of the module `object` is the alias of, if it is one.
+    fn module_of(&self, object: Option<NodeId>) -> Option<&'t Vec<(String, IRNode)>> {
+        let binding = self
+            .resolution
+            .binding(*self.resolution.uses.get(&object?)?);
+        if binding.kind != BindingKind::Import {
+            return None;
+        }
+        return self.imports.get(&self.tree.parent(binding.node?)?);
+    }
+
+    fn fn_ref(&mut self, f: NodeId, ty: Type, span: Option<Span>) -> IRNode {
+        let captures = self.capture_args(f);
+        let symbol = self.symbols[&f].clone();
+        return IRNode::new(IRKind::FnRef { symbol, captures }, ty, span);
+    }
+
+    /// The expressions of a `Program`, `Exprs` or `Parens`, without `Expr` wrappers.
+    fn block_items(&self, id: NodeId) -> Vec<NodeId> {
+        let tree = self.tree;
+        let mut items = Vec::new();
+        for child in tree.child_nodes(id) {
+            match tree.rule(child) {
+                Some("Exprs") => items.extend(self.block_items(child)),
+                Some("Expr") => items.extend(tree.child_nodes(child)),
+                _ => items.push(child),
+            }
+        }
+        return items;
+    }
+
+    /// Lowers a block's items. `fn`s are only lifted, unless one is the block's value,
+    /// and annotations go to `Module::annotations`.
+    fn lower_items(&mut self, items: Vec<NodeId>) -> Vec<IRNode> {
+        let last = items.len().saturating_sub(1);
+        let mut out = Vec::new();
+        for (i, item) in items.into_iter().enumerate() {
+            let node = self.lower(item);
+            let declares = self.tree.declared_fn(item).is_some();
+            if (declares && i != last) || self.tree.is(item, "Annotation") {
+                continue;
+            }
+            out.push(node);
+        }
+        return out;
+    }
+
+    fn lower_opt(&mut self, id: Option<NodeId>) -> IRNode {
+        match id {
+            None => IRNode::unit(None),
+            Some(id) => self.lower(id),
+        }
+    }
+
+    fn lower_list(&mut self, id: NodeId, label: &str) -> Vec<IRNode> {
+        let items = self
+            .tree
+            .get(id, label)
+            .map(|l| self.tree.child_nodes(l))
+            .unwrap_or_default();
+        items.into_iter().map(|i| self.lower(i)).collect()
+    }
+
+    /// The span of each `key: value` of the record `value` is written as.
+    fn field_spans(&self, value: Option<NodeId>) -> Vec<(String, Span)> {
+        let tree = self.tree;
+        let record = value.and_then(|v| {
+            tree.descendants(v)
+                .into_iter()
+                .find(|d| tree.is(*d, "Record"))
+        });
+        let fields = record
+            .and_then(|r| tree.get(r, "fields"))
+            .map(|f| tree.child_nodes(f))
+            .unwrap_or_default();
+        let mut out = Vec::new();
+        for field in fields {
+            let key = tree.get(field, "key").and_then(|k| tree.text(k));
+            if let (Some(key), Some(span)) = (key, tree.span(field)) {
+                out.push((key.to_string(), span));
+            }
+        }
+        return out;
+    }
+
+    fn lower(&mut self, id: NodeId) -> IRNode {
+        let tree = self.tree;
+        let ty = self.ty(id);
+        let span = tree.span(id);
+
+        let kind = match tree.rule(id).unwrap_or("") {
+            "Literal" => {
+                // The type checker reported the literals that don't parse.
+                let negative = tree.get(id, "sign").is_some();
+                let literal = tree
+                    .get(id, "value")
+                    .and_then(|v| tree.token(v))
+                    .and_then(|(name, text)| Literal::parse(name, text, negative));
+                IRKind::Literal(literal.unwrap_or(Literal::Unit))
+            }
+            "Name" => return self.lower_name(id),
+            "BinaryExpr"
+                if matches!(
+                    tree.get(id, "op").and_then(|o| tree.token(o)),
+                    Some((TokenName::Pipe, _))
+                ) =>
+            {
+                let value = self.lower_opt(tree.get(id, "lhs"));
+                return self.call(tree.get(id, "rhs"), vec![value], ty, span);
+            }
+            "BinaryExpr" => {
+                let op = match tree.get(id, "op").and_then(|o| tree.token(o)) {
+                    Some((TokenName::OpPlus, _)) => "sum",
+                    Some((TokenName::OpDash, _)) => "sub",
+                    Some((TokenName::OpAsterisk, _)) => "mul",
+                    Some((TokenName::OpForwardSlash, _)) => "div",
+                    Some((TokenName::OpPercent, _)) => "rem",
+                    Some((TokenName::OpIs, _)) => "eq",
+                    Some((TokenName::OpXis, _)) => "ne",
+                    _ => "unknown",
+                };
+                let lhs = self.lower_opt(tree.get(id, "lhs"));
+                let rhs = self.lower_opt(tree.get(id, "rhs"));
+                IRKind::SymbolCall {
+                    symbol: intrinsic(op),
+                    args: vec![lhs, rhs],
+                }
+            }
+            "Range" => {
+                let start = self.lower_opt(tree.get(id, "start"));
+                let end = self.lower_opt(tree.get(id, "end"));
+                IRKind::SymbolCall {
+                    symbol: intrinsic("range"),
+                    args: vec![start, end],
+                }
+            }
+            "Member" => {
+                let object = tree.get(id, "object");
+                let field = tree.get(id, "field").and_then(|f| tree.text(f));
+                let field = field.unwrap_or("").to_string();
+                let export = self
+                    .module_of(object)
+                    .and_then(|exports| exports.iter().find(|(name, _)| *name == field));
+                if let Some((_, export)) = export {
+                    return IRNode {
+                        ty,
+                        span,
+                        ..export.clone()
+                    };
+                }
+                IRKind::GetField {
+                    record: Box::new(self.lower_opt(object)),
+                    key: field,
+                }
+            }
+            "Call" => return self.lower_call(id),
+            "List" => IRKind::List(self.lower_list(id, "items")),
+            "Record" => {
+                let fields = tree
+                    .get(id, "fields")
+                    .map(|f| tree.child_nodes(f))
+                    .unwrap_or_default();
+                IRKind::Record(
+                    fields
+                        .into_iter()
+                        .map(|f| {
+                            let key = tree.get(f, "key").and_then(|k| tree.text(k));
+                            let key = key.unwrap_or("").to_string();
+                            (key, self.lower_opt(tree.get(f, "value")))
+                        })
+                        .collect(),
+                )
+            }
+            "Parens" => {
+                let items = self.block_items(id);
+                IRKind::Block(self.lower_items(items))
+            }
+            "Let" => {
+                let value = Box::new(self.lower_opt(tree.get(id, "value")));
+                let name = tree.get(id, "name");
+                let is_global = name
+                    .and_then(|n| self.resolution.definitions.get(&n))
+                    .is_some_and(|b| self.is_global(*b));
+                let name = self.define(name);
+                match is_global {
+                    true => IRKind::SetGlobal {
+                        symbol: name,
+                        value,
+                    },
+                    false => IRKind::Let { name, value },
+                }
+            }
+            "Import" => {
+                let path = tree.get(id, "path").and_then(|p| tree.token(p));
+                let path = match path.and_then(|(name, text)| Literal::parse(name, text, false)) {
+                    Some(Literal::String(s)) => s,
+                    _ => String::new(),
+                };
+                // A loaded module is a record of its exports.
+                let module = match self.imports.get(&id) {
+                    Some(exports) => {
+                        let alias = tree.get(id, "alias").map(|a| self.ty(a));
+                        IRNode::new(
+                            IRKind::Record(exports.clone()),
+                            alias.unwrap_or(Type::Unit),
+                            span,
+                        )
+                    }
+                    None => IRNode::new(IRKind::Import { path }, ty.clone(), span),
+                };
+
+                let alias = tree.get(id, "alias");
+                let is_global = alias
+                    .and_then(|n| self.resolution.definitions.get(&n))
+                    .is_some_and(|b| self.is_global(*b));
+                let name = self.define(alias);
+                match is_global {
+                    true => IRKind::SetGlobal {
+                        symbol: name,
+                        value: Box::new(module),
+                    },
+                    false => IRKind::Let {
+                        name,
+                        value: Box::new(module),
+                    },
+                }
+            }
+            "FnDecl" => {
+                let params = tree
+                    .get(id, "params")
+                    .map(|p| tree.child_nodes(p))
+                    .unwrap_or_default();
+                self.lift(id, params, tree.get(id, "body"));
+                return self.fn_ref(id, ty, span);
+            }
+            "Lambda" => {
+                let params = tree.get(id, "param").into_iter().collect();
+                self.lift(id, params, tree.get(id, "body"));
+                return self.fn_ref(id, ty, span);
+            }
+            "Annotation" => {
+                let name = tree.get(id, "name").and_then(|n| tree.text(n));
+                let name = name.unwrap_or("").trim_start_matches("#%").to_string();
+                let value = self.lower_opt(tree.get(id, "value"));
+                let fields = match value.kind {
+                    IRKind::Record(_) => self.field_spans(tree.get(id, "value")),
+                    _ => Vec::new(),
+                };
+                self.module.annotations.push(Annotation {
+                    name,
+                    value,
+                    span,
+                    fields,
+                });
+                IRKind::Literal(Literal::Unit)
+            }
+            "ScopeAnnotation" => {
+                let value = tree.get(id, "value");
+                if let Some(decl) = value.and_then(|v| tree.declared_fn(v)) {
+                    let symbol = self.symbols[&decl].clone();
+                    self.module.tests.push(Test { symbol, span });
+                }
+                return self.lower_opt(value);
+            }
+            "Assertion" => return self.lower_assertion(id),
+            "Comptime" => IRKind::Comptime(Box::new(self.lower_opt(tree.get(id, "value")))),
+            "For" | "Select" => {
+                let iterable = Box::new(self.lower_opt(tree.get(id, "iterable")));
+                let var = self.define(tree.get(id, "name"));
+                let body = Box::new(self.lower_opt(tree.get(id, "body")));
+                IRKind::Loop {
+                    var,
+                    iterable,
+                    body,
+                    collect: tree.is(id, "Select"),
+                }
+            }
+            // Wrappers, like `Expr`.
+            _ => {
+                let mut children: Vec<IRNode> = tree
+                    .child_nodes(id)
+                    .into_iter()
+                    .map(|c| self.lower(c))
+                    .collect();
+                match children.len() {
+                    1 => return children.remove(0),
+                    0 => IRKind::Literal(Literal::Unit),
+                    _ => IRKind::Block(children),
+                }
+            }
+        };
+
+        return IRNode::new(kind, ty, span);
+    }
+
+    fn lower_name(&mut self, id: NodeId) -> IRNode {
+        let ty = self.ty(id);
+        let span = self.tree.span(id);
+        let binding = match self.resolution.uses.get(&id) {
+            // Undefined names never make it past the resolver.
+            None => return IRNode::unit(span),
+            Some(b) => *b,
+        };
+
+        let b = self.resolution.binding(binding);
+        if b.kind == BindingKind::Builtin {
+            let symbol = intrinsic(&b.name);
+            let captures = Vec::new();
+            return IRNode::new(IRKind::FnRef { symbol, captures }, ty, span);
+        }
+        if let Some(f) = self.fn_node(binding) {
+            return self.fn_ref(f, ty, span);
+        }
+        return self.variable(binding, Some(id));
+    }
+
+    /// `%%t_eq(actual, expected)` -> `__nano__::t_eq(actual, expected, locals...)`,
+    /// where the locals are the variables `actual` reads, so the report can show
+    /// their values in its text.
+    fn lower_assertion(&mut self, id: NodeId) -> IRNode {
+        let tree = self.tree;
+        let mut args = self.lower_list(id, "args");
+        if let Some(actual) = tree
+            .get(id, "args")
+            .and_then(|a| tree.child_nodes(a).first().copied())
+        {
+            let inside: HashSet<NodeId> = tree.descendants(actual).into_iter().collect();
+            for name in tree.descendants(actual) {
+                let binding = match self.resolution.uses.get(&name) {
+                    Some(b) if tree.is(name, "Name") => *b,
+                    _ => continue,
+                };
+                let b = self.resolution.binding(binding);
+                let local = matches!(
+                    b.kind,
+                    BindingKind::Let | BindingKind::Param | BindingKind::Loop
+                );
+                if local && !b.node.is_some_and(|n| inside.contains(&n)) {
+                    args.push(self.variable(binding, Some(name)));
+                }
+            }
+        }
+
+        let kind = IRKind::SymbolCall {
+            symbol: intrinsic("t_eq"),
+            args,
+        };
+        return IRNode::new(kind, Type::Bool, tree.span(id));
+    }
+
+    fn lower_call(&mut self, id: NodeId) -> IRNode {
+        let args = self.lower_list(id, "args");
+        return self.call(
+            self.tree.get(id, "callee"),
+            args,
+            self.ty(id),
+            self.tree.span(id),
+        );
+    }
+
+    /// A call to `callee`, direct when it names a declared function.
+    fn call(
+        &mut self,
+        callee: Option<NodeId>,
+        args: Vec<IRNode>,
+        ty: Type,
+        span: Option<Span>,
+    ) -> IRNode {
+        let binding = callee.and_then(|c| self.resolution.uses.get(&c)).copied();
+        let known = binding.map(|b| (b, self.resolution.binding(b).kind));
+
+        let kind = match known {
+            Some((b, BindingKind::Builtin)) => IRKind::SymbolCall {
+                symbol: intrinsic(&self.resolution.binding(b).name),
+                args,
+            },
+            Some((b, BindingKind::Fn)) => {
+                let f = self.fn_node(b).expect("`Fn` bindings come from a `FnDecl`");
+                let mut all = self.capture_args(f);
+                all.extend(args);
+                IRKind::SymbolCall {
+                    symbol: self.symbols[&f].clone(),
+                    args: all,
+                }
+            }
+            _ => match self.lower_opt(callee) {
+                // Like `module.function(...)`, where the function is known after all.
+                IRNode {
+                    kind: IRKind::FnRef { symbol, captures },
+                    ..
+                } => {
+                    let mut all = captures;
+                    all.extend(args);
+                    IRKind::SymbolCall { symbol, args: all }
+                }
+                callee => IRKind::Call {
+                    callee: Box::new(callee),
+                    args,
+                },
+            },
+        };
+
+        return IRNode::new(kind, ty, span);
+    }
+}
@@ -1,16 +1,31 @@
-use crate::parser::build_tree;
+// Explicit `return`s at the end of functions are the house style.
+#![allow(clippy::needless_return)]
+
 use colored::Colorize;
-use file_importer::import_as_text;
-use parser::tokenize;
-use parser::ParseError;
+use nnc::build;
+use nnc::comptime::{self, Comptime, DEFAULT_FUEL};
+use nnc::diagnostic::{Diagnostic, Severity, SingleFile, Sources};
+use nnc::file_importer::{import_as_text, SourceMap};
+use nnc::fmt as formatter;
+use nnc::interp::{self, RuntimeError};
+use nnc::ir::{print_module, Module};
+use nnc::modules::{self, LoadError, ModuleGraph};
+use nnc::nbbc::{self, DecodeError, Program};
+use nnc::opt;
+use nnc::parser::build_tree;
+use nnc::parser::tokenize;
+use nnc::parser::ParseError;
+use nnc::repl;
+use nnc::sexpr::{print_ast, print_tokens, print_tree, SExprOptions};
+use nnc::style;
+use nnc::testing::{self, TestReport};
+use nnc::trace::ParseTrace;
+use nnc::vm;
 use std::env;
+use std::path::Path;
 
-mod file_importer;
-pub mod grammar;
-pub mod nano_grammar;
-mod parser;
-
-const VERSION: &'static str = "0.0.1";
+const VERSION: &str = "0.0.1";
+const INTERPRETER_STACK_SIZE: usize = 256 * 1024 * 1024;
 
 /// The entry point of the CLI app.
 fn main() {
@@ -22,7 +37,11 @@ fn main() {
         return;
     }
 
-    print!("\n");
+    // Reports for other programs are printed as they are, with no blank lines around.
+    let padded = !(args[1] == "test" && flag_value(&args, "format").is_some_and(|f| f != "pretty"));
+    if padded {
+        println!();
+    }
     match args[1].as_str() {
         "version" => print_info(),
         "help" => {
@@ -35,22 +54,103 @@ fn main() {
         "compile" => {
             if args.len() < 3 {
                 println!(
-                    "{}: `nnc compile <entry_file>`\n{}\n",
+                    "{}: `nnc compile <entry_file> [--emit=ast|ir|comptime] [--trace-parse[=tree|json]] [--comptime-fuel=<steps>] [--comptime-io]`\n{}\n",
                     "Usage".bold(),
                     "for example: `nnc compile ./index.nano`".dimmed()
                 );
                 return;
             }
 
-            let _ = compile(&args);
+            // Compile-time code runs in the interpreter too.
+            // A failing stage or target makes for a failing exit status, for CI.
+            with_interpreter_stack(move || {
+                if !matches!(compile(&args), Ok(true)) {
+                    println!();
+                    std::process::exit(1);
+                }
+            });
+        }
+        "parse" => {
+            if args.len() < 3 {
+                println!(
+                    "{}: `nnc parse <file> [--emit=ast|cst|tokens] [--spans]`\n{}\n",
+                    "Usage".bold(),
+                    "for example: `nnc parse ./index.nano --emit=cst`".dimmed()
+                );
+                return;
+            }
+
+            let _ = parse(&args);
+        }
+        "run" => {
+            if args.len() < 3 {
+                println!(
+                    "{}: `nnc run <entry_file|program.nb8>`\n{}\n",
+                    "Usage".bold(),
+                    "for example: `nnc run ./index.nano`".dimmed()
+                );
+                return;
+            }
+
+            // Errors, at compile time or at run time, make for a failing exit status.
+            with_interpreter_stack(move || {
+                if !matches!(run(&args), Ok(true)) {
+                    println!();
+                    std::process::exit(1);
+                }
+            });
+        }
+        "disasm" => {
+            if args.len() < 3 {
+                println!(
+                    "{}: `nnc disasm <program.nb8>`\n{}\n",
+                    "Usage".bold(),
+                    "for example: `nnc disasm ./dist/program.nb8`".dimmed()
+                );
+                return;
+            }
+
+            let _ = disasm(&args);
         }
-        "run" => println!("Not yet implemented."),
-        "repl" => println!("Not yet implemented."),
+        "repl" => with_interpreter_stack(repl::start),
         "interact" => println!("Not yet implemented."),
         "clean" => println!("Not yet implemented."),
-        "test" => println!("Not yet implemented."),
+        "test" => {
+            if args.len() < 3 {
+                println!(
+                    "{}: `nnc test <entry_file> [filters...] [--format=pretty|junit|tap|json]`\n{}\n",
+                    "Usage".bold(),
+                    "for example: `nnc test ./index.nano arithmetic`".dimmed()
+                );
+                return;
+            }
+
+            // Failing tests make for a failing exit status, for CI.
+            with_interpreter_stack(move || {
+                if !matches!(test(&args), Ok(true)) {
+                    if padded {
+                        println!();
+                    }
+                    std::process::exit(1);
+                }
+            });
+        }
         "lint" => println!("Not yet implemented."),
-        "fmt" => println!("Not yet implemented."),
+        "fmt" => {
+            if args.len() < 3 {
+                println!(
+                    "{}: `nnc fmt <file> [files...] [--check] [--show-style]`\n{}\n",
+                    "Usage".bold(),
+                    "for example: `nnc fmt ./index.nano --check`".dimmed()
+                );
+                return;
+            }
+
+            if !matches!(fmt(&args), Ok(true)) {
+                println!();
+                std::process::exit(1);
+            }
+        }
         "lsp" => println!("Not yet implemented."),
         _ => {
             println!(
@@ -59,11 +159,24 @@ fn main() {
             )
         }
     }
-    print!("\n");
+    if padded {
+        println!();
+    }
 
     //file_importer::import_nano_source("./examples/hello_world.nano");
 }
 
+/// Runs `f` on a thread with a roomy stack, since deep nano recursion
+/// means deep interpreter recursion.
+fn with_interpreter_stack(f: impl FnOnce() + Send + 'static) {
+    std::thread::Builder::new()
+        .stack_size(INTERPRETER_STACK_SIZE)
+        .spawn(f)
+        .expect("the interpreter thread should start")
+        .join()
+        .expect("the interpreter shouldn't panic");
+}
+
 fn print_info() {
     println!(
         "\n{} - the {} compiler\n{} {}.\n\n{}\n",
@@ -75,36 +188,568 @@ fn print_info() {
     );
 }
 
+#[allow(dead_code)]
 enum CompilationError {
     FileNotFound(std::io::Error),
     ParseError(ParseError),
+    SemanticErrors(Vec<Diagnostic>),
+    RuntimeError(RuntimeError),
+    BytecodeError(DecodeError),
 }
 
-/// nnc compile <entry_point_path>
-fn compile(args: &Vec<String>) -> Result<bool, CompilationError> {
+/// nnc compile <entry_point_path> [--emit=ast|ir|comptime]
+fn compile(args: &[String]) -> Result<bool, CompilationError> {
+    let emit = flag_value(args, "emit").unwrap_or("");
+    let (graph, mut module, comptime) = front_end(args, emit, &mut print_diagnostic)?;
+    build::strip_tests(&mut module);
+
+    // Optimization, when `#%optimization` asks for it. `--emit=ir` shows the IR before and after.
+    match opt::options(&comptime) {
+        Ok(None) => {}
+        Ok(Some(options)) => {
+            let passes = opt::optimize(&mut module, &options);
+            if emit == "ir" {
+                let passes = match passes.is_empty() {
+                    true => "nothing to do".to_string(),
+                    false => passes.join(", "),
+                };
+                println!("{} {}\n", "#%optimization".cyan(), passes.dimmed());
+                println!("{}", print_module(&module));
+            }
+        }
+        Err(d) => {
+            println!("{}", d.render_with(&graph));
+            return Err(CompilationError::SemanticErrors(vec![d]));
+        }
+    }
+
+    // Back ends: one pipeline per `#%compilation.output` target.
+    let targets = match build::targets(&comptime) {
+        Ok(targets) => targets,
+        Err(d) => {
+            println!("{}", d.render_with(&graph));
+            return Err(CompilationError::SemanticErrors(vec![d]));
+        }
+    };
+    if targets.is_empty() {
+        if emit.is_empty() {
+            println!(
+                "{}",
+                "Nothing to build, the entry file has no `#%compilation.output`.".dimmed()
+            );
+        }
+        return Ok(true);
+    }
+
+    let reports = build::build(&module, &targets, &graph.root);
+    let failed = reports.iter().filter(|r| r.result.is_err()).count();
+    match failed {
+        0 => println!(
+            "`{} {} -- {}`:",
+            "nnc".green(),
+            "compile".cyan(),
+            "OK".green()
+        ),
+        n => println!(
+            "`{} {} -- {}` ({} of {} targets):",
+            "nnc".green(),
+            "compile".cyan(),
+            "FAILED".red(),
+            n,
+            reports.len()
+        ),
+    }
+    for report in &reports {
+        match &report.result {
+            Ok(size) => println!(
+                " {} {} → {} {}",
+                "✓".green(),
+                report.target.name.bold(),
+                report.path.display(),
+                format!("({} bytes)", size).dimmed()
+            ),
+            Err(e) => println!(
+                " {} {} → {}: {}",
+                "✗".red(),
+                report.target.name.bold(),
+                report.path.display(),
+                e
+            ),
+        }
+    }
+    return Ok(failed == 0);
+}
+
+/// nnc run <entry_point_path>
+fn run(args: &[String]) -> Result<bool, CompilationError> {
+    if args[2].ends_with(".nb8") {
+        let program = read_bytecode(args)?;
+        if let Err(e) = vm::run(&program) {
+            println!("{}", e.render(&args[2], ""));
+            return Err(CompilationError::RuntimeError(e));
+        }
+        return Ok(true);
+    }
+
+    let (graph, module, _) = front_end(args, "", &mut print_diagnostic)?;
+
+    if let Err(e) = interp::run(&module) {
+        println!("{}", e.render_with(&graph));
+        return Err(CompilationError::RuntimeError(e));
+    }
+    return Ok(true);
+}
+
+/// nnc test <entry_point_path> [filters...]
+fn test(args: &[String]) -> Result<bool, CompilationError> {
+    let format = flag_value(args, "format").unwrap_or("pretty");
+    let format = match testing::Format::parse(format) {
+        Some(format) => format,
+        None => {
+            println!(
+                "Unknown test format '{}', expected one of `pretty`, `junit`, `tap` or `json`.",
+                format
+            );
+            return Ok(false);
+        }
+    };
+
+    // Reports for other programs are all there is on stdout, so diagnostics go to
+    // stderr then, errors also making failed reports of their own.
+    let capture_output = format != testing::Format::Pretty;
+    let mut failures = Vec::new();
+    let mut collect = |d: &Diagnostic, sources: &dyn Sources| {
+        eprintln!("{}", d.render_with(sources));
+        if d.severity == Severity::Error {
+            failures.push(TestReport::compile_failure("<compile>", d, sources));
+        }
+    };
+    let compiled = match capture_output {
+        true => front_end(args, "", &mut collect),
+        false => front_end(args, "", &mut print_diagnostic),
+    };
+    let (graph, module, _) = match compiled {
+        Ok(compiled) => compiled,
+        Err(e) => {
+            print_reports(format, &failures);
+            return Err(e);
+        }
+    };
+    let filters: Vec<String> = args[3..]
+        .iter()
+        .filter(|a| !a.starts_with("--"))
+        .cloned()
+        .collect();
+
+    let reports = match testing::run_tests(&module, &filters, &graph, capture_output) {
+        Ok(reports) => reports,
+        Err(e) if capture_output => {
+            eprintln!("{}", e.render_with(&graph));
+            print_reports(format, &[TestReport::failure("<main>", e.clone(), &graph)]);
+            return Err(CompilationError::RuntimeError(e));
+        }
+        Err(e) => {
+            println!("{}", e.render_with(&graph));
+            return Err(CompilationError::RuntimeError(e));
+        }
+    };
+    let failed = reports.iter().filter(|r| !r.passed()).count();
+    print_reports(format, &reports);
+    if capture_output {
+        return Ok(failed == 0);
+    }
+
+    for report in &reports {
+        println!("{}", testing::render_box(report));
+        if let Some((e, _)) = &report.error {
+            println!("{}", e.render_with(&graph));
+        }
+    }
+    match (failed, reports.len()) {
+        (_, 0) => println!("{}", "No tests to run.".dimmed()),
+        (0, 1) => println!(
+            "`{} {} -- {}` (1 test)",
+            "nnc".green(),
+            "test".cyan(),
+            "OK".green()
+        ),
+        (0, n) => println!(
+            "`{} {} -- {}` ({} tests)",
+            "nnc".green(),
+            "test".cyan(),
+            "OK".green(),
+            n
+        ),
+        (failed, n) => println!(
+            "`{} {} -- {}` ({} of {} tests)",
+            "nnc".green(),
+            "test".cyan(),
+            "FAILED".red(),
+            failed,
+            n
+        ),
+    }
+    return Ok(failed == 0);
+}
+
+/// nnc fmt <file> [files...] [--check]
+fn fmt(args: &[String]) -> Result<bool, CompilationError> {
+    let check = flag_value(args, "check").is_some();
+    let paths: Vec<&String> = args[2..].iter().filter(|a| !a.starts_with("--")).collect();
+
+    if flag_value(args, "show-style").is_some() {
+        let mut ok = true;
+        for (i, path) in paths.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+            match style::discover(Path::new(path)) {
+                Ok(config) => show_style(path, &config),
+                Err(e) => {
+                    println!("{} {}:\n{}", "✗".red(), path, e);
+                    ok = false;
+                }
+            }
+        }
+        return Ok(ok);
+    }
+
+    // Each file's outcome: whether it changed (or would), or why it couldn't be formatted.
+    let mut results = Vec::new();
+    for path in &paths {
+        let config = style::discover(Path::new(path));
+        let result = match (import_as_text(path), config) {
+            (Err(e), _) => Err(format!("not found: {}", e)),
+            (_, Err(e)) => Err(format!("its style configuration is invalid.\n{}", e)),
+            (Ok(source), Ok(config)) => match formatter::format(&source, &config.style()) {
+                Err(e) => Err(e.to_string()),
+                Ok(formatted) if formatted == source => Ok(None),
+                Ok(formatted) if check => Ok(Some(first_difference(&source, &formatted))),
+                Ok(formatted) => match std::fs::write(path, &formatted) {
+                    Ok(()) => Ok(Some(first_difference(&source, &formatted))),
+                    Err(e) => Err(format!("couldn't be written: {}", e)),
+                },
+            },
+        };
+        results.push(result);
+    }
+
+    let failed = results
+        .iter()
+        .filter(|r| r.is_err() || (check && matches!(r, Ok(Some(_)))))
+        .count();
+    match failed {
+        0 => println!("`{} {} -- {}`:", "nnc".green(), "fmt".cyan(), "OK".green()),
+        n => println!(
+            "`{} {} -- {}` ({} of {} files):",
+            "nnc".green(),
+            "fmt".cyan(),
+            "FAILED".red(),
+            n,
+            paths.len()
+        ),
+    }
+    for (path, result) in paths.iter().zip(&results) {
+        match result {
+            Ok(None) => println!(
+                " {} {} {}",
+                "✓".green(),
+                path,
+                "(already formatted)".dimmed()
+            ),
+            Ok(Some(line)) if check => println!(
+                " {} {}: would be reformatted, from line {} on",
+                "✗".red(),
+                path,
+                line
+            ),
+            Ok(Some(_)) => println!(" {} {} {}", "✓".green(), path, "(formatted)".dimmed()),
+            Err(e) => println!(" {} {}: {}", "✗".red(), path, e),
+        }
+    }
+    return Ok(failed == 0);
+}
+
+/// Prints the settings in effect for `path`, and where each one comes from.
+fn show_style(path: &str, config: &style::Config) {
+    println!("{} {}", "Style of".bold(), path.bold());
+    match config.files.len() {
+        0 => println!("  {}", "(no nano.style found, all defaults)".dimmed()),
+        _ => {
+            for file in &config.files {
+                println!("  {} {}", "read".dimmed(), file.display());
+            }
+        }
+    }
+    let line = |name: &str, value: String, origin: &style::Origin| {
+        println!(
+            "  {} {} {}",
+            format!("{}:", name).cyan(),
+            value,
+            format!("({})", origin).dimmed()
+        );
+    };
+    line(
+        "max_col",
+        config.max_col.value.to_string(),
+        &config.max_col.origin,
+    );
+    line(
+        "indent",
+        config.indent.value.to_string(),
+        &config.indent.origin,
+    );
+    let trailing_commas = match config.trailing_commas.value {
+        true => "yes",
+        false => "no",
+    };
+    line(
+        "trailing_commas",
+        trailing_commas.to_string(),
+        &config.trailing_commas.origin,
+    );
+    for (rule, level) in &config.rules {
+        line(
+            &format!("lint.{}", rule),
+            level.value.to_string(),
+            &level.origin,
+        );
+    }
+}
+
+/// The first line, 1-based, where two texts differ.
+fn first_difference(a: &str, b: &str) -> usize {
+    let same = a.lines().zip(b.lines()).take_while(|(x, y)| x == y).count();
+    return same + 1;
+}
+
+/// nnc disasm <program.nb8>
+fn disasm(args: &[String]) -> Result<bool, CompilationError> {
+    let program = read_bytecode(args)?;
+    print!("{}", nbbc::disassemble(&program));
+    return Ok(true);
+}
+
+/// Reads and decodes the `.nb8` file in `args[2]`.
+fn read_bytecode(args: &[String]) -> Result<Program, CompilationError> {
+    let bytes = match std::fs::read(&args[2]) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            println!(
+                "`{} {} -- {}`:\n File '{}' not found:\n → {}\n",
+                "nnc".green(),
+                args[1].cyan(),
+                "FAILED".red(),
+                args[2],
+                e
+            );
+            return Err(CompilationError::FileNotFound(e));
+        }
+    };
+    return match nbbc::decode(&bytes) {
+        Ok(program) => Ok(program),
+        Err(e) => {
+            println!("{}: '{}': {}", "error".red().bold(), args[2], e);
+            Err(CompilationError::BytecodeError(e))
+        }
+    };
+}
+
+/// How a command shows the front end's diagnostics, given the files their spans point into.
+type Report<'a> = dyn FnMut(&Diagnostic, &dyn Sources) + 'a;
+
+/// What most commands do with the front end's diagnostics: print them.
+fn print_diagnostic(diagnostic: &Diagnostic, sources: &dyn Sources) {
+    println!("{}", diagnostic.render_with(sources));
+}
+
+/// Prints reports for other programs in `format`; `test` prints the pretty tables itself.
+fn print_reports(format: testing::Format, reports: &[TestReport]) {
+    match format {
+        testing::Format::Pretty => {}
+        testing::Format::Junit => print!("{}", testing::render_junit(reports)),
+        testing::Format::Tap => print!("{}", testing::render_tap(reports)),
+        testing::Format::Json => print!("{}", testing::render_json(reports)),
+    }
+}
+
+/// Everything from reading the entry file (and the modules it imports) to lowering them
+/// to IR and running their compile-time code, every diagnostic going to `report`.
+/// Prints the AST, the IR or the annotations along the way when `emit` asks for it.
+fn front_end(
+    args: &[String],
+    emit: &str,
+    report: &mut Report,
+) -> Result<(ModuleGraph, Module, Comptime), CompilationError> {
     // Read text from source file
+    let source_path = (args[2]).as_str();
+    let source = match import_as_text(source_path) {
+        Ok(value) => value,
+        Err(e) => {
+            let message = format!("Couldn't read '{}': {}.", source_path, e);
+            let entry = SingleFile {
+                path: source_path,
+                source: "",
+            };
+            report(&Diagnostic::error(message, None), &entry);
+            return Err(CompilationError::FileNotFound(e));
+        }
+    };
+    let entry = SingleFile {
+        path: source_path,
+        source: &source,
+    };
+
+    // Loading: parses the entry file, then every file it imports, once.
+    let trace_format = flag_value(args, "trace-parse");
+    let trace = ParseTrace::new();
+    let mut sources = SourceMap::new();
+    sources.add(source_path, source.clone());
+    let graph = modules::load(sources, source_path, trace_format.map(|_| &trace));
+
+    match trace_format {
+        None => {}
+        Some("json") => println!("{}", trace.to_json()),
+        Some(_) => println!("{}", trace.render_tree()),
+    }
+
+    let mut graph = match graph {
+        Err(LoadError::NotFound(e)) => {
+            let message = format!("Couldn't read '{}': {}.", source_path, e);
+            report(&Diagnostic::error(message, None), &entry);
+            return Err(CompilationError::FileNotFound(e));
+        }
+        // The entry file is the first one, so its spans are the same in the map.
+        Err(LoadError::Parse(e)) => {
+            report(&e.diagnostic(), &entry);
+            return Err(CompilationError::ParseError(e));
+        }
+        Ok(g) => g,
+    };
+
+    if emit == "ast" {
+        println!(
+            "{}",
+            print_tree(&graph.entry().tree, &SExprOptions::default())
+        );
+    }
+
+    let options = comptime::Options {
+        fuel: flag_value(args, "comptime-fuel")
+            .and_then(|f| f.parse().ok())
+            .unwrap_or(DEFAULT_FUEL),
+        allow_io: flag_value(args, "comptime-io").is_some(),
+    };
+    let mut reported = 0;
+    let (module, comptime) = loop {
+        for d in &graph.diagnostics[reported..] {
+            report(d, &graph);
+        }
+        reported = graph.diagnostics.len();
+        if graph.has_errors() {
+            return Err(CompilationError::SemanticErrors(graph.diagnostics));
+        }
+
+        // Name resolution, type checking and lowering to IR, module by module.
+        let program = modules::analyze(&graph);
+        for d in &program.diagnostics {
+            report(d, &graph);
+        }
+        let mut module = match program.module {
+            None => return Err(CompilationError::SemanticErrors(program.diagnostics)),
+            Some(module) => module,
+        };
+
+        // Compile-time evaluation: annotations and `comptime` expressions -> values
+        let comptime = comptime::evaluate(&mut module, &options);
+        for d in &comptime.diagnostics {
+            report(d, &graph);
+        }
+        if comptime.has_errors() {
+            return Err(CompilationError::SemanticErrors(comptime.diagnostics));
+        }
+
+        // `#%also_include` brings in more modules, which are analyzed with the others.
+        if !modules::include_annotated(&mut graph, &comptime) {
+            break (module, comptime);
+        }
+    };
+    for d in &graph.diagnostics[reported..] {
+        report(d, &graph);
+    }
+    if graph.has_errors() {
+        return Err(CompilationError::SemanticErrors(graph.diagnostics));
+    }
+
+    if emit == "ir" {
+        println!("{}", print_module(&module));
+    }
+    if emit == "comptime" {
+        for annotation in &comptime.annotations {
+            println!(
+                "{} {}",
+                format!("#%{}", annotation.name).cyan(),
+                annotation.value
+            );
+        }
+    }
+
+    return Ok((graph, module, comptime));
+}
+
+/// nnc parse <file> [--emit=ast|cst|tokens] [--spans]
+fn parse(args: &[String]) -> Result<bool, CompilationError> {
     let source_path = (args[2]).as_str();
     let source = match read_file(source_path, args) {
         Ok(value) => value,
         Err(e) => return Err(CompilationError::FileNotFound(e)),
     };
 
-    // Tokenization: &str -> Vec<Token>
     let tokens = tokenize(source.as_str());
 
-    // Parsing: Vec<Token> -> AST
-    let tree = build_tree(&source, &tokens, "Program", false);
-
-    let tree = match tree {
-        Err(e) => return Err(CompilationError::ParseError(e)),
-        Ok(t) => t,
+    let emit = flag_value(args, "emit").unwrap_or("ast");
+    let options = SExprOptions {
+        spans: flag_value(args, "spans").is_some(),
+        ghost_tokens: emit != "ast",
     };
 
-    println!("{:#?}", tree);
+    match emit {
+        "tokens" => println!("{}", print_tokens(&tokens, &options)),
+        "ast" | "cst" => {
+            let tree = build_tree(&source, &tokens, "Program", emit == "cst", None);
+            let tree = match tree {
+                Err(e) => {
+                    println!("{}", e.diagnostic().render(source_path, &source));
+                    return Err(CompilationError::ParseError(e));
+                }
+                Ok(t) => t,
+            };
+            println!("{}", print_ast(&tree, &options));
+        }
+        other => {
+            println!(
+                "Unknown emit kind '{}', expected one of `ast`, `cst` or `tokens`.",
+                other
+            );
+            return Ok(false);
+        }
+    }
 
     return Ok(true);
 }
 
+/// Looks for `--name` or `--name=value` among the arguments.
+/// Returns the value, or `""` if the flag was given without one.
+fn flag_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().find_map(|arg| {
+        let rest = arg.strip_prefix("--")?.strip_prefix(name)?;
+        match rest {
+            "" => Some(""),
+            _ => rest.strip_prefix('='),
+        }
+    })
+}
+
 /// nnc help
 fn print_help() {
     println!(
@@ -112,7 +757,7 @@ fn print_help() {
         "nnc".green().bold(),
         "<subcommand>".blue().bold()
     );
-    println!("");
+    println!();
     println!(
         "{} - Prints the compiler name and version.\n{} {}{}\n",
         "version".bold(),
@@ -125,12 +770,23 @@ fn print_help() {
         "help [subcommand]".bold(),
     );
     println!(
-        "{} - Begins compilation starting at <entry_file>.\n",
-        "compile <entry_file>".bold()
+        "{} - Begins compilation starting at <entry_file>.\n{}\n",
+        "compile <entry_file> [--emit=ast|ir|comptime]".bold(),
+        "Writes every output `#%compilation.output` lists, like `{ ir: 'dist/program.ir' }`, relative to the entry file's folder.\n`--emit` picks what to print: the syntax tree, the intermediate representation or the values of the `#%` annotations.\nWith `--trace-parse`, prints every grammar rule tried while parsing each file, as a tree or, with `--trace-parse=json`, as JSON.\nCompile-time code can't do I/O unless `--comptime-io` is given, and stops after `--comptime-fuel=<steps>` steps, a million by default.".dimmed()
     );
     println!(
-        "{} - Same as `compile`, but immediately runs the exported executable.\n",
-        "run <entry_file>".bold()
+        "{} - Parses <file> and prints its tree, or its tokens, in S-expression form.\n{}\n",
+        "parse <file> [--emit=ast|cst|tokens] [--spans]".bold(),
+        "The CST keeps every token, including whitespace and comments.".dimmed()
+    );
+    println!(
+        "{} - Same as `compile`, but immediately runs the exported executable.\n{}\n",
+        "run <entry_file>".bold(),
+        "Without a native executable, the program is run by nnc's own interpreter.\nA `.nb8` file, the `nbbc` target's bytecode, is run by nnc's virtual machine instead.".dimmed()
+    );
+    println!(
+        "{} - Prints the constants, globals and instructions of a bytecode file.\n",
+        "disasm <program.nb8>".bold()
     );
     println!(
         "{} - Creates a new environment to play with nano without creating files.\n",
@@ -143,13 +799,15 @@ fn print_help() {
     );
     println!("{} - Cleans unnecessary cache.\n", "clean".bold());
     println!(
-        "{} - Begins testing starting at <entry_file>.\n",
-        "test <entry_file>".bold()
+        "{} - Runs the `%%test fn`s of <entry_file> and reports each `%%t_eq` they check.\n{}\n",
+        "test <entry_file> [filters...] [--format=pretty|junit|tap|json]".bold(),
+        "Only the tests whose name contains one of the filters run, if there are filters. Exits with 1 if a test fails.\n`junit`, `tap` and `json` print a report for CI instead of tables, with what the tests print inside.".dimmed()
     );
     println!("{} - Locates linting configuration in the workspace, then provides ERRORs and WARNINGs for a file.\n", "lint <file>".bold());
     println!(
-        "{} - Locates a style configuration and formats the file accordingly.\n",
-        "fmt <file>".bold()
+        "{} - Locates a style configuration and formats the file accordingly.\n{}\n",
+        "fmt <file> [files...] [--check] [--show-style]".bold(),
+        "The nearest `nano.style` files are read, from the file's folder up. Comments and blank lines are kept. With `--check`, files are only checked, and nnc exits with 1 if one would change. `--show-style` prints the settings in effect and where each comes from.".dimmed()
     );
     println!(
         "{} - Starts the language server in a given port.\n",
@@ -160,7 +818,7 @@ fn print_help() {
 
 /// Reads a file as text, errors if an error is encountered,
 /// and returns its string if all was successful.
-fn read_file(source_path: &str, args: &Vec<String>) -> Result<String, std::io::Error> {
+fn read_file(source_path: &str, args: &[String]) -> Result<String, std::io::Error> {
     let source = import_as_text(source_path);
     let source = match source {
         Err(e) => {
@@ -178,3 +836,69 @@ fn read_file(source_path: &str, args: &Vec<String>) -> Result<String, std::io::E
     };
     Ok(source)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A folder of its own for `name`, with a root style file so no style above it applies.
+    fn folder(name: &str) -> PathBuf {
+        let folder = env::temp_dir().join(format!("nnc-fmt-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(folder.join(style::FILE_NAME), "#%root\n").unwrap();
+        return folder;
+    }
+
+    /// Runs `nnc fmt` on `files`, with `flags`, giving whether nnc would exit with 0.
+    fn run_fmt(files: &[&PathBuf], flags: &[&str]) -> bool {
+        let mut args = vec!["nnc".to_string(), "fmt".to_string()];
+        args.extend(files.iter().map(|f| f.to_string_lossy().to_string()));
+        args.extend(flags.iter().map(|f| f.to_string()));
+        return matches!(fmt(&args), Ok(true));
+    }
+
+    const MESSY: &str = "let x  = [1,2,3]\n\n\nprint( x ) # shown\n";
+    const TIDY: &str = "let x = [1, 2, 3]\n\nprint(x) # shown\n";
+
+    #[test]
+    fn check_fails_on_files_that_would_change_and_leaves_them() {
+        let folder = folder("check");
+        let (messy, tidy) = (folder.join("messy.nano"), folder.join("tidy.nano"));
+        std::fs::write(&messy, MESSY).unwrap();
+        std::fs::write(&tidy, TIDY).unwrap();
+
+        assert!(run_fmt(&[&tidy], &["--check"]));
+        assert!(!run_fmt(&[&messy], &["--check"]));
+        assert!(!run_fmt(&[&tidy, &messy], &["--check"]));
+        assert_eq!(std::fs::read_to_string(&messy).unwrap(), MESSY);
+        assert_eq!(std::fs::read_to_string(&tidy).unwrap(), TIDY);
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn formatted_files_pass_the_check() {
+        let folder = folder("write");
+        let messy = folder.join("messy.nano");
+        std::fs::write(&messy, MESSY).unwrap();
+
+        assert!(run_fmt(&[&messy], &[]));
+        assert_eq!(std::fs::read_to_string(&messy).unwrap(), TIDY);
+        assert!(run_fmt(&[&messy], &["--check"]));
+        // Formatting it again changes nothing.
+        assert!(run_fmt(&[&messy], &[]));
+        assert_eq!(std::fs::read_to_string(&messy).unwrap(), TIDY);
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn check_fails_on_files_that_dont_parse() {
+        let folder = folder("broken");
+        let broken = folder.join("broken.nano");
+        std::fs::write(&broken, "let x = (1\n").unwrap();
+
+        assert!(!run_fmt(&[&broken], &["--check"]));
+        assert_eq!(std::fs::read_to_string(&broken).unwrap(), "let x = (1\n");
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,572 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::{
+    comptime::Comptime,
+    diagnostic::{Diagnostic, Sources},
+    file_importer::{normalize, FileId, SourceMap},
+    grammar::Span,
+    interp::Value,
+    ir::{IRNode, Module},
+    lower::lower_with_imports,
+    parser::{build_tree, tokenize, ParseError},
+    resolve::resolve,
+    trace::ParseTrace,
+    tree::{NodeId, Tree},
+    types::{check_with_imports, Exports},
+};
+
+// The module loader: `import 'path' as alias` across files.
+//
+// Import paths are relative to the importing file; paths starting with `/`
+// are relative to the entry file's folder, the project's root. Every file
+// is read (through the graph's `SourceMap`) and parsed once, however many
+// modules import it, and import cycles are reported instead of followed.
+// Files matching the globs of `#%also_include` are loaded as modules too,
+// without an alias, once the entry's annotations are known.
+//
+// Modules are then checked and lowered dependencies first, so that each one
+// knows the types and symbols of what it imports, and linked into a single
+// IR module whose top-level code runs every module's, in that same order.
+// An alias is a record of its module's exports, its top-level `fn`s and
+// `let`s; `alias.name` reads one.
+
+pub type ModuleId = usize;
+
+pub struct SourceModule {
+    pub file: FileId,
+    // The path relative to the root, without extension, which the module's
+    // symbols start with. Empty for the entry module.
+    pub name: String,
+    pub tree: Tree,
+    // The module each `Import` node loaded.
+    pub imports: HashMap<NodeId, ModuleId>,
+}
+
+pub struct ModuleGraph {
+    pub sources: SourceMap,
+    // The entry file's folder, where `/` paths start.
+    pub root: PathBuf,
+    // The entry module comes first.
+    pub modules: Vec<SourceModule>,
+    // Dependencies before their importers, so the entry module is last.
+    pub order: Vec<ModuleId>,
+    pub diagnostics: Vec<Diagnostic>,
+    // The modules being visited, importer first.
+    stack: Vec<ModuleId>,
+    // The `#%also_include` globs already expanded.
+    included: Vec<String>,
+}
+
+impl ModuleGraph {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(Diagnostic::is_error)
+    }
+
+    pub fn entry(&self) -> &SourceModule {
+        &self.modules[0]
+    }
+
+    pub fn path(&self, id: ModuleId) -> &Path {
+        &self.sources.file(self.modules[id].file).path
+    }
+
+    pub fn source(&self, id: ModuleId) -> &str {
+        &self.sources.file(self.modules[id].file).text
+    }
+
+    fn module_of_file(&self, file: FileId) -> Option<ModuleId> {
+        self.modules.iter().position(|m| m.file == file)
+    }
+}
+
+impl Sources for ModuleGraph {
+    fn locate(&self, span: Span) -> Option<(&str, &str, Span)> {
+        self.sources.locate(span)
+    }
+
+    fn position(&self, offset: usize) -> Option<(&str, usize, usize)> {
+        self.sources.position(offset)
+    }
+}
+
+pub enum LoadError {
+    NotFound(std::io::Error),
+    Parse(ParseError),
+}
+
+/// Loads the module at `path`, and every module it imports, reading them through `sources`.
+/// Their parses are traced into `trace`, if given.
+pub fn load(
+    sources: SourceMap,
+    path: &str,
+    trace: Option<&ParseTrace>,
+) -> Result<ModuleGraph, LoadError> {
+    let path = PathBuf::from(path);
+    let mut graph = ModuleGraph {
+        sources,
+        root: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        modules: Vec::new(),
+        order: Vec::new(),
+        diagnostics: Vec::new(),
+        stack: Vec::new(),
+        included: Vec::new(),
+    };
+
+    let file = graph.sources.load(&path).map_err(LoadError::NotFound)?;
+    let source = graph.sources.file(file);
+    let tree = parse(&source.path, &source.text, trace).map_err(LoadError::Parse)?;
+    let entry = graph.add(file, tree);
+    graph.visit(entry, trace);
+    return Ok(graph);
+}
+
+/// Loads the files matching the entry's `#%also_include` globs, and what they import,
+/// to be run before the entry module. Returns whether there were any new ones.
+pub fn include_annotated(graph: &mut ModuleGraph, comptime: &Comptime) -> bool {
+    let before = graph.modules.len();
+    for annotation in comptime.annotations.iter() {
+        if annotation.name != "also_include" {
+            continue;
+        }
+        let patterns = match &annotation.value {
+            Value::List(items) => items.iter().map(|i| match i {
+                Value::String(s) => Some(s.to_string()),
+                _ => None,
+            }),
+            _ => {
+                let message = "`#%also_include` takes a list of paths, like `[\"/lib/*\"]`.";
+                graph
+                    .diagnostics
+                    .push(Diagnostic::error(message, annotation.span));
+                continue;
+            }
+        };
+        for pattern in patterns.collect::<Vec<_>>() {
+            match pattern {
+                Some(pattern) => graph.include(&pattern, annotation.span),
+                None => {
+                    let message = "`#%also_include` paths must be strings.";
+                    graph
+                        .diagnostics
+                        .push(Diagnostic::error(message, annotation.span));
+                }
+            }
+        }
+    }
+    return graph.modules.len() > before;
+}
+
+/// Parses the file at `path`, tracing it into `trace`, if given.
+fn parse(path: &Path, source: &str, trace: Option<&ParseTrace>) -> Result<Tree, ParseError> {
+    let tokens = tokenize(source);
+    if let Some(trace) = trace {
+        trace.begin_file(&path.display().to_string(), &tokens);
+    }
+    let ast = build_tree(source, &tokens, "Program", false, trace)?;
+    return Ok(Tree::from_ast(&ast));
+}
+
+impl ModuleGraph {
+    fn add(&mut self, file: FileId, tree: Tree) -> ModuleId {
+        let id = self.modules.len();
+        let name = match id {
+            0 => String::new(),
+            _ => self.name_of(&self.sources.file(file).path),
+        };
+        self.modules.push(SourceModule {
+            file,
+            name,
+            tree,
+            imports: HashMap::new(),
+        });
+        return id;
+    }
+
+    /// `root/lib/math.nano` -> `lib/math`, unique among the modules.
+    fn name_of(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let relative = normalize(&relative.with_extension(""));
+        let parts: Vec<String> = relative
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+                _ => None,
+            })
+            .collect();
+        let name = parts.join("/");
+
+        let mut candidate = name.clone();
+        let mut n = 0;
+        while self.modules.iter().any(|m| m.name == candidate) {
+            n += 1;
+            candidate = format!("{}#{}", name, n);
+        }
+        return candidate;
+    }
+
+    /// Where `import 'path'` in the module at `importer` points to.
+    fn resolve_path(&self, importer: &Path, path: &str) -> PathBuf {
+        let joined = match path.strip_prefix('/') {
+            Some(from_root) => self.root.join(from_root),
+            None => importer.parent().unwrap_or(Path::new("")).join(path),
+        };
+        return normalize(&joined);
+    }
+
+    fn display(&self, id: ModuleId) -> String {
+        self.path(id).display().to_string()
+    }
+
+    /// The module for the file at `path`, loading and visiting it if it's new.
+    /// Errors are reported at `span`.
+    fn module_at(
+        &mut self,
+        path: &Path,
+        span: Option<Span>,
+        trace: Option<&ParseTrace>,
+    ) -> Option<ModuleId> {
+        if let Some(module) = self.sources.find(path).and_then(|f| self.module_of_file(f)) {
+            return Some(module);
+        }
+
+        let file = match self.sources.load(path) {
+            Ok(file) => file,
+            Err(e) => {
+                let message = format!("Can't import '{}': {}.", path.display(), e);
+                self.diagnostics.push(Diagnostic::error(message, span));
+                return None;
+            }
+        };
+        let tree = match parse(path, &self.sources.file(file).text, trace) {
+            Ok(tree) => tree,
+            Err(e) => {
+                let base = self.sources.file(file).base;
+                let note = format!("'{}' is imported here.", path.display());
+                let diagnostic = shift_diagnostic(&e.diagnostic(), base).with_note(span, note);
+                self.diagnostics.push(diagnostic);
+                return None;
+            }
+        };
+        let module = self.add(file, tree);
+        self.visit(module, trace);
+        return Some(module);
+    }
+
+    /// Loads everything module `id` imports, depth first, tracing their parses into `trace`.
+    fn visit(&mut self, id: ModuleId, trace: Option<&ParseTrace>) {
+        self.stack.push(id);
+
+        let file = self.sources.file(self.modules[id].file);
+        let tree = &self.modules[id].tree;
+        let imports: Vec<(NodeId, String, Option<Span>)> = tree
+            .descendants(tree.root())
+            .into_iter()
+            .filter(|n| tree.is(*n, "Import"))
+            .filter_map(|n| {
+                let path = tree.get(n, "path")?;
+                let text = tree.text(path)?;
+                let span = tree.span(path).map(|s| file.global(s));
+                Some((n, text[1..text.len() - 1].to_string(), span))
+            })
+            .collect();
+
+        for (node, path, span) in imports {
+            let path = self.resolve_path(self.path(id), &path);
+            let loaded = self
+                .sources
+                .find(&path)
+                .and_then(|f| self.module_of_file(f));
+            if let Some(at) = loaded.and_then(|m| self.stack.iter().position(|s| *s == m)) {
+                let diagnostic = self.cycle(&self.stack[at..], span);
+                self.diagnostics.push(diagnostic);
+                continue;
+            }
+            if let Some(dependency) = self.module_at(&path, span, trace) {
+                self.modules[id].imports.insert(node, dependency);
+            }
+        }
+
+        self.stack.pop();
+        self.order.push(id);
+    }
+
+    /// Loads the files under the root matching `pattern`, before the entry module.
+    fn include(&mut self, pattern: &str, span: Option<Span>) {
+        if self.included.iter().any(|p| p == pattern) {
+            return;
+        }
+        self.included.push(pattern.to_string());
+
+        let paths = self.sources.expand_glob(&self.root, pattern);
+        if paths.is_empty() {
+            let message = format!("'{}' doesn't match any file.", pattern);
+            self.diagnostics.push(Diagnostic::warning(message, span));
+            return;
+        }
+
+        // The entry module was visited last, and stays last.
+        let entry = self.order.pop();
+        for path in paths {
+            self.module_at(&path, span, None);
+        }
+        self.order.extend(entry);
+    }
+
+    /// The error for `cycle`, whose last module imports its first one again, at `span`.
+    fn cycle(&self, cycle: &[ModuleId], span: Option<Span>) -> Diagnostic {
+        let mut message = format!("Import cycle: '{}'", self.display(cycle[0]));
+        for (i, m) in cycle.iter().skip(1).chain(&cycle[..1]).enumerate() {
+            let verb = match i {
+                0 => " imports",
+                _ => ", which imports",
+            };
+            message.push_str(&format!("{} '{}'", verb, self.display(*m)));
+        }
+        message.push('.');
+
+        return Diagnostic::error(message, span)
+            .with_note(None, "Modules can't import each other, directly or not.");
+    }
+}
+
+/// What checking and lowering a whole module graph gives.
+pub struct Program {
+    // Every module linked into one, unless there were errors.
+    pub module: Option<Module>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Checks and lowers every module of `graph`, dependencies first, and links them.
+/// Stops at the first module with errors.
+pub fn analyze(graph: &ModuleGraph) -> Program {
+    let mut exports: HashMap<ModuleId, Exports> = HashMap::new();
+    let mut lowered: HashMap<ModuleId, Vec<(String, IRNode)>> = HashMap::new();
+    let mut diagnostics = Vec::new();
+    let mut linked = Module::default();
+
+    for id in &graph.order {
+        let module = &graph.modules[*id];
+        let base = graph.sources.file(module.file).base;
+        let shift = |d: &Diagnostic| shift_diagnostic(d, base);
+
+        let resolution = resolve(&module.tree);
+        diagnostics.extend(resolution.diagnostics.iter().map(shift));
+        if resolution.has_errors() {
+            return Program {
+                module: None,
+                diagnostics,
+            };
+        }
+
+        let import_types = module
+            .imports
+            .iter()
+            .filter_map(|(node, m)| Some((*node, exports.get(m)?.clone())))
+            .collect();
+        let types = check_with_imports(&module.tree, &resolution, &import_types);
+        diagnostics.extend(types.diagnostics.iter().map(shift));
+        if types.has_errors() {
+            return Program {
+                module: None,
+                diagnostics,
+            };
+        }
+
+        let import_nodes = module
+            .imports
+            .iter()
+            .filter_map(|(node, m)| Some((*node, lowered.get(m)?.clone())))
+            .collect();
+        let mut ir = lower_with_imports(
+            &module.tree,
+            &resolution,
+            &types,
+            &module.name,
+            &import_nodes,
+        );
+        shift_module(&mut ir, base);
+
+        exports.insert(*id, types.exports(&module.tree, &resolution));
+        lowered.insert(*id, ir.exports.clone());
+
+        linked.functions.extend(ir.functions);
+        linked.globals.extend(ir.globals);
+        linked.annotations.extend(ir.annotations);
+        linked.body.extend(ir.body);
+        linked.exports = ir.exports;
+        linked.tests = ir.tests;
+    }
+
+    return Program {
+        module: Some(linked),
+        diagnostics,
+    };
+}
+
+fn shift_span(span: Option<Span>, base: usize) -> Option<Span> {
+    span.map(|s| Span::new(s.start + base, s.end + base))
+}
+
+fn shift_diagnostic(d: &Diagnostic, base: usize) -> Diagnostic {
+    let mut shifted = d.clone();
+    shifted.span = shift_span(d.span, base);
+    for (span, _) in &mut shifted.notes {
+        *span = shift_span(*span, base);
+    }
+    return shifted;
+}
+
+fn shift_node(node: &mut IRNode, base: usize) {
+    node.span = shift_span(node.span, base);
+    for child in node.children_mut() {
+        shift_node(child, base);
+    }
+}
+
+/// Moves every span of a module's IR `base` bytes further.
+fn shift_module(module: &mut Module, base: usize) {
+    if base == 0 {
+        return;
+    }
+    for function in &mut module.functions {
+        function.span = shift_span(function.span, base);
+        shift_node(&mut function.body, base);
+    }
+    for annotation in &mut module.annotations {
+        annotation.span = shift_span(annotation.span, base);
+        shift_node(&mut annotation.value, base);
+        for (_, span) in &mut annotation.fields {
+            *span = Span::new(span.start + base, span.end + base);
+        }
+    }
+    for node in &mut module.body {
+        shift_node(node, base);
+    }
+    for test in &mut module.tests {
+        test.span = shift_span(test.span, base);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build::interpreted, file_importer::MemoryFileSystem};
+
+    /// The graph of `app/main.nano` among `files`, read from memory.
+    fn graph(files: &[(&str, &str)]) -> ModuleGraph {
+        let mut memory = MemoryFileSystem::new();
+        for (path, text) in files {
+            memory.insert(path, *text);
+        }
+        return match load(SourceMap::with_fs(Box::new(memory)), "app/main.nano", None) {
+            Ok(graph) => graph,
+            Err(_) => panic!("the entry file doesn't load"),
+        };
+    }
+
+    /// The messages of the diagnostics loading and analyzing `files` gave.
+    fn messages(files: &[(&str, &str)]) -> Vec<String> {
+        let graph = graph(files);
+        let mut diagnostics = graph.diagnostics.clone();
+        if !graph.has_errors() {
+            diagnostics.extend(analyze(&graph).diagnostics);
+        }
+        return diagnostics.into_iter().map(|d| d.message).collect();
+    }
+
+    /// What `files` print once linked.
+    fn printed(files: &[(&str, &str)]) -> String {
+        let graph = graph(files);
+        assert!(!graph.has_errors(), "{:?}", graph.diagnostics);
+        let program = analyze(&graph);
+        return interpreted(&program.module.expect("the program doesn't compile"));
+    }
+
+    #[test]
+    fn imports_are_relative_to_the_importing_file() {
+        let files = [
+            (
+                "app/main.nano",
+                "import './lib/math.nano' as math\nprint(math.twice(2))",
+            ),
+            (
+                "app/lib/math.nano",
+                "import '../util.nano' as util\nfn twice(x) -> util.add(x, x)",
+            ),
+            (
+                "app/util.nano",
+                "import '/lib/../base.nano' as base\nfn add(a, b) -> a + b + base.zero",
+            ),
+            ("app/base.nano", "let zero = 0"),
+        ];
+        let graph = graph(&files);
+        let names: Vec<&str> = graph.modules.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["", "lib/math", "util", "base"]);
+        let order: Vec<&str> = graph.order.iter().map(|m| names[*m]).collect();
+        assert_eq!(order, ["base", "util", "lib/math", ""]);
+        assert_eq!(printed(&files), "4\n");
+    }
+
+    #[test]
+    fn modules_imported_twice_are_loaded_once() {
+        let files = [
+            (
+                "app/main.nano",
+                "import './a.nano' as a\nimport './b.nano' as b\nprint(a.x + b.y)",
+            ),
+            ("app/a.nano", "import './c.nano' as c\nlet x = c.z"),
+            ("app/b.nano", "import './c.nano' as c\nlet y = c.z"),
+            ("app/c.nano", "print('c runs')\nlet z = 1"),
+        ];
+        assert_eq!(graph(&files).modules.len(), 4);
+        assert_eq!(printed(&files), "c runs\n2\n");
+    }
+
+    #[test]
+    fn import_cycles_are_reported_where_they_close() {
+        let files = [
+            ("app/main.nano", "import './b.nano' as b\nprint(1)"),
+            ("app/b.nano", "import './main.nano' as m\nlet x = 1"),
+        ];
+        let graph = graph(&files);
+        let cycle = &graph.diagnostics[0];
+        assert_eq!(
+            cycle.message,
+            "Import cycle: 'app/main.nano' imports 'app/b.nano', which imports 'app/main.nano'."
+        );
+        let at = graph.position(cycle.span.unwrap().start);
+        assert_eq!(at, Some(("app/b.nano", 1, 8)));
+    }
+
+    #[test]
+    fn missing_imports_are_reported() {
+        let files = [("app/main.nano", "import './nope.nano' as nope\nprint(1)")];
+        assert_eq!(
+            messages(&files),
+            ["Can't import 'app/nope.nano': No such file in memory."]
+        );
+    }
+
+    #[test]
+    fn aliases_read_their_modules_exports() {
+        let math = "let base = 10\nfn square(x) -> x * x";
+        let files = [
+            (
+                "app/main.nano",
+                "import './math.nano' as math\nprint(math.square(3) + math.base)",
+            ),
+            ("app/math.nano", math),
+        ];
+        assert_eq!(printed(&files), "19\n");
+
+        let files = [
+            (
+                "app/main.nano",
+                "import './math.nano' as math\nprint(math.cube(3))",
+            ),
+            ("app/math.nano", math),
+        ];
+        assert_eq!(messages(&files), ["Module 'math' has no export 'cube'."]);
+    }
+}
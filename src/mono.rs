@@ -0,0 +1,190 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    ir::{type_symbol, Function, IRKind, IRNode, Module, INTRINSIC_PREFIX},
+    types::{substitute, Type, TypeVar},
+};
+
+// Monomorphization, for backends that need to know the type of every value.
+//
+// A polymorphic function gets a copy for each set of types it's used at,
+// found by matching its parameter and return types against the argument
+// and result types of each call and `FnRef`. Copies are named after their
+// types, `id<__nano__::int>`, while functions without type variables keep
+// their symbol. Only what the top-level code reaches is kept, and type
+// variables nothing decides, like the item type of an unused `[]`, become
+// unit.
+
+/// `module`, with every type in it known.
+pub fn monomorphize(module: &Module) -> Module {
+    let mut mono = Mono {
+        functions: module
+            .functions
+            .iter()
+            .map(|f| (f.symbol.as_str(), f))
+            .collect(),
+        done: HashSet::new(),
+        out: Vec::new(),
+    };
+
+    let none = HashMap::new();
+    let body = module
+        .body
+        .iter()
+        .map(|node| mono.rewrite(node, &none))
+        .collect();
+    let globals = module
+        .globals
+        .iter()
+        .map(|(symbol, ty)| (symbol.clone(), concrete(ty, &none)))
+        .collect();
+
+    return Module {
+        functions: mono.out,
+        globals,
+        annotations: module.annotations.clone(),
+        exports: module.exports.clone(),
+        body,
+        tests: module.tests.clone(),
+    };
+}
+
+/// The fields of a record of type `ty` in the order backends lay them out, sorted by name,
+/// or `fallback` sorted when the type isn't a record.
+pub fn field_names(ty: &Type, fallback: impl Iterator<Item = String>) -> Vec<String> {
+    match ty {
+        Type::Record(fields) => fields.keys().cloned().collect(),
+        _ => {
+            let mut names: Vec<String> = fallback.collect();
+            names.sort();
+            names
+        }
+    }
+}
+
+struct Mono<'a> {
+    functions: HashMap<&'a str, &'a Function>,
+    // The symbols of the copies made so far, or being made.
+    done: HashSet<String>,
+    out: Vec<Function>,
+}
+
+impl Mono<'_> {
+    /// The symbol of the copy of `symbol` taking `params` (captures first) and returning `ret`.
+    fn specialize(&mut self, symbol: &str, params: &[Type], ret: &Type) -> String {
+        let function = match self.functions.get(symbol) {
+            None => return symbol.to_string(),
+            Some(f) => *f,
+        };
+
+        let declared: Vec<&Type> = function
+            .captures
+            .iter()
+            .chain(function.params.iter())
+            .map(|(_, t)| t)
+            .collect();
+        let mut subst = HashMap::new();
+        for (pattern, ty) in declared.iter().zip(params) {
+            bind(pattern, ty, &mut subst);
+        }
+        bind(&function.ret, ret, &mut subst);
+
+        let mut vars = Vec::new();
+        for ty in declared.iter().copied().chain([&function.ret]) {
+            for v in ty.vars() {
+                if !vars.contains(&v) {
+                    vars.push(v);
+                }
+            }
+        }
+        let name = match vars.is_empty() {
+            true => symbol.to_string(),
+            false => {
+                let types: Vec<String> = vars
+                    .iter()
+                    .map(|v| type_symbol(&concrete(&Type::Var(*v), &subst)))
+                    .collect();
+                format!("{}<{}>", symbol, types.join(", "))
+            }
+        };
+
+        // Marked before the body is rewritten, which may call itself.
+        if !self.done.insert(name.clone()) {
+            return name;
+        }
+        let typed = |list: &[(String, Type)]| -> Vec<(String, Type)> {
+            list.iter()
+                .map(|(n, t)| (n.clone(), concrete(t, &subst)))
+                .collect()
+        };
+        let copy = Function {
+            symbol: name.clone(),
+            captures: typed(&function.captures),
+            params: typed(&function.params),
+            ret: concrete(&function.ret, &subst),
+            body: self.rewrite(&function.body, &subst),
+            span: function.span,
+        };
+        self.out.push(copy);
+        return name;
+    }
+
+    /// `node` with `subst` applied to its types, calling the copies of functions its types ask for.
+    fn rewrite(&mut self, node: &IRNode, subst: &HashMap<TypeVar, Type>) -> IRNode {
+        let mut out = node.clone();
+        out.ty = concrete(&node.ty, subst);
+        for child in out.children_mut() {
+            *child = self.rewrite(child, subst);
+        }
+
+        match &mut out.kind {
+            IRKind::SymbolCall { symbol, args } if !symbol.starts_with(INTRINSIC_PREFIX) => {
+                let types: Vec<Type> = args.iter().map(|a| a.ty.clone()).collect();
+                *symbol = self.specialize(symbol, &types, &out.ty);
+            }
+            IRKind::FnRef { symbol, captures } if !symbol.starts_with(INTRINSIC_PREFIX) => {
+                if let Type::Fn(params, ret) = &out.ty {
+                    let types: Vec<Type> = captures
+                        .iter()
+                        .map(|c| c.ty.clone())
+                        .chain(params.iter().cloned())
+                        .collect();
+                    *symbol = self.specialize(symbol, &types, ret);
+                }
+            }
+            _ => {}
+        }
+        return out;
+    }
+}
+
+/// Fills `subst` with what the variables of `pattern` are in `ty`.
+pub(crate) fn bind(pattern: &Type, ty: &Type, subst: &mut HashMap<TypeVar, Type>) {
+    match (pattern, ty) {
+        (Type::Var(v), ty) => {
+            subst.entry(*v).or_insert_with(|| ty.clone());
+        }
+        (Type::List(a), Type::List(b)) => bind(a, b, subst),
+        (Type::Record(a), Type::Record(b)) => {
+            for (key, field) in a {
+                if let Some(other) = b.get(key) {
+                    bind(field, other, subst);
+                }
+            }
+        }
+        (Type::Fn(a, ret_a), Type::Fn(b, ret_b)) => {
+            for (a, b) in a.iter().zip(b) {
+                bind(a, b, subst);
+            }
+            bind(ret_a, ret_b, subst);
+        }
+        _ => {}
+    }
+}
+
+/// `ty` with `subst` applied, and unit for the variables left.
+fn concrete(ty: &Type, subst: &HashMap<TypeVar, Type>) -> Type {
+    let ty = substitute(ty, subst);
+    let rest: HashMap<TypeVar, Type> = ty.vars().into_iter().map(|v| (v, Type::Unit)).collect();
+    return substitute(&ty, &rest);
+}
@@ -1,8 +1,10 @@
-use crate::grammar::{ParseRule, TokenMatcher, TokenName};
+use crate::grammar::{
+    ASTNode, ASTNodeContent, ParseRule, ParseRuleList, ReduceActionList, TokenMatcher, TokenName,
+};
 use lazy_regex::regex as rx;
 
 // The rules used to create the AST building blocks
-pub static NANO_TOKEN_RULES: &'static [TokenMatcher] = &[
+pub static NANO_TOKEN_RULES: &[TokenMatcher] = &[
     // Whitespace
     TokenMatcher {
         name: TokenName::Newline,
@@ -10,7 +12,7 @@ pub static NANO_TOKEN_RULES: &'static [TokenMatcher] = &[
     },
     TokenMatcher {
         name: TokenName::Whitespace,
-        regex: rx!(r"^[ \s\r\f\t]+"),
+        regex: rx!(r"^[ \r\f\t]+"),
     },
     // AST Operators
     TokenMatcher {
@@ -25,6 +27,18 @@ pub static NANO_TOKEN_RULES: &'static [TokenMatcher] = &[
         name: TokenName::Colon,
         regex: rx!(r"^:"),
     },
+    TokenMatcher {
+        name: TokenName::Reticences,
+        regex: rx!(r"^\.\.\."),
+    },
+    TokenMatcher {
+        name: TokenName::ExclusiveReticences,
+        regex: rx!(r"^\.\."),
+    },
+    TokenMatcher {
+        name: TokenName::Dot,
+        regex: rx!(r"^\."),
+    },
     TokenMatcher {
         name: TokenName::ThinArrow,
         regex: rx!(r"^->"),
@@ -58,63 +72,591 @@ pub static NANO_TOKEN_RULES: &'static [TokenMatcher] = &[
         regex: rx!(r"^\}"),
     },
     // Literals
+    TokenMatcher {
+        name: TokenName::FloatLiteral,
+        regex: rx!(r"^[0-9][0-9_]*\.[0-9][0-9_]*"),
+    },
     TokenMatcher {
         name: TokenName::IntLiteral,
-        regex: rx!(r"^(0x[0-9a-zA-Z_]+|0b[0-9]+|[0-9_]+)"),
+        regex: rx!(r"^(0x[0-9a-zA-Z_]+|0b[0-9]+|[0-9][0-9_]*)"),
     },
     TokenMatcher {
         name: TokenName::StringLiteral,
-        regex: rx!(r#"^".*?""#),
+        regex: rx!(r#"^("[^"\n]*"|'[^'\n]*')"#),
+    },
+    TokenMatcher {
+        name: TokenName::BooleanLiteral,
+        regex: rx!(r"^(true|false|yes|no)\b"),
+    },
+    // Word operators
+    TokenMatcher {
+        name: TokenName::OpIs,
+        regex: rx!(r"^is\b"),
+    },
+    TokenMatcher {
+        name: TokenName::OpXis,
+        regex: rx!(r"^xis\b"),
     },
     // Identifier / Keyword
     TokenMatcher {
         name: TokenName::Identifier,
         regex: rx!(r"^[a-zA-Z_][a-zA-Z0-9_]*"),
     },
+    // Annotations, before comments since they start with `#` too
+    TokenMatcher {
+        name: TokenName::BranchAnnotation,
+        regex: rx!(r"^#%[a-zA-Z_][a-zA-Z0-9_]*(\.[a-zA-Z_][a-zA-Z0-9_]*)*"),
+    },
+    // Scope annotations, before the `%` operator
+    TokenMatcher {
+        name: TokenName::ScopeAnnotation,
+        regex: rx!(r"^%%[a-zA-Z_][a-zA-Z0-9_]*"),
+    },
     // Comments
     TokenMatcher {
-        name: TokenName::Comment,
+        name: TokenName::BlockComment,
         regex: rx!(r"^###[\s\S]*?###"),
     },
     TokenMatcher {
         name: TokenName::Comment,
-        regex: rx!(r"^#[\s\S]*?\n"),
+        regex: rx!(r"^#[^\n]*"),
     },
     // Operators
     TokenMatcher {
-        name: TokenName::OpAnd,
-        regex: rx!(r"^#.*?\n"),
+        name: TokenName::OpPlus,
+        regex: rx!(r"^\+"),
+    },
+    TokenMatcher {
+        name: TokenName::OpDash,
+        regex: rx!(r"^-"),
+    },
+    TokenMatcher {
+        name: TokenName::OpAsterisk,
+        regex: rx!(r"^\*"),
+    },
+    TokenMatcher {
+        name: TokenName::OpForwardSlash,
+        regex: rx!(r"^/"),
+    },
+    TokenMatcher {
+        name: TokenName::OpPercent,
+        regex: rx!(r"^%"),
+    },
+    TokenMatcher {
+        name: TokenName::OpEqSign,
+        regex: rx!(r"^="),
     },
 ];
 
 pub fn is_ghost_token(tname: &TokenName) -> bool {
-    match tname {
+    matches!(
+        tname,
         TokenName::Whitespace
-        | TokenName::Indent
-        | TokenName::Comment
-        | TokenName::BlockComment
-        | TokenName::Newline => true,
-        _ => return false,
-    }
+            | TokenName::Indent
+            | TokenName::Comment
+            | TokenName::BlockComment
+            | TokenName::Newline
+    )
 }
 
 // The rules used to create the AST
-pub static NANO_PARSE_RULES: &'static [(&'static str, &[ParseRule])] = &[
-    ("Program", &[ParseRule::Nest("Exprs")]),
+pub static NANO_PARSE_RULES: &ParseRuleList = &[
+    (
+        "Program",
+        &[
+            ParseRule::Nest("Exprs"),
+            // Nothing but ghost tokens may be left over.
+            ParseRule::NotLookahead(&[ParseRule::AnyToken]),
+        ],
+    ),
     (
         "Exprs",
+        &[ParseRule::Flatten(&[ParseRule::SeparatedBy {
+            item: &[ParseRule::Nest("Expr")],
+            sep: &[ParseRule::TokenSet(&[
+                TokenName::Semicolon,
+                TokenName::Newline,
+            ])],
+            allow_trailing: true,
+            min: 1,
+        }])],
+    ),
+    (
+        "Expr",
+        &[ParseRule::Flatten(&[ParseRule::Disjunction(&[
+            &[ParseRule::Nest("Annotation")],
+            &[ParseRule::Nest("ScopeAnnotation")],
+            &[ParseRule::Nest("Comptime")],
+            &[ParseRule::Nest("FnDecl")],
+            &[ParseRule::Nest("Let")],
+            &[ParseRule::Nest("Import")],
+            &[ParseRule::Nest("For")],
+            &[ParseRule::Nest("Select")],
+            &[ParseRule::Nest("Lambda")],
+            &[ParseRule::Nest("Pipeline")],
+        ])])],
+    ),
+    // #%name value
+    // #%name
+    (
+        "Annotation",
+        &[
+            ParseRule::Label(
+                "name",
+                &[ParseRule::SingleToken(TokenName::BranchAnnotation, None)],
+            ),
+            // The value, if any, starts on the same line.
+            ParseRule::Flatten(&[ParseRule::Optional(&[
+                ParseRule::NotLookahead(&[ParseRule::SingleToken(TokenName::Newline, None)]),
+                ParseRule::Label("value", &[ParseRule::Nest("Expr")]),
+            ])]),
+        ],
+    ),
+    // %%name value, like `%%test fn name -> body`
+    (
+        "ScopeAnnotation",
+        &[
+            ParseRule::Label(
+                "name",
+                &[ParseRule::SingleToken(TokenName::ScopeAnnotation, None)],
+            ),
+            // `%%name(...)` is an assertion.
+            ParseRule::NotLookahead(&[ParseRule::SingleToken(TokenName::ParenthesisOpen, None)]),
+            ParseRule::Label("value", &[ParseRule::Nest("Expr")]),
+        ],
+    ),
+    // comptime value
+    (
+        "Comptime",
+        &[
+            ParseRule::Skip(&[ParseRule::SingleToken(
+                TokenName::Identifier,
+                Some("comptime"),
+            )]),
+            ParseRule::Label("value", &[ParseRule::Nest("Expr")]),
+        ],
+    ),
+    // fn name -> body
+    // fn name(param, param, ...) -> body
+    (
+        "FnDecl",
+        &[
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::Identifier, Some("fn"))]),
+            ParseRule::Label("name", &[ParseRule::Nest("Name")]),
+            ParseRule::Flatten(&[ParseRule::Optional(&[
+                ParseRule::Skip(&[ParseRule::SingleToken(TokenName::ParenthesisOpen, None)]),
+                ParseRule::Label(
+                    "params",
+                    &[ParseRule::SeparatedBy {
+                        item: &[ParseRule::Nest("Name")],
+                        sep: &[ParseRule::SingleToken(TokenName::Comma, None)],
+                        allow_trailing: true,
+                        min: 0,
+                    }],
+                ),
+                ParseRule::Skip(&[ParseRule::SingleToken(TokenName::ParenthesisClose, None)]),
+            ])]),
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::ThinArrow, None)]),
+            ParseRule::Label("body", &[ParseRule::Nest("Expr")]),
+        ],
+    ),
+    // let name = value
+    (
+        "Let",
+        &[
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::Identifier, Some("let"))]),
+            ParseRule::Label("name", &[ParseRule::Nest("Name")]),
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::OpEqSign, None)]),
+            ParseRule::Label("value", &[ParseRule::Nest("Expr")]),
+        ],
+    ),
+    // import 'path/to/file.nano' as alias
+    (
+        "Import",
+        &[
+            ParseRule::Skip(&[ParseRule::SingleToken(
+                TokenName::Identifier,
+                Some("import"),
+            )]),
+            ParseRule::Label(
+                "path",
+                &[ParseRule::SingleToken(TokenName::StringLiteral, None)],
+            ),
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::Identifier, Some("as"))]),
+            ParseRule::Label("alias", &[ParseRule::Nest("Name")]),
+        ],
+    ),
+    // for name in iterable -> body
+    (
+        "For",
+        &[
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::Identifier, Some("for"))]),
+            ParseRule::Label("name", &[ParseRule::Nest("Name")]),
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::Identifier, Some("in"))]),
+            ParseRule::Label("iterable", &[ParseRule::Nest("Range")]),
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::ThinArrow, None)]),
+            ParseRule::Label("body", &[ParseRule::Nest("Expr")]),
+        ],
+    ),
+    // select body for name in iterable
+    (
+        "Select",
         &[
-            ParseRule::Nest("Expr"),
-            ParseRule::OptionalMany(&[
-                ParseRule::SingleToken(TokenName::Semicolon, None),
-                ParseRule::Nest("Expr"),
+            ParseRule::Skip(&[ParseRule::SingleToken(
+                TokenName::Identifier,
+                Some("select"),
+            )]),
+            ParseRule::Label("body", &[ParseRule::Nest("Range")]),
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::Identifier, Some("for"))]),
+            ParseRule::Label("name", &[ParseRule::Nest("Name")]),
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::Identifier, Some("in"))]),
+            ParseRule::Label("iterable", &[ParseRule::Nest("Range")]),
+        ],
+    ),
+    // param -> body
+    (
+        "Lambda",
+        &[
+            ParseRule::Lookahead(&[
+                ParseRule::Nest("Name"),
+                ParseRule::SingleToken(TokenName::ThinArrow, None),
             ]),
+            ParseRule::Label("param", &[ParseRule::Nest("Name")]),
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::ThinArrow, None)]),
+            ParseRule::Label("body", &[ParseRule::Nest("Expr")]),
         ],
     ),
+    // value |> function
     (
-        "Expr",
-        &[ParseRule::SingleToken(TokenName::IntLiteral, None)],
+        "Pipeline",
+        &[
+            ParseRule::Nest("Comparison"),
+            ParseRule::Flatten(&[ParseRule::OptionalMany(&[
+                ParseRule::SingleToken(TokenName::Pipe, None),
+                ParseRule::Nest("Comparison"),
+            ])]),
+        ],
     ),
+    // lhs is rhs, lhs xis rhs
+    (
+        "Comparison",
+        &[
+            ParseRule::Nest("Range"),
+            ParseRule::Flatten(&[ParseRule::OptionalMany(&[
+                ParseRule::TokenSet(&[TokenName::OpIs, TokenName::OpXis]),
+                ParseRule::Nest("Range"),
+            ])]),
+        ],
+    ),
+    // start..end
+    (
+        "Range",
+        &[
+            ParseRule::Nest("Sum"),
+            ParseRule::Flatten(&[ParseRule::Optional(&[
+                ParseRule::SingleToken(TokenName::ExclusiveReticences, None),
+                ParseRule::Nest("Sum"),
+            ])]),
+        ],
+    ),
+    (
+        "Sum",
+        &[
+            ParseRule::Nest("Product"),
+            ParseRule::Flatten(&[ParseRule::OptionalMany(&[
+                ParseRule::TokenSet(&[TokenName::OpPlus, TokenName::OpDash]),
+                ParseRule::Nest("Product"),
+            ])]),
+        ],
+    ),
+    (
+        "Product",
+        &[
+            ParseRule::Nest("Atom"),
+            ParseRule::Flatten(&[ParseRule::OptionalMany(&[
+                ParseRule::TokenSet(&[
+                    TokenName::OpAsterisk,
+                    TokenName::OpForwardSlash,
+                    TokenName::OpPercent,
+                ]),
+                ParseRule::Nest("Atom"),
+            ])]),
+        ],
+    ),
+    (
+        "Atom",
+        &[ParseRule::Disjunction(&[
+            &[ParseRule::Nest("Assertion")],
+            &[ParseRule::Nest("Literal")],
+            &[ParseRule::Nest("Call")],
+            &[ParseRule::Nest("Member")],
+            &[ParseRule::Nest("Name")],
+            &[ParseRule::Nest("Parens")],
+            &[ParseRule::Nest("List")],
+            &[ParseRule::Nest("Record")],
+        ])],
+    ),
+    (
+        "Literal",
+        &[
+            // Numbers may be negative, `-1`.
+            ParseRule::Flatten(&[ParseRule::Optional(&[
+                ParseRule::Label("sign", &[ParseRule::SingleToken(TokenName::OpDash, None)]),
+                ParseRule::Lookahead(&[ParseRule::TokenSet(&[
+                    TokenName::IntLiteral,
+                    TokenName::FloatLiteral,
+                ])]),
+            ])]),
+            ParseRule::Label(
+                "value",
+                &[ParseRule::TokenSet(&[
+                    TokenName::IntLiteral,
+                    TokenName::FloatLiteral,
+                    TokenName::StringLiteral,
+                    TokenName::BooleanLiteral,
+                ])],
+            ),
+        ],
+    ),
+    // An identifier that is not a keyword
+    (
+        "Name",
+        &[
+            ParseRule::NotLookahead(&[ParseRule::Nest("Keyword")]),
+            ParseRule::Label(
+                "name",
+                &[ParseRule::SingleToken(TokenName::Identifier, None)],
+            ),
+        ],
+    ),
+    (
+        "Parens",
+        &[
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::ParenthesisOpen, None)]),
+            ParseRule::Label(
+                "exprs",
+                &[ParseRule::SeparatedBy {
+                    item: &[ParseRule::Nest("Expr")],
+                    sep: &[ParseRule::TokenSet(&[
+                        TokenName::Semicolon,
+                        TokenName::Newline,
+                    ])],
+                    allow_trailing: true,
+                    min: 1,
+                }],
+            ),
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::ParenthesisClose, None)]),
+        ],
+    ),
+    // object.field, object.field.field, ...
+    (
+        "Member",
+        &[
+            ParseRule::Nest("Name"),
+            ParseRule::Flatten(&[ParseRule::Many(&[
+                ParseRule::Skip(&[ParseRule::SingleToken(TokenName::Dot, None)]),
+                ParseRule::Nest("Name"),
+            ])]),
+        ],
+    ),
+    // callee(arg, arg, ...)
+    (
+        "Call",
+        &[
+            ParseRule::Label(
+                "callee",
+                &[ParseRule::Flatten(&[ParseRule::Disjunction(&[
+                    &[ParseRule::Nest("Member")],
+                    &[ParseRule::Nest("Name")],
+                ])])],
+            ),
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::ParenthesisOpen, None)]),
+            ParseRule::Label(
+                "args",
+                &[ParseRule::SeparatedBy {
+                    item: &[ParseRule::Nest("Expr")],
+                    sep: &[ParseRule::SingleToken(TokenName::Comma, None)],
+                    allow_trailing: true,
+                    min: 0,
+                }],
+            ),
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::ParenthesisClose, None)]),
+        ],
+    ),
+    // %%name(arg, arg, ...), like `%%t_eq(actual, expected)`
+    (
+        "Assertion",
+        &[
+            ParseRule::Label(
+                "name",
+                &[ParseRule::SingleToken(TokenName::ScopeAnnotation, None)],
+            ),
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::ParenthesisOpen, None)]),
+            ParseRule::Label(
+                "args",
+                &[ParseRule::SeparatedBy {
+                    item: &[ParseRule::Nest("Expr")],
+                    sep: &[ParseRule::SingleToken(TokenName::Comma, None)],
+                    allow_trailing: true,
+                    min: 0,
+                }],
+            ),
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::ParenthesisClose, None)]),
+        ],
+    ),
+    // [item, item, ...], items may also be separated by newlines
+    (
+        "List",
+        &[
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::SqBracketsOpen, None)]),
+            ParseRule::Label(
+                "items",
+                &[ParseRule::SeparatedBy {
+                    item: &[ParseRule::Nest("Expr")],
+                    sep: &[ParseRule::TokenSet(&[TokenName::Comma, TokenName::Newline])],
+                    allow_trailing: true,
+                    min: 0,
+                }],
+            ),
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::SqBracketsClose, None)]),
+        ],
+    ),
+    // { key: value, key: value, ... }, fields may also be separated by newlines
+    (
+        "Record",
+        &[
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::CrBracketsOpen, None)]),
+            ParseRule::Label(
+                "fields",
+                &[ParseRule::SeparatedBy {
+                    item: &[ParseRule::Nest("Field")],
+                    sep: &[ParseRule::TokenSet(&[TokenName::Comma, TokenName::Newline])],
+                    allow_trailing: true,
+                    min: 0,
+                }],
+            ),
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::CrBracketsClose, None)]),
+        ],
+    ),
+    (
+        "Field",
+        &[
+            ParseRule::Label("key", &[ParseRule::Nest("Name")]),
+            ParseRule::Skip(&[ParseRule::SingleToken(TokenName::Colon, None)]),
+            ParseRule::Label("value", &[ParseRule::Nest("Expr")]),
+        ],
+    ),
+    (
+        "Keyword",
+        &[ParseRule::Disjunction(&[
+            &[ParseRule::SingleToken(TokenName::Identifier, Some("fn"))],
+            &[ParseRule::SingleToken(TokenName::Identifier, Some("let"))],
+            &[ParseRule::SingleToken(TokenName::Identifier, Some("for"))],
+            &[ParseRule::SingleToken(TokenName::Identifier, Some("in"))],
+            &[ParseRule::SingleToken(
+                TokenName::Identifier,
+                Some("import"),
+            )],
+            &[ParseRule::SingleToken(TokenName::Identifier, Some("as"))],
+            &[ParseRule::SingleToken(
+                TokenName::Identifier,
+                Some("select"),
+            )],
+            &[ParseRule::SingleToken(
+                TokenName::Identifier,
+                Some("comptime"),
+            )],
+        ])],
+    ),
+];
+
+// The actions used to reshape nodes right after their rule matched
+pub static NANO_REDUCE_ACTIONS: &ReduceActionList = &[
+    ("Range", reduce_range),
+    ("Member", reduce_member),
+    ("Pipeline", reduce_binary_chain),
+    ("Comparison", reduce_binary_chain),
+    ("Sum", reduce_binary_chain),
+    ("Product", reduce_binary_chain),
+    ("Atom", reduce_transparent),
 ];
 
-pub static NANO_AI_RULES: &'static [(&'static str, fn(i32) -> i32)] = &[("Program", |i| i)];
+/// Replaces a node that only wraps a single alternative by that alternative.
+fn reduce_transparent(node: ASTNode) -> ASTNodeContent {
+    let mut items = node.content.into_iter().filter_map(|c| match c {
+        ASTNodeContent::Grouping(mut inner) if inner.len() == 1 => Some(inner.remove(0)),
+        ASTNodeContent::None => None,
+        other => Some(other),
+    });
+
+    return items.next().unwrap_or(ASTNodeContent::None);
+}
+
+/// Labels the bounds of `start..end`; a lone `start` is left as is.
+fn reduce_range(node: ASTNode) -> ASTNodeContent {
+    let mut items = node.content.into_iter();
+
+    let (start, _op, end) = match (items.next(), items.next(), items.next()) {
+        (Some(start), None, None) => return start,
+        (Some(start), Some(op), Some(end)) => (start, op, end),
+        _ => return ASTNodeContent::None,
+    };
+
+    return ASTNodeContent::Node(ASTNode {
+        matched_with: "Range",
+        content: vec![
+            ASTNodeContent::Labelled("start", Box::new(start)),
+            ASTNodeContent::Labelled("end", Box::new(end)),
+        ],
+        consumed: None,
+    });
+}
+
+/// Folds `a.b.c` into nested `Member` nodes labelled `object` and `field`, `(a.b).c`.
+fn reduce_member(node: ASTNode) -> ASTNodeContent {
+    let mut items = node.content.into_iter();
+
+    let mut object = match items.next() {
+        None => return ASTNodeContent::None,
+        Some(first) => first,
+    };
+
+    for field in items {
+        object = ASTNodeContent::Node(ASTNode {
+            matched_with: "Member",
+            content: vec![
+                ASTNodeContent::Labelled("object", Box::new(object)),
+                ASTNodeContent::Labelled("field", Box::new(field)),
+            ],
+            consumed: None,
+        });
+    }
+
+    return object;
+}
+
+/// Folds a flat `operand (operator operand)*` chain into
+/// left-associative `BinaryExpr` nodes labelled `op`, `lhs` and `rhs`.
+/// A chain with a single operand is replaced by that operand.
+fn reduce_binary_chain(node: ASTNode) -> ASTNodeContent {
+    let mut items = node.content.into_iter();
+
+    let mut lhs = match items.next() {
+        None => return ASTNodeContent::None,
+        Some(first) => first,
+    };
+
+    while let (Some(op), Some(rhs)) = (items.next(), items.next()) {
+        lhs = ASTNodeContent::Node(ASTNode {
+            matched_with: "BinaryExpr",
+            content: vec![
+                ASTNodeContent::Labelled("op", Box::new(op)),
+                ASTNodeContent::Labelled("lhs", Box::new(lhs)),
+                ASTNodeContent::Labelled("rhs", Box::new(rhs)),
+            ],
+            consumed: None,
+        });
+    }
+
+    return lhs;
+}
@@ -0,0 +1,257 @@
+/* The runtime of C programs made by nnc, from its `c` target.
+ *
+ * Values are typed like in nano: `int64_t`, `double`, `bool`, `nano_unit`,
+ * and pointers for strings, lists, records and closures. Containers hold
+ * `nano_value`s, which is any of them. Memory comes from `malloc` and is
+ * never freed, the program being over soon enough.
+ *
+ *   string   length, then its bytes
+ *   list     length, then its items
+ *   record   its fields, in the order of their names
+ *   closure  the function it's called through, its name, then its captures
+ *
+ * That function takes the closure and its arguments as `nano_value`s, and
+ * returns one, so how a closure is called only depends on how many
+ * arguments it takes.
+ */
+
+#include <float.h>
+#include <inttypes.h>
+#include <math.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef union nano_value {
+    int64_t i;
+    double f;
+    bool b;
+    void *p;
+} nano_value;
+
+/* Unit is any value: where a type nothing decides was made unit, the value
+ * is passed along untouched. */
+typedef nano_value nano_unit;
+#define NANO_UNIT ((nano_value){.i = 0})
+
+typedef struct nano_string {
+    int64_t length;
+    char bytes[];
+} nano_string;
+
+typedef struct nano_list {
+    int64_t length;
+    nano_value items[];
+} nano_list;
+
+typedef struct nano_closure {
+    void (*code)(void);
+    nano_string *name;
+    nano_value captures[];
+} nano_closure;
+
+static inline void nano_panic(const char *message) {
+    fflush(stdout);
+    fprintf(stderr, "error: %s\n", message);
+    exit(1);
+}
+
+static inline void *nano_alloc(size_t size) {
+    void *memory = malloc(size > 0 ? size : 1);
+    if (memory == NULL) {
+        nano_panic("Out of memory.");
+    }
+    return memory;
+}
+
+static inline nano_string *nano_string_new(const char *bytes, int64_t length) {
+    nano_string *s = nano_alloc(sizeof(nano_string) + (size_t)length);
+    s->length = length;
+    memcpy(s->bytes, bytes, (size_t)length);
+    return s;
+}
+
+static inline nano_list *nano_list_new(int64_t length) {
+    nano_list *list = nano_alloc(sizeof(nano_list) + (size_t)length * sizeof(nano_value));
+    list->length = length;
+    return list;
+}
+
+static inline nano_value *nano_record_new(int64_t fields) {
+    return nano_alloc((size_t)fields * sizeof(nano_value));
+}
+
+static inline nano_closure *nano_closure_new(void (*code)(void), nano_string *name, int64_t captures) {
+    nano_closure *c = nano_alloc(sizeof(nano_closure) + (size_t)captures * sizeof(nano_value));
+    c->code = code;
+    c->name = name;
+    return c;
+}
+
+/* Integers wrap around, like in the other compiled targets. */
+static inline int64_t nano_add(int64_t a, int64_t b) {
+    return (int64_t)((uint64_t)a + (uint64_t)b);
+}
+
+static inline int64_t nano_sub(int64_t a, int64_t b) {
+    return (int64_t)((uint64_t)a - (uint64_t)b);
+}
+
+static inline int64_t nano_mul(int64_t a, int64_t b) {
+    return (int64_t)((uint64_t)a * (uint64_t)b);
+}
+
+static inline int64_t nano_div(int64_t a, int64_t b) {
+    if (b == 0) {
+        nano_panic("Division by zero.");
+    }
+    if (a == INT64_MIN && b == -1) {
+        return a;
+    }
+    return a / b;
+}
+
+static inline int64_t nano_rem(int64_t a, int64_t b) {
+    if (b == 0) {
+        nano_panic("Division by zero.");
+    }
+    if (b == -1) {
+        return 0;
+    }
+    return a % b;
+}
+
+static inline nano_string *nano_concat(nano_string *a, nano_string *b) {
+    nano_string *s = nano_alloc(sizeof(nano_string) + (size_t)(a->length + b->length));
+    s->length = a->length + b->length;
+    memcpy(s->bytes, a->bytes, (size_t)a->length);
+    memcpy(s->bytes + a->length, b->bytes, (size_t)b->length);
+    return s;
+}
+
+static inline bool nano_string_eq(nano_string *a, nano_string *b) {
+    return a->length == b->length && memcmp(a->bytes, b->bytes, (size_t)a->length) == 0;
+}
+
+static inline nano_list *nano_range(int64_t start, int64_t end) {
+    int64_t length = end > start ? end - start : 0;
+    nano_list *list = nano_list_new(length);
+    for (int64_t i = 0; i < length; i++) {
+        list->items[i].i = start + i;
+    }
+    return list;
+}
+
+static inline void nano_out_string(nano_string *s) {
+    fwrite(s->bytes, 1, (size_t)s->length, stdout);
+}
+
+static inline void nano_write_uint(uint64_t n) {
+    char digits[20];
+    int count = 0;
+    do {
+        digits[count++] = (char)('0' + n % 10);
+        n /= 10;
+    } while (n != 0);
+    while (count > 0) {
+        putchar(digits[--count]);
+    }
+}
+
+static inline void nano_write_int(int64_t n) {
+    if (n < 0) {
+        putchar('-');
+        nano_write_uint(0 - (uint64_t)n);
+    } else {
+        nano_write_uint((uint64_t)n);
+    }
+}
+
+/* Like Rust's `{:?}`, `1.0`, `0.25`, `1e16`: the fewest digits that read back
+ * as the same double, like the interpreter prints. */
+static inline void nano_write_float(double x) {
+    char text[32], digits[24];
+    uint64_t bits;
+    int precision, exponent, length = 0, i;
+    char *p;
+
+    if (x != x) {
+        fputs("NaN", stdout);
+        return;
+    }
+    memcpy(&bits, &x, sizeof bits);
+    if (bits >> 63) {
+        putchar('-');
+        x = -x;
+    }
+    if (x > DBL_MAX) {
+        fputs("inf", stdout);
+        return;
+    }
+
+    /* More and more digits, until they're enough; 17 always are. */
+    for (precision = 0;; precision++) {
+        snprintf(text, sizeof text, "%.*e", precision, x);
+        if (precision == 16 || strtod(text, NULL) == x) {
+            break;
+        }
+    }
+    /* `d.ddde+x`, as its digits, trailing zeros gone, and its exponent. */
+    for (p = text; *p != 'e'; p++) {
+        if (*p != '.') {
+            digits[length++] = *p;
+        }
+    }
+    exponent = atoi(p + 1);
+    while (length > 1 && digits[length - 1] == '0') {
+        length--;
+    }
+
+    if (x != 0.0 && (x >= 1e16 || x < 1e-4)) {
+        /* Too big or too small numbers are shown as `mantissa e exponent`, `1e16` without a fraction. */
+        putchar(digits[0]);
+        if (length > 1) {
+            putchar('.');
+            fwrite(digits + 1, 1, (size_t)(length - 1), stdout);
+        }
+        putchar('e');
+        nano_write_int(exponent);
+    } else if (exponent >= 0) {
+        for (i = 0; i <= exponent; i++) {
+            putchar(i < length ? digits[i] : '0');
+        }
+        putchar('.');
+        if (length > exponent + 1) {
+            fwrite(digits + exponent + 1, 1, (size_t)(length - exponent - 1), stdout);
+        } else {
+            putchar('0');
+        }
+    } else {
+        fputs("0.", stdout);
+        for (i = -1; i > exponent; i--) {
+            putchar('0');
+        }
+        fwrite(digits, 1, (size_t)length, stdout);
+    }
+}
+
+static inline void nano_write_bool(bool x) {
+    fputs(x ? "yes" : "no", stdout);
+}
+
+static inline void nano_write_unit(nano_unit x) {
+    (void)x;
+    fputs("()", stdout);
+}
+
+static inline bool nano_unit_eq(nano_unit a, nano_unit b) {
+    return a.i == b.i;
+}
+
+static inline void nano_write_string(nano_string *s) {
+    putchar('\'');
+    nano_out_string(s);
+    putchar('\'');
+}
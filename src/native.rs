@@ -0,0 +1,1651 @@
+use std::collections::HashMap;
+
+use crate::{
+    build::Backend,
+    elf,
+    ir::{intrinsic, type_symbol, Function, IRKind, IRNode, Literal, Module, INTRINSIC_PREFIX},
+    mono::{field_names, monomorphize},
+    pe,
+    types::Type,
+    x86::{Alu, Asm, Cond, Data, Label, Reg, Shift, Sse, Xmm},
+};
+
+// The native x86-64 backend, for Linux and Windows.
+//
+// Values are laid out exactly like the wasm backend's, each one a 64-bit
+// word, and the same helper functions are generated for printing and
+// comparing them. Every function, nano's and the runtime's alike, takes its
+// arguments on the stack, pushed left to right and popped by the caller,
+// and returns in `rax`; everything but `rbp` and `rsp` may be clobbered.
+// Inside a function, parameters and locals are slots around `rbp`, and
+// expressions keep their temporaries on the stack, so code generation is a
+// straightforward walk of the IR.
+//
+// Only a few runtime functions differ between platforms: writing to the
+// standard output or error, getting memory and exiting, which are system calls on
+// Linux and calls into kernel32 on Windows. The heap is a bump allocator
+// over chunks of at least a megabyte that are never given back.
+//
+// The code and its data then go to an object writer: `elf.rs` makes a
+// relocatable object whose `_start` runs the program, linked with a plain
+// `ld program.o -o program`, and `pe.rs` a Windows executable.
+
+/// The `linux_x86` target, an ELF object.
+pub struct LinuxX86;
+
+impl Backend for LinuxX86 {
+    fn emit(&self, module: &Module) -> Result<Vec<u8>, String> {
+        return elf::write(&compile(module, Platform::Linux)?);
+    }
+}
+
+/// The `windows_x86` target, a PE executable.
+pub struct WindowsX86;
+
+impl Backend for WindowsX86 {
+    fn emit(&self, module: &Module) -> Result<Vec<u8>, String> {
+        return pe::write(&compile(module, Platform::Windows)?);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Linux,
+    Windows,
+}
+
+/// Machine code and its data, for an object writer to lay out.
+pub struct Object {
+    pub text: Vec<u8>,
+    pub rodata: Vec<u8>,
+    // The size of the zeroed data.
+    pub bss: u32,
+    // Where in `text` a displacement to some data goes, relative to the end of it.
+    pub relocations: Vec<(usize, Data)>,
+    // The kernel32 functions `Data::Import` refers to.
+    pub imports: Vec<&'static str>,
+    pub symbols: Vec<Symbol>,
+    // Where the program starts, in `text`.
+    pub entry: usize,
+    // Where the top-level code is, in `text`.
+    pub main: usize,
+}
+
+/// A function in `text`.
+pub struct Symbol {
+    pub name: String,
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// Compiles `module` for `platform`.
+pub fn compile(module: &Module, platform: Platform) -> Result<Object, String> {
+    let module = monomorphize(module);
+    return Compiler::new(&module, platform).finish();
+}
+
+// The runtime's state in the zeroed data, then nano's globals.
+const HEAP: u32 = 0;
+const HEAP_END: u32 = 8;
+const OUT_LENGTH: u32 = 16;
+// Where `WriteFile` says how much it wrote.
+const WRITTEN: u32 = 24;
+const OUT_BUFFER: u32 = 32;
+const OUT_CAPACITY: i32 = 1024;
+const GLOBALS: u32 = OUT_BUFFER + OUT_CAPACITY as u32;
+
+// The heap grows by this much at least.
+const CHUNK: i64 = 1 << 20;
+
+/// The functions the generated code relies on.
+#[derive(Debug, Clone)]
+enum Runtime {
+    Alloc,
+    Flush,
+    OutByte,
+    OutString,
+    OutUint,
+    OutInt,
+    OutFloat,
+    Concat,
+    StringEq,
+    FloatEq,
+    Range,
+    // `Display` for values of a type, strings quoted.
+    Write(Type),
+    // Walks two lists or records of a type side by side, stopping at the first difference.
+    Eq(Type),
+    Print(Type),
+    // Reports an error like the interpreter does, and exits with 1.
+    Panic,
+    // What the platform provides: writing bytes out, getting memory and exiting.
+    SysWrite,
+    SysMap,
+    SysExit,
+}
+
+impl Runtime {
+    fn name(&self) -> String {
+        match self {
+            Runtime::Alloc => intrinsic("alloc"),
+            Runtime::Flush => intrinsic("flush"),
+            Runtime::OutByte => intrinsic("out_byte"),
+            Runtime::OutString => intrinsic("out_string"),
+            Runtime::OutUint => intrinsic("out_uint"),
+            Runtime::OutInt => intrinsic("out_int"),
+            Runtime::OutFloat => intrinsic("out_float"),
+            Runtime::Concat => intrinsic("concat"),
+            Runtime::StringEq => intrinsic("string_eq"),
+            Runtime::FloatEq => intrinsic("float_eq"),
+            Runtime::Range => intrinsic("range"),
+            Runtime::Write(ty) => intrinsic(&format!("write<{}>", type_symbol(ty))),
+            Runtime::Eq(ty) => intrinsic(&format!("eq<{}>", type_symbol(ty))),
+            Runtime::Print(ty) => intrinsic(&format!("print<{}>", type_symbol(ty))),
+            Runtime::Panic => intrinsic("panic"),
+            Runtime::SysWrite => intrinsic("sys_write"),
+            Runtime::SysMap => intrinsic("sys_map"),
+            Runtime::SysExit => intrinsic("sys_exit"),
+        }
+    }
+
+    fn arity(&self) -> u32 {
+        match self {
+            Runtime::Flush => 0,
+            Runtime::Alloc
+            | Runtime::OutByte
+            | Runtime::OutString
+            | Runtime::OutUint
+            | Runtime::OutInt
+            | Runtime::OutFloat
+            | Runtime::Write(_)
+            | Runtime::Print(_)
+            | Runtime::Panic
+            | Runtime::SysMap
+            | Runtime::SysExit => 1,
+            Runtime::Concat
+            | Runtime::StringEq
+            | Runtime::FloatEq
+            | Runtime::Range
+            | Runtime::Eq(_) => 2,
+            Runtime::SysWrite => 3,
+        }
+    }
+}
+
+/// A function whose label is known but whose body is still to be compiled.
+enum Pending<'a> {
+    User(&'a Function),
+    // The code a closure of `symbol` at type `ty` points to, called with the closure first.
+    Thunk { symbol: String, ty: Type },
+    Runtime(Runtime),
+}
+
+/// The frame of a function being compiled.
+struct Body {
+    params: u32,
+    locals: u32,
+    // The slots in scope by name, searched from the end.
+    scopes: Vec<(String, u32)>,
+    // Where the frame's size goes, once it's known.
+    frame: usize,
+}
+
+impl Body {
+    /// A new slot, after the parameters.
+    fn local(&mut self) -> u32 {
+        self.locals += 1;
+        return self.params + self.locals - 1;
+    }
+
+    /// Where a slot is from `rbp`: parameters above the return address, locals below.
+    fn offset(&self, slot: u32) -> i32 {
+        match slot < self.params {
+            true => 16 + 8 * (self.params - 1 - slot) as i32,
+            false => -8 * (slot - self.params + 1) as i32,
+        }
+    }
+}
+
+struct Compiler<'a> {
+    module: &'a Module,
+    platform: Platform,
+    a: Asm,
+    functions: HashMap<String, Label>,
+    pending: Vec<(String, Label, Pending<'a>)>,
+    symbols: Vec<Symbol>,
+    rodata: Vec<u8>,
+    strings: HashMap<String, u32>,
+    globals: HashMap<String, u32>,
+    imports: Vec<&'static str>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(module: &'a Module, platform: Platform) -> Compiler<'a> {
+        let globals = module
+            .globals
+            .iter()
+            .enumerate()
+            .map(|(i, (symbol, _))| (symbol.clone(), GLOBALS + 8 * i as u32))
+            .collect();
+        return Compiler {
+            module,
+            platform,
+            a: Asm::new(),
+            functions: HashMap::new(),
+            pending: Vec::new(),
+            symbols: Vec::new(),
+            rodata: Vec::new(),
+            strings: HashMap::new(),
+            globals,
+            imports: Vec::new(),
+        };
+    }
+
+    /// The label of the function called `name`, to be compiled from `pending` if it's new.
+    fn function(&mut self, name: String, pending: impl FnOnce() -> Pending<'a>) -> Label {
+        if let Some(label) = self.functions.get(&name) {
+            return *label;
+        }
+        let label = self.a.label();
+        self.functions.insert(name.clone(), label);
+        self.pending.push((name, label, pending()));
+        return label;
+    }
+
+    fn runtime(&mut self, runtime: Runtime) -> Label {
+        return self.function(runtime.name(), || Pending::Runtime(runtime));
+    }
+
+    fn user(&mut self, symbol: &str) -> Result<Label, String> {
+        let function = match self.module.function(symbol) {
+            None => return Err(format!("The x86 backend can't find '{}'.", symbol)),
+            Some(f) => f,
+        };
+        return Ok(self.function(symbol.to_string(), || Pending::User(function)));
+    }
+
+    /// The thunk closures of `symbol` are called through.
+    fn thunk(&mut self, symbol: &str, ty: &Type) -> Label {
+        let name = format!("{}$closure<{}>", symbol, type_symbol(ty));
+        return self.function(name, || Pending::Thunk {
+            symbol: symbol.to_string(),
+            ty: ty.clone(),
+        });
+    }
+
+    /// A string with `text`, in the read-only data.
+    fn string(&mut self, text: &str) -> Data {
+        if let Some(offset) = self.strings.get(text) {
+            return Data::Rodata(*offset);
+        }
+        while !self.rodata.len().is_multiple_of(8) {
+            self.rodata.push(0);
+        }
+        let offset = self.rodata.len() as u32;
+        self.rodata
+            .extend_from_slice(&(text.len() as i64).to_le_bytes());
+        self.rodata.extend_from_slice(text.as_bytes());
+        self.strings.insert(text.to_string(), offset);
+        return Data::Rodata(offset);
+    }
+
+    /// An entry of the import address table, on Windows.
+    fn import(&mut self, name: &'static str) -> Data {
+        let index = match self.imports.iter().position(|i| *i == name) {
+            Some(index) => index,
+            None => {
+                self.imports.push(name);
+                self.imports.len() - 1
+            }
+        };
+        return Data::Import(index as u32);
+    }
+
+    fn finish(mut self) -> Result<Object, String> {
+        // The entry point calls the top-level code and exits.
+        let main = self.a.label();
+        let exit = self.runtime(Runtime::SysExit);
+        self.a.alu_imm(Alu::And, Reg::Rsp, -16);
+        self.a.call(main);
+        self.a.mov_imm(Reg::Rax, 0);
+        self.a.push(Reg::Rax);
+        self.a.call(exit);
+        self.symbols.push(Symbol {
+            name: "_start".to_string(),
+            offset: 0,
+            size: self.a.here(),
+        });
+
+        let mut body = self.begin(main, 0);
+        for node in &self.module.body {
+            self.expr(&mut body, node)?;
+        }
+        let flush = self.runtime(Runtime::Flush);
+        self.call(flush, 0);
+        self.a.mov_imm(Reg::Rax, 0);
+        self.end(body);
+        self.symbol("nano_main".to_string(), main);
+
+        while let Some((name, label, pending)) = self.pending.pop() {
+            match pending {
+                Pending::User(function) => self.user_body(label, function)?,
+                Pending::Thunk { symbol, ty } => self.thunk_body(label, &symbol, &ty)?,
+                Pending::Runtime(runtime) => self.runtime_body(label, &runtime),
+            }
+            self.symbol(name, label);
+        }
+        self.a.finish()?;
+
+        return Ok(Object {
+            main: self.a.offset(main).unwrap_or(0),
+            text: self.a.code,
+            rodata: self.rodata,
+            bss: GLOBALS + 8 * self.module.globals.len() as u32,
+            relocations: self.a.relocations,
+            imports: self.imports,
+            symbols: self.symbols,
+            entry: 0,
+        });
+    }
+
+    /// Starts a function taking `params` values at `label`.
+    fn begin(&mut self, label: Label, params: u32) -> Body {
+        self.a.bind(label);
+        self.a.push(Reg::Rbp);
+        self.a.mov(Reg::Rbp, Reg::Rsp);
+        self.a.alu_imm(Alu::Sub, Reg::Rsp, 0);
+        return Body {
+            params,
+            locals: 0,
+            scopes: Vec::new(),
+            frame: self.a.here() - 4,
+        };
+    }
+
+    /// Ends a function, returning what's in `rax`.
+    fn end(&mut self, b: Body) {
+        self.ret();
+        // The stack stays aligned to 16 bytes.
+        let frame = (8 * b.locals + 15) & !15;
+        self.a.patch_u32(b.frame, frame);
+    }
+
+    /// Names the function just compiled at `label`.
+    fn symbol(&mut self, name: String, label: Label) {
+        let offset = self.a.offset(label).unwrap_or(0);
+        self.symbols.push(Symbol {
+            name,
+            offset,
+            size: self.a.here() - offset,
+        });
+    }
+
+    fn ret(&mut self) {
+        self.a.leave();
+        self.a.ret();
+    }
+
+    /// Calls `label`, then drops the `args` values pushed for it.
+    fn call(&mut self, label: Label, args: usize) {
+        self.a.call(label);
+        if args > 0 {
+            self.a.alu_imm(Alu::Add, Reg::Rsp, 8 * args as i32);
+        }
+    }
+
+    fn get(&mut self, b: &Body, dst: Reg, slot: u32) {
+        self.a.load(dst, Reg::Rbp, b.offset(slot));
+    }
+
+    fn set(&mut self, b: &Body, slot: u32, src: Reg) {
+        self.a.store(Reg::Rbp, b.offset(slot), src);
+    }
+
+    /// Adds `n` to a slot.
+    fn bump(&mut self, b: &Body, slot: u32, n: i32) {
+        self.get(b, Reg::Rax, slot);
+        self.a.alu_imm(Alu::Add, Reg::Rax, n);
+        self.set(b, slot, Reg::Rax);
+    }
+
+    fn user_body(&mut self, label: Label, function: &Function) -> Result<(), String> {
+        let names = function.captures.iter().chain(function.params.iter());
+        let mut body = self.begin(label, names.clone().count() as u32);
+        for (i, (name, _)) in names.enumerate() {
+            body.scopes.push((name.clone(), i as u32));
+        }
+        self.expr(&mut body, &function.body)?;
+        self.end(body);
+        return Ok(());
+    }
+
+    /// `(closure, args...)`, pushing the closure's captures before `args`.
+    fn thunk_body(&mut self, label: Label, symbol: &str, ty: &Type) -> Result<(), String> {
+        let (params, ret) = match ty {
+            Type::Fn(params, ret) => (params.clone(), ret.as_ref().clone()),
+            _ => (Vec::new(), Type::Unit),
+        };
+        let body = self.begin(label, 1 + params.len() as u32);
+
+        if let Some(name) = symbol.strip_prefix(INTRINSIC_PREFIX) {
+            for i in 0..params.len() {
+                self.get(&body, Reg::Rax, 1 + i as u32);
+                self.a.push(Reg::Rax);
+            }
+            self.intrinsic(name, &params, &ret)?;
+            self.end(body);
+            return Ok(());
+        }
+
+        let function = self.user(symbol)?;
+        let captures = self
+            .module
+            .function(symbol)
+            .map(|f| f.captures.len())
+            .unwrap_or(0);
+        for i in 0..captures {
+            self.get(&body, Reg::Rax, 0);
+            self.a.load(Reg::Rax, Reg::Rax, 16 + 8 * i as i32);
+            self.a.push(Reg::Rax);
+        }
+        for i in 0..params.len() {
+            self.get(&body, Reg::Rax, 1 + i as u32);
+            self.a.push(Reg::Rax);
+        }
+        self.call(function, captures + params.len());
+        self.end(body);
+        return Ok(());
+    }
+
+    /// Compiles `node`, leaving its value in `rax`.
+    fn expr(&mut self, b: &mut Body, node: &IRNode) -> Result<(), String> {
+        match &node.kind {
+            IRKind::Literal(literal) => {
+                let value = match literal {
+                    Literal::Unit => 0,
+                    Literal::Int(i) => *i,
+                    Literal::Float(x) => x.to_bits() as i64,
+                    Literal::Bool(x) => *x as i64,
+                    Literal::String(s) => {
+                        let text = self.string(s);
+                        self.a.lea_data(Reg::Rax, text);
+                        return Ok(());
+                    }
+                };
+                self.a.mov_imm(Reg::Rax, value);
+            }
+            IRKind::Local(name) => {
+                let local = b.scopes.iter().rev().find(|(n, _)| n == name);
+                match local {
+                    Some((_, slot)) => {
+                        let slot = *slot;
+                        self.get(b, Reg::Rax, slot);
+                    }
+                    None => return Err(format!("The x86 backend can't find local '{}'.", name)),
+                }
+            }
+            IRKind::Global(symbol) => {
+                let global = self.global(symbol)?;
+                self.a.load_data(Reg::Rax, global);
+            }
+            IRKind::SetGlobal { symbol, value } => {
+                let global = self.global(symbol)?;
+                self.expr(b, value)?;
+                self.a.store_data(global, Reg::Rax);
+            }
+            IRKind::Let { name, value } => {
+                self.expr(b, value)?;
+                let slot = b.local();
+                self.set(b, slot, Reg::Rax);
+                b.scopes.push((name.clone(), slot));
+            }
+            IRKind::Block(items) => {
+                let depth = b.scopes.len();
+                if items.is_empty() {
+                    self.a.mov_imm(Reg::Rax, 0);
+                }
+                for item in items {
+                    self.expr(b, item)?;
+                }
+                b.scopes.truncate(depth);
+            }
+            IRKind::FnRef { symbol, captures } => {
+                let thunk = self.thunk(symbol, &node.ty);
+                let name = symbol.split('<').next().unwrap_or(symbol).to_string();
+                let name = self.string(&name);
+
+                let closure = self.alloc(b, 16 + 8 * captures.len() as i64);
+                self.get(b, Reg::Rdx, closure);
+                self.a.lea_label(Reg::Rcx, thunk);
+                self.a.store(Reg::Rdx, 0, Reg::Rcx);
+                self.a.lea_data(Reg::Rcx, name);
+                self.a.store(Reg::Rdx, 8, Reg::Rcx);
+                for (i, capture) in captures.iter().enumerate() {
+                    self.expr(b, capture)?;
+                    self.get(b, Reg::Rdx, closure);
+                    self.a.store(Reg::Rdx, 16 + 8 * i as i32, Reg::Rax);
+                }
+                self.get(b, Reg::Rax, closure);
+            }
+            IRKind::SymbolCall { symbol, args } => {
+                for arg in args {
+                    self.expr(b, arg)?;
+                    self.a.push(Reg::Rax);
+                }
+                match symbol.strip_prefix(INTRINSIC_PREFIX) {
+                    Some(name) => {
+                        let types: Vec<Type> = args.iter().map(|a| a.ty.clone()).collect();
+                        self.intrinsic(name, &types, &node.ty)?;
+                    }
+                    None => {
+                        let function = self.user(symbol)?;
+                        self.call(function, args.len());
+                    }
+                }
+            }
+            IRKind::Call { callee, args } => {
+                self.expr(b, callee)?;
+                self.a.push(Reg::Rax);
+                for arg in args {
+                    self.expr(b, arg)?;
+                    self.a.push(Reg::Rax);
+                }
+                self.a.load(Reg::Rax, Reg::Rsp, 8 * args.len() as i32);
+                self.a.call_mem(Reg::Rax, 0);
+                self.a
+                    .alu_imm(Alu::Add, Reg::Rsp, 8 * (args.len() + 1) as i32);
+            }
+            IRKind::List(items) => {
+                let list = self.alloc(b, 8 + 8 * items.len() as i64);
+                self.get(b, Reg::Rdx, list);
+                self.a.mov_imm(Reg::Rcx, items.len() as i64);
+                self.a.store(Reg::Rdx, 0, Reg::Rcx);
+                for (i, item) in items.iter().enumerate() {
+                    self.expr(b, item)?;
+                    self.get(b, Reg::Rdx, list);
+                    self.a.store(Reg::Rdx, 8 + 8 * i as i32, Reg::Rax);
+                }
+                self.get(b, Reg::Rax, list);
+            }
+            IRKind::Record(fields) => {
+                let keys = field_names(&node.ty, fields.iter().map(|(k, _)| k.clone()));
+                let record = self.alloc(b, 8 * keys.len().max(1) as i64);
+                for (key, value) in fields {
+                    let slot = match keys.iter().position(|k| k == key) {
+                        None => return Err(format!("The x86 backend can't find field '{}'.", key)),
+                        Some(slot) => slot,
+                    };
+                    self.expr(b, value)?;
+                    self.get(b, Reg::Rdx, record);
+                    self.a.store(Reg::Rdx, 8 * slot as i32, Reg::Rax);
+                }
+                self.get(b, Reg::Rax, record);
+            }
+            IRKind::GetField { record, key } => {
+                let keys = field_names(&record.ty, std::iter::empty());
+                let slot = match keys.iter().position(|k| k == key) {
+                    None => return Err(format!("The x86 backend can't find field '{}'.", key)),
+                    Some(slot) => slot,
+                };
+                self.expr(b, record)?;
+                self.a.load(Reg::Rax, Reg::Rax, 8 * slot as i32);
+            }
+            IRKind::Loop {
+                var,
+                iterable,
+                body,
+                collect,
+            } => self.for_loop(b, var, iterable, body, *collect)?,
+            IRKind::Comptime(value) => self.expr(b, value)?,
+            IRKind::Import { path } => {
+                return Err(format!(
+                    "The x86 backend can't compile the import of '{}'.",
+                    path
+                ));
+            }
+        }
+        return Ok(());
+    }
+
+    fn global(&self, symbol: &str) -> Result<Data, String> {
+        match self.globals.get(symbol) {
+            Some(offset) => Ok(Data::Bss(*offset)),
+            None => Err(format!("The x86 backend can't find global '{}'.", symbol)),
+        }
+    }
+
+    /// Allocates `size` bytes, returning the slot holding their address.
+    fn alloc(&mut self, b: &mut Body, size: i64) -> u32 {
+        let alloc = self.runtime(Runtime::Alloc);
+        let slot = b.local();
+        self.a.mov_imm(Reg::Rax, size);
+        self.a.push(Reg::Rax);
+        self.call(alloc, 1);
+        self.set(b, slot, Reg::Rax);
+        return slot;
+    }
+
+    /// `dst` becomes the address of item `i` of the list in `list`, minus the 8 bytes of its length.
+    fn item_address(&mut self, b: &Body, dst: Reg, list: u32, i: u32) {
+        self.get(b, dst, i);
+        self.a.shift(Shift::Shl, dst, 3);
+        self.get(b, Reg::R11, list);
+        self.a.alu(Alu::Add, dst, Reg::R11);
+    }
+
+    /// Runs what `body` emits for `i` from 0 to the value of `length`.
+    fn counted_loop(
+        &mut self,
+        b: &mut Body,
+        i: u32,
+        length: u32,
+        body: impl FnOnce(&mut Self, &mut Body),
+    ) {
+        let (top, done) = (self.a.label(), self.a.label());
+        self.a.mov_imm(Reg::Rax, 0);
+        self.set(b, i, Reg::Rax);
+        self.a.bind(top);
+        self.get(b, Reg::Rax, i);
+        self.get(b, Reg::Rcx, length);
+        self.a.alu(Alu::Cmp, Reg::Rax, Reg::Rcx);
+        self.a.jcc(Cond::AboveEqual, done);
+        body(self, b);
+        self.bump(b, i, 1);
+        self.a.jmp(top);
+        self.a.bind(done);
+    }
+
+    fn for_loop(
+        &mut self,
+        b: &mut Body,
+        var: &str,
+        iterable: &IRNode,
+        body: &IRNode,
+        collect: bool,
+    ) -> Result<(), String> {
+        let (list, length, i, item, results) =
+            (b.local(), b.local(), b.local(), b.local(), b.local());
+        self.expr(b, iterable)?;
+        self.set(b, list, Reg::Rax);
+        self.a.load(Reg::Rcx, Reg::Rax, 0);
+        self.set(b, length, Reg::Rcx);
+
+        if collect {
+            let alloc = self.runtime(Runtime::Alloc);
+            self.get(b, Reg::Rax, length);
+            self.a.shift(Shift::Shl, Reg::Rax, 3);
+            self.a.alu_imm(Alu::Add, Reg::Rax, 8);
+            self.a.push(Reg::Rax);
+            self.call(alloc, 1);
+            self.set(b, results, Reg::Rax);
+            self.get(b, Reg::Rcx, length);
+            self.a.store(Reg::Rax, 0, Reg::Rcx);
+        }
+
+        let mut result = Ok(());
+        self.counted_loop(b, i, length, |c, b| {
+            c.item_address(b, Reg::Rax, list, i);
+            c.a.load(Reg::Rax, Reg::Rax, 8);
+            c.set(b, item, Reg::Rax);
+            let depth = b.scopes.len();
+            b.scopes.push((var.to_string(), item));
+            result = c.expr(b, body);
+            b.scopes.truncate(depth);
+            if collect {
+                c.item_address(b, Reg::Rdx, results, i);
+                c.a.store(Reg::Rdx, 8, Reg::Rax);
+            }
+        });
+        result?;
+
+        match collect {
+            true => self.get(b, Reg::Rax, results),
+            false => self.a.mov_imm(Reg::Rax, 0),
+        }
+        return Ok(());
+    }
+
+    /// Compiles an intrinsic, whose arguments were pushed, leaving its value in `rax`.
+    fn intrinsic(&mut self, name: &str, args: &[Type], ret: &Type) -> Result<(), String> {
+        let first = args.first().cloned().unwrap_or(Type::Unit);
+        match (name, &first) {
+            ("print", ty) => {
+                let print = self.runtime(Runtime::Print(ty.clone()));
+                self.call(print, 1);
+            }
+            ("range", _) => {
+                let range = self.runtime(Runtime::Range);
+                self.call(range, 2);
+            }
+            ("eq", ty) => self.eq(ty),
+            ("ne", ty) => {
+                self.eq(ty);
+                self.a.alu_imm(Alu::Xor, Reg::Rax, 1);
+            }
+            ("sum", Type::String) => {
+                let concat = self.runtime(Runtime::Concat);
+                self.call(concat, 2);
+            }
+            (op @ ("sum" | "sub" | "mul" | "div" | "rem"), Type::Int) => {
+                self.a.pop(Reg::Rcx);
+                self.a.pop(Reg::Rax);
+                match op {
+                    "sum" => self.a.alu(Alu::Add, Reg::Rax, Reg::Rcx),
+                    "sub" => self.a.alu(Alu::Sub, Reg::Rax, Reg::Rcx),
+                    "mul" => self.a.imul(Reg::Rax, Reg::Rcx),
+                    _ => self.divide(op == "rem"),
+                }
+            }
+            (op @ ("sum" | "sub" | "mul" | "div" | "rem"), Type::Float) => {
+                let (lhs, rhs) = (Xmm(0), Xmm(1));
+                self.a.pop(Reg::Rcx);
+                self.a.pop(Reg::Rax);
+                self.a.movq_to_xmm(lhs, Reg::Rax);
+                self.a.movq_to_xmm(rhs, Reg::Rcx);
+                match op {
+                    "sum" => self.a.sse(Sse::Add, lhs, rhs),
+                    "sub" => self.a.sse(Sse::Sub, lhs, rhs),
+                    "mul" => self.a.sse(Sse::Mul, lhs, rhs),
+                    "div" => self.a.sse(Sse::Div, lhs, rhs),
+                    // lhs - trunc(lhs / rhs) * rhs, like Rust's `%`.
+                    _ => {
+                        let t = Xmm(2);
+                        self.a.movq_to_xmm(t, Reg::Rax);
+                        self.a.sse(Sse::Div, t, rhs);
+                        self.a.roundsd(t, t, 3);
+                        self.a.sse(Sse::Mul, t, rhs);
+                        self.a.sse(Sse::Sub, lhs, t);
+                    }
+                }
+                self.a.movq_from_xmm(Reg::Rax, lhs);
+            }
+            _ => {
+                let types: Vec<String> = args.iter().map(|t| t.to_string()).collect();
+                return Err(format!(
+                    "The x86 backend has no '{}{}' for ({}) -> {}.",
+                    INTRINSIC_PREFIX,
+                    name,
+                    types.join(", "),
+                    ret
+                ));
+            }
+        }
+        return Ok(());
+    }
+
+    /// Divides `rax` by `rcx`, leaving the quotient or the remainder in `rax`. Dividing by 0
+    /// panics, and by -1 wraps around like the other operations instead of faulting.
+    fn divide(&mut self, remainder: bool) {
+        let (nonzero, divide, done) = (self.a.label(), self.a.label(), self.a.label());
+        self.a.test(Reg::Rcx, Reg::Rcx);
+        self.a.jcc(Cond::NotEqual, nonzero);
+        self.panic("Division by zero.");
+
+        self.a.bind(nonzero);
+        self.a.alu_imm(Alu::Cmp, Reg::Rcx, -1);
+        self.a.jcc(Cond::NotEqual, divide);
+        match remainder {
+            true => self.a.mov_imm(Reg::Rax, 0),
+            false => self.a.neg(Reg::Rax),
+        }
+        self.a.jmp(done);
+
+        self.a.bind(divide);
+        self.a.cqo();
+        self.a.idiv(Reg::Rcx);
+        if remainder {
+            self.a.mov(Reg::Rax, Reg::Rdx);
+        }
+        self.a.bind(done);
+    }
+
+    /// Calls `panic` with `message`, which never returns.
+    fn panic(&mut self, message: &str) {
+        let panic = self.runtime(Runtime::Panic);
+        let message = self.string(&format!("error: {}\n", message));
+        self.a.lea_data(Reg::Rax, message);
+        self.a.push(Reg::Rax);
+        self.call(panic, 1);
+    }
+
+    /// Compares the two values of type `ty` that were pushed, leaving 1 in `rax` if they're equal.
+    fn eq(&mut self, ty: &Type) {
+        let helper = match ty {
+            Type::Float => Runtime::FloatEq,
+            Type::String => Runtime::StringEq,
+            Type::List(_) | Type::Record(_) => Runtime::Eq(ty.clone()),
+            _ => {
+                self.a.pop(Reg::Rcx);
+                self.a.pop(Reg::Rax);
+                self.a.alu(Alu::Cmp, Reg::Rax, Reg::Rcx);
+                self.a.set(Cond::Equal, Reg::Rax);
+                return;
+            }
+        };
+        let helper = self.runtime(helper);
+        self.call(helper, 2);
+    }
+
+    /// Writes the value of type `ty` in `rax`, as nano shows it.
+    fn write(&mut self, ty: &Type) {
+        let helper = match ty {
+            Type::Int => Runtime::OutInt,
+            Type::Float => Runtime::OutFloat,
+            _ => Runtime::Write(ty.clone()),
+        };
+        let helper = self.runtime(helper);
+        self.a.push(Reg::Rax);
+        self.call(helper, 1);
+    }
+
+    fn out_text(&mut self, text: &str) {
+        let out_string = self.runtime(Runtime::OutString);
+        let text = self.string(text);
+        self.a.lea_data(Reg::Rax, text);
+        self.a.push(Reg::Rax);
+        self.call(out_string, 1);
+    }
+
+    fn out_byte(&mut self, c: u8) {
+        let out_byte = self.runtime(Runtime::OutByte);
+        self.a.mov_imm(Reg::Rax, c as i64);
+        self.a.push(Reg::Rax);
+        self.call(out_byte, 1);
+    }
+
+    fn runtime_body(&mut self, label: Label, runtime: &Runtime) {
+        let mut b = self.begin(label, runtime.arity());
+        match runtime {
+            Runtime::Alloc => self.alloc_body(&mut b),
+            Runtime::Flush => self.flush_body(),
+            Runtime::OutByte => self.out_byte_body(&b),
+            Runtime::OutString => self.out_string_body(&mut b),
+            Runtime::OutUint => self.out_uint_body(&b),
+            Runtime::OutInt => self.out_int_body(&b),
+            Runtime::OutFloat => self.out_float_body(&mut b),
+            Runtime::Concat => self.concat_body(&mut b),
+            Runtime::StringEq => self.string_eq_body(&mut b),
+            Runtime::FloatEq => {
+                self.get(&b, Reg::Rax, 0);
+                self.a.movq_to_xmm(Xmm(0), Reg::Rax);
+                self.get(&b, Reg::Rax, 1);
+                self.a.movq_to_xmm(Xmm(1), Reg::Rax);
+                self.a.ucomisd(Xmm(0), Xmm(1));
+                // NaN is unordered, which sets the parity flag.
+                self.a.set(Cond::Equal, Reg::Rax);
+                self.a.set(Cond::NoParity, Reg::Rcx);
+                self.a.alu(Alu::And, Reg::Rax, Reg::Rcx);
+            }
+            Runtime::Range => self.range_body(&mut b),
+            Runtime::Write(ty) => {
+                self.write_body(&mut b, ty);
+                self.a.mov_imm(Reg::Rax, 0);
+            }
+            Runtime::Eq(ty) => self.eq_body(&mut b, ty),
+            Runtime::Print(ty) => {
+                self.get(&b, Reg::Rax, 0);
+                match ty {
+                    Type::String => {
+                        let out_string = self.runtime(Runtime::OutString);
+                        self.a.push(Reg::Rax);
+                        self.call(out_string, 1);
+                    }
+                    ty => self.write(ty),
+                }
+                self.out_byte(b'\n');
+                let flush = self.runtime(Runtime::Flush);
+                self.call(flush, 0);
+            }
+            Runtime::Panic => self.panic_body(&b),
+            Runtime::SysWrite => self.sys_write_body(&b),
+            Runtime::SysMap => self.sys_map_body(&b),
+            Runtime::SysExit => self.sys_exit_body(&b),
+        }
+        self.end(b);
+    }
+
+    /// Windows functions want a 16-byte aligned stack with 32 bytes for them on top,
+    /// and room for a fifth argument.
+    fn windows_frame(&mut self) {
+        self.a.alu_imm(Alu::And, Reg::Rsp, -16);
+        self.a.alu_imm(Alu::Sub, Reg::Rsp, 48);
+    }
+
+    /// sys_write(fd, address, length): writes bytes to the standard output (1) or error (2).
+    fn sys_write_body(&mut self, b: &Body) {
+        match self.platform {
+            Platform::Linux => {
+                self.get(b, Reg::Rdi, 0);
+                self.get(b, Reg::Rsi, 1);
+                self.get(b, Reg::Rdx, 2);
+                self.a.mov_imm(Reg::Rax, 1);
+                self.a.syscall();
+            }
+            Platform::Windows => {
+                let get_std_handle = self.import("GetStdHandle");
+                let write_file = self.import("WriteFile");
+                self.windows_frame();
+                // STD_OUTPUT_HANDLE is -11 and STD_ERROR_HANDLE -12.
+                self.a.mov_imm(Reg::Rcx, -10);
+                self.get(b, Reg::Rax, 0);
+                self.a.alu(Alu::Sub, Reg::Rcx, Reg::Rax);
+                self.a.call_data(get_std_handle);
+                self.a.mov(Reg::Rcx, Reg::Rax);
+                self.get(b, Reg::Rdx, 1);
+                self.get(b, Reg::R8, 2);
+                self.a.lea_data(Reg::R9, Data::Bss(WRITTEN));
+                self.a.mov_imm(Reg::Rax, 0);
+                self.a.store(Reg::Rsp, 32, Reg::Rax);
+                self.a.call_data(write_file);
+            }
+        }
+        self.a.mov_imm(Reg::Rax, 0);
+    }
+
+    /// sys_map(size): the address of `size` new zeroed bytes.
+    fn sys_map_body(&mut self, b: &Body) {
+        match self.platform {
+            Platform::Linux => {
+                // mmap(NULL, size, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0)
+                self.a.mov_imm(Reg::Rdi, 0);
+                self.get(b, Reg::Rsi, 0);
+                self.a.mov_imm(Reg::Rdx, 3);
+                self.a.mov_imm(Reg::R10, 0x22);
+                self.a.mov_imm(Reg::R8, -1);
+                self.a.mov_imm(Reg::R9, 0);
+                self.a.mov_imm(Reg::Rax, 9);
+                self.a.syscall();
+            }
+            Platform::Windows => {
+                // VirtualAlloc(NULL, size, MEM_COMMIT | MEM_RESERVE, PAGE_READWRITE)
+                let virtual_alloc = self.import("VirtualAlloc");
+                self.windows_frame();
+                self.a.mov_imm(Reg::Rcx, 0);
+                self.get(b, Reg::Rdx, 0);
+                self.a.mov_imm(Reg::R8, 0x3000);
+                self.a.mov_imm(Reg::R9, 4);
+                self.a.call_data(virtual_alloc);
+            }
+        }
+    }
+
+    /// panic(message): writes out what was printed, then `message` to the standard error,
+    /// and exits with 1.
+    fn panic_body(&mut self, b: &Body) {
+        let flush = self.runtime(Runtime::Flush);
+        let sys_write = self.runtime(Runtime::SysWrite);
+        let sys_exit = self.runtime(Runtime::SysExit);
+        self.call(flush, 0);
+        self.a.mov_imm(Reg::Rax, 2);
+        self.a.push(Reg::Rax);
+        self.get(b, Reg::Rax, 0);
+        self.a.lea(Reg::Rcx, Reg::Rax, 8);
+        self.a.push(Reg::Rcx);
+        self.a.load(Reg::Rax, Reg::Rax, 0);
+        self.a.push(Reg::Rax);
+        self.call(sys_write, 3);
+        self.a.mov_imm(Reg::Rax, 1);
+        self.a.push(Reg::Rax);
+        self.call(sys_exit, 1);
+    }
+
+    /// sys_exit(code): ends the process.
+    fn sys_exit_body(&mut self, b: &Body) {
+        match self.platform {
+            Platform::Linux => {
+                self.get(b, Reg::Rdi, 0);
+                self.a.mov_imm(Reg::Rax, 60);
+                self.a.syscall();
+            }
+            Platform::Windows => {
+                let exit_process = self.import("ExitProcess");
+                self.windows_frame();
+                self.get(b, Reg::Rcx, 0);
+                self.a.call_data(exit_process);
+            }
+        }
+    }
+
+    /// alloc(size): bumps the heap, getting a new chunk when it's too small.
+    fn alloc_body(&mut self, b: &mut Body) {
+        let (size, chunk) = (b.local(), b.local());
+        let (grow, bigger, done) = (self.a.label(), self.a.label(), self.a.label());
+        let sys_map = self.runtime(Runtime::SysMap);
+
+        self.get(b, Reg::Rcx, 0);
+        self.a.alu_imm(Alu::Add, Reg::Rcx, 7);
+        self.a.alu_imm(Alu::And, Reg::Rcx, -8);
+        self.set(b, size, Reg::Rcx);
+        self.a.load_data(Reg::Rax, Data::Bss(HEAP));
+        self.a.mov(Reg::Rdx, Reg::Rax);
+        self.a.alu(Alu::Add, Reg::Rdx, Reg::Rcx);
+        self.a.load_data(Reg::Rsi, Data::Bss(HEAP_END));
+        self.a.alu(Alu::Cmp, Reg::Rdx, Reg::Rsi);
+        self.a.jcc(Cond::Above, grow);
+        self.a.store_data(Data::Bss(HEAP), Reg::Rdx);
+        self.a.jmp(done);
+
+        // What's left of the current chunk is abandoned.
+        self.a.bind(grow);
+        self.a.mov_imm(Reg::Rax, CHUNK);
+        self.a.alu(Alu::Cmp, Reg::Rcx, Reg::Rax);
+        self.a.jcc(Cond::BelowEqual, bigger);
+        self.a.mov(Reg::Rax, Reg::Rcx);
+        self.a.bind(bigger);
+        self.set(b, chunk, Reg::Rax);
+        self.a.push(Reg::Rax);
+        self.call(sys_map, 1);
+        for (slot, data) in [(chunk, HEAP_END), (size, HEAP)] {
+            self.get(b, Reg::Rdx, slot);
+            self.a.alu(Alu::Add, Reg::Rdx, Reg::Rax);
+            self.a.store_data(Data::Bss(data), Reg::Rdx);
+        }
+        self.a.bind(done);
+    }
+
+    /// flush(): writes out what `out_byte` gathered.
+    fn flush_body(&mut self) {
+        let sys_write = self.runtime(Runtime::SysWrite);
+        let done = self.a.label();
+        self.a.load_data(Reg::Rax, Data::Bss(OUT_LENGTH));
+        self.a.test(Reg::Rax, Reg::Rax);
+        self.a.jcc(Cond::Equal, done);
+        self.a.mov_imm(Reg::Rcx, 1);
+        self.a.push(Reg::Rcx);
+        self.a.lea_data(Reg::Rcx, Data::Bss(OUT_BUFFER));
+        self.a.push(Reg::Rcx);
+        self.a.push(Reg::Rax);
+        self.call(sys_write, 3);
+        self.a.mov_imm(Reg::Rax, 0);
+        self.a.store_data(Data::Bss(OUT_LENGTH), Reg::Rax);
+        self.a.bind(done);
+        self.a.mov_imm(Reg::Rax, 0);
+    }
+
+    /// out_byte(c): adds a byte to what's written, flushing it first if it's full.
+    fn out_byte_body(&mut self, b: &Body) {
+        let flush = self.runtime(Runtime::Flush);
+        let room = self.a.label();
+        self.a.load_data(Reg::Rax, Data::Bss(OUT_LENGTH));
+        self.a.alu_imm(Alu::Cmp, Reg::Rax, OUT_CAPACITY);
+        self.a.jcc(Cond::Below, room);
+        self.call(flush, 0);
+        self.a.bind(room);
+
+        self.a.load_data(Reg::Rax, Data::Bss(OUT_LENGTH));
+        self.a.lea_data(Reg::Rcx, Data::Bss(OUT_BUFFER));
+        self.a.alu(Alu::Add, Reg::Rcx, Reg::Rax);
+        self.get(b, Reg::Rdx, 0);
+        self.a.store_byte(Reg::Rcx, 0, Reg::Rdx);
+        self.a.alu_imm(Alu::Add, Reg::Rax, 1);
+        self.a.store_data(Data::Bss(OUT_LENGTH), Reg::Rax);
+        self.a.mov_imm(Reg::Rax, 0);
+    }
+
+    /// out_string(s): the bytes of `s`, without quotes.
+    fn out_string_body(&mut self, b: &mut Body) {
+        let out_byte = self.runtime(Runtime::OutByte);
+        let (length, i) = (b.local(), b.local());
+        self.get(b, Reg::Rax, 0);
+        self.a.load(Reg::Rax, Reg::Rax, 0);
+        self.set(b, length, Reg::Rax);
+        self.counted_loop(b, i, length, |c, b| {
+            c.get(b, Reg::Rax, 0);
+            c.get(b, Reg::Rcx, i);
+            c.a.alu(Alu::Add, Reg::Rax, Reg::Rcx);
+            c.a.load_byte(Reg::Rax, Reg::Rax, 8);
+            c.a.push(Reg::Rax);
+            c.call(out_byte, 1);
+        });
+        self.a.mov_imm(Reg::Rax, 0);
+    }
+
+    /// Divides `rax` by 10, unsigned, the remainder going to `rdx`.
+    fn divide_by_ten(&mut self) {
+        self.a.mov_imm(Reg::Rdx, 0);
+        self.a.mov_imm(Reg::Rcx, 10);
+        self.a.div(Reg::Rcx);
+    }
+
+    /// out_uint(n): `n` in decimal, as an unsigned number.
+    fn out_uint_body(&mut self, b: &Body) {
+        let out_uint = self.runtime(Runtime::OutUint);
+        let out_byte = self.runtime(Runtime::OutByte);
+        let last = self.a.label();
+        self.get(b, Reg::Rax, 0);
+        self.a.alu_imm(Alu::Cmp, Reg::Rax, 10);
+        self.a.jcc(Cond::Below, last);
+        self.divide_by_ten();
+        self.a.push(Reg::Rax);
+        self.call(out_uint, 1);
+        self.a.bind(last);
+        self.get(b, Reg::Rax, 0);
+        self.divide_by_ten();
+        self.a.alu_imm(Alu::Add, Reg::Rdx, b'0' as i32);
+        self.a.push(Reg::Rdx);
+        self.call(out_byte, 1);
+    }
+
+    /// out_int(n): `n` in decimal.
+    fn out_int_body(&mut self, b: &Body) {
+        let out_uint = self.runtime(Runtime::OutUint);
+        let positive = self.a.label();
+        self.get(b, Reg::Rax, 0);
+        self.a.test(Reg::Rax, Reg::Rax);
+        self.a.jcc(Cond::GreaterEqual, positive);
+        self.out_byte(b'-');
+        // Negating the smallest int gives itself, which is right as unsigned.
+        self.get(b, Reg::Rax, 0);
+        self.a.neg(Reg::Rax);
+        self.set(b, 0, Reg::Rax);
+        self.a.bind(positive);
+        self.get(b, Reg::Rax, 0);
+        self.a.push(Reg::Rax);
+        self.call(out_uint, 1);
+    }
+
+    /// Loads the double in a slot.
+    fn fget(&mut self, b: &Body, dst: Xmm, slot: u32) {
+        self.get(b, Reg::Rax, slot);
+        self.a.movq_to_xmm(dst, Reg::Rax);
+    }
+
+    fn fset(&mut self, b: &Body, slot: u32, src: Xmm) {
+        self.a.movq_from_xmm(Reg::Rax, src);
+        self.set(b, slot, Reg::Rax);
+    }
+
+    fn fconst(&mut self, dst: Xmm, value: f64) {
+        self.a.mov_imm(Reg::Rax, value.to_bits() as i64);
+        self.a.movq_to_xmm(dst, Reg::Rax);
+    }
+
+    /// Takes one off `digits` for each decimal digit of the number in `slot`,
+    /// which ends up as 0.
+    fn count_digits(&mut self, b: &mut Body, slot: u32, digits: u32) {
+        let (top, done) = (self.a.label(), self.a.label());
+        self.a.bind(top);
+        self.get(b, Reg::Rax, slot);
+        self.a.test(Reg::Rax, Reg::Rax);
+        self.a.jcc(Cond::Equal, done);
+        self.divide_by_ten();
+        self.set(b, slot, Reg::Rax);
+        self.bump(b, digits, -1);
+        self.a.jmp(top);
+        self.a.bind(done);
+    }
+
+    /// out_float(bits): the same digits as the wasm backend's `out_float`.
+    fn out_float_body(&mut self, b: &mut Body) {
+        let out_uint = self.runtime(Runtime::OutUint);
+        let out_int = self.runtime(Runtime::OutInt);
+        let (x, exponent, scientific, whole) = (b.local(), b.local(), b.local(), b.local());
+        let (fraction, digits, scale, t) = (b.local(), b.local(), b.local(), b.local());
+        let (x0, x1) = (Xmm(0), Xmm(1));
+
+        // NaN is unordered, even with itself.
+        let number = self.a.label();
+        self.fget(b, x0, 0);
+        self.a.ucomisd(x0, x0);
+        self.a.jcc(Cond::NoParity, number);
+        self.out_text("NaN");
+        self.a.mov_imm(Reg::Rax, 0);
+        self.ret();
+        self.a.bind(number);
+
+        let positive = self.a.label();
+        self.get(b, Reg::Rax, 0);
+        self.set(b, x, Reg::Rax);
+        self.a.test(Reg::Rax, Reg::Rax);
+        self.a.jcc(Cond::GreaterEqual, positive);
+        self.out_byte(b'-');
+        self.get(b, Reg::Rax, x);
+        self.a.shift(Shift::Shl, Reg::Rax, 1);
+        self.a.shift(Shift::Shr, Reg::Rax, 1);
+        self.set(b, x, Reg::Rax);
+        self.a.bind(positive);
+
+        let finite = self.a.label();
+        self.get(b, Reg::Rax, x);
+        self.a.mov_imm(Reg::Rcx, f64::INFINITY.to_bits() as i64);
+        self.a.alu(Alu::Cmp, Reg::Rax, Reg::Rcx);
+        self.a.jcc(Cond::NotEqual, finite);
+        self.out_text("inf");
+        self.a.mov_imm(Reg::Rax, 0);
+        self.ret();
+        self.a.bind(finite);
+
+        // Too big or too small numbers are shown as `mantissa e exponent`.
+        let (scaled, plain) = (self.a.label(), self.a.label());
+        self.a.mov_imm(Reg::Rax, 0);
+        self.set(b, exponent, Reg::Rax);
+        self.set(b, scientific, Reg::Rax);
+        self.get(b, Reg::Rax, x);
+        self.a.test(Reg::Rax, Reg::Rax);
+        self.a.jcc(Cond::Equal, plain);
+        self.fget(b, x0, x);
+        self.fconst(x1, 1e16);
+        self.a.ucomisd(x0, x1);
+        self.a.jcc(Cond::AboveEqual, scaled);
+        self.fconst(x1, 1e-4);
+        self.a.ucomisd(x0, x1);
+        self.a.jcc(Cond::AboveEqual, plain);
+        self.a.bind(scaled);
+        self.a.mov_imm(Reg::Rax, 1);
+        self.set(b, scientific, Reg::Rax);
+        for (bound, skip, op, step) in [
+            (10.0, Cond::Below, Sse::Div, 1),
+            (1.0, Cond::AboveEqual, Sse::Mul, -1),
+        ] {
+            let (top, done) = (self.a.label(), self.a.label());
+            self.a.bind(top);
+            self.fget(b, x0, x);
+            self.fconst(x1, bound);
+            self.a.ucomisd(x0, x1);
+            self.a.jcc(skip, done);
+            self.fconst(x1, 10.0);
+            self.a.sse(op, x0, x1);
+            self.fset(b, x, x0);
+            self.bump(b, exponent, step);
+            self.a.jmp(top);
+            self.a.bind(done);
+        }
+        self.a.bind(plain);
+
+        // The whole part, and how many digits are left for the fraction.
+        let enough = self.a.label();
+        self.fget(b, x0, x);
+        self.a.cvttsd2si(Reg::Rax, x0);
+        self.set(b, whole, Reg::Rax);
+        self.set(b, t, Reg::Rax);
+        self.a.mov_imm(Reg::Rax, 15);
+        self.set(b, digits, Reg::Rax);
+        self.count_digits(b, t, digits);
+        self.get(b, Reg::Rax, digits);
+        self.a.alu_imm(Alu::Cmp, Reg::Rax, 1);
+        self.a.jcc(Cond::GreaterEqual, enough);
+        self.a.mov_imm(Reg::Rax, 1);
+        self.set(b, digits, Reg::Rax);
+        self.a.bind(enough);
+
+        let (top, done) = (self.a.label(), self.a.label());
+        self.fconst(x0, 1.0);
+        self.fset(b, scale, x0);
+        self.get(b, Reg::Rax, digits);
+        self.set(b, t, Reg::Rax);
+        self.a.bind(top);
+        self.get(b, Reg::Rax, t);
+        self.a.test(Reg::Rax, Reg::Rax);
+        self.a.jcc(Cond::Equal, done);
+        self.fget(b, x0, scale);
+        self.fconst(x1, 10.0);
+        self.a.sse(Sse::Mul, x0, x1);
+        self.fset(b, scale, x0);
+        self.bump(b, t, -1);
+        self.a.jmp(top);
+        self.a.bind(done);
+
+        // The fraction, rounded to those digits, may round up into the whole part.
+        let kept = self.a.label();
+        self.fget(b, x0, x);
+        self.get(b, Reg::Rax, whole);
+        self.a.cvtsi2sd(x1, Reg::Rax);
+        self.a.sse(Sse::Sub, x0, x1);
+        self.fget(b, x1, scale);
+        self.a.sse(Sse::Mul, x0, x1);
+        self.a.roundsd(x0, x0, 0);
+        self.a.cvttsd2si(Reg::Rax, x0);
+        self.set(b, fraction, Reg::Rax);
+        self.fget(b, x1, scale);
+        self.a.cvttsd2si(Reg::Rcx, x1);
+        self.get(b, Reg::Rax, fraction);
+        self.a.alu(Alu::Cmp, Reg::Rax, Reg::Rcx);
+        self.a.jcc(Cond::Below, kept);
+        self.bump(b, whole, 1);
+        self.a.mov_imm(Reg::Rax, 0);
+        self.set(b, fraction, Reg::Rax);
+        self.a.bind(kept);
+
+        self.get(b, Reg::Rax, whole);
+        self.a.push(Reg::Rax);
+        self.call(out_uint, 1);
+
+        // `1e16` has no fraction, `1.0` does.
+        let (dot, no_dot, nonzero) = (self.a.label(), self.a.label(), self.a.label());
+        self.get(b, Reg::Rax, scientific);
+        self.a.test(Reg::Rax, Reg::Rax);
+        self.a.jcc(Cond::Equal, dot);
+        self.get(b, Reg::Rax, fraction);
+        self.a.test(Reg::Rax, Reg::Rax);
+        self.a.jcc(Cond::Equal, no_dot);
+        self.a.bind(dot);
+        self.out_byte(b'.');
+        self.get(b, Reg::Rax, fraction);
+        self.a.test(Reg::Rax, Reg::Rax);
+        self.a.jcc(Cond::NotEqual, nonzero);
+        self.out_byte(b'0');
+        self.a.jmp(no_dot);
+        self.a.bind(nonzero);
+
+        // Trailing zeros go, leading ones stay.
+        let (top, done) = (self.a.label(), self.a.label());
+        self.a.bind(top);
+        self.get(b, Reg::Rax, fraction);
+        self.divide_by_ten();
+        self.a.test(Reg::Rdx, Reg::Rdx);
+        self.a.jcc(Cond::NotEqual, done);
+        self.set(b, fraction, Reg::Rax);
+        self.bump(b, digits, -1);
+        self.a.jmp(top);
+        self.a.bind(done);
+        self.get(b, Reg::Rax, fraction);
+        self.set(b, t, Reg::Rax);
+        self.count_digits(b, t, digits);
+        let (top, done) = (self.a.label(), self.a.label());
+        self.a.bind(top);
+        self.get(b, Reg::Rax, digits);
+        self.a.alu_imm(Alu::Cmp, Reg::Rax, 0);
+        self.a.jcc(Cond::LessEqual, done);
+        self.out_byte(b'0');
+        self.bump(b, digits, -1);
+        self.a.jmp(top);
+        self.a.bind(done);
+        self.get(b, Reg::Rax, fraction);
+        self.a.push(Reg::Rax);
+        self.call(out_uint, 1);
+        self.a.bind(no_dot);
+
+        let end = self.a.label();
+        self.get(b, Reg::Rax, scientific);
+        self.a.test(Reg::Rax, Reg::Rax);
+        self.a.jcc(Cond::Equal, end);
+        self.out_byte(b'e');
+        self.get(b, Reg::Rax, exponent);
+        self.a.push(Reg::Rax);
+        self.call(out_int, 1);
+        self.a.bind(end);
+        self.a.mov_imm(Reg::Rax, 0);
+    }
+
+    /// concat(a, b): a new string with the bytes of both.
+    fn concat_body(&mut self, b: &mut Body) {
+        let alloc = self.runtime(Runtime::Alloc);
+        let (left, right, result) = (b.local(), b.local(), b.local());
+        for (string, length) in [(0, left), (1, right)] {
+            self.get(b, Reg::Rax, string);
+            self.a.load(Reg::Rax, Reg::Rax, 0);
+            self.set(b, length, Reg::Rax);
+        }
+        self.get(b, Reg::Rax, left);
+        self.get(b, Reg::Rcx, right);
+        self.a.alu(Alu::Add, Reg::Rax, Reg::Rcx);
+        self.a.alu_imm(Alu::Add, Reg::Rax, 8);
+        self.a.push(Reg::Rax);
+        self.call(alloc, 1);
+        self.set(b, result, Reg::Rax);
+        self.get(b, Reg::Rcx, left);
+        self.get(b, Reg::Rdx, right);
+        self.a.alu(Alu::Add, Reg::Rcx, Reg::Rdx);
+        self.a.store(Reg::Rax, 0, Reg::Rcx);
+
+        // Both copied after the length, `b` after `a`.
+        for (string, at, length) in [(0, None, left), (1, Some(left), right)] {
+            self.get(b, Reg::Rdi, result);
+            self.a.alu_imm(Alu::Add, Reg::Rdi, 8);
+            if let Some(at) = at {
+                self.get(b, Reg::Rax, at);
+                self.a.alu(Alu::Add, Reg::Rdi, Reg::Rax);
+            }
+            self.get(b, Reg::Rsi, string);
+            self.a.alu_imm(Alu::Add, Reg::Rsi, 8);
+            self.get(b, Reg::Rcx, length);
+            self.a.rep_movsb();
+        }
+        self.get(b, Reg::Rax, result);
+    }
+
+    /// Returns `value` from the function if `rax` is 0.
+    fn return_if_zero(&mut self, value: i64) {
+        let go_on = self.a.label();
+        self.a.test(Reg::Rax, Reg::Rax);
+        self.a.jcc(Cond::NotEqual, go_on);
+        self.a.mov_imm(Reg::Rax, value);
+        self.ret();
+        self.a.bind(go_on);
+    }
+
+    /// Returns 0 from the function if the lengths of the two parameters differ,
+    /// leaving the length in `length`.
+    fn same_length(&mut self, b: &Body, length: u32) {
+        self.get(b, Reg::Rax, 0);
+        self.a.load(Reg::Rax, Reg::Rax, 0);
+        self.set(b, length, Reg::Rax);
+        self.get(b, Reg::Rcx, 1);
+        self.a.load(Reg::Rcx, Reg::Rcx, 0);
+        self.a.alu(Alu::Cmp, Reg::Rax, Reg::Rcx);
+        self.a.set(Cond::Equal, Reg::Rax);
+        self.return_if_zero(0);
+    }
+
+    /// string_eq(a, b): whether both have the same bytes.
+    fn string_eq_body(&mut self, b: &mut Body) {
+        let (length, i) = (b.local(), b.local());
+        self.same_length(b, length);
+        self.counted_loop(b, i, length, |c, b| {
+            for (string, reg) in [(0, Reg::Rdx), (1, Reg::Rsi)] {
+                c.get(b, reg, string);
+                c.get(b, Reg::Rcx, i);
+                c.a.alu(Alu::Add, reg, Reg::Rcx);
+                c.a.load_byte(reg, reg, 8);
+            }
+            c.a.alu(Alu::Cmp, Reg::Rdx, Reg::Rsi);
+            c.a.set(Cond::Equal, Reg::Rax);
+            c.return_if_zero(0);
+        });
+        self.a.mov_imm(Reg::Rax, 1);
+    }
+
+    /// range(start, end): `[start, start + 1, ..., end - 1]`.
+    fn range_body(&mut self, b: &mut Body) {
+        let alloc = self.runtime(Runtime::Alloc);
+        let (length, list, i) = (b.local(), b.local(), b.local());
+        let counted = self.a.label();
+        self.get(b, Reg::Rax, 1);
+        self.get(b, Reg::Rcx, 0);
+        self.a.alu(Alu::Sub, Reg::Rax, Reg::Rcx);
+        self.set(b, length, Reg::Rax);
+        self.a.test(Reg::Rax, Reg::Rax);
+        self.a.jcc(Cond::GreaterEqual, counted);
+        self.a.mov_imm(Reg::Rax, 0);
+        self.set(b, length, Reg::Rax);
+        self.a.bind(counted);
+
+        self.get(b, Reg::Rax, length);
+        self.a.shift(Shift::Shl, Reg::Rax, 3);
+        self.a.alu_imm(Alu::Add, Reg::Rax, 8);
+        self.a.push(Reg::Rax);
+        self.call(alloc, 1);
+        self.set(b, list, Reg::Rax);
+        self.get(b, Reg::Rcx, length);
+        self.a.store(Reg::Rax, 0, Reg::Rcx);
+        self.counted_loop(b, i, length, |c, b| {
+            c.item_address(b, Reg::Rdx, list, i);
+            c.get(b, Reg::Rax, 0);
+            c.get(b, Reg::Rcx, i);
+            c.a.alu(Alu::Add, Reg::Rax, Reg::Rcx);
+            c.a.store(Reg::Rdx, 8, Reg::Rax);
+        });
+        self.get(b, Reg::Rax, list);
+    }
+
+    /// write<T>(value), for everything but ints and floats, which have their own.
+    fn write_body(&mut self, b: &mut Body, ty: &Type) {
+        match ty {
+            Type::String => {
+                let out_string = self.runtime(Runtime::OutString);
+                self.out_byte(b'\'');
+                self.get(b, Reg::Rax, 0);
+                self.a.push(Reg::Rax);
+                self.call(out_string, 1);
+                self.out_byte(b'\'');
+            }
+            Type::Bool => {
+                let (no, done) = (self.a.label(), self.a.label());
+                self.get(b, Reg::Rax, 0);
+                self.a.test(Reg::Rax, Reg::Rax);
+                self.a.jcc(Cond::Equal, no);
+                self.out_text("yes");
+                self.a.jmp(done);
+                self.a.bind(no);
+                self.out_text("no");
+                self.a.bind(done);
+            }
+            Type::List(item) => {
+                let (length, i) = (b.local(), b.local());
+                self.out_byte(b'[');
+                self.get(b, Reg::Rax, 0);
+                self.a.load(Reg::Rax, Reg::Rax, 0);
+                self.set(b, length, Reg::Rax);
+                self.counted_loop(b, i, length, |c, b| {
+                    let first = c.a.label();
+                    c.get(b, Reg::Rax, i);
+                    c.a.test(Reg::Rax, Reg::Rax);
+                    c.a.jcc(Cond::Equal, first);
+                    c.out_text(", ");
+                    c.a.bind(first);
+                    c.item_address(b, Reg::Rax, 0, i);
+                    c.a.load(Reg::Rax, Reg::Rax, 8);
+                    c.write(item);
+                });
+                self.out_byte(b']');
+            }
+            Type::Record(fields) => {
+                self.out_byte(b'{');
+                for (i, (key, field)) in fields.iter().enumerate() {
+                    let separator = match i {
+                        0 => "",
+                        _ => ", ",
+                    };
+                    self.out_text(&format!("{}{}: ", separator, key));
+                    self.get(b, Reg::Rax, 0);
+                    self.a.load(Reg::Rax, Reg::Rax, 8 * i as i32);
+                    self.write(field);
+                }
+                self.out_byte(b'}');
+            }
+            Type::Fn(_, _) => {
+                let out_string = self.runtime(Runtime::OutString);
+                self.out_text("<fn ");
+                self.get(b, Reg::Rax, 0);
+                self.a.load(Reg::Rax, Reg::Rax, 8);
+                self.a.push(Reg::Rax);
+                self.call(out_string, 1);
+                self.out_byte(b'>');
+            }
+            Type::Int | Type::Float => {
+                self.get(b, Reg::Rax, 0);
+                self.write(ty);
+            }
+            Type::Unit | Type::Var(_) => self.out_text("()"),
+        }
+    }
+
+    /// eq<T>(a, b), for lists and records.
+    fn eq_body(&mut self, b: &mut Body, ty: &Type) {
+        match ty {
+            Type::List(item) => {
+                let (length, i) = (b.local(), b.local());
+                self.same_length(b, length);
+                self.counted_loop(b, i, length, |c, b| {
+                    for list in [0, 1] {
+                        c.item_address(b, Reg::Rax, list, i);
+                        c.a.load(Reg::Rax, Reg::Rax, 8);
+                        c.a.push(Reg::Rax);
+                    }
+                    c.eq(item);
+                    c.return_if_zero(0);
+                });
+            }
+            Type::Record(fields) => {
+                for (i, field) in fields.values().enumerate() {
+                    for record in [0, 1] {
+                        self.get(b, Reg::Rax, record);
+                        self.a.load(Reg::Rax, Reg::Rax, 8 * i as i32);
+                        self.a.push(Reg::Rax);
+                    }
+                    self.eq(field);
+                    self.return_if_zero(0);
+                }
+            }
+            _ => {}
+        }
+        self.a.mov_imm(Reg::Rax, 1);
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+    use crate::build::front_end;
+
+    /// Links `source`'s object with `ld` and runs it, giving its output, errors and exit code.
+    fn run(name: &str, source: &str) -> (String, String, Option<i32>) {
+        let folder =
+            std::env::temp_dir().join(format!("nnc-native-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        let (object, program) = (folder.join("program.o"), folder.join("program"));
+        let bytes = LinuxX86
+            .emit(&front_end(source))
+            .expect("the backend failed");
+        std::fs::write(&object, bytes).unwrap();
+        let linked = Command::new("ld")
+            .arg(&object)
+            .arg("-o")
+            .arg(&program)
+            .status()
+            .expect("couldn't run ld");
+        assert!(linked.success());
+        let output = Command::new(&program).output().unwrap();
+        std::fs::remove_dir_all(&folder).unwrap();
+        return (
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+            output.status.code(),
+        );
+    }
+
+    #[test]
+    fn division_wraps_like_the_c_runtime() {
+        let (out, errors, code) = run(
+            "wrap",
+            "let min = 0 - 9223372036854775807 - 1
+            let m1 = 0 - 1
+            print(7 / 2)
+            print((0 - 7) / 2)
+            print((0 - 7) % 3)
+            print(min / m1)
+            print(min % m1)",
+        );
+        assert_eq!(out, "3\n-3\n-1\n-9223372036854775808\n0\n");
+        assert_eq!((errors.as_str(), code), ("", Some(0)));
+    }
+
+    #[test]
+    fn division_by_zero_panics() {
+        for op in ["/", "%"] {
+            let source = format!(
+                "let z = 0
+                print('before')
+                print(5 {} z)
+                print('after')",
+                op
+            );
+            let (out, errors, code) = run(if op == "/" { "div" } else { "rem" }, &source);
+            assert_eq!(out, "before\n");
+            assert_eq!(errors, "error: Division by zero.\n");
+            assert_eq!(code, Some(1));
+        }
+    }
+}
@@ -0,0 +1,901 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use colored::Colorize;
+
+use crate::{
+    build::Backend,
+    interp::INTRINSICS,
+    ir::{IRKind, IRNode, Literal, Module, INTRINSIC_PREFIX},
+};
+
+// nano bytecode, `nbbc`, and its `.nb8` files.
+//
+// A program is a constant pool, the names of its globals and a table of
+// functions, the last of which is the top-level code. Functions run on a
+// stack machine (see `vm.rs`): instructions take their operands from the
+// stack and push their result, and locals live in numbered slots, the
+// captures and parameters first. Values are dynamically typed, exactly like
+// the interpreter's, so no type information is kept.
+//
+// In a file, every number is little-endian:
+//
+//   magic      "NB8\0"
+//   version    u16
+//   constants  u32 count, then a tag byte each: 0 int (i64), 1 float (f64),
+//              2 string (u32 length, UTF-8 bytes)
+//   globals    u32 count, a string each
+//   functions  u32 count, then for each: name (string), arity (u16),
+//              locals (u16), u32 instruction count, instructions
+//
+// An instruction is an opcode byte followed by its operands. Jumps point at
+// instruction indices, not byte offsets.
+
+pub const MAGIC: &[u8; 4] = b"NB8\0";
+
+/// Bumped whenever the format changes; files with another version aren't read.
+pub const VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    // Pushes a constant from the pool.
+    Const(u32),
+    Unit,
+    Bool(bool),
+    Load(u16),
+    // Pops into a local.
+    Store(u16),
+    LoadGlobal(u32),
+    StoreGlobal(u32),
+    Dup,
+    Pop,
+    // Calls a function of the program with the arguments on the stack.
+    Call { function: u32, args: u8 },
+    // Calls `__nano__::<INTRINSICS[id]>`.
+    Intrinsic { id: u8, args: u8 },
+    // A closure of the function named by a string constant, over the captures on the stack.
+    Closure { symbol: u32, captures: u8 },
+    // Calls the closure under the arguments.
+    CallValue { args: u8 },
+    // Gathers items into a list.
+    List(u32),
+    // Gathers (key, value) pairs into a record, keys being string constants.
+    Record(u32),
+    // Reads the field named by a string constant.
+    GetField(u32),
+    // Pops a value into the list in a local.
+    Push(u16),
+    // Pushes the next item of the list in `list`, at the position in `index`,
+    // and moves `index` forward; jumps to `done` when there's none left.
+    Next { list: u16, index: u16, done: u32 },
+    Jump(u32),
+    Return,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionCode {
+    pub name: String,
+    // The captures and parameters, which come first among the locals.
+    pub arity: u16,
+    pub locals: u16,
+    pub code: Vec<Instruction>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Program {
+    pub constants: Vec<Constant>,
+    pub globals: Vec<String>,
+    // The top-level code is the last one.
+    pub functions: Vec<FunctionCode>,
+}
+
+impl Program {
+    pub fn entry(&self) -> usize {
+        self.functions.len() - 1
+    }
+
+    pub fn constant_string(&self, index: u32) -> Option<&str> {
+        match self.constants.get(index as usize) {
+            Some(Constant::String(s)) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// The `nbbc` target.
+pub struct Nbbc;
+
+impl Backend for Nbbc {
+    fn emit(&self, module: &Module) -> Result<Vec<u8>, String> {
+        return Ok(encode(&compile(module)?));
+    }
+}
+
+/// Compiles a whole IR module to bytecode.
+pub fn compile(module: &Module) -> Result<Program, String> {
+    let mut compiler = Compiler {
+        program: Program::default(),
+        functions: module
+            .functions
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.symbol.clone(), i as u32))
+            .collect(),
+        globals: HashMap::new(),
+    };
+    for (i, (symbol, _)) in module.globals.iter().enumerate() {
+        compiler.globals.insert(symbol.clone(), i as u32);
+        compiler.program.globals.push(symbol.clone());
+    }
+
+    for function in &module.functions {
+        let names = function.captures.iter().chain(function.params.iter());
+        let mut body = Body::default();
+        for (name, _) in names {
+            body.declare(name);
+        }
+        let arity = body.locals;
+        compiler.expr(&mut body, &function.body)?;
+        body.code.push(Instruction::Return);
+        compiler.program.functions.push(FunctionCode {
+            name: function.symbol.clone(),
+            arity,
+            locals: body.locals,
+            code: body.code,
+        });
+    }
+
+    let mut body = Body::default();
+    body.code.push(Instruction::Unit);
+    for node in &module.body {
+        body.code.push(Instruction::Pop);
+        compiler.expr(&mut body, node)?;
+    }
+    body.code.push(Instruction::Return);
+    compiler.program.functions.push(FunctionCode {
+        name: "<main>".to_string(),
+        arity: 0,
+        locals: body.locals,
+        code: body.code,
+    });
+
+    return Ok(compiler.program);
+}
+
+#[derive(Default)]
+struct Body {
+    locals: u16,
+    // The locals in scope by name, searched from the end.
+    scopes: Vec<(String, u16)>,
+    code: Vec<Instruction>,
+}
+
+impl Body {
+    fn local(&mut self) -> u16 {
+        self.locals += 1;
+        return self.locals - 1;
+    }
+
+    fn declare(&mut self, name: &str) -> u16 {
+        let local = self.local();
+        self.scopes.push((name.to_string(), local));
+        return local;
+    }
+
+    /// Where the next instruction goes.
+    fn here(&self) -> u32 {
+        self.code.len() as u32
+    }
+}
+
+struct Compiler {
+    program: Program,
+    functions: HashMap<String, u32>,
+    globals: HashMap<String, u32>,
+}
+
+impl Compiler {
+    fn constant(&mut self, constant: Constant) -> u32 {
+        // Floats are compared by their bits, as `0.0 == -0.0` but they print differently.
+        let same = |c: &Constant| match (c, &constant) {
+            (Constant::Float(a), Constant::Float(b)) => a.to_bits() == b.to_bits(),
+            (a, b) => a == b,
+        };
+        if let Some(i) = self.program.constants.iter().position(same) {
+            return i as u32;
+        }
+        self.program.constants.push(constant);
+        return self.program.constants.len() as u32 - 1;
+    }
+
+    fn string(&mut self, s: &str) -> u32 {
+        self.constant(Constant::String(s.to_string()))
+    }
+
+    fn global(&self, symbol: &str) -> Result<u32, String> {
+        match self.globals.get(symbol) {
+            Some(g) => Ok(*g),
+            None => Err(format!("The nbbc backend can't find global '{}'.", symbol)),
+        }
+    }
+
+    /// Compiles `node`, leaving its value on the stack.
+    fn expr(&mut self, b: &mut Body, node: &IRNode) -> Result<(), String> {
+        match &node.kind {
+            IRKind::Literal(literal) => {
+                let instruction = match literal {
+                    Literal::Unit => Instruction::Unit,
+                    Literal::Bool(x) => Instruction::Bool(*x),
+                    Literal::Int(i) => Instruction::Const(self.constant(Constant::Int(*i))),
+                    Literal::Float(x) => Instruction::Const(self.constant(Constant::Float(*x))),
+                    Literal::String(s) => Instruction::Const(self.string(s)),
+                };
+                b.code.push(instruction);
+            }
+            IRKind::Local(name) => match b.scopes.iter().rev().find(|(n, _)| n == name) {
+                Some((_, local)) => b.code.push(Instruction::Load(*local)),
+                None => return Err(format!("The nbbc backend can't find local '{}'.", name)),
+            },
+            IRKind::Global(symbol) => {
+                let global = self.global(symbol)?;
+                b.code.push(Instruction::LoadGlobal(global));
+            }
+            IRKind::SetGlobal { symbol, value } => {
+                let global = self.global(symbol)?;
+                self.expr(b, value)?;
+                b.code.push(Instruction::Dup);
+                b.code.push(Instruction::StoreGlobal(global));
+            }
+            IRKind::Let { name, value } => {
+                self.expr(b, value)?;
+                let local = b.declare(name);
+                b.code.push(Instruction::Dup);
+                b.code.push(Instruction::Store(local));
+            }
+            IRKind::Block(items) => {
+                let depth = b.scopes.len();
+                b.code.push(Instruction::Unit);
+                for item in items {
+                    b.code.push(Instruction::Pop);
+                    self.expr(b, item)?;
+                }
+                b.scopes.truncate(depth);
+            }
+            IRKind::FnRef { symbol, captures } => {
+                for capture in captures {
+                    self.expr(b, capture)?;
+                }
+                let symbol = self.string(symbol);
+                b.code.push(Instruction::Closure {
+                    symbol,
+                    captures: count(captures.len())?,
+                });
+            }
+            IRKind::SymbolCall { symbol, args } => {
+                for arg in args {
+                    self.expr(b, arg)?;
+                }
+                let n = count(args.len())?;
+                let instruction = match symbol.strip_prefix(INTRINSIC_PREFIX) {
+                    Some(name) => match INTRINSICS.iter().position(|i| *i == name) {
+                        Some(id) => Instruction::Intrinsic {
+                            id: id as u8,
+                            args: n,
+                        },
+                        None => return Err(format!("The nbbc backend has no '{}'.", symbol)),
+                    },
+                    None => match self.functions.get(symbol) {
+                        Some(function) => Instruction::Call {
+                            function: *function,
+                            args: n,
+                        },
+                        None => return Err(format!("The nbbc backend can't find '{}'.", symbol)),
+                    },
+                };
+                b.code.push(instruction);
+            }
+            IRKind::Call { callee, args } => {
+                self.expr(b, callee)?;
+                for arg in args {
+                    self.expr(b, arg)?;
+                }
+                b.code.push(Instruction::CallValue {
+                    args: count(args.len())?,
+                });
+            }
+            IRKind::List(items) => {
+                for item in items {
+                    self.expr(b, item)?;
+                }
+                b.code.push(Instruction::List(items.len() as u32));
+            }
+            IRKind::Record(fields) => {
+                for (key, value) in fields {
+                    let key = self.string(key);
+                    b.code.push(Instruction::Const(key));
+                    self.expr(b, value)?;
+                }
+                b.code.push(Instruction::Record(fields.len() as u32));
+            }
+            IRKind::GetField { record, key } => {
+                self.expr(b, record)?;
+                let key = self.string(key);
+                b.code.push(Instruction::GetField(key));
+            }
+            IRKind::Loop {
+                var,
+                iterable,
+                body,
+                collect,
+            } => {
+                let (list, index, results) = (b.local(), b.local(), b.local());
+                self.expr(b, iterable)?;
+                b.code.push(Instruction::Store(list));
+                let zero = self.constant(Constant::Int(0));
+                b.code.push(Instruction::Const(zero));
+                b.code.push(Instruction::Store(index));
+                if *collect {
+                    b.code.push(Instruction::List(0));
+                    b.code.push(Instruction::Store(results));
+                }
+
+                let start = b.here();
+                b.code.push(Instruction::Next {
+                    list,
+                    index,
+                    done: 0,
+                });
+                let depth = b.scopes.len();
+                let item = b.declare(var);
+                b.code.push(Instruction::Store(item));
+                self.expr(b, body)?;
+                b.scopes.truncate(depth);
+                match collect {
+                    true => b.code.push(Instruction::Push(results)),
+                    false => b.code.push(Instruction::Pop),
+                }
+                b.code.push(Instruction::Jump(start));
+
+                let end = b.here();
+                b.code[start as usize] = Instruction::Next {
+                    list,
+                    index,
+                    done: end,
+                };
+                match collect {
+                    true => b.code.push(Instruction::Load(results)),
+                    false => b.code.push(Instruction::Unit),
+                }
+            }
+            IRKind::Comptime(value) => self.expr(b, value)?,
+            IRKind::Import { path } => {
+                return Err(format!(
+                    "The nbbc backend can't compile the import of '{}'.",
+                    path
+                ));
+            }
+        }
+        return Ok(());
+    }
+}
+
+fn count(n: usize) -> Result<u8, String> {
+    u8::try_from(n).map_err(|_| format!("nbbc calls take up to 255 values, not {}.", n))
+}
+
+// Opcodes, in the order of `Instruction`'s variants.
+const CONST: u8 = 0x01;
+const UNIT: u8 = 0x02;
+const BOOL: u8 = 0x03;
+const LOAD: u8 = 0x04;
+const STORE: u8 = 0x05;
+const LOAD_GLOBAL: u8 = 0x06;
+const STORE_GLOBAL: u8 = 0x07;
+const DUP: u8 = 0x08;
+const POP: u8 = 0x09;
+const CALL: u8 = 0x0a;
+const INTRINSIC: u8 = 0x0b;
+const CLOSURE: u8 = 0x0c;
+const CALL_VALUE: u8 = 0x0d;
+const LIST: u8 = 0x0e;
+const RECORD: u8 = 0x0f;
+const GET_FIELD: u8 = 0x10;
+const PUSH: u8 = 0x11;
+const NEXT: u8 = 0x12;
+const JUMP: u8 = 0x13;
+const RETURN: u8 = 0x14;
+
+/// The contents of a `.nb8` file.
+pub fn encode(program: &Program) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+
+    out.extend_from_slice(&(program.constants.len() as u32).to_le_bytes());
+    for constant in &program.constants {
+        match constant {
+            Constant::Int(i) => {
+                out.push(0);
+                out.extend_from_slice(&i.to_le_bytes());
+            }
+            Constant::Float(x) => {
+                out.push(1);
+                out.extend_from_slice(&x.to_le_bytes());
+            }
+            Constant::String(s) => {
+                out.push(2);
+                write_string(&mut out, s);
+            }
+        }
+    }
+
+    out.extend_from_slice(&(program.globals.len() as u32).to_le_bytes());
+    for global in &program.globals {
+        write_string(&mut out, global);
+    }
+
+    out.extend_from_slice(&(program.functions.len() as u32).to_le_bytes());
+    for function in &program.functions {
+        write_string(&mut out, &function.name);
+        out.extend_from_slice(&function.arity.to_le_bytes());
+        out.extend_from_slice(&function.locals.to_le_bytes());
+        out.extend_from_slice(&(function.code.len() as u32).to_le_bytes());
+        for instruction in &function.code {
+            encode_instruction(&mut out, instruction);
+        }
+    }
+    return out;
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn encode_instruction(out: &mut Vec<u8>, instruction: &Instruction) {
+    match *instruction {
+        Instruction::Const(c) => {
+            out.push(CONST);
+            out.extend_from_slice(&c.to_le_bytes());
+        }
+        Instruction::Unit => out.push(UNIT),
+        Instruction::Bool(x) => out.extend_from_slice(&[BOOL, x as u8]),
+        Instruction::Load(l) => {
+            out.push(LOAD);
+            out.extend_from_slice(&l.to_le_bytes());
+        }
+        Instruction::Store(l) => {
+            out.push(STORE);
+            out.extend_from_slice(&l.to_le_bytes());
+        }
+        Instruction::LoadGlobal(g) => {
+            out.push(LOAD_GLOBAL);
+            out.extend_from_slice(&g.to_le_bytes());
+        }
+        Instruction::StoreGlobal(g) => {
+            out.push(STORE_GLOBAL);
+            out.extend_from_slice(&g.to_le_bytes());
+        }
+        Instruction::Dup => out.push(DUP),
+        Instruction::Pop => out.push(POP),
+        Instruction::Call { function, args } => {
+            out.push(CALL);
+            out.extend_from_slice(&function.to_le_bytes());
+            out.push(args);
+        }
+        Instruction::Intrinsic { id, args } => out.extend_from_slice(&[INTRINSIC, id, args]),
+        Instruction::Closure { symbol, captures } => {
+            out.push(CLOSURE);
+            out.extend_from_slice(&symbol.to_le_bytes());
+            out.push(captures);
+        }
+        Instruction::CallValue { args } => out.extend_from_slice(&[CALL_VALUE, args]),
+        Instruction::List(n) => {
+            out.push(LIST);
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        Instruction::Record(n) => {
+            out.push(RECORD);
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        Instruction::GetField(key) => {
+            out.push(GET_FIELD);
+            out.extend_from_slice(&key.to_le_bytes());
+        }
+        Instruction::Push(l) => {
+            out.push(PUSH);
+            out.extend_from_slice(&l.to_le_bytes());
+        }
+        Instruction::Next { list, index, done } => {
+            out.push(NEXT);
+            out.extend_from_slice(&list.to_le_bytes());
+            out.extend_from_slice(&index.to_le_bytes());
+            out.extend_from_slice(&done.to_le_bytes());
+        }
+        Instruction::Jump(to) => {
+            out.push(JUMP);
+            out.extend_from_slice(&to.to_le_bytes());
+        }
+        Instruction::Return => out.push(RETURN),
+    }
+}
+
+/// Why a `.nb8` file couldn't be read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    NotBytecode,
+    Version(u16),
+    // The byte offset where the file stopped making sense.
+    Malformed(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::NotBytecode => write!(f, "This isn't a nano bytecode file."),
+            DecodeError::Version(v) => write!(
+                f,
+                "This file is nbbc version {}, but this nnc reads version {}.",
+                v, VERSION
+            ),
+            DecodeError::Malformed(at) => write!(f, "The file is malformed at byte {}.", at),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], DecodeError> {
+        let end = self.at + n;
+        if end > self.bytes.len() {
+            return Err(DecodeError::Malformed(self.at));
+        }
+        let slice = &self.bytes[self.at..end];
+        self.at = end;
+        return Ok(slice);
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes(
+            self.take(2)?.try_into().unwrap_or_default(),
+        ))
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(
+            self.take(4)?.try_into().unwrap_or_default(),
+        ))
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(
+            self.take(8)?.try_into().unwrap_or_default(),
+        ))
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let at = self.at;
+        let length = self.u32()? as usize;
+        let bytes = self.take(length)?;
+        return String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::Malformed(at));
+    }
+}
+
+/// Reads a `.nb8` file back.
+pub fn decode(bytes: &[u8]) -> Result<Program, DecodeError> {
+    let mut r = Reader { bytes, at: 0 };
+    if r.take(4).map_err(|_| DecodeError::NotBytecode)? != MAGIC {
+        return Err(DecodeError::NotBytecode);
+    }
+    let version = r.u16()?;
+    if version != VERSION {
+        return Err(DecodeError::Version(version));
+    }
+
+    let mut program = Program::default();
+    for _ in 0..r.u32()? {
+        let at = r.at;
+        let constant = match r.u8()? {
+            0 => Constant::Int(r.u64()? as i64),
+            1 => Constant::Float(f64::from_bits(r.u64()?)),
+            2 => Constant::String(r.string()?),
+            _ => return Err(DecodeError::Malformed(at)),
+        };
+        program.constants.push(constant);
+    }
+    for _ in 0..r.u32()? {
+        program.globals.push(r.string()?);
+    }
+    for _ in 0..r.u32()? {
+        let name = r.string()?;
+        let arity = r.u16()?;
+        let locals = r.u16()?;
+        let mut code = Vec::new();
+        for _ in 0..r.u32()? {
+            code.push(decode_instruction(&mut r)?);
+        }
+        program.functions.push(FunctionCode {
+            name,
+            arity,
+            locals,
+            code,
+        });
+    }
+
+    if program.functions.is_empty() || r.at != bytes.len() {
+        return Err(DecodeError::Malformed(r.at));
+    }
+    return Ok(program);
+}
+
+fn decode_instruction(r: &mut Reader) -> Result<Instruction, DecodeError> {
+    let at = r.at;
+    let instruction = match r.u8()? {
+        CONST => Instruction::Const(r.u32()?),
+        UNIT => Instruction::Unit,
+        BOOL => Instruction::Bool(r.u8()? != 0),
+        LOAD => Instruction::Load(r.u16()?),
+        STORE => Instruction::Store(r.u16()?),
+        LOAD_GLOBAL => Instruction::LoadGlobal(r.u32()?),
+        STORE_GLOBAL => Instruction::StoreGlobal(r.u32()?),
+        DUP => Instruction::Dup,
+        POP => Instruction::Pop,
+        CALL => Instruction::Call {
+            function: r.u32()?,
+            args: r.u8()?,
+        },
+        INTRINSIC => Instruction::Intrinsic {
+            id: r.u8()?,
+            args: r.u8()?,
+        },
+        CLOSURE => Instruction::Closure {
+            symbol: r.u32()?,
+            captures: r.u8()?,
+        },
+        CALL_VALUE => Instruction::CallValue { args: r.u8()? },
+        LIST => Instruction::List(r.u32()?),
+        RECORD => Instruction::Record(r.u32()?),
+        GET_FIELD => Instruction::GetField(r.u32()?),
+        PUSH => Instruction::Push(r.u16()?),
+        NEXT => Instruction::Next {
+            list: r.u16()?,
+            index: r.u16()?,
+            done: r.u32()?,
+        },
+        JUMP => Instruction::Jump(r.u32()?),
+        RETURN => Instruction::Return,
+        _ => return Err(DecodeError::Malformed(at)),
+    };
+    return Ok(instruction);
+}
+
+/// `program` in a readable form, like `nnc disasm` shows it:
+///
+/// fn square (arity 1, locals 1)
+///   0000  LOAD          0
+///   0001  LOAD          0
+///   0002  INTRINSIC     mul 2
+///   0003  RETURN
+pub fn disassemble(program: &Program) -> String {
+    let mut out = format!(
+        "{} {}, {} constants, {} globals, {} functions\n",
+        "nbbc".cyan().bold(),
+        format!("v{}", VERSION).dimmed(),
+        program.constants.len(),
+        program.globals.len(),
+        program.functions.len()
+    );
+
+    out.push_str(&format!("\n{}\n", "constants".bold()));
+    for (i, constant) in program.constants.iter().enumerate() {
+        let shown = match constant {
+            Constant::Int(x) => format!("int     {}", x),
+            Constant::Float(x) => format!("float   {:?}", x),
+            Constant::String(s) => format!("string  {:?}", s),
+        };
+        out.push_str(&format!("  {:>4}  {}\n", format!("#{}", i).dimmed(), shown));
+    }
+
+    out.push_str(&format!("\n{}\n", "globals".bold()));
+    for (i, global) in program.globals.iter().enumerate() {
+        out.push_str(&format!(
+            "  {:>4}  {}\n",
+            format!("@{}", i).dimmed(),
+            global
+        ));
+    }
+
+    for function in &program.functions {
+        out.push_str(&format!(
+            "\n{} {} {}\n",
+            "fn".bold(),
+            function.name.cyan(),
+            format!("(arity {}, locals {})", function.arity, function.locals).dimmed()
+        ));
+        for (i, instruction) in function.code.iter().enumerate() {
+            let (name, operands) = describe(program, instruction);
+            let line = format!(
+                "  {}  {:<12}  {}",
+                format!("{:04}", i).dimmed(),
+                name,
+                operands
+            );
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+    }
+    return out;
+}
+
+/// The mnemonic of an instruction and its operands, with constants and names spelled out.
+fn describe(program: &Program, instruction: &Instruction) -> (&'static str, String) {
+    let constant = |c: u32| match program.constants.get(c as usize) {
+        Some(Constant::Int(x)) => format!("#{} {}", c, x.to_string().dimmed()),
+        Some(Constant::Float(x)) => format!("#{} {}", c, format!("{:?}", x).dimmed()),
+        Some(Constant::String(s)) => format!("#{} {}", c, format!("{:?}", s).dimmed()),
+        None => format!("#{} ?", c),
+    };
+    let global = |g: u32| {
+        let name = program.globals.get(g as usize).map(|s| s.as_str());
+        format!("@{} {}", g, name.unwrap_or("?").dimmed())
+    };
+
+    match *instruction {
+        Instruction::Const(c) => ("CONST", constant(c)),
+        Instruction::Unit => ("UNIT", String::new()),
+        Instruction::Bool(x) => ("BOOL", if x { "yes" } else { "no" }.to_string()),
+        Instruction::Load(l) => ("LOAD", l.to_string()),
+        Instruction::Store(l) => ("STORE", l.to_string()),
+        Instruction::LoadGlobal(g) => ("LOAD_GLOBAL", global(g)),
+        Instruction::StoreGlobal(g) => ("STORE_GLOBAL", global(g)),
+        Instruction::Dup => ("DUP", String::new()),
+        Instruction::Pop => ("POP", String::new()),
+        Instruction::Call { function, args } => {
+            let name = program
+                .functions
+                .get(function as usize)
+                .map(|f| f.name.as_str());
+            ("CALL", format!("{} {}", name.unwrap_or("?").cyan(), args))
+        }
+        Instruction::Intrinsic { id, args } => {
+            let name = INTRINSICS.get(id as usize).copied().unwrap_or("?");
+            ("INTRINSIC", format!("{} {}", name, args))
+        }
+        Instruction::Closure { symbol, captures } => {
+            let name = program.constant_string(symbol).unwrap_or("?");
+            ("CLOSURE", format!("{} {}", name.cyan(), captures))
+        }
+        Instruction::CallValue { args } => ("CALL_VALUE", args.to_string()),
+        Instruction::List(n) => ("LIST", n.to_string()),
+        Instruction::Record(n) => ("RECORD", n.to_string()),
+        Instruction::GetField(key) => ("GET_FIELD", constant(key)),
+        Instruction::Push(l) => ("PUSH", l.to_string()),
+        Instruction::Next { list, index, done } => {
+            ("NEXT", format!("{} {} -> {:04}", list, index, done))
+        }
+        Instruction::Jump(to) => ("JUMP", format!("-> {:04}", to)),
+        Instruction::Return => ("RETURN", String::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::front_end;
+
+    /// Checks that `program` encodes to bytes that decode to it, and encode to the same bytes again.
+    fn round_trips(program: &Program) {
+        let bytes = encode(program);
+        let decoded = decode(&bytes).expect("the encoding doesn't decode");
+        assert_eq!(&decoded, program);
+        assert_eq!(encode(&decoded), bytes);
+    }
+
+    #[test]
+    fn compiled_programs_round_trip() {
+        let module = front_end(
+            "fn square(x) -> x * x
+            let k = 10
+            let addk = x -> x + k
+            let xs = select addk(square(n)) for n in 0..5
+            print(xs)
+            let r = {name: 'nano', size: 1.5, tags: ['a', 'b']}
+            print(r.name)
+            for x in xs -> print(x is 26)",
+        );
+        round_trips(&compile(&module).expect("the backend failed"));
+    }
+
+    #[test]
+    fn every_instruction_round_trips() {
+        let code = vec![
+            Instruction::Const(0),
+            Instruction::Unit,
+            Instruction::Bool(true),
+            Instruction::Bool(false),
+            Instruction::Load(1),
+            Instruction::Store(u16::MAX),
+            Instruction::LoadGlobal(0),
+            Instruction::StoreGlobal(u32::MAX),
+            Instruction::Dup,
+            Instruction::Pop,
+            Instruction::Call {
+                function: 0,
+                args: 2,
+            },
+            Instruction::Intrinsic { id: 3, args: 1 },
+            Instruction::Closure {
+                symbol: 2,
+                captures: 1,
+            },
+            Instruction::CallValue { args: 0 },
+            Instruction::List(3),
+            Instruction::Record(2),
+            Instruction::GetField(2),
+            Instruction::Push(0),
+            Instruction::Next {
+                list: 0,
+                index: 1,
+                done: 20,
+            },
+            Instruction::Jump(0),
+            Instruction::Return,
+        ];
+        let program = Program {
+            constants: vec![
+                Constant::Int(i64::MIN),
+                Constant::Float(-0.1),
+                Constant::String("ünïcode".to_string()),
+                Constant::String(String::new()),
+            ],
+            globals: vec!["main::k".to_string()],
+            functions: vec![FunctionCode {
+                name: "<main>".to_string(),
+                arity: 0,
+                locals: 2,
+                code,
+            }],
+        };
+        round_trips(&program);
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let program = compile(&front_end("print(1)")).expect("the backend failed");
+        let mut bytes = encode(&program);
+        bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert_eq!(decode(&bytes), Err(DecodeError::Version(VERSION + 1)));
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        let program = compile(&front_end("print(1)")).expect("the backend failed");
+        let bytes = encode(&program);
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert_eq!(decode(&wrong_magic), Err(DecodeError::NotBytecode));
+        assert_eq!(decode(b"NB"), Err(DecodeError::NotBytecode));
+
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(matches!(decode(truncated), Err(DecodeError::Malformed(_))));
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(decode(&trailing), Err(DecodeError::Malformed(bytes.len())));
+    }
+}
@@ -0,0 +1,864 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    comptime::Comptime,
+    diagnostic::Diagnostic,
+    interp::{self, Value},
+    ir::{Function, IRKind, IRNode, Literal, Module, INTRINSIC_PREFIX},
+    lower::unique,
+    mono::bind,
+    types::{substitute, Type, TypeVar},
+};
+
+// The optimizer, what `#%optimization` turns on for `nnc compile`.
+//
+// Passes rewrite the whole module in place and say whether they changed
+// anything. Inlining, constant folding and dead code elimination feed each
+// other, inlining a call with constant arguments gives folding something
+// to do, which leaves `let`s nobody reads, so they run in rounds until
+// nothing changes. Renaming comes last, once.
+//
+// Nothing here changes what a program does: calls that would fail, like
+// `1 / 0` or an overflowing `+`, are left for the program to fail at, and
+// only code without side effects is removed. Integer arithmetic counts as
+// having some, since it can fail. The one visible difference is renaming,
+// printing a function shows its new name, which `keep_symbol_names` stops.
+
+/// What `#%optimization` can set, every pass but renaming being on unless it says no.
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    pub constant_folding: bool,
+    pub dead_code: bool,
+    pub inlining: bool,
+    // Off, functions and globals are renamed `f0`, `g0`...
+    pub keep_symbol_names: bool,
+    // Inline every call to a function that isn't recursive, whatever its size.
+    pub force_always_inline: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            constant_folding: true,
+            dead_code: true,
+            inlining: true,
+            keep_symbol_names: false,
+            force_always_inline: false,
+        }
+    }
+}
+
+/// Every setting `#%optimization` accepts.
+pub const SETTINGS: [&str; 5] = [
+    "constant_folding",
+    "dead_code",
+    "inlining",
+    "keep_symbol_names",
+    "force_always_inline",
+];
+
+/// What `#%optimization` asks for, `None` when the entry file doesn't have it.
+pub fn options(comptime: &Comptime) -> Result<Option<Options>, Diagnostic> {
+    let annotation = comptime
+        .annotations
+        .iter()
+        .rev()
+        .find(|a| a.name == "optimization");
+    let annotation = match annotation {
+        None => return Ok(None),
+        Some(annotation) => annotation,
+    };
+
+    let usage = "`#%optimization` takes a record of switches, like `{ keep_symbol_names: yes }`.";
+    let fields = match &annotation.value {
+        Value::Unit => return Ok(Some(Options::default())),
+        Value::Record(fields) => fields,
+        _ => return Err(Diagnostic::error(usage, annotation.span)),
+    };
+
+    let mut options = Options::default();
+    for (name, value) in fields.iter() {
+        let on = match value {
+            Value::Bool(b) => *b,
+            _ => return Err(Diagnostic::error(usage, annotation.span)),
+        };
+        match name.as_str() {
+            "constant_folding" => options.constant_folding = on,
+            "dead_code" => options.dead_code = on,
+            "inlining" => options.inlining = on,
+            "keep_symbol_names" => options.keep_symbol_names = on,
+            "force_always_inline" => options.force_always_inline = on,
+            _ => {
+                let message = format!("Unknown optimization setting '{}'.", name);
+                let note = format!("The settings are {}.", SETTINGS.join(", "));
+                return Err(Diagnostic::error(message, annotation.span).with_note(None, note));
+            }
+        }
+    }
+    return Ok(Some(options));
+}
+
+/// A rewrite of a whole module.
+pub trait Pass {
+    /// What `--emit=ir` calls it.
+    fn name(&self) -> &'static str;
+    /// Rewrites `module`, telling whether anything changed.
+    fn run(&self, module: &mut Module, options: &Options) -> bool;
+}
+
+/// The most rounds of inlining, folding and dead code elimination, in case they never settle.
+const MAX_ROUNDS: usize = 8;
+
+/// Optimizes `module` in place, returning the passes that changed something.
+pub fn optimize(module: &mut Module, options: &Options) -> Vec<&'static str> {
+    let mut passes: Vec<Box<dyn Pass>> = Vec::new();
+    if options.inlining {
+        passes.push(Box::new(Inline));
+    }
+    if options.constant_folding {
+        passes.push(Box::new(Fold));
+    }
+    if options.dead_code {
+        passes.push(Box::new(DeadCode));
+    }
+
+    let mut applied = Vec::new();
+    for _ in 0..MAX_ROUNDS {
+        let mut changed = false;
+        for pass in &passes {
+            if pass.run(module, options) {
+                changed = true;
+                if !applied.contains(&pass.name()) {
+                    applied.push(pass.name());
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    if !options.keep_symbol_names && Rename.run(module, options) {
+        applied.push(Rename.name());
+    }
+    return applied;
+}
+
+/// Functions this small are inlined even without `force_always_inline`, in nodes.
+const INLINE_SIZE: usize = 12;
+
+/// Replaces calls to functions that don't call themselves by their bodies.
+struct Inline;
+
+impl Pass for Inline {
+    fn name(&self) -> &'static str {
+        "inlining"
+    }
+
+    fn run(&self, module: &mut Module, options: &Options) -> bool {
+        let recursive = recursive(module);
+        let inlined: HashMap<String, Function> = module
+            .functions
+            .iter()
+            .filter(|f| !recursive.contains(&f.symbol))
+            .filter(|f| options.force_always_inline || size(&f.body) <= INLINE_SIZE)
+            .map(|f| (f.symbol.clone(), f.clone()))
+            .collect();
+        if inlined.is_empty() {
+            return false;
+        }
+
+        let mut changed = false;
+        for function in &mut module.functions {
+            let mut taken: HashSet<String> = function
+                .captures
+                .iter()
+                .chain(function.params.iter())
+                .map(|(name, _)| name.clone())
+                .collect();
+            locals(&function.body, &mut taken);
+            changed |= inline(&mut function.body, &inlined, &mut taken);
+        }
+        let mut taken = HashSet::new();
+        for node in &module.body {
+            locals(node, &mut taken);
+        }
+        for node in &mut module.body {
+            changed |= inline(node, &inlined, &mut taken);
+        }
+        return changed;
+    }
+}
+
+/// The functions that can end up calling themselves, or be called through themselves.
+fn recursive(module: &Module) -> HashSet<String> {
+    let callees: HashMap<&str, Vec<String>> = module
+        .functions
+        .iter()
+        .map(|f| {
+            let mut symbols = Vec::new();
+            calls(&f.body, &mut symbols);
+            (f.symbol.as_str(), symbols)
+        })
+        .collect();
+
+    let mut recursive = HashSet::new();
+    for function in &module.functions {
+        let mut seen = HashSet::new();
+        let mut stack = callees[function.symbol.as_str()].clone();
+        while let Some(symbol) = stack.pop() {
+            if symbol == function.symbol {
+                recursive.insert(symbol);
+                break;
+            }
+            if seen.insert(symbol.clone()) {
+                stack.extend(callees.get(symbol.as_str()).into_iter().flatten().cloned());
+            }
+        }
+    }
+    return recursive;
+}
+
+/// Inlines the calls to `inlined` under `node`, naming the new locals apart from `taken`.
+fn inline(
+    node: &mut IRNode,
+    inlined: &HashMap<String, Function>,
+    taken: &mut HashSet<String>,
+) -> bool {
+    let mut changed = false;
+    for child in node.children_mut() {
+        changed |= inline(child, inlined, taken);
+    }
+
+    if let IRKind::SymbolCall { symbol, args } = &mut node.kind {
+        if let Some(callee) = inlined.get(symbol.as_str()) {
+            let args = std::mem::take(args);
+            node.kind = IRKind::Block(inline_call(callee, args, &node.ty, taken));
+            changed = true;
+        }
+    }
+    return changed;
+}
+
+/// `callee(args)` as a block: its parameters bound to the arguments, then its body.
+fn inline_call(
+    callee: &Function,
+    args: Vec<IRNode>,
+    ty: &Type,
+    taken: &mut HashSet<String>,
+) -> Vec<IRNode> {
+    let params: Vec<&(String, Type)> = callee.captures.iter().chain(&callee.params).collect();
+
+    // What the callee's type variables are at this call.
+    let mut subst = HashMap::new();
+    for ((_, pattern), arg) in params.iter().zip(&args) {
+        bind(pattern, &arg.ty, &mut subst);
+    }
+    bind(&callee.ret, ty, &mut subst);
+
+    let mut items = Vec::new();
+    let mut names = HashMap::new();
+    for ((name, _), arg) in params.into_iter().zip(args) {
+        let fresh = unique(base(name), taken);
+        names.insert(name.clone(), fresh.clone());
+        let span = arg.span;
+        let value = Box::new(arg);
+        items.push(IRNode::new(
+            IRKind::Let { name: fresh, value },
+            Type::Unit,
+            span,
+        ));
+    }
+
+    let mut body = callee.body.clone();
+    retype(&mut body, &subst);
+    freshen(&mut body, &names, taken);
+    items.push(body);
+    return items;
+}
+
+/// `x` for `x#2`, so that renaming a renamed local doesn't pile up suffixes.
+fn base(name: &str) -> String {
+    return name.split('#').next().unwrap_or(name).to_string();
+}
+
+/// Applies `subst` to the types of `node` and everything under it.
+fn retype(node: &mut IRNode, subst: &HashMap<TypeVar, Type>) {
+    if subst.is_empty() {
+        return;
+    }
+    node.ty = substitute(&node.ty, subst);
+    for child in node.children_mut() {
+        retype(child, subst);
+    }
+}
+
+/// Gives every local bound under `node` a name not in `taken`, `names` being the renamings in scope.
+fn freshen(node: &mut IRNode, names: &HashMap<String, String>, taken: &mut HashSet<String>) {
+    match &mut node.kind {
+        IRKind::Local(name) => {
+            if let Some(fresh) = names.get(name.as_str()) {
+                *name = fresh.clone();
+            }
+        }
+        IRKind::Block(items) => {
+            let mut names = names.clone();
+            for item in items {
+                freshen(item, &names, taken);
+                if let IRKind::Let { name, .. } = &mut item.kind {
+                    let fresh = unique(base(name), taken);
+                    names.insert(std::mem::replace(name, fresh.clone()), fresh);
+                }
+            }
+        }
+        IRKind::Loop {
+            var,
+            iterable,
+            body,
+            ..
+        } => {
+            freshen(iterable, names, taken);
+            let fresh = unique(base(var), taken);
+            let mut names = names.clone();
+            names.insert(std::mem::replace(var, fresh.clone()), fresh);
+            freshen(body, &names, taken);
+        }
+        _ => {
+            for child in node.children_mut() {
+                freshen(child, names, taken);
+            }
+        }
+    }
+}
+
+/// Computes the intrinsic calls whose arguments are all literals, and propagates `let`s of literals.
+struct Fold;
+
+impl Pass for Fold {
+    fn name(&self) -> &'static str {
+        "constant_folding"
+    }
+
+    fn run(&self, module: &mut Module, _: &Options) -> bool {
+        let mut changed = false;
+        for node in code_mut(module) {
+            changed |= fold(node);
+        }
+        return changed;
+    }
+}
+
+fn fold(node: &mut IRNode) -> bool {
+    let mut changed = false;
+    for child in node.children_mut() {
+        changed |= fold(child);
+    }
+
+    let folded = match &node.kind {
+        IRKind::SymbolCall { symbol, args } => fold_call(symbol, args),
+        _ => None,
+    };
+    if let Some(literal) = folded {
+        node.kind = IRKind::Literal(literal);
+        return true;
+    }
+
+    // `let x = 1` makes the `x`s read after it `1`s, the `let` is then left for dead code elimination.
+    if let IRKind::Block(items) = &mut node.kind {
+        for i in 0..items.len() {
+            let constant = match &items[i].kind {
+                IRKind::Let { name, value } if matches!(value.kind, IRKind::Literal(_)) => {
+                    HashMap::from([(name.clone(), value.as_ref().clone())])
+                }
+                _ => continue,
+            };
+            changed |= replace_in_items(&mut items[i + 1..], &constant);
+        }
+    }
+    return changed;
+}
+
+/// The value of `symbol(args)` when it's an intrinsic that can run now and succeeds.
+fn fold_call(symbol: &str, args: &[IRNode]) -> Option<Literal> {
+    let name = symbol.strip_prefix(INTRINSIC_PREFIX)?;
+    // `print` has to wait for run time, and a list from `range` is bigger than its call.
+    if name == "print" || name == "range" {
+        return None;
+    }
+    let values = args
+        .iter()
+        .map(|a| match &a.kind {
+            IRKind::Literal(literal) => Some(literal_value(literal)),
+            _ => None,
+        })
+        .collect::<Option<Vec<Value>>>()?;
+
+    match interp::intrinsic(name, &values, false).ok()? {
+        Value::Unit => Some(Literal::Unit),
+        Value::Int(i) => Some(Literal::Int(i)),
+        Value::Float(x) => Some(Literal::Float(x)),
+        Value::Bool(b) => Some(Literal::Bool(b)),
+        Value::String(s) => Some(Literal::String(s.to_string())),
+        Value::List(_) | Value::Record(_) | Value::Closure { .. } => None,
+    }
+}
+
+fn literal_value(literal: &Literal) -> Value {
+    match literal {
+        Literal::Unit => Value::Unit,
+        Literal::Int(i) => Value::Int(*i),
+        Literal::Float(x) => Value::Float(*x),
+        Literal::Bool(b) => Value::Bool(*b),
+        Literal::String(s) => Value::String(s.as_str().into()),
+    }
+}
+
+/// Replaces the reads of the locals in `with` by their values, in items of the same block.
+fn replace_in_items(items: &mut [IRNode], with: &HashMap<String, IRNode>) -> bool {
+    let mut with = with.clone();
+    let mut changed = false;
+    for item in items {
+        if with.is_empty() {
+            break;
+        }
+        changed |= replace_locals(item, &with);
+        // Shadowed from there on.
+        if let IRKind::Let { name, .. } = &item.kind {
+            with.remove(name);
+        }
+    }
+    return changed;
+}
+
+fn replace_locals(node: &mut IRNode, with: &HashMap<String, IRNode>) -> bool {
+    match &mut node.kind {
+        IRKind::Local(name) => match with.get(name.as_str()) {
+            None => false,
+            Some(value) => {
+                let span = node.span;
+                *node = value.clone();
+                node.span = span;
+                true
+            }
+        },
+        IRKind::Block(items) => replace_in_items(items, with),
+        IRKind::Loop {
+            var,
+            iterable,
+            body,
+            ..
+        } => {
+            let mut changed = replace_locals(iterable, with);
+            let mut with = with.clone();
+            with.remove(var.as_str());
+            changed |= replace_locals(body, &with);
+            changed
+        }
+        _ => {
+            let mut changed = false;
+            for child in node.children_mut() {
+                changed |= replace_locals(child, with);
+            }
+            changed
+        }
+    }
+}
+
+/// Removes code without effects whose value isn't used, globals nobody reads and functions nobody calls.
+struct DeadCode;
+
+impl Pass for DeadCode {
+    fn name(&self) -> &'static str {
+        "dead_code"
+    }
+
+    fn run(&self, module: &mut Module, _: &Options) -> bool {
+        // A built program is only run, nothing imports its entry module.
+        let mut changed = !module.exports.is_empty();
+        module.exports.clear();
+        for node in code_mut(module) {
+            changed |= prune(node);
+        }
+        changed |= prune_items(&mut module.body);
+        changed |= remove_unread_globals(module);
+        changed |= remove_unused_functions(module);
+        return changed;
+    }
+}
+
+fn prune(node: &mut IRNode) -> bool {
+    let mut changed = false;
+    for child in node.children_mut() {
+        changed |= prune(child);
+    }
+
+    let single = match &mut node.kind {
+        IRKind::Block(items) => {
+            changed |= prune_items(items);
+            match items.len() {
+                0 => Some(IRNode::unit(node.span)),
+                1 if !matches!(items[0].kind, IRKind::Let { .. }) => items.pop(),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    // A block of a single expression is that expression.
+    if let Some(single) = single {
+        *node = single;
+        changed = true;
+    }
+    return changed;
+}
+
+/// Removes the items of a block whose value is dropped and that have no effect, the last one being its value.
+fn prune_items(items: &mut Vec<IRNode>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i + 1 < items.len() {
+        let unused = match &items[i].kind {
+            IRKind::Let { name, value } => {
+                let read = items[i + 1..].iter().any(|item| reads_local(item, name));
+                match read {
+                    true => None,
+                    false => Some(value.as_ref().clone()),
+                }
+            }
+            _ if is_pure(&items[i]) => {
+                items.remove(i);
+                changed = true;
+                continue;
+            }
+            _ => None,
+        };
+        // An unread `let` goes, its value stays if it has effects.
+        if let Some(value) = unused {
+            match is_pure(&value) {
+                true => {
+                    items.remove(i);
+                    changed = true;
+                    continue;
+                }
+                false => {
+                    items[i] = value;
+                    changed = true;
+                }
+            }
+        }
+        i += 1;
+    }
+    return changed;
+}
+
+/// Whether evaluating `node` can do nothing but give its value: no I/O, calls, writes or errors.
+fn is_pure(node: &IRNode) -> bool {
+    let own = match &node.kind {
+        IRKind::Literal(_)
+        | IRKind::Local(_)
+        | IRKind::Global(_)
+        | IRKind::FnRef { .. }
+        | IRKind::Let { .. }
+        | IRKind::Block(_)
+        | IRKind::List(_)
+        | IRKind::Record(_)
+        | IRKind::GetField { .. } => true,
+        IRKind::SymbolCall { symbol, .. } => match symbol.strip_prefix(INTRINSIC_PREFIX) {
+            Some("eq" | "ne" | "range") => true,
+            // Integers can overflow, or be divided by zero.
+            Some("sum" | "sub" | "mul") => matches!(node.ty, Type::Float | Type::String),
+            Some("div" | "rem") => node.ty == Type::Float,
+            _ => false,
+        },
+        IRKind::Call { .. }
+        | IRKind::SetGlobal { .. }
+        | IRKind::Loop { .. }
+        | IRKind::Import { .. }
+        | IRKind::Comptime(_) => false,
+    };
+    return own && node.children().into_iter().all(is_pure);
+}
+
+/// Whether the local `name` is read under `node`, shadowed or not.
+fn reads_local(node: &IRNode, name: &str) -> bool {
+    let mut read = false;
+    each(node, &mut |n| {
+        read |= matches!(&n.kind, IRKind::Local(l) if l == name)
+    });
+    return read;
+}
+
+/// Turns the `let`s of globals nobody reads into their values, and forgets those globals.
+fn remove_unread_globals(module: &mut Module) -> bool {
+    let mut read = HashSet::new();
+    for node in code(module) {
+        each(node, &mut |n| {
+            if let IRKind::Global(symbol) = &n.kind {
+                read.insert(symbol.clone());
+            }
+        });
+    }
+
+    // The last item is the module's value, which a `let` is not.
+    let mut changed = false;
+    let last = module.body.len().saturating_sub(1);
+    for item in &mut module.body[..last] {
+        let value = match &mut item.kind {
+            IRKind::SetGlobal { symbol, value } if !read.contains(symbol) => {
+                std::mem::replace(value.as_mut(), IRNode::unit(None))
+            }
+            _ => continue,
+        };
+        *item = value;
+        changed = true;
+    }
+
+    let mut set = HashSet::new();
+    for node in code(module) {
+        each(node, &mut |n| {
+            if let IRKind::SetGlobal { symbol, .. } = &n.kind {
+                set.insert(symbol.clone());
+            }
+        });
+    }
+    let before = module.globals.len();
+    module
+        .globals
+        .retain(|(symbol, _)| read.contains(symbol) || set.contains(symbol));
+    return changed || module.globals.len() != before;
+}
+
+/// Removes the functions the module's code can't reach.
+fn remove_unused_functions(module: &mut Module) -> bool {
+    let mut stack = Vec::new();
+    for node in code(module).into_iter().skip(module.functions.len()) {
+        calls(node, &mut stack);
+    }
+    let mut used = HashSet::new();
+    while let Some(symbol) = stack.pop() {
+        if !used.insert(symbol.clone()) {
+            continue;
+        }
+        if let Some(function) = module.function(&symbol) {
+            calls(&function.body, &mut stack);
+        }
+    }
+
+    let before = module.functions.len();
+    module.functions.retain(|f| used.contains(&f.symbol));
+    return module.functions.len() != before;
+}
+
+/// Gives functions and globals short names, `f0`, `f1`... and `g0`, `g1`...
+struct Rename;
+
+impl Pass for Rename {
+    fn name(&self) -> &'static str {
+        "renaming"
+    }
+
+    fn run(&self, module: &mut Module, _: &Options) -> bool {
+        let functions: HashMap<String, String> = module
+            .functions
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.symbol.clone(), format!("f{}", i)))
+            .collect();
+        let globals: HashMap<String, String> = module
+            .globals
+            .iter()
+            .enumerate()
+            .map(|(i, (symbol, _))| (symbol.clone(), format!("g{}", i)))
+            .collect();
+        let changed = functions
+            .iter()
+            .chain(&globals)
+            .any(|(old, new)| old != new);
+
+        for function in &mut module.functions {
+            function.symbol = functions[&function.symbol].clone();
+        }
+        for (symbol, _) in &mut module.globals {
+            *symbol = globals[symbol.as_str()].clone();
+        }
+        for node in code_mut(module) {
+            rename(node, &functions, &globals);
+        }
+        return changed;
+    }
+}
+
+fn rename(
+    node: &mut IRNode,
+    functions: &HashMap<String, String>,
+    globals: &HashMap<String, String>,
+) {
+    let renamed = match &mut node.kind {
+        IRKind::FnRef { symbol, .. } | IRKind::SymbolCall { symbol, .. } => {
+            Some((symbol, functions))
+        }
+        IRKind::Global(symbol) | IRKind::SetGlobal { symbol, .. } => Some((symbol, globals)),
+        _ => None,
+    };
+    if let Some((symbol, names)) = renamed {
+        if let Some(new) = names.get(symbol.as_str()) {
+            *symbol = new.clone();
+        }
+    }
+    for child in node.children_mut() {
+        rename(child, functions, globals);
+    }
+}
+
+/// The code of `module`: function bodies first, then the top-level code, exports and annotations.
+fn code(module: &Module) -> Vec<&IRNode> {
+    let mut nodes: Vec<&IRNode> = module.functions.iter().map(|f| &f.body).collect();
+    nodes.extend(module.body.iter());
+    nodes.extend(module.exports.iter().map(|(_, node)| node));
+    nodes.extend(module.annotations.iter().map(|a| &a.value));
+    return nodes;
+}
+
+/// Same as `code`, mutably.
+fn code_mut(module: &mut Module) -> Vec<&mut IRNode> {
+    let mut nodes: Vec<&mut IRNode> = module.functions.iter_mut().map(|f| &mut f.body).collect();
+    nodes.extend(module.body.iter_mut());
+    nodes.extend(module.exports.iter_mut().map(|(_, node)| node));
+    nodes.extend(module.annotations.iter_mut().map(|a| &mut a.value));
+    return nodes;
+}
+
+/// Calls `f` on `node` and everything under it.
+fn each(node: &IRNode, f: &mut impl FnMut(&IRNode)) {
+    f(node);
+    for child in node.children() {
+        each(child, f);
+    }
+}
+
+/// How many nodes `node` is made of.
+fn size(node: &IRNode) -> usize {
+    let mut n = 0;
+    each(node, &mut |_| n += 1);
+    return n;
+}
+
+/// Adds the symbols of the functions `node` calls or refers to to `out`.
+fn calls(node: &IRNode, out: &mut Vec<String>) {
+    each(node, &mut |n| match &n.kind {
+        IRKind::SymbolCall { symbol, .. } | IRKind::FnRef { symbol, .. }
+            if !symbol.starts_with(INTRINSIC_PREFIX) =>
+        {
+            out.push(symbol.clone())
+        }
+        _ => {}
+    });
+}
+
+/// Adds the names of the locals under `node`, bound or read, to `out`.
+fn locals(node: &IRNode, out: &mut HashSet<String>) {
+    each(node, &mut |n| match &n.kind {
+        IRKind::Local(name) | IRKind::Let { name, .. } | IRKind::Loop { var: name, .. } => {
+            out.insert(name.clone());
+        }
+        _ => {}
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{front_end, interpreted};
+    use crate::ir::{intrinsic, Literal};
+
+    /// Only the passes `on` names, renaming off.
+    fn only(on: &[&str]) -> Options {
+        return Options {
+            constant_folding: on.contains(&"constant_folding"),
+            dead_code: on.contains(&"dead_code"),
+            inlining: on.contains(&"inlining"),
+            keep_symbol_names: !on.contains(&"renaming"),
+            force_always_inline: false,
+        };
+    }
+
+    /// `source` optimized with `options`, the passes that changed it, and checks it prints the same.
+    fn optimized(source: &str, options: &Options) -> (Module, Vec<&'static str>) {
+        let mut module = front_end(source);
+        let before = interpreted(&module);
+        let passes = optimize(&mut module, options);
+        assert_eq!(interpreted(&module), before);
+        return (module, passes);
+    }
+
+    /// The arguments of the top-level call to `print` at `index`.
+    fn printed(module: &Module, index: usize) -> &[IRNode] {
+        match &module.body[index].kind {
+            IRKind::SymbolCall { symbol, args } if *symbol == intrinsic("print") => args,
+            kind => panic!("not a print: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn constant_arithmetic_is_folded() {
+        let (module, passes) = optimized("print(1 + 2 * 3)", &only(&["constant_folding"]));
+        assert_eq!(passes, ["constant_folding"]);
+        assert_eq!(
+            printed(&module, 0)[0].kind,
+            IRKind::Literal(Literal::Int(7))
+        );
+    }
+
+    #[test]
+    fn overflowing_arithmetic_is_left_to_fail() {
+        let mut module = front_end("print(9223372036854775807 + 1)");
+        assert!(optimize(&mut module, &only(&["constant_folding"])).is_empty());
+        match &printed(&module, 0)[0].kind {
+            IRKind::SymbolCall { symbol, .. } => assert_eq!(*symbol, intrinsic("sum")),
+            kind => panic!("the sum was folded into {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn dead_code_elimination_keeps_side_effects() {
+        let source = "let unused = 'nobody reads this'
+let noisy = print('side')
+print(1)";
+        let (module, passes) = optimized(source, &only(&["dead_code"]));
+        assert_eq!(passes, ["dead_code"]);
+        assert!(module.globals.is_empty());
+        assert_eq!(module.body.len(), 2);
+        assert_eq!(
+            printed(&module, 0)[0].kind,
+            IRKind::Literal(Literal::String("side".into()))
+        );
+    }
+
+    #[test]
+    fn inlined_calls_fold_and_their_functions_go() {
+        let source = "fn add(a, b) -> a + b
+print(add(1, 2))";
+        let (module, passes) = optimized(source, &Options::default());
+        assert_eq!(passes, ["inlining", "constant_folding", "dead_code"]);
+        assert!(module.functions.is_empty());
+        assert_eq!(
+            printed(&module, 0)[0].kind,
+            IRKind::Literal(Literal::Int(3))
+        );
+    }
+
+    #[test]
+    fn renaming_shortens_symbols_unless_told_to_keep_them() {
+        let source = "fn add(a, b) -> a + b
+let total = add(1, 2)
+print(total)";
+        let (module, passes) = optimized(source, &only(&["renaming"]));
+        assert_eq!(passes, ["renaming"]);
+        assert_eq!(module.functions[0].symbol, "f0");
+        assert_eq!(module.globals[0].0, "g0");
+
+        let (module, passes) = optimized(source, &only(&[]));
+        assert!(passes.is_empty());
+        assert_eq!(module.functions[0].symbol, "add");
+        assert_eq!(module.globals[0].0, "total");
+    }
+}
@@ -1,20 +1,25 @@
-use colored::Colorize;
+use std::{cell::RefCell, collections::HashMap};
 
 use crate::{
-    grammar::{get_rule, ASTNode, ASTNodeContent, ParseRule, Token, TokenName, AST},
+    diagnostic::Diagnostic,
+    grammar::{
+        get_action, get_rule, ASTNode, ASTNodeContent, ParseRule, ParseRuleList, ReduceActionList,
+        Span, Token, TokenName, AST,
+    },
     nano_grammar::NANO_TOKEN_RULES,
-    nano_grammar::{is_ghost_token, NANO_PARSE_RULES},
+    nano_grammar::{is_ghost_token, NANO_PARSE_RULES, NANO_REDUCE_ACTIONS},
+    trace::ParseTrace,
 };
 
 /// Tokenizer, which will be used both by the compiler,
 /// the formatter, the linter and the LSP.
-pub fn tokenize(source: &str) -> Vec<Token> {
+///
+/// Stops at the first character no token starts with, which `build_tree`
+/// then reports; the tokens always end with an EOF at the end of the source.
+pub fn tokenize(source: &str) -> Vec<Token<'_>> {
     let mut tokens = Vec::new();
     let mut char_offset: usize = 0;
 
-    let mut column: usize = 0;
-    let mut line: usize = 0;
-
     while char_offset < source.len() {
         let mut has_match = false;
 
@@ -33,44 +38,17 @@ pub fn tokenize(source: &str) -> Vec<Token> {
                 Some(ms) => ms.as_str(),
             };
 
-            match matcher.name {
-                TokenName::BlockComment | TokenName::StringLiteral => {
-                    for c in matched_string.chars() {
-                        match c {
-                            '\n' => {
-                                column = 0;
-                                line += 1
-                            }
-                            _ => {
-                                column += 1;
-                            }
-                        }
-                    }
-                }
-                TokenName::Newline => {
-                    column = 0;
-                    line += 1
-                }
-                _ => column += matched_string.len(),
-            }
-
             tokens.push(Token {
                 name: matcher.name,
                 str_content: Some(matched_string),
+                span: Span::new(char_offset, char_offset + matched_string.len()),
             });
             char_offset += matched_string.len();
             has_match = true;
+            break 'matchers;
         }
 
-        if has_match {
-        } else {
-            println!(
-                "{} : Unexpected token '{}' at Ln {}, Col {}.",
-                "FAILED".red(),
-                &source.chars().nth(char_offset).unwrap(),
-                line,
-                column
-            );
+        if !has_match {
             break;
         }
     }
@@ -78,50 +56,115 @@ pub fn tokenize(source: &str) -> Vec<Token> {
     tokens.push(Token {
         name: TokenName::EOF,
         str_content: None,
+        span: Span::new(source.len(), source.len()),
     });
 
     return tokens;
 }
 
-pub struct ParseError {}
+/// Why a source doesn't parse.
+///
+/// Rules that don't match fail with an empty one, which the parser backtracks
+/// from; `build_tree` fills in what went wrong for the source as a whole.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl ParseError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        return Diagnostic::error(&self.message, self.span);
+    }
+}
+
+/// The first character `tokenize` couldn't read, if there's one.
+fn unexpected_character(source_str: &str, source: &[Token]) -> Option<ParseError> {
+    let tokenized = source.iter().rev().nth(1).map_or(0, |t| t.span.end);
+    let c = source_str[tokenized..].chars().next()?;
+    return Some(ParseError {
+        message: format!("Unexpected character '{}'.", c),
+        span: Some(Span::new(tokenized, tokenized + c.len_utf8())),
+    });
+}
+
+/// Where parsing got stuck: the first real token past the furthest any rule matched up to.
+fn stuck_at(source: &[Token], memo: &MemoTable) -> ParseError {
+    let furthest = memo
+        .iter()
+        .filter_map(|((_, offset), result)| result.as_ref().ok().map(|(a, _)| offset + a))
+        .max()
+        .unwrap_or(0);
+    let token = source[furthest..]
+        .iter()
+        .find(|t| !is_ghost_token(&t.name))
+        .unwrap_or(&source[source.len() - 1]);
+    let message = match token.str_content {
+        Some(text) if token.name != TokenName::EOF => format!("Unexpected '{}'.", text),
+        _ => "Unexpected end of file.".to_string(),
+    };
+    return ParseError {
+        message,
+        span: Some(token.span),
+    };
+}
 
 /// Builds a tree given a pool of vectors, and a starting rule.
+/// If a `trace` is given, every rule the parser tries is recorded in it.
+///
+/// With `keep_ghost_tokens`, the tree is *concrete* (a CST): ghost tokens
+/// and skipped punctuation are kept and no reduction actions are ran,
+/// so the tree's tokens spell out the whole source.
 pub fn build_tree<'a>(
     source_str: &'a str,
-    source: &'a Vec<Token>,
+    source: &'a [Token],
     top_level_rule_name: &'static str,
     keep_ghost_tokens: bool,
+    trace: Option<&'a ParseTrace>,
 ) -> Result<AST<'a>, ParseError> {
-    let top_level_rule = get_rule(NANO_PARSE_RULES, top_level_rule_name);
-    let top_level_rule = match top_level_rule {
-        None => return Err(ParseError {}),
-        Some(r) => r,
+    let context = ParseContext {
+        source_string: source_str,
+        parse_rule_list: NANO_PARSE_RULES,
+        reduce_actions: NANO_REDUCE_ACTIONS,
+        token_count: source.len(),
+        memo: RefCell::new(HashMap::new()),
+        trace,
     };
 
-    let tree = match_rule(
-        &source[..],
-        top_level_rule,
-        &ParseContext {
-            source_string: source_str,
-            parse_rule_list: NANO_PARSE_RULES,
-        },
-        keep_ghost_tokens,
-    );
+    // A source `tokenize` gave up on doesn't parse, whatever its tokens match.
+    if let Some(e) = unexpected_character(source_str, source) {
+        return Err(e);
+    }
+    let tree = match_nested_rule(source, top_level_rule_name, &context, keep_ghost_tokens);
 
     let tree = match tree {
-        Err(e) => {
-            println!("Parsing stage failed.");
-            return Err(e);
-        }
+        Err(_) => return Err(stuck_at(source, &context.memo.borrow())),
         Ok(t) => t,
     };
+    let (advance, tree) = tree;
 
-    return Ok(AST {
-        is_abstract: !keep_ghost_tokens,
-        root: ASTNode {
+    let mut root = match tree {
+        ASTNodeContent::Node(n) => n,
+        // The root must stay a node, so wrap whatever the action gave back.
+        other => ASTNode {
             matched_with: top_level_rule_name,
-            content: tree.content,
+            consumed: other.span(),
+            content: vec![other],
         },
+    };
+
+    // In a concrete tree, trailing ghost tokens belong to the root.
+    if keep_ghost_tokens {
+        for token in &source[advance..] {
+            if token.name != TokenName::EOF {
+                root.content.push(ASTNodeContent::Tok(token));
+            }
+        }
+    }
+
+    return Ok(AST {
+        is_abstract: !keep_ghost_tokens,
+        root,
     });
 }
 
@@ -129,7 +172,7 @@ pub fn build_tree<'a>(
 pub fn match_rule<'a>(
     source_token_pool: &'a [Token],
     rule: &[ParseRule],
-    context: &ParseContext,
+    context: &ParseContext<'a>,
     keep_ghost_tokens: bool,
 ) -> Result<ParseRuleMatchResult<'a>, ParseError> {
     let mut token_slice_offset = 0;
@@ -141,7 +184,6 @@ pub fn match_rule<'a>(
         let fragment = &rule[fragment_index];
 
         if token_slice_offset >= source_token_pool.len() {
-            println!("Ran out of tokens.");
             break;
         }
 
@@ -149,18 +191,11 @@ pub fn match_rule<'a>(
         match fragment {
             // Single token o/ IDENTIFIER /
             // Optionally matching the token's content as well
-            ParseRule::SingleToken(rule_token_name, rule_token_content) => {
+            ParseRule::SingleToken(..) | ParseRule::AnyToken | ParseRule::TokenSet(_) => {
                 let current_source_token = &source_token_pool[token_slice_offset];
-                let token_content_matches = match rule_token_content {
-                    None => true,
-                    Some(s) => match current_source_token.str_content {
-                        None => false,
-                        Some(st) => st == *s,
-                    },
-                };
 
                 // Source token matches Rule token!
-                if current_source_token.name == *rule_token_name && token_content_matches {
+                if token_fragment_matches(fragment, current_source_token) {
                     content.push(ASTNodeContent::Tok(&source_token_pool[token_slice_offset]));
                     fragment_index += 1;
                     token_slice_offset += 1;
@@ -172,10 +207,40 @@ pub fn match_rule<'a>(
                     token_slice_offset += 1;
                 // Neither, rule can not be accepted
                 } else {
-                    return Err(ParseError {});
+                    return Err(ParseError::default());
                 }
             }
 
+            // Positive lookahead o/ &(IDENTIFIER THIN_ARROW) /
+            // Succeeds if the fragments match, but consumes nothing.
+            ParseRule::Lookahead(sub_fragments) => {
+                match_rule(
+                    &source_token_pool[token_slice_offset..],
+                    sub_fragments,
+                    context,
+                    keep_ghost_tokens,
+                )?;
+
+                fragment_index += 1;
+            }
+
+            // Negative lookahead o/ !Keyword /
+            // Succeeds if the fragments do NOT match, and consumes nothing.
+            ParseRule::NotLookahead(sub_fragments) => {
+                let nested_match = match_rule(
+                    &source_token_pool[token_slice_offset..],
+                    sub_fragments,
+                    context,
+                    keep_ghost_tokens,
+                );
+
+                if nested_match.is_ok() {
+                    return Err(ParseError::default());
+                }
+
+                fragment_index += 1;
+            }
+
             ParseRule::Optional(sub_fragments) => {
                 let sub_match = match_rule(
                     &source_token_pool[token_slice_offset..],
@@ -212,7 +277,6 @@ pub fn match_rule<'a>(
                     match sub_match {
                         Ok(t) => {
                             many_content.push(ASTNodeContent::Grouping(t.content));
-                            println!("Matching");
                             token_slice_offset += t.advance;
                             matched_at_least_once = true;
                         }
@@ -245,7 +309,6 @@ pub fn match_rule<'a>(
                     match sub_match {
                         Ok(t) => {
                             many_content.push(ASTNodeContent::Grouping(t.content));
-                            println!("Matching");
                             token_slice_offset += t.advance;
                         }
                         Err(_e) => {
@@ -265,7 +328,7 @@ pub fn match_rule<'a>(
                     let nested_match = match_rule(
                         &source_token_pool[token_slice_offset..],
                         case,
-                        &context,
+                        context,
                         keep_ghost_tokens,
                     );
                     let nested_match = match nested_match {
@@ -278,11 +341,13 @@ pub fn match_rule<'a>(
 
                     content.push(ASTNodeContent::Grouping(nested_match.content));
                     matched_any = true;
+                    // The first case that matches wins.
+                    break;
                 }
 
                 if !matched_any {
                     // TODO Change this to an array of Parse Errors!
-                    return Err(ParseError {});
+                    return Err(ParseError::default());
                 }
             }
 
@@ -298,7 +363,7 @@ pub fn match_rule<'a>(
                     let nested_match = match_rule(
                         &source_token_pool[token_slice_offset..],
                         case,
-                        &context,
+                        context,
                         keep_ghost_tokens,
                     );
                     let nested_match = match nested_match {
@@ -313,11 +378,11 @@ pub fn match_rule<'a>(
 
                 if failed_any {
                     // TODO Change this to an array of Parse Errors!
-                    return Err(ParseError {});
+                    return Err(ParseError::default());
                 }
 
                 match last_match {
-                    None => return Err(ParseError {}),
+                    None => return Err(ParseError::default()),
                     Some(lm) => {
                         fragment_index += 1;
                         token_slice_offset += lm.advance;
@@ -330,31 +395,127 @@ pub fn match_rule<'a>(
             // Reference to another ParseRule --
             // it's what makes this a recursive descent parser
             ParseRule::Nest(sub_rule_name) => {
-                let sub_rule = get_rule(context.parse_rule_list, &sub_rule_name);
-                let sub_rule = match sub_rule {
-                    // TODO Add case in ParseError for rule not found?
-                    None => return Err(ParseError {}),
-                    Some(r) => r,
-                };
+                let (advance, node) = match_nested_rule(
+                    &source_token_pool[token_slice_offset..],
+                    sub_rule_name,
+                    context,
+                    keep_ghost_tokens,
+                )?;
+
+                fragment_index += 1;
+                token_slice_offset += advance;
+                content.push(node);
+            }
 
+            // Labelled capture o/ lhs:Expr /
+            ParseRule::Label(label, sub_fragments) => {
                 let nested_match = match_rule(
                     &source_token_pool[token_slice_offset..],
-                    sub_rule,
-                    &context,
+                    sub_fragments,
+                    context,
                     keep_ghost_tokens,
-                );
-                let nested_match = match nested_match {
-                    Err(e) => return Err(e),
-                    Ok(nm) => nm,
-                };
+                )?;
 
                 fragment_index += 1;
                 token_slice_offset += nested_match.advance;
 
-                content.push(ASTNodeContent::Node(ASTNode {
-                    matched_with: sub_rule_name,
-                    content: nested_match.content,
-                }))
+                let captured = single_or_grouping(nested_match.content);
+                content.push(ASTNodeContent::Labelled(label, Box::new(captured)));
+            }
+
+            // Separated list o/ Expr (COMMA Expr)* COMMA? /
+            // Yields a single Grouping holding one entry per item, separators dropped.
+            ParseRule::SeparatedBy {
+                item,
+                sep,
+                allow_trailing,
+                min,
+            } => {
+                let mut items: Vec<ASTNodeContent> = Vec::new();
+                let mut item_count = 0;
+                // Where the list would end if we stopped now.
+                let mut accepted_offset = token_slice_offset;
+                let mut cursor = token_slice_offset;
+                // Concrete trees keep the separators, but only the ones that end up accepted.
+                let mut pending_sep: Vec<ASTNodeContent> = Vec::new();
+
+                loop {
+                    let item_match = match_rule(
+                        &source_token_pool[cursor..],
+                        item,
+                        context,
+                        keep_ghost_tokens,
+                    );
+                    let item_match = match item_match {
+                        Err(_e) => break,
+                        Ok(m) => m,
+                    };
+                    cursor += item_match.advance;
+                    accepted_offset = cursor;
+                    items.append(&mut pending_sep);
+                    items.push(single_or_grouping(item_match.content));
+                    item_count += 1;
+
+                    let sep_match = match_rule(
+                        &source_token_pool[cursor..],
+                        sep,
+                        context,
+                        keep_ghost_tokens,
+                    );
+                    let sep_match = match sep_match {
+                        Err(_e) => break,
+                        Ok(m) => m,
+                    };
+                    cursor += sep_match.advance;
+                    if keep_ghost_tokens {
+                        flatten_into(sep_match.content, &mut pending_sep);
+                    }
+                    if *allow_trailing {
+                        accepted_offset = cursor;
+                        items.append(&mut pending_sep);
+                    }
+                }
+
+                if item_count < *min {
+                    return Err(ParseError::default());
+                }
+
+                fragment_index += 1;
+                token_slice_offset = accepted_offset;
+                content.push(ASTNodeContent::Grouping(items));
+            }
+
+            // Skipped fragments o/ _:SEMICOLON /
+            ParseRule::Skip(sub_fragments) => {
+                let nested_match = match_rule(
+                    &source_token_pool[token_slice_offset..],
+                    sub_fragments,
+                    context,
+                    keep_ghost_tokens,
+                )?;
+
+                fragment_index += 1;
+                token_slice_offset += nested_match.advance;
+
+                // Concrete trees keep everything.
+                if keep_ghost_tokens {
+                    content.push(ASTNodeContent::Grouping(nested_match.content));
+                }
+            }
+
+            // Flattened fragments o/ ...(SEMICOLON Expr)* /
+            ParseRule::Flatten(sub_fragments) => {
+                let nested_match = match_rule(
+                    &source_token_pool[token_slice_offset..],
+                    sub_fragments,
+                    context,
+                    keep_ghost_tokens,
+                )?;
+
+                fragment_index += 1;
+                token_slice_offset += nested_match.advance;
+
+                flatten_into(nested_match.content, &mut content);
             }
         };
     }
@@ -366,9 +527,142 @@ pub fn match_rule<'a>(
     });
 }
 
+/// Matches the rule called `rule_name` at the beggining of the slice,
+/// wrapping what it matched in a node and running its reduction action.
+///
+/// Results are memoized by rule and token offset, so backtracking
+/// into the same rule at the same place is free (packrat parsing).
+fn match_nested_rule<'a>(
+    source_token_pool: &'a [Token],
+    rule_name: &'static str,
+    context: &ParseContext<'a>,
+    keep_ghost_tokens: bool,
+) -> Result<(usize, ASTNodeContent<'a>), ParseError> {
+    let offset = context.token_count - source_token_pool.len();
+
+    if let Some(memoized) = context.memo.borrow().get(&(rule_name, offset)) {
+        if let Some(trace) = context.trace {
+            trace.memo_hit(rule_name, offset, memoized.as_ref().ok().map(|(a, _)| *a));
+        }
+        return memoized.clone();
+    }
+
+    if let Some(trace) = context.trace {
+        trace.enter(rule_name, offset);
+    }
+
+    let result = match get_rule(context.parse_rule_list, rule_name) {
+        // TODO Add case in ParseError for rule not found?
+        None => Err(ParseError::default()),
+        Some(sub_rule) => match_rule(source_token_pool, sub_rule, context, keep_ghost_tokens).map(
+            |nested_match| {
+                let consumed = consumed_span(&source_token_pool[..nested_match.advance]);
+                let node = ASTNode {
+                    matched_with: rule_name,
+                    content: nested_match.content,
+                    consumed,
+                };
+
+                // Reduction actions get to reshape the node before the parent sees it.
+                // Concrete trees are left as the grammar shaped them.
+                let action = match keep_ghost_tokens {
+                    true => None,
+                    false => get_action(context.reduce_actions, rule_name),
+                };
+                let node = match action {
+                    None => ASTNodeContent::Node(node),
+                    Some(action) => match action(node) {
+                        // A node the action built stands for everything the rule matched.
+                        ASTNodeContent::Node(n) if n.consumed.is_none() => {
+                            ASTNodeContent::Node(ASTNode { consumed, ..n })
+                        }
+                        other => other,
+                    },
+                };
+
+                (nested_match.advance, node)
+            },
+        ),
+    };
+
+    if let Some(trace) = context.trace {
+        trace.exit(rule_name, offset, result.as_ref().ok().map(|(a, _)| *a));
+    }
+
+    context
+        .memo
+        .borrow_mut()
+        .insert((rule_name, offset), result.clone());
+
+    return result;
+}
+
+/// Whether a token-level fragment (`SingleToken`, `AnyToken` or `TokenSet`)
+/// accepts the given source token.
+fn token_fragment_matches(fragment: &ParseRule, token: &Token) -> bool {
+    match fragment {
+        ParseRule::SingleToken(rule_token_name, rule_token_content) => {
+            let token_content_matches = match rule_token_content {
+                None => true,
+                Some(s) => match token.str_content {
+                    None => false,
+                    Some(st) => st == *s,
+                },
+            };
+
+            token.name == *rule_token_name && token_content_matches
+        }
+        // Ghost tokens are skipped over, never matched, and
+        // EOF is not a token one can "take".
+        ParseRule::AnyToken => token.name != TokenName::EOF && !is_ghost_token(&token.name),
+        ParseRule::TokenSet(names) => names.contains(&token.name),
+        _ => false,
+    }
+}
+
+/// From the first to the last token of `tokens` that isn't a ghost token.
+fn consumed_span(tokens: &[Token]) -> Option<Span> {
+    let mut real = tokens
+        .iter()
+        .filter(|t| !is_ghost_token(&t.name) && t.name != TokenName::EOF);
+    let first = real.next()?;
+    let last = real.next_back().unwrap_or(first);
+    return Some(first.span.merge(last.span));
+}
+
+/// Unwraps content holding a single entry, otherwise groups it.
+fn single_or_grouping(mut content: Vec<ASTNodeContent>) -> ASTNodeContent {
+    if content.len() == 1 {
+        content.remove(0)
+    } else {
+        ASTNodeContent::Grouping(content)
+    }
+}
+
+/// Splices every `Grouping` (and drops every `None`) in `content` into `out`,
+/// keeping nodes, tokens and labelled captures in order.
+fn flatten_into<'a>(content: Vec<ASTNodeContent<'a>>, out: &mut Vec<ASTNodeContent<'a>>) {
+    for c in content {
+        match c {
+            ASTNodeContent::Grouping(inner) => flatten_into(inner, out),
+            ASTNodeContent::None => {}
+            other => out.push(other),
+        }
+    }
+}
+
+type MemoTable<'a> =
+    HashMap<(&'static str, usize), Result<(usize, ASTNodeContent<'a>), ParseError>>;
+
 pub struct ParseContext<'a> {
     pub source_string: &'a str,
-    pub parse_rule_list: &'a [(&'static str, &'a [ParseRule<'a>])],
+    pub parse_rule_list: &'a ParseRuleList<'a>,
+    pub reduce_actions: &'a ReduceActionList,
+
+    // Length of the whole token pool, used to turn slices back into offsets.
+    pub token_count: usize,
+    pub memo: RefCell<MemoTable<'a>>,
+    pub trace: Option<&'a ParseTrace>,
 }
 
 pub struct ParseRuleMatchResult<'a> {
@@ -377,3 +671,382 @@ pub struct ParseRuleMatchResult<'a> {
 
     pub content: Vec<ASTNodeContent<'a>>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::TraceEventKind;
+
+    /// Matches `rule` at the start of `source`, giving back how many tokens it
+    /// consumed and the names of the tokens it kept, in order.
+    fn matched(source: &str, rule: &[ParseRule]) -> Option<(usize, Vec<TokenName>)> {
+        let tokens = tokenize(source);
+        let context = ParseContext {
+            source_string: source,
+            parse_rule_list: NANO_PARSE_RULES,
+            reduce_actions: NANO_REDUCE_ACTIONS,
+            token_count: tokens.len(),
+            memo: RefCell::new(HashMap::new()),
+            trace: None,
+        };
+        let result = match_rule(&tokens, rule, &context, false).ok()?;
+
+        let mut kept = Vec::new();
+        flatten_into(result.content, &mut kept);
+        let names = kept
+            .iter()
+            .filter_map(|c| c.as_token())
+            .map(|t| t.name)
+            .collect();
+        return Some((result.advance, names));
+    }
+
+    const IDENTIFIER: ParseRule = ParseRule::SingleToken(TokenName::Identifier, None);
+
+    /// Matches `rule` at the start of `source` like `matched`, but shows what it
+    /// produced as written by `shape`, concrete if `cst`.
+    fn shaped(source: &str, rule: &[ParseRule], cst: bool) -> Option<(usize, String)> {
+        let tokens = tokenize(source);
+        let context = ParseContext {
+            source_string: source,
+            parse_rule_list: NANO_PARSE_RULES,
+            reduce_actions: NANO_REDUCE_ACTIONS,
+            token_count: tokens.len(),
+            memo: RefCell::new(HashMap::new()),
+            trace: None,
+        };
+        let result = match_rule(&tokens, rule, &context, cst).ok()?;
+        let shapes: Vec<String> = result.content.iter().map(shape).collect();
+        return Some((result.advance, shapes.join(" ")));
+    }
+
+    /// The tree of `source` as a whole program, as written by `shape`, concrete if `cst`.
+    fn program(source: &str, cst: bool) -> String {
+        let tokens = tokenize(source);
+        let ast = build_tree(source, &tokens, "Program", cst, None)
+            .ok()
+            .unwrap();
+        return shape(&ASTNodeContent::Node(ast.root));
+    }
+
+    /// Some content as text: tokens quoted, `(...)` for groupings, `Rule{...}`
+    /// for nodes and `label:...` for labels.
+    fn shape(content: &ASTNodeContent) -> String {
+        let all = |items: &[ASTNodeContent]| -> String {
+            return items.iter().map(shape).collect::<Vec<String>>().join(" ");
+        };
+        match content {
+            ASTNodeContent::None => "_".to_string(),
+            ASTNodeContent::Tok(t) => format!("{:?}", t.str_content.unwrap_or("")),
+            ASTNodeContent::Grouping(items) => format!("({})", all(items)),
+            ASTNodeContent::Node(n) => format!("{}{{{}}}", n.matched_with, all(&n.content)),
+            ASTNodeContent::Labelled(label, inner) => format!("{}:{}", label, shape(inner)),
+        }
+    }
+
+    #[test]
+    fn lookahead_matches_without_consuming() {
+        let rule = [
+            ParseRule::Lookahead(&[
+                IDENTIFIER,
+                ParseRule::SingleToken(TokenName::ThinArrow, None),
+            ]),
+            IDENTIFIER,
+        ];
+
+        // Only the identifier is taken, `->` is left for whatever comes next.
+        assert_eq!(
+            matched("main -> 1", &rule),
+            Some((1, vec![TokenName::Identifier]))
+        );
+        assert_eq!(matched("main(1)", &rule), None);
+    }
+
+    #[test]
+    fn not_lookahead_rejects_what_it_matches() {
+        let rule = [
+            ParseRule::NotLookahead(&[ParseRule::SingleToken(TokenName::Identifier, Some("let"))]),
+            IDENTIFIER,
+        ];
+
+        assert_eq!(
+            matched("total", &rule),
+            Some((1, vec![TokenName::Identifier]))
+        );
+        assert_eq!(matched("let", &rule), None);
+    }
+
+    #[test]
+    fn any_token_takes_one_real_token() {
+        let rule = [ParseRule::AnyToken];
+
+        assert_eq!(matched("+ 1", &rule), Some((1, vec![TokenName::OpPlus])));
+        // Ghost tokens are skipped over, not taken.
+        assert_eq!(
+            matched("  # note\n42", &rule),
+            Some((4, vec![TokenName::IntLiteral]))
+        );
+    }
+
+    #[test]
+    fn any_token_rejects_eof_and_ghost_tokens() {
+        assert_eq!(matched("", &[ParseRule::AnyToken]), None);
+        assert_eq!(matched("   \n", &[ParseRule::AnyToken]), None);
+
+        for token in tokenize(" # note\n") {
+            assert!(!token_fragment_matches(&ParseRule::AnyToken, &token));
+        }
+    }
+
+    #[test]
+    fn node_spans_cover_skipped_punctuation() {
+        let source = "g(a, h(1))";
+        let tokens = tokenize(source);
+        let ast = build_tree(source, &tokens, "Program", false, None)
+            .ok()
+            .unwrap();
+
+        let mut calls = Vec::new();
+        let mut stack = vec![&ast.root];
+        while let Some(node) = stack.pop() {
+            if node.matched_with == "Call" {
+                calls.push(node.span());
+            }
+            stack.extend(node.content.iter().flat_map(nodes_in));
+        }
+        calls.sort_by_key(|s| s.map(|s| s.start));
+
+        assert_eq!(calls, vec![Some(Span::new(0, 10)), Some(Span::new(5, 9))]);
+    }
+
+    #[test]
+    fn rules_are_tried_once_per_offset() {
+        let source = "let total = sum(1, 2) + [3, 4] |> first\nfn f(x) -> x.a";
+        let tokens = tokenize(source);
+        let trace = ParseTrace::new();
+        assert!(build_tree(source, &tokens, "Program", false, Some(&trace)).is_ok());
+
+        let events = trace.events();
+        let mut entered = std::collections::HashSet::new();
+        for event in &events {
+            if event.kind == TraceEventKind::Enter {
+                assert!(entered.insert((event.rule, event.offset)), "{:?}", event);
+            }
+        }
+        // Backtracking into a rule at the same place reuses its result.
+        assert!(events
+            .iter()
+            .any(|e| matches!(e.kind, TraceEventKind::MemoHit { .. })));
+    }
+
+    /// The nodes directly under some content, looking through groupings and labels.
+    fn nodes_in<'t, 'a>(content: &'t ASTNodeContent<'a>) -> Vec<&'t ASTNode<'a>> {
+        match content {
+            ASTNodeContent::Node(n) => vec![n],
+            ASTNodeContent::Grouping(items) => items.iter().flat_map(nodes_in).collect(),
+            ASTNodeContent::Labelled(_, inner) => nodes_in(inner),
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn token_set_takes_any_of_its_names() {
+        let rule = [ParseRule::TokenSet(&[TokenName::OpPlus, TokenName::OpDash])];
+
+        assert_eq!(matched("+", &rule), Some((1, vec![TokenName::OpPlus])));
+        assert_eq!(matched("- 2", &rule), Some((1, vec![TokenName::OpDash])));
+        assert_eq!(matched("* 2", &rule), None);
+        assert_eq!(matched("", &rule), None);
+    }
+
+    const PLUS: ParseRule = ParseRule::SingleToken(TokenName::OpPlus, None);
+
+    #[test]
+    fn labels_name_what_they_capture() {
+        let rule = [
+            ParseRule::Label("lhs", &[IDENTIFIER]),
+            PLUS,
+            ParseRule::Label("rhs", &[IDENTIFIER, IDENTIFIER]),
+        ];
+
+        // A single capture is labelled as is, several are grouped first.
+        assert_eq!(
+            shaped("a + b c", &rule, false),
+            Some((7, r#"lhs:"a" "+" rhs:("b" "c")"#.to_string()))
+        );
+        assert_eq!(shaped("a + b", &rule, false), None);
+    }
+
+    #[test]
+    fn skipped_fragments_are_matched_but_dropped() {
+        let rule = [IDENTIFIER, ParseRule::Skip(&[PLUS]), IDENTIFIER];
+
+        assert_eq!(
+            shaped("a + b", &rule, false),
+            Some((5, r#""a" "b""#.to_string()))
+        );
+        assert_eq!(shaped("a b", &rule, false), None);
+        // Concrete trees keep them, with the ghost tokens before them.
+        assert_eq!(
+            shaped("a + b", &rule, true),
+            Some((5, r#""a" (" " "+") " " "b""#.to_string()))
+        );
+    }
+
+    #[test]
+    fn flattened_fragments_are_spliced_into_the_parent() {
+        const MANY: ParseRule = ParseRule::Many(&[PLUS, IDENTIFIER]);
+
+        assert_eq!(
+            shaped("a + b + c", &[IDENTIFIER, MANY], false),
+            Some((9, r#""a" (("+" "b") ("+" "c"))"#.to_string()))
+        );
+        assert_eq!(
+            shaped(
+                "a + b + c",
+                &[IDENTIFIER, ParseRule::Flatten(&[MANY])],
+                false
+            ),
+            Some((9, r#""a" "+" "b" "+" "c""#.to_string()))
+        );
+    }
+
+    #[test]
+    fn operator_chains_reduce_to_left_associative_binary_expressions() {
+        assert_eq!(
+            program("1 + 2 - 3", false),
+            r#"Program{Exprs{Expr{BinaryExpr{op:"-" lhs:BinaryExpr{op:"+" lhs:Literal{value:"1"} rhs:Literal{value:"2"}} rhs:Literal{value:"3"}}}}}"#
+        );
+        // A chain of one operand is that operand.
+        assert_eq!(
+            program("1", false),
+            r#"Program{Exprs{Expr{Literal{value:"1"}}}}"#
+        );
+    }
+
+    #[test]
+    fn members_and_ranges_reduce_to_labelled_nodes() {
+        assert_eq!(
+            program("a.b.c", false),
+            r#"Program{Exprs{Expr{Member{object:Member{object:Name{name:"a"} field:Name{name:"b"}} field:Name{name:"c"}}}}}"#
+        );
+        assert_eq!(
+            program("0..n", false),
+            r#"Program{Exprs{Expr{Range{start:Literal{value:"0"} end:Name{name:"n"}}}}}"#
+        );
+    }
+
+    #[test]
+    fn concrete_trees_are_not_reduced() {
+        let cst = program("1 + 2", true);
+        assert!(cst.contains("Sum{"), "{}", cst);
+        assert!(!cst.contains("BinaryExpr"), "{}", cst);
+    }
+
+    /// `a, b, c`, a trailing comma allowed if `trailing`, at least `min` names.
+    fn names_list(trailing: bool, min: usize) -> [ParseRule<'static>; 1] {
+        return [ParseRule::SeparatedBy {
+            item: &[IDENTIFIER],
+            sep: &[ParseRule::SingleToken(TokenName::Comma, None)],
+            allow_trailing: trailing,
+            min,
+        }];
+    }
+
+    #[test]
+    fn separated_lists_take_a_trailing_separator_when_allowed() {
+        // One flat grouping, separators dropped.
+        assert_eq!(
+            shaped("a, b,", &names_list(true, 0), false),
+            Some((5, r#"("a" "b")"#.to_string()))
+        );
+        // Otherwise the trailing `,` is left for what comes next.
+        assert_eq!(
+            shaped("a, b,", &names_list(false, 0), false),
+            Some((4, r#"("a" "b")"#.to_string()))
+        );
+    }
+
+    #[test]
+    fn separated_lists_need_at_least_min_items() {
+        assert_eq!(
+            shaped("", &names_list(false, 0), false),
+            Some((0, "()".to_string()))
+        );
+        assert_eq!(shaped("a", &names_list(false, 2), false), None);
+        assert_eq!(
+            shaped("a, b", &names_list(false, 2), false),
+            Some((4, r#"("a" "b")"#.to_string()))
+        );
+    }
+
+    #[test]
+    fn concrete_separated_lists_keep_the_separators_they_accept() {
+        assert_eq!(
+            shaped("a, b,", &names_list(true, 0), true),
+            Some((5, r#"("a" "," (" " "b") ",")"#.to_string()))
+        );
+        // A trailing separator that isn't allowed isn't part of the list.
+        assert_eq!(
+            shaped("a, b,", &names_list(false, 0), true),
+            Some((4, r#"("a" "," (" " "b"))"#.to_string()))
+        );
+    }
+
+    /// Where and why `source` doesn't parse.
+    fn parse_error(source: &str) -> (String, Option<Span>) {
+        let tokens = tokenize(source);
+        let error = build_tree(source, &tokens, "Program", false, None).unwrap_err();
+        return (error.message, error.span);
+    }
+
+    #[test]
+    fn names_can_start_with_an_underscore() {
+        let tokens = |source| {
+            tokenize(source)
+                .iter()
+                .filter(|t| !is_ghost_token(&t.name))
+                .map(|t| (t.name, t.str_content.unwrap_or("").to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            tokens("_x 1_000"),
+            [
+                (TokenName::Identifier, "_x".to_string()),
+                (TokenName::IntLiteral, "1_000".to_string()),
+                (TokenName::EOF, "".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn characters_no_token_starts_with_are_errors() {
+        // The tokens before it would parse on their own.
+        assert_eq!(
+            parse_error("print(1) $ 2"),
+            (
+                "Unexpected character '$'.".to_string(),
+                Some(Span::new(9, 10))
+            )
+        );
+        assert_eq!(
+            parse_error("1\n¤"),
+            (
+                "Unexpected character '¤'.".to_string(),
+                Some(Span::new(2, 4))
+            )
+        );
+    }
+
+    #[test]
+    fn parse_errors_point_past_the_longest_match() {
+        assert_eq!(
+            parse_error("print(1)\n)"),
+            ("Unexpected ')'.".to_string(), Some(Span::new(9, 10)))
+        );
+        assert_eq!(
+            parse_error("print(1"),
+            ("Unexpected end of file.".to_string(), Some(Span::new(7, 7)))
+        );
+    }
+}
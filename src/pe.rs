@@ -0,0 +1,369 @@
+use crate::{native::Object, x86::Data};
+
+// PE32+ executables, what the `windows_x86` target writes.
+//
+// The image has three sections after the headers: `.text`, `.rdata` with
+// the string literals followed by the import tables for kernel32.dll, and
+// `.bss`. Since the code only refers to data relative to itself, the
+// relocations are resolved right here, and the image needs no base
+// relocations: it's marked as only loading at its preferred base.
+//
+// Nothing depends on the time or the machine the file is made on, so the
+// same program always gives the same bytes.
+
+const IMAGE_BASE: u64 = 0x1_4000_0000;
+const SECTION_ALIGNMENT: u32 = 0x1000;
+const FILE_ALIGNMENT: u32 = 0x200;
+const HEADERS: u32 = 64 + 4 + 20 + 240 + 3 * 40;
+
+// Characteristics of the file, then of its sections.
+const IMAGE_FILE_RELOCS_STRIPPED: u16 = 0x0001;
+const IMAGE_FILE_EXECUTABLE_IMAGE: u16 = 0x0002;
+const IMAGE_FILE_LARGE_ADDRESS_AWARE: u16 = 0x0020;
+const IMAGE_SCN_CNT_CODE: u32 = 0x20;
+const IMAGE_SCN_CNT_INITIALIZED_DATA: u32 = 0x40;
+const IMAGE_SCN_CNT_UNINITIALIZED_DATA: u32 = 0x80;
+const IMAGE_SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+const IMAGE_SCN_MEM_READ: u32 = 0x4000_0000;
+const IMAGE_SCN_MEM_WRITE: u32 = 0x8000_0000;
+
+const IMAGE_SUBSYSTEM_WINDOWS_CUI: u16 = 3;
+const IMAGE_DLLCHARACTERISTICS_NX_COMPAT: u16 = 0x0100;
+const IMAGE_DLLCHARACTERISTICS_TERMINAL_SERVER_AWARE: u16 = 0x8000;
+
+// The data directories used, by index.
+const IMPORT_DIRECTORY: usize = 1;
+const IAT_DIRECTORY: usize = 12;
+
+fn align(n: u32, to: u32) -> u32 {
+    n.div_ceil(to) * to
+}
+
+/// The import tables, laid out from `at` in `.rdata`.
+struct Imports {
+    bytes: Vec<u8>,
+    // Offsets from the start of `.rdata`.
+    iat: u32,
+    directory: u32,
+}
+
+fn imports(names: &[&str], at: u32, rva: u32) -> Imports {
+    let slots = 8 * (names.len() as u32 + 1);
+    let iat = at;
+    let directory = iat + slots;
+    let lookup = directory + 2 * 20;
+    let mut hints = lookup + slots;
+
+    // Each name is a hint, 0, then the name, padded to an even size.
+    let mut names_bytes = Vec::new();
+    let mut name_rvas = Vec::new();
+    for name in names {
+        name_rvas.push(rva + hints + names_bytes.len() as u32);
+        names_bytes.extend_from_slice(&0u16.to_le_bytes());
+        names_bytes.extend_from_slice(name.as_bytes());
+        names_bytes.push(0);
+        if names_bytes.len() % 2 == 1 {
+            names_bytes.push(0);
+        }
+    }
+    let dll = rva + hints + names_bytes.len() as u32;
+    names_bytes.extend_from_slice(b"KERNEL32.dll\0");
+
+    let mut thunks = Vec::new();
+    for name in &name_rvas {
+        thunks.extend_from_slice(&(*name as u64).to_le_bytes());
+    }
+    thunks.extend_from_slice(&0u64.to_le_bytes());
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&thunks);
+    for field in [rva + lookup, 0, 0, dll, rva + iat] {
+        bytes.extend_from_slice(&field.to_le_bytes());
+    }
+    bytes.extend_from_slice(&[0; 20]);
+    bytes.extend_from_slice(&thunks);
+    bytes.extend_from_slice(&names_bytes);
+    hints += names_bytes.len() as u32;
+    debug_assert_eq!(at + bytes.len() as u32, hints);
+    return Imports {
+        bytes,
+        iat,
+        directory,
+    };
+}
+
+/// The bytes of a Windows executable running `object`.
+pub fn write(object: &Object) -> Result<Vec<u8>, String> {
+    let headers = align(HEADERS, FILE_ALIGNMENT);
+    let text_rva = SECTION_ALIGNMENT;
+    let text_raw = align(object.text.len() as u32, FILE_ALIGNMENT);
+
+    let rdata_rva = text_rva + align(object.text.len() as u32, SECTION_ALIGNMENT);
+    let mut rdata = object.rodata.clone();
+    while !rdata.len().is_multiple_of(8) {
+        rdata.push(0);
+    }
+    let imports = imports(&object.imports, rdata.len() as u32, rdata_rva);
+    rdata.extend_from_slice(&imports.bytes);
+    let rdata_raw = align(rdata.len() as u32, FILE_ALIGNMENT);
+
+    let bss_rva = rdata_rva + align(rdata.len() as u32, SECTION_ALIGNMENT);
+    let image_size = bss_rva + align(object.bss, SECTION_ALIGNMENT);
+
+    // Every displacement is relative to its own end, 4 bytes on.
+    let mut text = object.text.clone();
+    for (at, data) in &object.relocations {
+        let target = match data {
+            Data::Rodata(offset) => rdata_rva + offset,
+            Data::Bss(offset) => bss_rva + offset,
+            Data::Import(index) => {
+                if *index as usize >= object.imports.len() {
+                    return Err(format!("There's no import #{}.", index));
+                }
+                rdata_rva + imports.iat + 8 * index
+            }
+        };
+        let displacement = target as i64 - (text_rva as i64 + *at as i64 + 4);
+        text[*at..*at + 4].copy_from_slice(&(displacement as i32).to_le_bytes());
+    }
+
+    let mut out = Vec::new();
+    // The MS-DOS header, only there to say where the PE header is.
+    out.extend_from_slice(b"MZ");
+    out.resize(0x3c, 0);
+    out.extend_from_slice(&64u32.to_le_bytes());
+
+    out.extend_from_slice(b"PE\0\0");
+    // The COFF header: x86-64, 3 sections, no timestamp nor symbols.
+    out.extend_from_slice(&0x8664u16.to_le_bytes());
+    out.extend_from_slice(&3u16.to_le_bytes());
+    out.extend_from_slice(&[0; 12]);
+    out.extend_from_slice(&240u16.to_le_bytes());
+    let characteristics =
+        IMAGE_FILE_RELOCS_STRIPPED | IMAGE_FILE_EXECUTABLE_IMAGE | IMAGE_FILE_LARGE_ADDRESS_AWARE;
+    out.extend_from_slice(&characteristics.to_le_bytes());
+
+    // The optional header, PE32+.
+    out.extend_from_slice(&0x20bu16.to_le_bytes());
+    out.extend_from_slice(&[0, 0]);
+    out.extend_from_slice(&text_raw.to_le_bytes());
+    out.extend_from_slice(&rdata_raw.to_le_bytes());
+    out.extend_from_slice(&align(object.bss, FILE_ALIGNMENT).to_le_bytes());
+    out.extend_from_slice(&(text_rva + object.entry as u32).to_le_bytes());
+    out.extend_from_slice(&text_rva.to_le_bytes());
+    out.extend_from_slice(&IMAGE_BASE.to_le_bytes());
+    out.extend_from_slice(&SECTION_ALIGNMENT.to_le_bytes());
+    out.extend_from_slice(&FILE_ALIGNMENT.to_le_bytes());
+    // Windows Vista and up, for the OS and the subsystem.
+    for version in [6u16, 0, 0, 0, 6, 0] {
+        out.extend_from_slice(&version.to_le_bytes());
+    }
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&image_size.to_le_bytes());
+    out.extend_from_slice(&headers.to_le_bytes());
+    // No checksum.
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&IMAGE_SUBSYSTEM_WINDOWS_CUI.to_le_bytes());
+    let dll_characteristics =
+        IMAGE_DLLCHARACTERISTICS_NX_COMPAT | IMAGE_DLLCHARACTERISTICS_TERMINAL_SERVER_AWARE;
+    out.extend_from_slice(&dll_characteristics.to_le_bytes());
+    // 8 MiB of stack like on Linux, and the default heap.
+    for size in [0x80_0000u64, 0x1000, 0x10_0000, 0x1000] {
+        out.extend_from_slice(&size.to_le_bytes());
+    }
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&16u32.to_le_bytes());
+    let mut directories = [(0u32, 0u32); 16];
+    if !object.imports.is_empty() {
+        directories[IMPORT_DIRECTORY] = (rdata_rva + imports.directory, 2 * 20);
+        let slots = 8 * (object.imports.len() as u32 + 1);
+        directories[IAT_DIRECTORY] = (rdata_rva + imports.iat, slots);
+    }
+    for (rva, size) in directories {
+        out.extend_from_slice(&rva.to_le_bytes());
+        out.extend_from_slice(&size.to_le_bytes());
+    }
+
+    let sections = [
+        (
+            b".text\0\0\0",
+            object.text.len() as u32,
+            text_rva,
+            text_raw,
+            headers,
+            IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ,
+        ),
+        (
+            b".rdata\0\0",
+            rdata.len() as u32,
+            rdata_rva,
+            rdata_raw,
+            headers + text_raw,
+            IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ,
+        ),
+        (
+            b".bss\0\0\0\0",
+            object.bss,
+            bss_rva,
+            0,
+            0,
+            IMAGE_SCN_CNT_UNINITIALIZED_DATA | IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE,
+        ),
+    ];
+    for (name, size, rva, raw_size, raw_at, characteristics) in sections {
+        out.extend_from_slice(name);
+        for field in [size, rva, raw_size, raw_at, 0, 0, 0] {
+            out.extend_from_slice(&field.to_le_bytes());
+        }
+        out.extend_from_slice(&characteristics.to_le_bytes());
+    }
+    debug_assert_eq!(out.len() as u32, HEADERS);
+
+    out.resize(headers as usize, 0);
+    out.extend_from_slice(&text);
+    out.resize((headers + text_raw) as usize, 0);
+    out.extend_from_slice(&rdata);
+    out.resize((headers + text_raw + rdata_raw) as usize, 0);
+    return Ok(out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{u16_at, u32_at, u64_at};
+
+    // Where the optional header and the section table start.
+    const OPTIONAL: usize = 64 + 4 + 20;
+    const SECTION_TABLE: usize = OPTIONAL + 240;
+
+    /// Code calling an import, then reading a string and a global.
+    fn object() -> Object {
+        Object {
+            text: vec![
+                0xff, 0x15, 0, 0, 0, 0, 0x48, 0x8d, 0x05, 0, 0, 0, 0, 0x48, 0x8b, 0x05, 0, 0, 0, 0,
+                0xc3,
+            ],
+            rodata: b"hi\0".to_vec(),
+            bss: 24,
+            relocations: vec![
+                (2, Data::Import(1)),
+                (9, Data::Rodata(1)),
+                (16, Data::Bss(8)),
+            ],
+            imports: vec!["GetStdHandle", "WriteFile"],
+            symbols: Vec::new(),
+            entry: 6,
+            main: 0,
+        }
+    }
+
+    /// A section's name, virtual size, RVA, raw size and raw offset.
+    fn section(bytes: &[u8], index: usize) -> (&[u8], u32, u32, u32, u32) {
+        let at = SECTION_TABLE + 40 * index;
+        return (
+            &bytes[at..at + 8],
+            u32_at(bytes, at + 8),
+            u32_at(bytes, at + 12),
+            u32_at(bytes, at + 16),
+            u32_at(bytes, at + 20),
+        );
+    }
+
+    #[test]
+    fn the_headers_describe_an_x86_64_console_executable() {
+        let bytes = write(&object()).unwrap();
+
+        assert_eq!(&bytes[..2], b"MZ");
+        assert_eq!(u32_at(&bytes, 0x3c), 64);
+        assert_eq!(&bytes[64..68], b"PE\0\0");
+        assert_eq!(u16_at(&bytes, 68), 0x8664);
+        assert_eq!(u16_at(&bytes, 70), 3);
+        assert_eq!(u16_at(&bytes, 68 + 16), 240);
+        assert_eq!(
+            u16_at(&bytes, 68 + 18),
+            IMAGE_FILE_RELOCS_STRIPPED
+                | IMAGE_FILE_EXECUTABLE_IMAGE
+                | IMAGE_FILE_LARGE_ADDRESS_AWARE
+        );
+
+        assert_eq!(u16_at(&bytes, OPTIONAL), 0x20b);
+        assert_eq!(u32_at(&bytes, OPTIONAL + 16), SECTION_ALIGNMENT + 6);
+        assert_eq!(u64_at(&bytes, OPTIONAL + 24), IMAGE_BASE);
+        assert_eq!(u32_at(&bytes, OPTIONAL + 32), SECTION_ALIGNMENT);
+        assert_eq!(u32_at(&bytes, OPTIONAL + 36), FILE_ALIGNMENT);
+        assert_eq!(u32_at(&bytes, OPTIONAL + 56), 4 * SECTION_ALIGNMENT);
+        assert_eq!(u32_at(&bytes, OPTIONAL + 60), FILE_ALIGNMENT);
+        assert_eq!(u16_at(&bytes, OPTIONAL + 68), IMAGE_SUBSYSTEM_WINDOWS_CUI);
+        assert_eq!(u32_at(&bytes, OPTIONAL + 108), 16);
+        assert_eq!(bytes.len() as u32, 3 * FILE_ALIGNMENT);
+    }
+
+    #[test]
+    fn sections_are_laid_out_and_aligned() {
+        let object = object();
+        let bytes = write(&object).unwrap();
+
+        let (name, size, rva, raw_size, raw_at) = section(&bytes, 0);
+        assert_eq!(name, b".text\0\0\0");
+        assert_eq!((size, rva, raw_size, raw_at), (21, 0x1000, 0x200, 0x200));
+        let (name, _, rva, raw_size, raw_at) = section(&bytes, 1);
+        assert_eq!(name, b".rdata\0\0");
+        assert_eq!((rva, raw_size, raw_at), (0x2000, 0x200, 0x400));
+        assert_eq!(&bytes[0x400..0x403], b"hi\0");
+        let (name, size, rva, raw_size, raw_at) = section(&bytes, 2);
+        assert_eq!(name, b".bss\0\0\0\0");
+        assert_eq!((size, rva, raw_size, raw_at), (24, 0x3000, 0, 0));
+    }
+
+    #[test]
+    fn imports_and_displacements_are_resolved() {
+        let bytes = write(&object()).unwrap();
+        let rdata = |rva: u32| 0x400 + (rva - 0x2000) as usize;
+        let c_string = |at: usize| {
+            let end = at + bytes[at..].iter().position(|b| *b == 0).unwrap();
+            return std::str::from_utf8(&bytes[at..end]).unwrap();
+        };
+
+        let directories = OPTIONAL + 112;
+        let (import, import_size) = (
+            u32_at(&bytes, directories + 8 * IMPORT_DIRECTORY),
+            u32_at(&bytes, directories + 8 * IMPORT_DIRECTORY + 4),
+        );
+        let (iat, iat_size) = (
+            u32_at(&bytes, directories + 8 * IAT_DIRECTORY),
+            u32_at(&bytes, directories + 8 * IAT_DIRECTORY + 4),
+        );
+        assert_eq!((import_size, iat_size), (40, 24));
+        assert_eq!(u32_at(&bytes, rdata(import) + 16), iat);
+        assert_eq!(
+            c_string(rdata(u32_at(&bytes, rdata(import) + 12))),
+            "KERNEL32.dll"
+        );
+        let names: Vec<&str> = (0..2)
+            .map(|i| c_string(rdata(u64_at(&bytes, rdata(iat) + 8 * i) as u32) + 2))
+            .collect();
+        assert_eq!(names, ["GetStdHandle", "WriteFile"]);
+        assert_eq!(u64_at(&bytes, rdata(iat) + 16), 0);
+
+        // Relative to the end of each displacement, in the image.
+        let displacement = |at: usize| {
+            let value = u32_at(&bytes, 0x200 + at) as i32 as i64;
+            return (0x1000 + at as i64 + 4 + value) as u32;
+        };
+        assert_eq!(displacement(2), iat + 8);
+        assert_eq!(displacement(9), 0x2001);
+        assert_eq!(displacement(16), 0x3008);
+    }
+
+    #[test]
+    fn the_same_object_gives_the_same_bytes() {
+        assert_eq!(write(&object()).unwrap(), write(&object()).unwrap());
+    }
+
+    #[test]
+    fn unknown_imports_are_refused() {
+        let mut object = object();
+        object.relocations.push((2, Data::Import(2)));
+        assert!(write(&object).is_err());
+    }
+}
@@ -0,0 +1,402 @@
+use colored::Colorize;
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+use crate::{
+    file_importer::{import_as_text, MemoryFileSystem, SourceMap},
+    grammar::TokenName,
+    interp::{Interpreter, Value},
+    ir::Module,
+    lower::lower,
+    parser::{build_tree, tokenize},
+    resolve::{resolve, Resolution},
+    sexpr::{print_ast, print_tokens, SExprOptions},
+    tree::Tree,
+    types::{check, Type, TypeInfo},
+};
+
+// `nnc repl`, the interactive environment.
+//
+// A session is the list of inputs it accepted so far. Every new input is
+// analyzed after all of them, as if they were one file, so it sees their
+// bindings with their types; but only the new input is run; what the old
+// ones bound lives on in the interpreter's globals. Each input is a file of
+// the session's source map, `<repl>` or the path it was `:load`ed from, so
+// that errors say where they are.
+
+const PROMPT: &str = "nano> ";
+const CONTINUATION_PROMPT: &str = "  ... ";
+
+/// Where typed inputs say they are.
+const REPL_PATH: &str = "<repl>";
+
+pub struct Session {
+    // The inputs accepted so far, with the path each is known by.
+    entries: Vec<(String, String)>,
+    interpreter: Interpreter,
+}
+
+/// An input, analyzed after every accepted input.
+struct Analysis {
+    // Every input, the new one last.
+    sources: SourceMap,
+    // Where the new input starts in the session's source.
+    offset: usize,
+    tree: Tree,
+    types: TypeInfo,
+    module: Module,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {
+            entries: Vec::new(),
+            interpreter: Interpreter::new(),
+        }
+    }
+
+    /// The session's inputs followed by `input`, from `path`, as one source and
+    /// as files, and where `input` starts.
+    fn source_with(&self, path: &str, input: &str) -> (String, SourceMap, usize) {
+        let mut source = String::new();
+        let mut sources = SourceMap::with_fs(Box::new(MemoryFileSystem::new()));
+        for (entry_path, entry) in &self.entries {
+            source.push_str(entry);
+            source.push('\n');
+            sources.push(entry_path, entry.clone());
+        }
+        let offset = source.len();
+        source.push_str(input);
+        sources.push(path, input.to_string());
+        return (source, sources, offset);
+    }
+
+    /// Parses and checks `input`, from `path`, after the session's inputs.
+    /// Only what's wrong with `input` is reported.
+    fn analyze(&self, path: &str, input: &str) -> Result<Analysis, String> {
+        let (source, sources, offset) = self.source_with(path, input);
+        let tokens = tokenize(&source);
+        let ast = match build_tree(&source, &tokens, "Program", false, None) {
+            Ok(ast) => ast,
+            Err(e) => {
+                let d = e.diagnostic();
+                return Err(match d.span.filter(|s| s.start >= offset) {
+                    Some(_) => format!("{}\n", d.render_with(&sources)),
+                    None => format!("{}: Couldn't parse that.\n", "error".red().bold()),
+                });
+            }
+        };
+        let tree = Tree::from_ast(&ast);
+
+        let resolution: Resolution = resolve(&tree);
+        let types = check(&tree, &resolution);
+        let diagnostics = resolution
+            .diagnostics
+            .iter()
+            .chain(types.diagnostics.iter());
+
+        let mut rendered = String::new();
+        let mut failed = false;
+        for d in diagnostics {
+            // Older inputs were already reported on.
+            if d.span.is_none_or(|s| s.start < offset) {
+                continue;
+            }
+            failed |= d.is_error();
+            rendered.push_str(&d.render_with(&sources));
+            rendered.push('\n');
+        }
+        if failed {
+            return Err(rendered);
+        }
+        if !rendered.is_empty() {
+            print!("{}", rendered);
+        }
+
+        let module = lower(&tree, &resolution, &types);
+        return Ok(Analysis {
+            sources,
+            offset,
+            tree,
+            types,
+            module,
+        });
+    }
+
+    /// Runs `input`, returning its value and type. Unit values aren't returned.
+    pub fn eval(&mut self, input: &str) -> Result<Option<(Value, Type)>, String> {
+        return self.eval_from(REPL_PATH, input);
+    }
+
+    /// Runs the file at `path` in the session, like `eval`.
+    pub fn load(&mut self, path: &str) -> Result<Option<(Value, Type)>, String> {
+        return match import_as_text(path) {
+            Ok(source) => self.eval_from(path, &source),
+            Err(e) => Err(format!(
+                "{}: Couldn't read '{}': {}\n",
+                "error".red().bold(),
+                path,
+                e
+            )),
+        };
+    }
+
+    /// Runs `input`, known as `path` in errors.
+    fn eval_from(&mut self, path: &str, input: &str) -> Result<Option<(Value, Type)>, String> {
+        let analysis = self.analyze(path, input)?;
+
+        // Everything from older inputs already ran, only their functions are reloaded.
+        let mut module = analysis.module;
+        module.body.retain(|node| match node.span {
+            None => false,
+            Some(span) => span.start >= analysis.offset,
+        });
+
+        let value = match self.interpreter.run(&module) {
+            Ok(value) => value,
+            Err(e) => return Err(e.render_with(&analysis.sources)),
+        };
+
+        self.entries.push((path.to_string(), input.to_string()));
+
+        let ty = analysis
+            .types
+            .type_of(analysis.tree.root())
+            .cloned()
+            .unwrap_or(Type::Unit);
+        return Ok(match ty {
+            Type::Unit => None,
+            ty => Some((value, ty)),
+        });
+    }
+
+    /// The type `input` would have, without running it.
+    pub fn type_of(&self, input: &str) -> Result<Type, String> {
+        let analysis = self.analyze(REPL_PATH, input)?;
+        return Ok(analysis
+            .types
+            .type_of(analysis.tree.root())
+            .cloned()
+            .unwrap_or(Type::Unit));
+    }
+}
+
+/// How many brackets `input` leaves open, not counting those in strings and comments.
+fn open_brackets(input: &str) -> i32 {
+    tokenize(input)
+        .iter()
+        .map(|t| match t.name {
+            TokenName::ParenthesisOpen | TokenName::SqBracketsOpen | TokenName::CrBracketsOpen => 1,
+            TokenName::ParenthesisClose
+            | TokenName::SqBracketsClose
+            | TokenName::CrBracketsClose => -1,
+            _ => 0,
+        })
+        .sum()
+}
+
+fn print_help() {
+    println!("Type nano expressions to run them. Bindings are kept between inputs.");
+    println!("Input goes on for as long as brackets are left open.\n");
+    println!("{} - Shows the type of <expr>.", ":type <expr>".bold());
+    println!(
+        "{} - Shows the syntax tree of <expr>.",
+        ":ast <expr>".bold()
+    );
+    println!("{} - Shows the tokens of <expr>.", ":tokens <expr>".bold());
+    println!("{} - Runs <file> in this session.", ":load <file>".bold());
+    println!("{} - Shows this.", ":help".bold());
+    println!("{} - Leaves.", ":quit".bold());
+}
+
+fn show(result: Result<Option<(Value, Type)>, String>) {
+    match result {
+        Ok(None) => {}
+        Ok(Some((value, ty))) => println!("{} {} {}", value, ":".dimmed(), ty.to_string().cyan()),
+        Err(e) => print!("{}", e),
+    }
+}
+
+/// Runs a meta-command, like `:type 1 + 2`. Returns whether the REPL should go on.
+fn meta_command(session: &mut Session, line: &str) -> bool {
+    let (command, rest) = match line.split_once(char::is_whitespace) {
+        None => (line, ""),
+        Some((c, r)) => (c, r.trim()),
+    };
+
+    match command {
+        ":quit" | ":q" => return false,
+        ":help" | ":h" => print_help(),
+        ":type" | ":t" => match session.type_of(rest) {
+            Ok(ty) => println!("{}", ty.to_string().cyan()),
+            Err(e) => print!("{}", e),
+        },
+        ":ast" => {
+            let tokens = tokenize(rest);
+            match build_tree(rest, &tokens, "Program", false, None) {
+                Ok(ast) => println!("{}", print_ast(&ast, &SExprOptions::default())),
+                Err(e) => println!("{}", e.diagnostic().render(REPL_PATH, rest)),
+            }
+        }
+        ":tokens" => println!(
+            "{}",
+            print_tokens(&tokenize(rest), &SExprOptions::default())
+        ),
+        ":load" | ":l" => show(session.load(rest)),
+        other => println!(
+            "Unknown command '{}', `:help` lists the ones there are.",
+            other
+        ),
+    }
+
+    return true;
+}
+
+/// nnc repl
+pub fn start() {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            println!("Couldn't start the REPL: {}", e);
+            return;
+        }
+    };
+    let mut session = Session::new();
+
+    println!(
+        "{} {}",
+        "nano".cyan().bold(),
+        "REPL, `:help` for help, `:quit` to leave.".dimmed()
+    );
+
+    let mut input = String::new();
+    loop {
+        let prompt = match input.is_empty() {
+            true => PROMPT,
+            false => CONTINUATION_PROMPT,
+        };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl-C drops the input being typed.
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            Err(_) => break,
+        };
+
+        if !input.is_empty() {
+            input.push('\n');
+        }
+        input.push_str(&line);
+        let is_command = input.trim_start().starts_with(':');
+        if !is_command && open_brackets(&input) > 0 {
+            continue;
+        }
+
+        let entry = std::mem::take(&mut input);
+        let trimmed = entry.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(trimmed);
+
+        if trimmed.starts_with(':') {
+            if !meta_command(&mut session, trimmed) {
+                break;
+            }
+            continue;
+        }
+        show(session.eval(&entry));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::plain;
+
+    /// What `input` gives in `session`, as the REPL shows values.
+    fn shown(session: &mut Session, input: &str) -> String {
+        return match session.eval(input) {
+            Ok(None) => String::new(),
+            Ok(Some((value, ty))) => format!("{} : {}", value, ty),
+            Err(e) => plain(&e),
+        };
+    }
+
+    /// Writes `source` to a file of its own for `name`, and gives its path.
+    fn file(name: &str, source: &str) -> String {
+        let folder = std::env::temp_dir().join(format!("nnc-repl-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("loaded.nano");
+        std::fs::write(&path, source).unwrap();
+        return path.display().to_string();
+    }
+
+    #[test]
+    fn bindings_are_kept_between_inputs() {
+        let mut session = Session::new();
+        assert_eq!(shown(&mut session, "let x = 20"), "20 : int");
+        assert!(session.eval("fn double(n) -> n * 2").is_ok());
+        assert_eq!(shown(&mut session, "double(x) + 2"), "42 : int");
+        assert_eq!(
+            plain(&session.type_of("double").unwrap().to_string()),
+            "(int) -> int"
+        );
+    }
+
+    #[test]
+    fn failed_inputs_are_forgotten() {
+        let mut session = Session::new();
+        assert!(session.eval("let y = 1 + 'a'").is_err());
+        assert!(shown(&mut session, "y").contains("Undefined name 'y'."));
+        assert_eq!(shown(&mut session, "let y = 2\ny"), "2 : int");
+    }
+
+    #[test]
+    fn errors_point_into_the_last_input() {
+        let mut session = Session::new();
+        shown(&mut session, "let a = 1\nlet b = 2");
+        let error = shown(&mut session, "a +\n  nothing");
+        assert!(error.contains("Undefined name 'nothing'."), "{}", error);
+        assert!(error.contains("<repl>:2:3"), "{}", error);
+    }
+
+    #[test]
+    fn loaded_files_are_known_by_their_path() {
+        let mut session = Session::new();
+        shown(&mut session, "let before = 1");
+
+        let types = file("types", "let fine = 1\n\nlet wrong = fine + 'a'");
+        let error = plain(&session.load(&types).unwrap_err());
+        assert!(error.contains(&format!("{}:3:", types)), "{}", error);
+
+        let runtime = file(
+            "runtime",
+            "fn divide(n) -> 10 / n\nlet zero = 0\ndivide(zero)",
+        );
+        let error = plain(&session.load(&runtime).unwrap_err());
+        assert!(error.contains("Division by zero."), "{}", error);
+        assert!(error.contains(&format!("{}:1:17", runtime)), "{}", error);
+        assert!(
+            error.contains(&format!("called at {}:3:1", runtime)),
+            "{}",
+            error
+        );
+
+        let fine = file("fine", "fn triple(n) -> n * 3");
+        assert!(session.load(&fine).is_ok());
+        assert_eq!(shown(&mut session, "triple(before)"), "3 : int");
+
+        for path in [types, runtime, fine] {
+            let folder = std::path::Path::new(&path).parent().unwrap();
+            std::fs::remove_dir_all(folder).unwrap();
+        }
+    }
+}
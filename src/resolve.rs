@@ -0,0 +1,507 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    diagnostic::Diagnostic,
+    tree::{NodeId, Tree},
+};
+
+// Name resolution: builds the lexical scopes of a program and links
+// every identifier use to the binding it refers to.
+//
+// Scopes are opened by the program itself, `( ... )` blocks, function
+// and lambda bodies, and `for`/`select` loops. Bindings come from `let`,
+// `fn` (and its parameters), lambda parameters, loop variables and
+// `import ... as`. A `let` is visible from the expression after it on,
+// while `fn`s are visible in their whole block, so they can call each other.
+
+/// Names every program can use without defining them.
+pub const PRELUDE: &[&str] = &["print"];
+
+pub type ScopeId = usize;
+pub type BindingId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingKind {
+    Builtin,
+    Import,
+    Fn,
+    Let,
+    Param,
+    Loop,
+}
+
+#[derive(Debug, Clone)]
+pub struct Binding {
+    pub name: String,
+    pub kind: BindingKind,
+    // The `Name` node that introduced the binding, `None` for builtins.
+    pub node: Option<NodeId>,
+    pub scope: ScopeId,
+}
+
+#[derive(Debug, Clone)]
+pub struct Scope {
+    pub parent: Option<ScopeId>,
+    // The node that opened the scope, `None` for the prelude.
+    pub node: Option<NodeId>,
+    pub bindings: Vec<BindingId>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Resolution {
+    pub scopes: Vec<Scope>,
+    pub bindings: Vec<Binding>,
+    // Every `Name` used as a value, mapped to what it refers to.
+    pub uses: HashMap<NodeId, BindingId>,
+    // Every `Name` that introduces a binding.
+    pub definitions: HashMap<NodeId, BindingId>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Resolution {
+    pub fn binding(&self, id: BindingId) -> &Binding {
+        &self.bindings[id]
+    }
+
+    /// The binding a `Name` node refers to, or introduces.
+    pub fn definition_of(&self, name: NodeId) -> Option<BindingId> {
+        self.uses
+            .get(&name)
+            .or_else(|| self.definitions.get(&name))
+            .copied()
+    }
+
+    /// Every `Name` node using a binding, in source order.
+    pub fn references_of(&self, binding: BindingId) -> Vec<NodeId> {
+        let mut out: Vec<NodeId> = self
+            .uses
+            .iter()
+            .filter(|(_, b)| **b == binding)
+            .map(|(n, _)| *n)
+            .collect();
+        out.sort();
+        return out;
+    }
+
+    /// The innermost scope opened by `node` or one of its ancestors.
+    pub fn scope_of(&self, tree: &Tree, node: NodeId) -> Option<ScopeId> {
+        std::iter::once(node)
+            .chain(tree.ancestors(node))
+            .find_map(|n| self.scopes.iter().position(|s| s.node == Some(n)))
+    }
+
+    /// What a module exposes through `import ... as alias`: its top-level `fn`s and `let`s.
+    /// When a name is bound more than once, the last binding is the one exported.
+    pub fn exports(&self, tree: &Tree) -> Vec<BindingId> {
+        let module = self.scopes.iter().find(|s| s.node == Some(tree.root()));
+        let mut out: Vec<BindingId> = Vec::new();
+        for b in module.map(|s| s.bindings.clone()).unwrap_or_default() {
+            let binding = self.binding(b);
+            if !matches!(binding.kind, BindingKind::Fn | BindingKind::Let) {
+                continue;
+            }
+            out.retain(|other| self.binding(*other).name != binding.name);
+            out.push(b);
+        }
+        return out;
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(Diagnostic::is_error)
+    }
+}
+
+pub fn resolve(tree: &Tree) -> Resolution {
+    let mut resolver = Resolver {
+        tree,
+        resolution: Resolution::default(),
+        current: 0,
+        use_counts: Vec::new(),
+        hoisted: HashSet::new(),
+        in_test: false,
+    };
+
+    resolver.push_scope(None);
+    for name in PRELUDE {
+        resolver.add_binding(name.to_string(), BindingKind::Builtin, None);
+    }
+
+    resolver.push_scope(Some(tree.root()));
+    resolver.visit(tree.root());
+    resolver.pop_scope();
+
+    let mut resolution = resolver.resolution;
+    resolution
+        .diagnostics
+        .sort_by_key(|d| d.span.map(|s| s.start));
+    return resolution;
+}
+
+struct Resolver<'t> {
+    tree: &'t Tree,
+    resolution: Resolution,
+    current: ScopeId,
+    use_counts: Vec<usize>,
+    // `fn`s already bound ahead of time by their block.
+    hoisted: HashSet<NodeId>,
+    // Whether what's visited is in a `%%test fn`, where assertions can be.
+    in_test: bool,
+}
+
+impl<'t> Resolver<'t> {
+    fn push_scope(&mut self, node: Option<NodeId>) {
+        let parent = match self.resolution.scopes.is_empty() {
+            true => None,
+            false => Some(self.current),
+        };
+        self.resolution.scopes.push(Scope {
+            parent,
+            node,
+            bindings: Vec::new(),
+        });
+        self.current = self.resolution.scopes.len() - 1;
+    }
+
+    fn pop_scope(&mut self) {
+        let scope = &self.resolution.scopes[self.current];
+
+        // Top-level bindings are what a module exports, so they are never "unused".
+        let is_module = scope.node == Some(self.tree.root());
+        if !is_module {
+            for b in scope.bindings.clone() {
+                let binding = &self.resolution.bindings[b];
+                if self.use_counts[b] > 0 || binding.name.starts_with('_') {
+                    continue;
+                }
+                let what = match binding.kind {
+                    BindingKind::Param => "parameter",
+                    BindingKind::Fn => "function",
+                    BindingKind::Import => "import",
+                    _ => "binding",
+                };
+                let span = binding.node.and_then(|n| self.tree.span(n));
+                let message = format!("Unused {} '{}'.", what, binding.name);
+                self.resolution
+                    .diagnostics
+                    .push(Diagnostic::warning(message, span));
+            }
+        }
+
+        self.current = scope.parent.unwrap_or(0);
+    }
+
+    fn add_binding(&mut self, name: String, kind: BindingKind, node: Option<NodeId>) -> BindingId {
+        let id = self.resolution.bindings.len();
+        self.resolution.bindings.push(Binding {
+            name,
+            kind,
+            node,
+            scope: self.current,
+        });
+        self.resolution.scopes[self.current].bindings.push(id);
+        self.use_counts.push(0);
+        if let Some(n) = node {
+            self.resolution.definitions.insert(n, id);
+        }
+        return id;
+    }
+
+    fn lookup(&self, name: &str) -> Option<BindingId> {
+        let mut scope = Some(self.current);
+        while let Some(s) = scope {
+            let found = self.resolution.scopes[s]
+                .bindings
+                .iter()
+                .rev()
+                .find(|b| self.resolution.bindings[**b].name == name);
+            if let Some(b) = found {
+                return Some(*b);
+            }
+            scope = self.resolution.scopes[s].parent;
+        }
+        return None;
+    }
+
+    /// Binds the `Name` node `name_node` in the current scope.
+    fn define(&mut self, name_node: Option<NodeId>, kind: BindingKind) {
+        let name_node = match name_node {
+            None => return,
+            Some(n) => n,
+        };
+        let name = self.tree.text(name_node).unwrap_or("").to_string();
+
+        if let Some(earlier) = self.lookup(&name) {
+            let earlier = &self.resolution.bindings[earlier];
+            if earlier.kind != BindingKind::Builtin {
+                let note_span = earlier.node.and_then(|n| self.tree.span(n));
+                let diagnostic = Diagnostic::warning(
+                    format!("'{}' shadows an earlier binding.", name),
+                    self.tree.span(name_node),
+                )
+                .with_note(note_span, format!("'{}' was first bound here.", name));
+                self.resolution.diagnostics.push(diagnostic);
+            }
+        }
+
+        self.add_binding(name, kind, Some(name_node));
+    }
+
+    fn use_name(&mut self, name_node: NodeId) {
+        let name = self.tree.text(name_node).unwrap_or("");
+        match self.lookup(name) {
+            Some(b) => {
+                self.use_counts[b] += 1;
+                self.resolution.uses.insert(name_node, b);
+            }
+            None => {
+                let message = format!("Undefined name '{}'.", name);
+                self.resolution
+                    .diagnostics
+                    .push(Diagnostic::error(message, self.tree.span(name_node)));
+            }
+        }
+    }
+
+    fn visit_opt(&mut self, id: Option<NodeId>) {
+        if let Some(id) = id {
+            self.visit(id);
+        }
+    }
+
+    /// Visits a sequence of expressions sharing a scope, binding its `fn`s first.
+    fn visit_block(&mut self, items: Vec<NodeId>) {
+        for item in &items {
+            if let Some(decl) = self.tree.declared_fn(*item) {
+                self.define(self.tree.get(decl, "name"), BindingKind::Fn);
+                self.hoisted.insert(decl);
+            }
+        }
+
+        for item in items {
+            self.visit(item);
+        }
+    }
+
+    fn error(&mut self, message: impl Into<String>, node: NodeId) {
+        let span = self.tree.span(node);
+        self.resolution
+            .diagnostics
+            .push(Diagnostic::error(message, span));
+    }
+
+    /// `%%test fn name -> body`, the only scope annotation for now.
+    fn visit_scope_annotation(&mut self, id: NodeId) {
+        let tree = self.tree;
+        let name = tree
+            .get(id, "name")
+            .and_then(|n| tree.text(n))
+            .unwrap_or("");
+        if name != "%%test" {
+            let message = format!("Unknown scope annotation '{}'.", name);
+            let span = tree.span(id);
+            let diagnostic = Diagnostic::error(message, span)
+                .with_note(None, "The only scope annotation is `%%test`.");
+            self.resolution.diagnostics.push(diagnostic);
+        }
+
+        match tree.get(id, "value").and_then(|v| tree.declared_fn(v)) {
+            None => self.error("`%%test` only goes before a `fn`.", id),
+            Some(decl) => {
+                if self.resolution.scopes[self.current].node != Some(tree.root()) {
+                    self.error("A `%%test fn` has to be at the top level.", id);
+                }
+                let params = tree.get(decl, "params").map(|p| tree.child_nodes(p));
+                if !params.unwrap_or_default().is_empty() {
+                    self.error("A `%%test fn` takes no parameters.", id);
+                }
+            }
+        }
+
+        let in_test = std::mem::replace(&mut self.in_test, true);
+        self.visit_opt(tree.get(id, "value"));
+        self.in_test = in_test;
+    }
+
+    /// `%%t_eq(actual, expected)`, in a `%%test fn`.
+    fn check_assertion(&mut self, id: NodeId) {
+        let tree = self.tree;
+        let name = tree
+            .get(id, "name")
+            .and_then(|n| tree.text(n))
+            .unwrap_or("");
+        if name != "%%t_eq" {
+            let message = format!("Unknown assertion '{}'.", name);
+            let diagnostic = Diagnostic::error(message, tree.span(id))
+                .with_note(None, "The only assertion is `%%t_eq(actual, expected)`.");
+            self.resolution.diagnostics.push(diagnostic);
+            return;
+        }
+        let args = tree.get(id, "args").map(|a| tree.child_nodes(a));
+        if args.unwrap_or_default().len() != 2 {
+            self.error("`%%t_eq` takes the actual value and the expected one.", id);
+        }
+        if !self.in_test {
+            self.error("`%%t_eq` can only be used in a `%%test fn`.", id);
+        }
+    }
+
+    fn visit(&mut self, id: NodeId) {
+        let tree = self.tree;
+        match tree.rule(id).unwrap_or("") {
+            "Name" => self.use_name(id),
+            "Exprs" => self.visit_block(tree.child_nodes(id)),
+            "Parens" => {
+                self.push_scope(Some(id));
+                self.visit_block(tree.child_nodes(id));
+                self.pop_scope();
+            }
+            "Let" => {
+                self.visit_opt(tree.get(id, "value"));
+                self.define(tree.get(id, "name"), BindingKind::Let);
+            }
+            "Import" => self.define(tree.get(id, "alias"), BindingKind::Import),
+            "FnDecl" => {
+                if !self.hoisted.contains(&id) {
+                    self.define(tree.get(id, "name"), BindingKind::Fn);
+                }
+                self.push_scope(Some(id));
+                if let Some(params) = tree.get(id, "params") {
+                    for p in tree.child_nodes(params) {
+                        self.define(Some(p), BindingKind::Param);
+                    }
+                }
+                self.visit_opt(tree.get(id, "body"));
+                self.pop_scope();
+            }
+            "Lambda" => {
+                self.push_scope(Some(id));
+                self.define(tree.get(id, "param"), BindingKind::Param);
+                self.visit_opt(tree.get(id, "body"));
+                self.pop_scope();
+            }
+            "For" | "Select" => {
+                self.visit_opt(tree.get(id, "iterable"));
+                self.push_scope(Some(id));
+                self.define(tree.get(id, "name"), BindingKind::Loop);
+                self.visit_opt(tree.get(id, "body"));
+                self.pop_scope();
+            }
+            "ScopeAnnotation" => self.visit_scope_annotation(id),
+            "Assertion" => {
+                self.check_assertion(id);
+                for child in tree.child_nodes(id) {
+                    self.visit(child);
+                }
+            }
+            // Record keys are not uses, and neither are the fields read from a value.
+            "Field" => self.visit_opt(tree.get(id, "value")),
+            "Member" => self.visit_opt(tree.get(id, "object")),
+            _ => {
+                for child in tree.child_nodes(id) {
+                    self.visit(child);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{build_tree, tokenize};
+
+    fn resolved(source: &str) -> (Tree, Resolution) {
+        let tokens = tokenize(source);
+        let ast = build_tree(source, &tokens, "Program", false, None).expect("it doesn't parse");
+        let tree = Tree::from_ast(&ast);
+        let resolution = resolve(&tree);
+        return (tree, resolution);
+    }
+
+    /// The `Name` nodes written `text`, in source order.
+    fn names(tree: &Tree, text: &str) -> Vec<NodeId> {
+        return tree
+            .descendants(tree.root())
+            .into_iter()
+            .filter(|n| tree.is(*n, "Name") && tree.text(*n) == Some(text))
+            .collect();
+    }
+
+    /// The messages of `source`'s diagnostics, in source order.
+    fn messages(source: &str) -> Vec<String> {
+        let (_, resolution) = resolved(source);
+        return resolution
+            .diagnostics
+            .into_iter()
+            .map(|d| d.message)
+            .collect();
+    }
+
+    #[test]
+    fn definitions_and_references_can_be_queried() {
+        let (tree, resolution) = resolved("let x = 1\nfn f(y) -> x + y\nprint(f(x))");
+        let xs = names(&tree, "x");
+        assert_eq!(xs.len(), 3);
+
+        let x = resolution.definition_of(xs[0]).unwrap();
+        assert_eq!(resolution.binding(x).kind, BindingKind::Let);
+        assert_eq!(resolution.definition_of(xs[1]), Some(x));
+        assert_eq!(resolution.definition_of(xs[2]), Some(x));
+        assert_eq!(resolution.references_of(x), [xs[1], xs[2]]);
+
+        let f = resolution.definition_of(names(&tree, "f")[0]).unwrap();
+        assert_eq!(resolution.binding(f).kind, BindingKind::Fn);
+        assert_eq!(resolution.references_of(f), [names(&tree, "f")[1]]);
+    }
+
+    #[test]
+    fn functions_can_be_used_before_they_are_declared() {
+        let (tree, resolution) = resolved("print(twice(1))\nfn twice(x) -> x * 2");
+        assert!(
+            resolution.diagnostics.is_empty(),
+            "{:?}",
+            resolution.diagnostics
+        );
+        let uses = names(&tree, "twice");
+        assert_eq!(
+            resolution.definition_of(uses[0]),
+            resolution.definition_of(uses[1])
+        );
+    }
+
+    #[test]
+    fn inner_bindings_shadow_outer_ones() {
+        let source = "fn f(y) -> (\n    let y = y + 1\n    y\n)\nprint(f(1))";
+        let (tree, resolution) = resolved(source);
+        let ys = names(&tree, "y");
+        let (param, inner) = (ys[0], ys[1]);
+        // `y + 1` still reads the parameter, the `let` binds after its value.
+        assert_eq!(
+            resolution.definition_of(ys[2]),
+            resolution.definition_of(param)
+        );
+        assert_eq!(
+            resolution.definition_of(ys[3]),
+            resolution.definition_of(inner)
+        );
+        assert_eq!(messages(source), ["'y' shadows an earlier binding."]);
+    }
+
+    #[test]
+    fn undefined_names_are_errors() {
+        assert_eq!(messages("let x = 1\nprint(x + z)"), ["Undefined name 'z'."]);
+        // A `let` isn't visible in its own value.
+        assert_eq!(messages("let w = w"), ["Undefined name 'w'."]);
+    }
+
+    #[test]
+    fn unused_names_are_warned_about_unless_they_start_with_an_underscore() {
+        assert_eq!(
+            messages("fn f(a, b) -> (\n    let c = 1\n    a\n)\nprint(f(1, 2))"),
+            ["Unused parameter 'b'.", "Unused binding 'c'."]
+        );
+        assert!(messages("fn f(a, _b) -> (\n    let _c = 1\n    a\n)\nprint(f(1, 2))").is_empty());
+        // What a module exports is used by whoever imports it.
+        assert!(messages("let unused = 1\nfn g() -> 2").is_empty());
+    }
+}
@@ -0,0 +1,525 @@
+use crate::{
+    grammar::{ASTNodeContent, Span, Token, TokenName, AST},
+    nano_grammar::is_ghost_token,
+    tree::Tree,
+    util::{camel_case, json_string, screaming_snake_case},
+};
+
+// The S-expression notation for syntax trees sketched in `examples/ast.nano`:
+//
+// (n PROGRAM
+//     (n EXPR
+//         (n BINARY_EXPR
+//             op: (t OP_PLUS)
+//             lhs: (n LITERAL value: (t INT_LITERAL 1))
+//             rhs: (n LITERAL value: (t INT_LITERAL 2))
+//         )
+//     )
+// )
+//
+// `(n NAME ...)` is a node, `(t NAME text)` a token (the text is left out
+// when the token can only be spelled one way), `[...]` a grouping,
+// `label: ...` a labelled capture and `_` an empty match.
+// Spans are written as `@start..end`, right after the name or the text.
+
+/// Lines longer than this are broken, one child per line.
+const MAX_FLAT_WIDTH: usize = 80;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SExprOptions {
+    pub spans: bool,
+    pub ghost_tokens: bool,
+}
+
+/// An owned syntax tree in S-expression form, as printed and as read back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SExpr {
+    None,
+    Token {
+        name: TokenName,
+        // Only `None` for tokens with no text at all, like EOF.
+        text: Option<String>,
+        span: Option<Span>,
+    },
+    Node {
+        name: String,
+        span: Option<Span>,
+        content: Vec<SExpr>,
+    },
+    Grouping(Vec<SExpr>),
+    Labelled(String, Box<SExpr>),
+}
+
+#[derive(Debug, Clone)]
+pub struct SExprError {
+    pub message: String,
+    pub offset: usize,
+}
+
+impl SExpr {
+    pub fn from_ast(ast: &AST, options: &SExprOptions) -> SExpr {
+        SExpr::Node {
+            name: ast.root.matched_with.to_string(),
+            span: ast.root.span().filter(|_| options.spans),
+            content: from_content_list(&ast.root.content, options),
+        }
+    }
+
+    pub fn from_token(token: &Token, options: &SExprOptions) -> SExpr {
+        SExpr::Token {
+            name: token.name,
+            text: token.str_content.map(str::to_string),
+            span: Some(token.span).filter(|_| options.spans),
+        }
+    }
+
+    /// Renders this tree, breaking lines that get too wide.
+    pub fn to_pretty_string(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0);
+        return out;
+    }
+
+    /// Renders this tree on a single line.
+    pub fn to_flat_string(&self) -> String {
+        match self {
+            SExpr::None => "_".to_string(),
+            SExpr::Token { name, text, span } => {
+                let mut s = format!("(t {}", screaming_snake_case(&format!("{:?}", name)));
+                if let Some(text) = text {
+                    if name.fixed_text() != Some(text.as_str()) {
+                        s.push(' ');
+                        s.push_str(&quote_if_needed(text));
+                    }
+                }
+                if let Some(span) = span {
+                    s.push_str(&format!(" @{}..{}", span.start, span.end));
+                }
+                s.push(')');
+                s
+            }
+            SExpr::Node {
+                name,
+                span,
+                content,
+            } => {
+                let mut s = format!("(n {}", screaming_snake_case(name));
+                if let Some(span) = span {
+                    s.push_str(&format!(" @{}..{}", span.start, span.end));
+                }
+                for c in content {
+                    s.push(' ');
+                    s.push_str(&c.to_flat_string());
+                }
+                s.push(')');
+                s
+            }
+            SExpr::Grouping(items) => {
+                let items: Vec<String> = items.iter().map(SExpr::to_flat_string).collect();
+                format!("[{}]", items.join(" "))
+            }
+            SExpr::Labelled(label, inner) => format!("{}: {}", label, inner.to_flat_string()),
+        }
+    }
+
+    fn write_pretty(&self, out: &mut String, indent: usize) {
+        let flat = self.to_flat_string();
+        if indent + flat.len() <= MAX_FLAT_WIDTH {
+            out.push_str(&flat);
+            return;
+        }
+
+        let pad = " ".repeat(indent);
+        match self {
+            SExpr::Node {
+                name,
+                span,
+                content,
+            } => {
+                out.push_str(&format!("(n {}", screaming_snake_case(name)));
+                if let Some(span) = span {
+                    out.push_str(&format!(" @{}..{}", span.start, span.end));
+                }
+                for c in content {
+                    out.push('\n');
+                    out.push_str(&pad);
+                    out.push_str("    ");
+                    c.write_pretty(out, indent + 4);
+                }
+                out.push('\n');
+                out.push_str(&pad);
+                out.push(')');
+            }
+            SExpr::Grouping(items) => {
+                out.push('[');
+                for c in items {
+                    out.push('\n');
+                    out.push_str(&pad);
+                    out.push_str("    ");
+                    c.write_pretty(out, indent + 4);
+                }
+                out.push('\n');
+                out.push_str(&pad);
+                out.push(']');
+            }
+            SExpr::Labelled(label, inner) => {
+                out.push_str(label);
+                out.push_str(": ");
+                inner.write_pretty(out, indent);
+            }
+            // Leaves never break.
+            _ => out.push_str(&flat),
+        }
+    }
+}
+
+fn from_content_list(content: &[ASTNodeContent], options: &SExprOptions) -> Vec<SExpr> {
+    content
+        .iter()
+        .filter_map(|c| from_content(c, options))
+        .collect()
+}
+
+fn from_content(content: &ASTNodeContent, options: &SExprOptions) -> Option<SExpr> {
+    let s = match content {
+        ASTNodeContent::None => SExpr::None,
+        ASTNodeContent::Tok(t) => {
+            if !options.ghost_tokens && is_ghost_token(&t.name) {
+                return None;
+            }
+            SExpr::from_token(t, options)
+        }
+        ASTNodeContent::Grouping(items) => SExpr::Grouping(from_content_list(items, options)),
+        ASTNodeContent::Node(n) => SExpr::Node {
+            name: n.matched_with.to_string(),
+            span: n.span().filter(|_| options.spans),
+            content: from_content_list(&n.content, options),
+        },
+        ASTNodeContent::Labelled(label, inner) => {
+            SExpr::Labelled(label.to_string(), Box::new(from_content(inner, options)?))
+        }
+    };
+
+    return Some(s);
+}
+
+/// Prints a tree in S-expression notation.
+pub fn print_ast(ast: &AST, options: &SExprOptions) -> String {
+    SExpr::from_ast(ast, options).to_pretty_string()
+}
+
+/// Prints an owned tree in S-expression notation.
+pub fn print_tree(tree: &Tree, options: &SExprOptions) -> String {
+    match tree.to_sexpr(tree.root(), options) {
+        None => String::new(),
+        Some(s) => s.to_pretty_string(),
+    }
+}
+
+/// Prints a token list, one `(t ...)` per line.
+pub fn print_tokens(tokens: &[Token], options: &SExprOptions) -> String {
+    let lines: Vec<String> = tokens
+        .iter()
+        .filter(|t| options.ghost_tokens || !is_ghost_token(&t.name))
+        .map(|t| SExpr::from_token(t, options).to_flat_string())
+        .collect();
+
+    return lines.join("\n");
+}
+
+fn quote_if_needed(text: &str) -> String {
+    let is_atom = !text.is_empty()
+        && text != "_"
+        && text
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '.');
+
+    match is_atom {
+        true => text.to_string(),
+        false => json_string(text),
+    }
+}
+
+/// Reads a tree back from its S-expression notation.
+/// `#` starts a comment that runs until the end of the line.
+pub fn read(text: &str) -> Result<SExpr, SExprError> {
+    let mut reader = Reader { text, offset: 0 };
+    let expr = reader.expr()?;
+
+    reader.skip_trivia();
+    if reader.offset < text.len() {
+        return Err(reader.error("Unexpected text after the tree"));
+    }
+
+    return Ok(expr);
+}
+
+struct Reader<'a> {
+    text: &'a str,
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, message: &str) -> SExprError {
+        SExprError {
+            message: message.to_string(),
+            offset: self.offset,
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.offset..]
+    }
+
+    fn skip_trivia(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.offset += rest.len() - trimmed.len();
+
+            if trimmed.starts_with('#') {
+                self.offset += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_trivia();
+        self.rest().chars().next()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), SExprError> {
+        match self.peek() {
+            Some(found) if found == c => {
+                self.offset += c.len_utf8();
+                Ok(())
+            }
+            _ => Err(self.error(&format!("Expected '{}'", c))),
+        }
+    }
+
+    /// A bare word, up to whitespace or a bracket.
+    fn atom(&mut self) -> Result<&'a str, SExprError> {
+        self.skip_trivia();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| c.is_whitespace() || "()[]\"".contains(c))
+            .unwrap_or(rest.len());
+
+        if len == 0 {
+            return Err(self.error("Expected a word"));
+        }
+
+        self.offset += len;
+        return Ok(&rest[..len]);
+    }
+
+    fn string(&mut self) -> Result<String, SExprError> {
+        self.expect('"')?;
+        let mut out = String::new();
+        let mut chars = self.rest().char_indices();
+
+        loop {
+            let (i, c) = match chars.next() {
+                None => return Err(self.error("Unterminated string")),
+                Some(ic) => ic,
+            };
+            match c {
+                '"' => {
+                    self.offset += i + 1;
+                    return Ok(out);
+                }
+                '\\' => {
+                    let escaped = match chars.next() {
+                        None => return Err(self.error("Unterminated string")),
+                        Some((_, e)) => e,
+                    };
+                    match escaped {
+                        'n' => out.push('\n'),
+                        'r' => out.push('\r'),
+                        't' => out.push('\t'),
+                        'u' => {
+                            let hex: String = (0..4)
+                                .filter_map(|_| chars.next())
+                                .map(|(_, h)| h)
+                                .collect();
+                            let code = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32);
+                            match code {
+                                None => return Err(self.error("Invalid unicode escape")),
+                                Some(code) => out.push(code),
+                            }
+                        }
+                        other => out.push(other),
+                    }
+                }
+                other => out.push(other),
+            }
+        }
+    }
+
+    /// An optional `@start..end` span.
+    fn span(&mut self) -> Result<Option<Span>, SExprError> {
+        if self.peek() != Some('@') {
+            return Ok(None);
+        }
+
+        let word = self.atom()?;
+        let span = word[1..]
+            .split_once("..")
+            .and_then(|(a, b)| Some(Span::new(a.parse().ok()?, b.parse().ok()?)));
+
+        match span {
+            None => Err(self.error(&format!("Invalid span '{}'", word))),
+            Some(s) => Ok(Some(s)),
+        }
+    }
+
+    fn expr(&mut self) -> Result<SExpr, SExprError> {
+        match self.peek() {
+            None => Err(self.error("Unexpected end of input")),
+            Some('(') => {
+                self.offset += 1;
+                let kind = self.atom()?;
+                let expr = match kind {
+                    "n" => self.node()?,
+                    "t" => self.token()?,
+                    _ => return Err(self.error(&format!("Unknown form '{}'", kind))),
+                };
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Some('[') => {
+                self.offset += 1;
+                let mut items = Vec::new();
+                while self.peek() != Some(']') {
+                    items.push(self.expr()?);
+                }
+                self.expect(']')?;
+                Ok(SExpr::Grouping(items))
+            }
+            Some(_) => {
+                let word = self.atom()?;
+                if word == "_" {
+                    return Ok(SExpr::None);
+                }
+                match word.strip_suffix(':') {
+                    None => Err(self.error(&format!("Unexpected '{}'", word))),
+                    Some(label) => Ok(SExpr::Labelled(label.to_string(), Box::new(self.expr()?))),
+                }
+            }
+        }
+    }
+
+    fn node(&mut self) -> Result<SExpr, SExprError> {
+        let name = camel_case(self.atom()?);
+        let span = self.span()?;
+        let mut content = Vec::new();
+        while self.peek() != Some(')') {
+            content.push(self.expr()?);
+        }
+
+        return Ok(SExpr::Node {
+            name,
+            span,
+            content,
+        });
+    }
+
+    fn token(&mut self) -> Result<SExpr, SExprError> {
+        let word = self.atom()?;
+        let name = TokenName::ALL
+            .iter()
+            .find(|n| screaming_snake_case(&format!("{:?}", n)) == word);
+        let name = match name {
+            None => return Err(self.error(&format!("Unknown token name '{}'", word))),
+            Some(n) => *n,
+        };
+
+        let text = match self.peek() {
+            Some('"') => Some(self.string()?),
+            Some(')') | Some('@') => name.fixed_text().map(str::to_string),
+            _ => Some(self.atom()?.to_string()),
+        };
+        let span = self.span()?;
+
+        return Ok(SExpr::Token { name, text, span });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{build_tree, tokenize};
+
+    const SOURCE: &str = "\
+# A comment, and a block one.
+### kept in
+the CST ###
+let xs = [1, -2, 3.5]
+let who = { name: 'Ada \"the\" first', age: 36 }
+fn add(a, b) -> (a + b * 2)
+xs |> print; add(1, 2) is 3
+";
+
+    /// Prints `SOURCE`'s tree, then checks that reading it back prints the same,
+    /// as an `SExpr` and as an owned `Tree`.
+    fn round_trip(concrete: bool, spans: bool) {
+        let options = SExprOptions {
+            spans,
+            ghost_tokens: concrete,
+        };
+        let tokens = tokenize(SOURCE);
+        let ast = build_tree(SOURCE, &tokens, "Program", concrete, None).unwrap();
+        let printed = print_ast(&ast, &options);
+
+        let read_back = match read(&printed) {
+            Ok(sexpr) => sexpr,
+            Err(e) => panic!("{} at {} in:\n{}", e.message, e.offset, printed),
+        };
+        assert_eq!(read_back.to_pretty_string(), printed);
+        assert_eq!(read_back, SExpr::from_ast(&ast, &options));
+
+        let tree = Tree::from_sexpr(&read_back);
+        assert_eq!(tree.is_abstract, !concrete);
+        assert_eq!(print_tree(&tree, &options), printed);
+    }
+
+    #[test]
+    fn ast_round_trips() {
+        round_trip(false, false);
+    }
+
+    #[test]
+    fn ast_round_trips_with_spans() {
+        round_trip(false, true);
+    }
+
+    #[test]
+    fn cst_round_trips() {
+        round_trip(true, false);
+    }
+
+    #[test]
+    fn cst_round_trips_with_spans() {
+        round_trip(true, true);
+    }
+
+    #[test]
+    fn reader_skips_comments_and_reports_leftovers() {
+        let text = "(n PROGRAM # the root\n    (t INT_LITERAL 1 @0..1)\n)";
+        let expected = SExpr::Node {
+            name: "Program".to_string(),
+            span: None,
+            content: vec![SExpr::Token {
+                name: TokenName::IntLiteral,
+                text: Some("1".to_string()),
+                span: Some(Span::new(0, 1)),
+            }],
+        };
+        assert_eq!(read(text).ok(), Some(expected));
+        assert!(read("(n PROGRAM) (n PROGRAM)").is_err());
+        assert!(read("(n PROGRAM").is_err());
+    }
+}
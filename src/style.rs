@@ -0,0 +1,426 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    comptime::{self, Annotation},
+    diagnostic::{Diagnostic, Sources},
+    file_importer::SourceMap,
+    fmt::Style,
+    interp::Value,
+    modules::{self, LoadError, ModuleGraph},
+};
+
+// Style configuration, the `nano.style` files `nnc fmt` and `nnc lint` read.
+//
+// A style file is nano, and its settings are annotations:
+//
+//   #%format {
+//     max_col: 100
+//     indent: 'tabs'
+//     trailing_commas: no
+//   }
+//   #%lint { unused_name: 'warn' }
+//
+// It's compiled like any other file and its annotations are evaluated at
+// compile time, sandboxed, so a setting can be computed. nnc looks for one
+// in the folder of the file it works on, then in every folder above it.
+// Nearer files win setting by setting: a project keeps one at its root and
+// a folder can change a single setting. `#%root` stops the search there.
+//
+// Every setting remembers where it came from, the file and line that set
+// it, or the defaults, for `--show-style`.
+
+/// The name of a style file.
+pub const FILE_NAME: &str = "nano.style";
+
+/// Every setting `#%format` accepts.
+pub const FORMAT_SETTINGS: [&str; 3] = ["max_col", "indent", "trailing_commas"];
+
+/// Every rule `#%lint` sets the level of, the warnings of name resolution.
+pub const LINT_RULES: [&str; 2] = ["unused_name", "shadowing"];
+
+/// Where a setting's value comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    Default,
+    File { path: PathBuf, line: usize },
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File { path, line } => write!(f, "{}:{}", path.display(), line),
+        }
+    }
+}
+
+/// A value, and where it comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setting<T> {
+    pub value: T,
+    pub origin: Origin,
+}
+
+impl<T> Setting<T> {
+    fn default(value: T) -> Setting<T> {
+        Setting {
+            value,
+            origin: Origin::Default,
+        }
+    }
+
+    fn at(value: T, origin: &Origin) -> Setting<T> {
+        Setting {
+            value,
+            origin: origin.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Indent {
+    Tabs,
+    Spaces(usize),
+}
+
+impl Indent {
+    /// One level of indentation.
+    pub fn text(&self) -> String {
+        match self {
+            Indent::Tabs => "\t".to_string(),
+            Indent::Spaces(n) => " ".repeat(*n),
+        }
+    }
+}
+
+impl fmt::Display for Indent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Indent::Tabs => write!(f, "tabs"),
+            Indent::Spaces(n) => write!(f, "{} spaces", n),
+        }
+    }
+}
+
+/// How much a lint rule matters, what `#%lint` sets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Off,
+    Warn,
+    Error,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::Off => write!(f, "off"),
+            Level::Warn => write!(f, "warn"),
+            Level::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// The settings in effect for a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub max_col: Setting<usize>,
+    pub indent: Setting<Indent>,
+    pub trailing_commas: Setting<bool>,
+    // By rule name; rules that no style file mentions keep their own level.
+    pub rules: BTreeMap<String, Setting<Level>>,
+    // The style files read, nearest first.
+    pub files: Vec<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let style = Style::default();
+        Config {
+            max_col: Setting::default(style.max_col),
+            indent: Setting::default(Indent::Spaces(style.indent.len())),
+            trailing_commas: Setting::default(style.trailing_commas),
+            rules: BTreeMap::new(),
+            files: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// What the formatter needs of it.
+    pub fn style(&self) -> Style {
+        Style {
+            max_col: self.max_col.value,
+            indent: self.indent.value.text(),
+            trailing_commas: self.trailing_commas.value,
+        }
+    }
+}
+
+/// The style files that apply to `target`, nearest first, up to one with `#%root`.
+/// Returns the settings they make, or why one of them couldn't be read, rendered.
+pub fn discover(target: &Path) -> Result<Config, String> {
+    // Canonical, so a relative path can still be walked up from.
+    let target = target
+        .canonicalize()
+        .map_err(|e| format!("{}: {}", target.display(), e))?;
+
+    let mut files = Vec::new();
+    for folder in target.ancestors().skip(1) {
+        let path = folder.join(FILE_NAME);
+        if !path.is_file() {
+            continue;
+        }
+        let (graph, annotations) = evaluate(&path)?;
+        let root = annotations.iter().any(|a| a.name == "root");
+        files.push((path, graph, annotations));
+        if root {
+            break;
+        }
+    }
+
+    // Farthest first, so nearer files overwrite what it sets.
+    let mut config = Config::default();
+    for (path, graph, annotations) in files.iter().rev() {
+        apply(&mut config, path, graph, annotations).map_err(|d| d.render_with(graph))?;
+    }
+    config.files = files.into_iter().map(|(path, _, _)| path).collect();
+    return Ok(config);
+}
+
+/// Compiles a style file, and evaluates its annotations.
+fn evaluate(path: &Path) -> Result<(ModuleGraph, Vec<Annotation>), String> {
+    let name = path.to_string_lossy();
+    let graph = match modules::load(SourceMap::new(), &name, None) {
+        Err(LoadError::NotFound(e)) => return Err(format!("{}: {}", name, e)),
+        Err(LoadError::Parse(e)) => {
+            let text = std::fs::read_to_string(path).unwrap_or_default();
+            return Err(e.diagnostic().render(&name, &text));
+        }
+        Ok(graph) => graph,
+    };
+    let render = |diagnostics: &[Diagnostic]| {
+        let rendered: Vec<String> = diagnostics.iter().map(|d| d.render_with(&graph)).collect();
+        return rendered.join("\n");
+    };
+    if graph.has_errors() {
+        return Err(render(&graph.diagnostics));
+    }
+
+    let program = modules::analyze(&graph);
+    let mut module = match program.module {
+        None => return Err(render(&program.diagnostics)),
+        Some(module) => module,
+    };
+    let comptime = comptime::evaluate(&mut module, &comptime::Options::default());
+    if comptime.has_errors() {
+        return Err(render(&comptime.diagnostics));
+    }
+    return Ok((graph, comptime.annotations));
+}
+
+/// Sets what a style file's annotations say in `config`.
+fn apply(
+    config: &mut Config,
+    path: &Path,
+    graph: &ModuleGraph,
+    annotations: &[Annotation],
+) -> Result<(), Diagnostic> {
+    for annotation in annotations {
+        // The file and line that set the field `name`.
+        let origin = |name: &str| Origin::File {
+            path: path.to_path_buf(),
+            line: annotation
+                .field_span(name)
+                .and_then(|s| graph.position(s.start))
+                .map(|(_, line, _)| line)
+                .unwrap_or(1),
+        };
+        match annotation.name.as_str() {
+            "root" => {}
+            "format" => apply_format(config, annotation, &origin)?,
+            "lint" => apply_lint(config, annotation, &origin)?,
+            name => {
+                let message = format!("Unknown style annotation '#%{}'.", name);
+                let note = "A style file has `#%format`, `#%lint` and `#%root`.";
+                return Err(Diagnostic::error(message, annotation.span).with_note(None, note));
+            }
+        }
+    }
+    return Ok(());
+}
+
+fn apply_format(
+    config: &mut Config,
+    annotation: &Annotation,
+    origin: &dyn Fn(&str) -> Origin,
+) -> Result<(), Diagnostic> {
+    let usage = "`#%format` takes a record of settings, like `{ max_col: 100, indent: 'tabs' }`.";
+    let fields = match &annotation.value {
+        Value::Record(fields) => fields,
+        _ => return Err(Diagnostic::error(usage, annotation.span)),
+    };
+
+    for (name, value) in fields.iter() {
+        let (origin, span) = (origin(name), annotation.field_span(name));
+        let invalid = |expected: &str| {
+            let message = format!("`{}` should be {}, not {}.", name, expected, value);
+            return Diagnostic::error(message, span);
+        };
+        match (name.as_str(), value) {
+            ("max_col", Value::Int(n)) if *n > 0 => {
+                config.max_col = Setting::at(*n as usize, &origin)
+            }
+            ("max_col", _) => return Err(invalid("a positive int")),
+            ("indent", Value::String(s)) if &**s == "tabs" => {
+                config.indent = Setting::at(Indent::Tabs, &origin)
+            }
+            ("indent", Value::Int(n)) if (1..=16).contains(n) => {
+                config.indent = Setting::at(Indent::Spaces(*n as usize), &origin)
+            }
+            ("indent", _) => return Err(invalid("'tabs' or a number of spaces")),
+            ("trailing_commas", Value::Bool(b)) => {
+                config.trailing_commas = Setting::at(*b, &origin)
+            }
+            ("trailing_commas", _) => return Err(invalid("yes or no")),
+            _ => {
+                let message = format!("Unknown format setting '{}'.", name);
+                let note = format!("The settings are {}.", FORMAT_SETTINGS.join(", "));
+                return Err(Diagnostic::error(message, span).with_note(None, note));
+            }
+        }
+    }
+    return Ok(());
+}
+
+fn apply_lint(
+    config: &mut Config,
+    annotation: &Annotation,
+    origin: &dyn Fn(&str) -> Origin,
+) -> Result<(), Diagnostic> {
+    let usage = "`#%lint` takes a record of rule levels, like `{ unused_name: 'warn' }`.";
+    let fields = match &annotation.value {
+        Value::Record(fields) => fields,
+        _ => return Err(Diagnostic::error(usage, annotation.span)),
+    };
+
+    for (name, value) in fields.iter() {
+        if !LINT_RULES.contains(&name.as_str()) {
+            let message = format!("Unknown lint rule '{}'.", name);
+            let note = format!("The rules are {}.", LINT_RULES.join(", "));
+            return Err(
+                Diagnostic::error(message, annotation.field_span(name)).with_note(None, note)
+            );
+        }
+        let level = match value {
+            Value::String(s) if &**s == "off" => Level::Off,
+            Value::String(s) if &**s == "warn" => Level::Warn,
+            Value::String(s) if &**s == "error" => Level::Error,
+            _ => {
+                let message = format!(
+                    "The level of `{}` should be 'off', 'warn' or 'error'.",
+                    name
+                );
+                return Err(Diagnostic::error(message, annotation.field_span(name)));
+            }
+        };
+        config
+            .rules
+            .insert(name.clone(), Setting::at(level, &origin(name)));
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A folder of its own for `name`, with `style` as its root style file and an empty nano file.
+    fn folder(name: &str, style: &str) -> PathBuf {
+        let folder =
+            std::env::temp_dir().join(format!("nnc-style-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(folder.join(FILE_NAME), format!("#%root\n{}", style)).unwrap();
+        std::fs::write(folder.join("main.nano"), "").unwrap();
+        return folder;
+    }
+
+    /// Why the style file in `folder` is refused, and the line and column it points at.
+    fn refused(folder: &Path) -> (String, usize, usize) {
+        let path = folder.join(FILE_NAME);
+        let (graph, annotations) = evaluate(&path).unwrap();
+        let error = apply(&mut Config::default(), &path, &graph, &annotations).unwrap_err();
+        let (_, line, column) = error.span.and_then(|s| graph.position(s.start)).unwrap();
+        std::fs::remove_dir_all(folder).unwrap();
+        return (error.message, line, column);
+    }
+
+    #[test]
+    fn settings_come_from_their_own_line() {
+        let folder = folder(
+            "origins",
+            "#%format {\n    max_col: 100\n\n    indent: 'tabs'\n}\n#%lint {\n    unused_name: 'warn'\n}\n",
+        );
+        let config = discover(&folder.join("main.nano")).unwrap();
+        let at = |line| Origin::File {
+            path: folder.join(FILE_NAME).canonicalize().unwrap(),
+            line,
+        };
+        assert_eq!(config.max_col, Setting::at(100, &at(3)));
+        assert_eq!(config.indent, Setting::at(Indent::Tabs, &at(5)));
+        assert_eq!(config.trailing_commas, Setting::default(true));
+        assert_eq!(
+            config.rules["unused_name"],
+            Setting::at(Level::Warn, &at(8))
+        );
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn invalid_settings_point_at_their_field() {
+        let folder = folder(
+            "invalid",
+            "#%format {\n    max_col: 100\n    indent: 7000\n}\n",
+        );
+        let (message, line, column) = refused(&folder);
+        assert_eq!(
+            message,
+            "`indent` should be 'tabs' or a number of spaces, not 7000."
+        );
+        assert_eq!((line, column), (4, 5));
+    }
+
+    #[test]
+    fn unknown_settings_point_at_their_field() {
+        let folder = folder(
+            "unknown",
+            "#%format {\n    max_col: 100\n    colour: 1\n}\n",
+        );
+        let (message, line, column) = refused(&folder);
+        assert_eq!(message, "Unknown format setting 'colour'.");
+        assert_eq!((line, column), (4, 5));
+    }
+
+    #[test]
+    fn unknown_lint_rules_point_at_their_field() {
+        let folder = folder(
+            "rule",
+            "#%lint {\n    unused_name: 'warn'\n    made_up_rule: 'error'\n}\n",
+        );
+        let (message, line, column) = refused(&folder);
+        assert_eq!(message, "Unknown lint rule 'made_up_rule'.");
+        assert_eq!((line, column), (4, 5));
+    }
+
+    #[test]
+    fn invalid_lint_levels_point_at_their_field() {
+        let folder = folder("level", "#%lint { unused_name: 'warn', shadowing: 1 }\n");
+        let (message, line, column) = refused(&folder);
+        assert_eq!(
+            message,
+            "The level of `shadowing` should be 'off', 'warn' or 'error'."
+        );
+        assert_eq!((line, column), (2, 31));
+    }
+}
@@ -0,0 +1,739 @@
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use colored::Colorize;
+
+use crate::{
+    diagnostic::{line_col, Diagnostic, Sources},
+    grammar::Span,
+    interp::{Assertion, Interpreter, RuntimeError, Value},
+    ir::Module,
+    util::json_string,
+};
+
+// The test runner behind `nnc test`.
+//
+// Tests are the `%%test fn`s of the entry module, run in the interpreter
+// one after the other, after the module's top-level code has set up its
+// globals. Each `%%t_eq` a test evaluates becomes a row of its report, even
+// the ones made in loops, so `select %%t_eq(f(n), n) for n in 0..5` checks
+// five cases. A test fails when any of its rows does, or when it crashes.
+//
+// Reports come as the boxed tables people read, or for CI as JUnit XML,
+// TAP or JSON. Those are plain text with no colors, and what the program
+// prints is captured into them rather than mixed with them.
+
+/// How a single `%%test fn` went.
+#[derive(Debug, Clone)]
+pub struct TestReport {
+    // The test's name, as written after `fn`.
+    pub name: String,
+    // Where the `%%test fn` is.
+    pub location: Option<Location>,
+    pub checks: Vec<Check>,
+    // What stopped the test before its end, if something did, and where.
+    pub error: Option<(RuntimeError, Option<Location>)>,
+    // Whether `error` is from compiling the program rather than running it.
+    pub compile_error: bool,
+    pub duration: Duration,
+    // What the test printed, when output is captured.
+    pub output: String,
+}
+
+/// A row of a report, an evaluated `%%t_eq(actual, expected)`.
+#[derive(Debug, Clone)]
+pub struct Check {
+    // The text of `actual`, with the values of the variables it reads.
+    pub expr: String,
+    pub actual: String,
+    pub expected: String,
+    pub passed: bool,
+    // Where the `%%t_eq(...)` is.
+    pub location: Option<Location>,
+}
+
+/// A span in a file, for reports read by other programs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub path: String,
+    // 1-based, of the span's start.
+    pub line: usize,
+    pub column: usize,
+    // In bytes, within the file.
+    pub span: Span,
+}
+
+impl Location {
+    pub fn find(sources: &dyn Sources, span: Option<Span>) -> Option<Location> {
+        let (path, source, span) = sources.locate(span?)?;
+        let (line, column) = line_col(source, span.start);
+        return Some(Location {
+            path: path.to_string(),
+            line,
+            column,
+            span,
+        });
+    }
+}
+
+/// How `nnc test --format=...` prints its results.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    // The boxed tables, for people.
+    Pretty,
+    Junit,
+    Tap,
+    Json,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "pretty" => Some(Format::Pretty),
+            "junit" => Some(Format::Junit),
+            "tap" => Some(Format::Tap),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+impl TestReport {
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.checks.iter().all(|c| c.passed)
+    }
+
+    /// A failed report for what went wrong outside of any test, like compiling the
+    /// program or running its top-level code, so reports for other programs say why.
+    pub fn failure(name: &str, error: RuntimeError, sources: &dyn Sources) -> TestReport {
+        let location = Location::find(sources, error.span);
+        return TestReport {
+            name: name.to_string(),
+            location: location.clone(),
+            checks: Vec::new(),
+            error: Some((error, location)),
+            compile_error: false,
+            duration: Duration::ZERO,
+            output: String::new(),
+        };
+    }
+
+    /// Same as `failure`, for a diagnostic that stopped the program from compiling.
+    pub fn compile_failure(
+        name: &str,
+        diagnostic: &Diagnostic,
+        sources: &dyn Sources,
+    ) -> TestReport {
+        let error = RuntimeError {
+            message: diagnostic.message.clone(),
+            span: diagnostic.span,
+            trace: Vec::new(),
+        };
+        return TestReport {
+            compile_error: true,
+            ..TestReport::failure(name, error, sources)
+        };
+    }
+}
+
+/// The name a test is reported with, its symbol without the module path.
+pub fn test_name(symbol: &str) -> &str {
+    symbol.rsplit("::").next().unwrap_or(symbol)
+}
+
+/// Runs the tests of `module` whose name contains one of `filters`, or all of
+/// them when there are no filters. Fails if the module's top-level code does.
+/// With `capture_output`, what tests print goes to their reports.
+pub fn run_tests(
+    module: &Module,
+    filters: &[String],
+    sources: &dyn Sources,
+    capture_output: bool,
+) -> Result<Vec<TestReport>, RuntimeError> {
+    let mut interpreter = Interpreter::new();
+    if capture_output {
+        interpreter.capture_output();
+    }
+    interpreter.run(module)?;
+    interpreter.take_assertions();
+    interpreter.take_output();
+
+    let mut reports = Vec::new();
+    for test in &module.tests {
+        let name = test_name(&test.symbol);
+        if !filters.is_empty() && !filters.iter().any(|f| name.contains(f.as_str())) {
+            continue;
+        }
+
+        interpreter.enter("<main>");
+        let test_fn = Value::Closure {
+            symbol: Rc::from(test.symbol.as_str()),
+            captures: Rc::new(Vec::new()),
+        };
+        let started = Instant::now();
+        let error = interpreter.call_value(&test_fn, Vec::new()).err();
+        let duration = started.elapsed();
+        let checks = interpreter
+            .take_assertions()
+            .iter()
+            .map(|a| check(a, sources))
+            .collect();
+        reports.push(TestReport {
+            name: name.to_string(),
+            location: Location::find(sources, test.span),
+            checks,
+            error: error.map(|e| {
+                let at = Location::find(sources, e.span);
+                (e, at)
+            }),
+            compile_error: false,
+            duration,
+            output: interpreter.take_output(),
+        });
+    }
+    return Ok(reports);
+}
+
+fn check(assertion: &Assertion, sources: &dyn Sources) -> Check {
+    let location = Location::find(sources, assertion.span);
+    return Check {
+        expr: expression_text(assertion, sources),
+        actual: assertion.actual.to_string(),
+        expected: assertion.expected.to_string(),
+        passed: assertion.actual == assertion.expected,
+        location,
+    };
+}
+
+/// The source of the asserted expression, each variable replaced by its value.
+fn expression_text(assertion: &Assertion, sources: &dyn Sources) -> String {
+    let located = assertion.span.and_then(|s| sources.locate(s));
+    let (source, span) =
+        match located.and_then(|(_, source, span)| Some((source, actual_span(source, span)?))) {
+            Some(found) => found,
+            None => return assertion.actual.to_string(),
+        };
+
+    let mut locals: Vec<(usize, usize, String)> = assertion
+        .locals
+        .iter()
+        .filter_map(|(at, value)| {
+            let (_, _, at) = sources.locate((*at)?)?;
+            Some((at.start, at.end, value.to_string()))
+        })
+        .filter(|(start, end, _)| *start >= span.start && *end <= span.end)
+        .collect();
+    locals.sort_by_key(|(start, _, _)| *start);
+
+    let mut text = String::new();
+    let mut at = span.start;
+    for (start, end, value) in locals {
+        if start < at {
+            continue;
+        }
+        text.push_str(&source[at..start]);
+        text.push_str(&value);
+        at = end;
+    }
+    text.push_str(&source[at..span.end]);
+    return text.split_whitespace().collect::<Vec<_>>().join(" ");
+}
+
+/// Where `actual` is in `%%t_eq(actual, expected)`, from the assertion's span.
+/// Read from the text, as only the whole assertion's span is known when it runs.
+fn actual_span(source: &str, assertion: Span) -> Option<Span> {
+    let text = &source[assertion.start..];
+    let start = assertion.start + text.find('(')? + 1;
+
+    let mut actual = None;
+    let mut depth = 0;
+    let mut quote = None;
+    for (i, c) in source[start..].char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '(' | '[' | '{') => depth += 1,
+            (None, ')') if depth == 0 => {
+                return Some(actual.unwrap_or(Span::new(start, start + i)));
+            }
+            (None, ')' | ']' | '}') => depth -= 1,
+            (None, ',') if depth == 0 && actual.is_none() => {
+                actual = Some(Span::new(start, start + i));
+            }
+            (None, _) => {}
+        }
+    }
+    return None;
+}
+
+/// A test's report as a box, a row per check:
+///
+/// ```text
+/// ---- floats ---------------------\
+/// | EQU | 0.1 + 0.1 | 0.2 | FAILED |
+/// \---------------------------------
+/// ```
+pub fn render_box(report: &TestReport) -> String {
+    let mut rows: Vec<[String; 3]> = report
+        .checks
+        .iter()
+        .map(|c| {
+            let status = if c.passed { "SUCCESS" } else { "FAILED" };
+            ["EQU".to_string(), c.expr.clone(), status.to_string()]
+        })
+        .collect();
+    let mut expected: Vec<String> = report.checks.iter().map(|c| c.expected.clone()).collect();
+    if let Some((error, _)) = &report.error {
+        rows.push([
+            "ERR".to_string(),
+            error.message.clone(),
+            "FAILED".to_string(),
+        ]);
+        expected.push(String::new());
+    }
+
+    let expr_width = rows.iter().map(|r| r[1].chars().count()).max().unwrap_or(0);
+    let expected_width = expected
+        .iter()
+        .map(|e| e.chars().count())
+        .max()
+        .unwrap_or(0);
+    let status_width = rows.iter().map(|r| r[2].len()).max().unwrap_or(0);
+    let row_width = 3 + 3 + expr_width + 3 + expected_width + 3 + status_width + 4;
+    let width = row_width.max(report.name.chars().count() + 7);
+
+    let mut out = format!(
+        "---- {} {}\\\n",
+        report.name.bold(),
+        "-".repeat(width - report.name.chars().count() - 7)
+    );
+    for (row, expected) in rows.iter().zip(&expected) {
+        let status = format!("{:<w$}", row[2], w = status_width);
+        let status = match row[2].as_str() {
+            "SUCCESS" => status.green(),
+            _ => status.red(),
+        };
+        let line = format!(
+            "| {} | {:<ew$} | {:>xw$} | {} |",
+            row[0],
+            row[1],
+            expected,
+            status,
+            ew = expr_width,
+            xw = expected_width
+        );
+        // Names longer than the rows widen the box, and the rows with it.
+        let padding = width - row_width;
+        out.push_str(&line[..line.len() - 1]);
+        out.push_str(&" ".repeat(padding));
+        out.push_str("|\n");
+    }
+    out.push_str(&format!("\\{}\n", "-".repeat(width - 1)));
+    return out;
+}
+
+/// The reports as JUnit XML, a `<testcase>` per test in a `<testsuite>` per file.
+/// Failed checks are each a line of the `<failure>`, where the test is.
+pub fn render_junit(reports: &[TestReport]) -> String {
+    let mut files: Vec<&str> = Vec::new();
+    for report in reports {
+        let path = report.location.as_ref().map_or("", |l| l.path.as_str());
+        if !files.contains(&path) {
+            files.push(path);
+        }
+    }
+
+    let all: Vec<&TestReport> = reports.iter().collect();
+    let (failures, errors, time) = junit_counts(&all);
+    let mut out = format!(
+        "<testsuites name=\"nnc test\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.6}\">\n",
+        reports.len(),
+        failures,
+        errors,
+        time.as_secs_f64()
+    );
+    for file in files {
+        let suite: Vec<&TestReport> = reports
+            .iter()
+            .filter(|r| r.location.as_ref().map_or("", |l| l.path.as_str()) == file)
+            .collect();
+        let (failures, errors, time) = junit_counts(&suite);
+        out.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.6}\">\n",
+            xml_escape(file),
+            suite.len(),
+            failures,
+            errors,
+            time.as_secs_f64()
+        ));
+        for report in suite {
+            out.push_str(&junit_case(report, file));
+        }
+        out.push_str("  </testsuite>\n");
+    }
+    out.push_str("</testsuites>\n");
+    return out;
+}
+
+/// How many tests failed a check, how many crashed, and how long they all took.
+fn junit_counts(reports: &[&TestReport]) -> (usize, usize, Duration) {
+    let crashed = reports.iter().filter(|r| r.error.is_some()).count();
+    let failed = reports.iter().filter(|r| !r.passed()).count() - crashed;
+    return (failed, crashed, reports.iter().map(|r| r.duration).sum());
+}
+
+fn junit_case(report: &TestReport, file: &str) -> String {
+    let line = match &report.location {
+        Some(l) => format!(" line=\"{}\"", l.line),
+        None => String::new(),
+    };
+    let mut out = format!(
+        "    <testcase name=\"{}\" classname=\"{}\" file=\"{}\"{} assertions=\"{}\" time=\"{:.6}\"",
+        xml_escape(&report.name),
+        xml_escape(file.trim_end_matches(".nano")),
+        xml_escape(file),
+        line,
+        report.checks.len(),
+        report.duration.as_secs_f64()
+    );
+
+    let failures: Vec<&Check> = report.checks.iter().filter(|c| !c.passed).collect();
+    if failures.is_empty() && report.error.is_none() && report.output.is_empty() {
+        out.push_str(" />\n");
+        return out;
+    }
+    out.push_str(">\n");
+
+    if !failures.is_empty() {
+        let lines: Vec<String> = failures
+            .iter()
+            .map(|c| {
+                format!(
+                    "{}: {} is {}, expected {}",
+                    at(&c.location),
+                    c.expr,
+                    c.actual,
+                    c.expected
+                )
+            })
+            .collect();
+        out.push_str(&format!(
+            "      <failure type=\"t_eq\" message=\"{} of {} checks failed\">{}</failure>\n",
+            failures.len(),
+            report.checks.len(),
+            xml_escape(&lines.join("\n"))
+        ));
+    }
+    if let Some((error, location)) = &report.error {
+        let kind = match report.compile_error {
+            true => "CompileError",
+            false => "RuntimeError",
+        };
+        out.push_str(&format!(
+            "      <error type=\"{}\" message=\"{}\">{}</error>\n",
+            kind,
+            xml_escape(&error.message),
+            xml_escape(&at(location))
+        ));
+    }
+    if !report.output.is_empty() {
+        out.push_str(&format!(
+            "      <system-out>{}</system-out>\n",
+            xml_escape(&report.output)
+        ));
+    }
+    out.push_str("    </testcase>\n");
+    return out;
+}
+
+/// The reports as TAP version 13, with the checks of each test in its YAML block.
+pub fn render_tap(reports: &[TestReport]) -> String {
+    let mut out = format!("TAP version 13\n1..{}\n", reports.len());
+    for (i, report) in reports.iter().enumerate() {
+        let status = if report.passed() { "ok" } else { "not ok" };
+        out.push_str(&format!("{} {} - {}\n", status, i + 1, report.name));
+        out.push_str("  ---\n");
+        out.push_str(&format!(
+            "  duration_ms: {:.3}\n",
+            report.duration.as_secs_f64() * 1000.0
+        ));
+        if let Some(location) = &report.location {
+            out.push_str(&format!(
+                "  at: {}\n",
+                yaml_string(&at(&Some(location.clone())))
+            ));
+        }
+        if let Some((error, location)) = &report.error {
+            out.push_str(&format!("  message: {}\n", yaml_string(&error.message)));
+            out.push_str(&format!("  error_at: {}\n", yaml_string(&at(location))));
+        }
+        if !report.checks.is_empty() {
+            out.push_str("  checks:\n");
+        }
+        for check in &report.checks {
+            out.push_str(&format!("    - expr: {}\n", yaml_string(&check.expr)));
+            out.push_str(&format!(
+                "      expected: {}\n",
+                yaml_string(&check.expected)
+            ));
+            out.push_str(&format!("      actual: {}\n", yaml_string(&check.actual)));
+            out.push_str(&format!("      passed: {}\n", check.passed));
+            out.push_str(&format!(
+                "      at: {}\n",
+                yaml_string(&at(&check.location))
+            ));
+        }
+        if !report.output.is_empty() {
+            out.push_str(&format!("  output: {}\n", yaml_string(&report.output)));
+        }
+        out.push_str("  ...\n");
+    }
+    return out;
+}
+
+/// The reports as a JSON object, every check with its span.
+pub fn render_json(reports: &[TestReport]) -> String {
+    let tests: Vec<String> = reports
+        .iter()
+        .map(|report| {
+            let checks: Vec<String> = report
+                .checks
+                .iter()
+                .map(|c| {
+                    format!(
+                        "{{\"expr\":{},\"expected\":{},\"actual\":{},\"passed\":{},\"location\":{}}}",
+                        json_string(&c.expr),
+                        json_string(&c.expected),
+                        json_string(&c.actual),
+                        c.passed,
+                        json_location(&c.location)
+                    )
+                })
+                .collect();
+            let error = match &report.error {
+                None => "null".to_string(),
+                Some((e, location)) => format!(
+                    "{{\"message\":{},\"location\":{}}}",
+                    json_string(&e.message),
+                    json_location(location)
+                ),
+            };
+            format!(
+                "{{\"name\":{},\"passed\":{},\"duration_ms\":{:.3},\"location\":{},\"checks\":[{}],\"error\":{},\"output\":{}}}",
+                json_string(&report.name),
+                report.passed(),
+                report.duration.as_secs_f64() * 1000.0,
+                json_location(&report.location),
+                checks.join(","),
+                error,
+                json_string(&report.output)
+            )
+        })
+        .collect();
+
+    let passed = reports.iter().filter(|r| r.passed()).count();
+    return format!(
+        "{{\"passed\":{},\"failed\":{},\"tests\":[\n  {}\n]}}\n",
+        passed,
+        reports.len() - passed,
+        tests.join(",\n  ")
+    );
+}
+
+/// `path:line:column`, or `?` when there's no location.
+fn at(location: &Option<Location>) -> String {
+    match location {
+        Some(l) => format!("{}:{}:{}", l.path, l.line, l.column),
+        None => "?".to_string(),
+    }
+}
+
+fn json_location(location: &Option<Location>) -> String {
+    match location {
+        None => "null".to_string(),
+        Some(l) => format!(
+            "{{\"file\":{},\"line\":{},\"column\":{},\"start\":{},\"end\":{}}}",
+            json_string(&l.path),
+            l.line,
+            l.column,
+            l.span.start,
+            l.span.end
+        ),
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// YAML accepts JSON strings as double-quoted scalars.
+fn yaml_string(text: &str) -> String {
+    json_string(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        comptime,
+        diagnostic::SingleFile,
+        file_importer::{MemoryFileSystem, SourceMap},
+        modules,
+        util::plain,
+    };
+
+    const SOURCE: &str = "fn add(a, b) -> a + b
+%%test fn arithmetic -> (
+    %%t_eq(add(1, 2), 3)
+    select %%t_eq(add(n, 0), n) for n in 0..2
+)
+%%test fn wrong -> (
+    print('checking')
+    %%t_eq(add(1, 1), 3)
+)
+%%test fn crash -> (
+    let zero = 0
+    %%t_eq(1 / zero, 1)
+)";
+
+    /// The reports of `source`'s tests matching `filters`, durations zeroed so they render the same every time.
+    fn reports(source: &str, filters: &[&str]) -> Vec<TestReport> {
+        let mut files = MemoryFileSystem::new();
+        files.insert("main.nano", source);
+        let graph = match modules::load(SourceMap::with_fs(Box::new(files)), "main.nano", None) {
+            Ok(graph) => graph,
+            Err(_) => panic!("the source doesn't parse"),
+        };
+        let mut module = modules::analyze(&graph)
+            .module
+            .expect("the source doesn't compile");
+        comptime::evaluate(&mut module, &comptime::Options::default());
+        let filters: Vec<String> = filters.iter().map(|f| f.to_string()).collect();
+        let mut reports = run_tests(&module, &filters, &graph, true).expect("the program failed");
+        for report in &mut reports {
+            report.duration = Duration::ZERO;
+        }
+        return reports;
+    }
+
+    #[test]
+    fn pretty_reports_are_a_box_per_test() {
+        let boxes: Vec<String> = reports(SOURCE, &[])
+            .iter()
+            .map(|r| plain(&render_box(r)))
+            .collect();
+        assert_eq!(
+            boxes.join(""),
+            r"---- arithmetic ----------------\
+| EQU | add(1, 2) | 3 | SUCCESS |
+| EQU | add(0, 0) | 0 | SUCCESS |
+| EQU | add(1, 0) | 1 | SUCCESS |
+\--------------------------------
+---- wrong --------------------\
+| EQU | add(1, 1) | 3 | FAILED |
+\-------------------------------
+---- crash ---------------------------\
+| ERR | Division by zero. |  | FAILED |
+\--------------------------------------
+"
+        );
+    }
+
+    #[test]
+    fn junit_reports_failures_errors_and_output() {
+        assert_eq!(
+            render_junit(&reports(SOURCE, &[])),
+            r#"<testsuites name="nnc test" tests="3" failures="1" errors="1" time="0.000000">
+  <testsuite name="main.nano" tests="3" failures="1" errors="1" time="0.000000">
+    <testcase name="arithmetic" classname="main" file="main.nano" line="2" assertions="3" time="0.000000" />
+    <testcase name="wrong" classname="main" file="main.nano" line="6" assertions="1" time="0.000000">
+      <failure type="t_eq" message="1 of 1 checks failed">main.nano:8:5: add(1, 1) is 2, expected 3</failure>
+      <system-out>checking
+</system-out>
+    </testcase>
+    <testcase name="crash" classname="main" file="main.nano" line="10" assertions="0" time="0.000000">
+      <error type="RuntimeError" message="Division by zero.">main.nano:12:12</error>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#
+        );
+    }
+
+    #[test]
+    fn tap_reports_checks_in_yaml_blocks() {
+        assert_eq!(
+            render_tap(&reports(SOURCE, &["wrong", "crash"])),
+            r#"TAP version 13
+1..2
+not ok 1 - wrong
+  ---
+  duration_ms: 0.000
+  at: "main.nano:6:1"
+  checks:
+    - expr: "add(1, 1)"
+      expected: "3"
+      actual: "2"
+      passed: false
+      at: "main.nano:8:5"
+  output: "checking\n"
+  ...
+not ok 2 - crash
+  ---
+  duration_ms: 0.000
+  at: "main.nano:10:1"
+  message: "Division by zero."
+  error_at: "main.nano:12:12"
+  ...
+"#
+        );
+    }
+
+    #[test]
+    fn json_reports_have_every_span() {
+        assert_eq!(
+            render_json(&reports(SOURCE, &["wrong"])),
+            r#"{"passed":0,"failed":1,"tests":[
+  {"name":"wrong","passed":false,"duration_ms":0.000,"location":{"file":"main.nano","line":6,"column":1,"start":121,"end":190},"checks":[{"expr":"add(1, 1)","expected":"3","actual":"2","passed":false,"location":{"file":"main.nano","line":8,"column":5,"start":168,"end":188}}],"error":null,"output":"checking\n"}
+]}
+"#
+        );
+    }
+
+    #[test]
+    fn junit_reports_compile_errors_as_such() {
+        let sources = SingleFile {
+            path: "main.nano",
+            source: "print(x)",
+        };
+        let undefined = Diagnostic::error("Undefined name 'x'.", Some(Span { start: 6, end: 7 }));
+        let report = TestReport::compile_failure("<compile>", &undefined, &sources);
+        assert!(render_junit(&[report]).contains(
+            "<error type=\"CompileError\" message=\"Undefined name 'x'.\">main.nano:1:7</error>"
+        ));
+    }
+
+    #[test]
+    fn filters_pick_tests_by_part_of_their_name() {
+        let names = |filters: &[&str]| -> Vec<String> {
+            return reports(SOURCE, filters)
+                .into_iter()
+                .map(|r| r.name)
+                .collect();
+        };
+        assert_eq!(names(&[]), ["arithmetic", "wrong", "crash"]);
+        assert_eq!(names(&["ith"]), ["arithmetic"]);
+        assert_eq!(names(&["cr", "wr"]), ["wrong", "crash"]);
+        assert!(names(&["nothing"]).is_empty());
+    }
+
+    #[test]
+    fn tests_pass_when_all_their_checks_do() {
+        let passed: Vec<bool> = reports(SOURCE, &[]).iter().map(|r| r.passed()).collect();
+        assert_eq!(passed, [true, false, false]);
+    }
+}
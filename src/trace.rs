@@ -0,0 +1,285 @@
+use std::cell::{Cell, RefCell};
+
+use colored::Colorize;
+
+use crate::{grammar::Token, nano_grammar::is_ghost_token, util::json_string};
+
+/// What happened to a rule at some point of the parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEventKind {
+    // The parser started trying the rule.
+    Enter,
+    // The rule matched, consuming `advance` tokens.
+    Success { advance: usize },
+    // The rule could not be matched here.
+    Failure,
+    // The result was already known from an earlier attempt.
+    MemoHit { success: bool, advance: usize },
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceEvent {
+    pub rule: &'static str,
+    // Which of the traced files, in the order their parses began.
+    pub file: usize,
+    /// Index of the token (in the whole token pool) where the rule was tried.
+    pub offset: usize,
+    pub depth: usize,
+    pub kind: TraceEventKind,
+}
+
+/// Records how the parser walks the grammar, rule by rule.
+/// Switched on with `nnc compile <file> --trace-parse`.
+#[derive(Debug, Default)]
+pub struct ParseTrace {
+    events: RefCell<Vec<TraceEvent>>,
+    depth: Cell<usize>,
+    files: RefCell<Vec<TracedFile>>,
+}
+
+/// A file whose parse is traced, with what's shown of its tokens.
+#[derive(Debug)]
+struct TracedFile {
+    path: String,
+    // The first meaningful token at or after each offset, for context.
+    previews: Vec<String>,
+}
+
+impl ParseTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts tracing the parse of the file at `path`, made of `tokens`.
+    /// The events that follow are about this file, until the next one begins.
+    pub fn begin_file(&self, path: &str, tokens: &[Token]) {
+        let mut previews = vec![String::new(); tokens.len() + 1];
+        let mut next = "<EOF>".to_string();
+        previews[tokens.len()] = next.clone();
+        for (i, token) in tokens.iter().enumerate().rev() {
+            if !is_ghost_token(&token.name) {
+                next = match token.str_content {
+                    None => "<EOF>".to_string(),
+                    Some(s) => format!("{:?}", s),
+                };
+            }
+            previews[i] = next.clone();
+        }
+        self.files.borrow_mut().push(TracedFile {
+            path: path.to_string(),
+            previews,
+        });
+        self.depth.set(0);
+    }
+
+    pub fn enter(&self, rule: &'static str, offset: usize) {
+        let depth = self.depth.get();
+        self.push(rule, offset, depth, TraceEventKind::Enter);
+        self.depth.set(depth + 1);
+    }
+
+    pub fn exit(&self, rule: &'static str, offset: usize, advance: Option<usize>) {
+        let depth = self.depth.get().saturating_sub(1);
+        self.depth.set(depth);
+        let kind = match advance {
+            None => TraceEventKind::Failure,
+            Some(advance) => TraceEventKind::Success { advance },
+        };
+        self.push(rule, offset, depth, kind);
+    }
+
+    pub fn memo_hit(&self, rule: &'static str, offset: usize, advance: Option<usize>) {
+        let depth = self.depth.get();
+        let kind = TraceEventKind::MemoHit {
+            success: advance.is_some(),
+            advance: advance.unwrap_or(0),
+        };
+        self.push(rule, offset, depth, kind);
+    }
+
+    fn push(&self, rule: &'static str, offset: usize, depth: usize, kind: TraceEventKind) {
+        // Events before any `begin_file` count as the first file's.
+        let file = self.files.borrow().len().saturating_sub(1);
+        self.events.borrow_mut().push(TraceEvent {
+            rule,
+            file,
+            offset,
+            depth,
+            kind,
+        });
+    }
+
+    /// Everything recorded so far, in order.
+    pub fn events(&self) -> Vec<TraceEvent> {
+        self.events.borrow().clone()
+    }
+
+    /// Renders the trace as an indented tree, one line per event, under the path of each file.
+    pub fn render_tree(&self) -> String {
+        let files = self.files.borrow();
+        let mut out = String::new();
+
+        let mut current = None;
+        for event in self.events.borrow().iter() {
+            let file = files.get(event.file);
+            if current != Some(event.file) {
+                if current.is_some() {
+                    out.push('\n');
+                }
+                if let Some(file) = file {
+                    out.push_str(&format!("{}\n", file.path.bold()));
+                }
+                current = Some(event.file);
+            }
+            let preview = file
+                .and_then(|f| f.previews.get(event.offset))
+                .map_or("<EOF>", |p| p.as_str());
+            let indent = "  ".repeat(event.depth);
+            let at = format!("@{}", event.offset).dimmed();
+            let line = match event.kind {
+                TraceEventKind::Enter => format!(
+                    "{}{} {} {}",
+                    indent,
+                    event.rule.bold(),
+                    at,
+                    preview.dimmed()
+                ),
+                TraceEventKind::Success { advance } => format!(
+                    "{}{} {} {}",
+                    indent,
+                    "✓".green(),
+                    event.rule,
+                    format!("@{}..{}", event.offset, event.offset + advance).dimmed()
+                ),
+                TraceEventKind::Failure => {
+                    format!("{}{} {} {}", indent, "✗".red(), event.rule, at)
+                }
+                TraceEventKind::MemoHit { success, advance } => format!(
+                    "{}{} {} {} {}",
+                    indent,
+                    "↺".cyan(),
+                    event.rule,
+                    at,
+                    if success {
+                        format!("(memo: matched {} tokens)", advance)
+                    } else {
+                        "(memo: failed)".to_string()
+                    }
+                    .dimmed()
+                ),
+            };
+            out.push_str(&line);
+            out.push('\n');
+        }
+
+        return out;
+    }
+
+    /// Exports the trace as a JSON array of events.
+    pub fn to_json(&self) -> String {
+        let files = self.files.borrow();
+        let events: Vec<String> = self
+            .events
+            .borrow()
+            .iter()
+            .map(|event| {
+                let (kind, extra) = match event.kind {
+                    TraceEventKind::Enter => ("enter", String::new()),
+                    TraceEventKind::Success { advance } => {
+                        ("success", format!(",\"advance\":{}", advance))
+                    }
+                    TraceEventKind::Failure => ("failure", String::new()),
+                    TraceEventKind::MemoHit { success, advance } => (
+                        "memo_hit",
+                        format!(",\"success\":{},\"advance\":{}", success, advance),
+                    ),
+                };
+                let file = files.get(event.file).map_or("", |f| f.path.as_str());
+                format!(
+                    "{{\"event\":\"{}\",\"rule\":{},\"file\":{},\"offset\":{},\"depth\":{}{}}}",
+                    kind,
+                    json_string(event.rule),
+                    json_string(file),
+                    event.offset,
+                    event.depth,
+                    extra
+                )
+            })
+            .collect();
+
+        return format!("[\n  {}\n]", events.join(",\n  "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::tokenize, util::plain};
+
+    /// A trace of `rule` entered at each token of `source` in turn and matching one token.
+    fn traced(trace: &ParseTrace, path: &str, source: &str, rule: &'static str) {
+        let tokens = tokenize(source);
+        trace.begin_file(path, &tokens);
+        trace.enter("Program", 0);
+        trace.enter(rule, 0);
+        trace.exit(rule, 0, Some(1));
+        trace.memo_hit(rule, 0, Some(1));
+        trace.enter(rule, 2);
+        trace.exit(rule, 2, None);
+        trace.exit("Program", 0, Some(1));
+    }
+
+    #[test]
+    fn events_nest_by_depth() {
+        let trace = ParseTrace::new();
+        traced(&trace, "main.nano", "a + b", "Expr");
+        let depths: Vec<usize> = trace.events().iter().map(|e| e.depth).collect();
+        assert_eq!(depths, [0, 1, 1, 1, 1, 1, 0]);
+    }
+
+    #[test]
+    fn the_tree_previews_tokens_of_each_file() {
+        let trace = ParseTrace::new();
+        traced(&trace, "main.nano", "a + b", "Expr");
+        traced(&trace, "lib.nano", "let x", "Let");
+        assert_eq!(
+            plain(&trace.render_tree()),
+            r#"main.nano
+Program @0 "a"
+  Expr @0 "a"
+  ✓ Expr @0..1
+  ↺ Expr @0 (memo: matched 1 tokens)
+  Expr @2 "+"
+  ✗ Expr @2
+✓ Program @0..1
+
+lib.nano
+Program @0 "let"
+  Let @0 "let"
+  ✓ Let @0..1
+  ↺ Let @0 (memo: matched 1 tokens)
+  Let @2 "x"
+  ✗ Let @2
+✓ Program @0..1
+"#
+        );
+    }
+
+    #[test]
+    fn json_has_an_object_per_event() {
+        let trace = ParseTrace::new();
+        let tokens = tokenize("a");
+        trace.begin_file("main.nano", &tokens);
+        trace.enter("Expr", 0);
+        trace.exit("Expr", 0, Some(1));
+        trace.memo_hit("Expr", 0, None);
+        assert_eq!(
+            trace.to_json(),
+            r#"[
+  {"event":"enter","rule":"Expr","file":"main.nano","offset":0,"depth":0},
+  {"event":"success","rule":"Expr","file":"main.nano","offset":0,"depth":0,"advance":1},
+  {"event":"memo_hit","rule":"Expr","file":"main.nano","offset":0,"depth":0,"success":false,"advance":0}
+]"#
+        );
+    }
+}
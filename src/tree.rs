@@ -0,0 +1,539 @@
+use std::collections::HashMap;
+
+use crate::{
+    grammar::{ASTNode, ASTNodeContent, Span, TokenName, AST},
+    nano_grammar::is_ghost_token,
+    sexpr::{SExpr, SExprOptions},
+};
+
+// An owned syntax tree, decoupled from the source string and the token vector.
+//
+// Nodes live in an arena and refer to each other by `NodeId`, so a tree
+// can be cached, sent across threads or kept in an LSP document map, and
+// a `NodeId` stays valid (and keeps meaning the same node) for as long
+// as the tree lives. Rule names, labels and token texts are interned.
+//
+// The borrowed `AST` is still what the parser builds, as a zero-copy
+// fast path; call `Tree::from_ast` when the tree has to outlive the source.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+/// Stores every distinct string once.
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    ids: HashMap<String, Symbol>,
+    strings: Vec<String>,
+}
+
+impl Interner {
+    pub fn intern(&mut self, s: &str) -> Symbol {
+        if let Some(sym) = self.ids.get(s) {
+            return *sym;
+        }
+
+        let sym = Symbol(self.strings.len() as u32);
+        self.strings.push(s.to_string());
+        self.ids.insert(s.to_string(), sym);
+        return sym;
+    }
+
+    pub fn lookup(&self, s: &str) -> Option<Symbol> {
+        self.ids.get(s).copied()
+    }
+
+    pub fn resolve(&self, sym: Symbol) -> &str {
+        &self.strings[sym.0 as usize]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    // An empty match, such as an absent `Optional`.
+    None,
+    Token { name: TokenName, text: Symbol },
+    Node { rule: Symbol },
+    Grouping,
+}
+
+#[derive(Debug, Clone)]
+pub struct TreeNode {
+    pub kind: NodeKind,
+    // The label this node was captured under in its parent, `lhs:Expr`.
+    pub label: Option<Symbol>,
+    pub parent: Option<NodeId>,
+    pub children: Vec<NodeId>,
+    pub span: Option<Span>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Tree {
+    pub is_abstract: bool,
+    pub strings: Interner,
+    nodes: Vec<TreeNode>,
+    root: NodeId,
+}
+
+impl Tree {
+    /// Copies a borrowed tree into an owned one.
+    pub fn from_ast(ast: &AST) -> Tree {
+        let mut tree = Tree::empty(ast.is_abstract);
+        tree.root = tree.push_ast_node(&ast.root, None);
+        return tree;
+    }
+
+    /// Builds an owned tree from its S-expression form, see `sexpr::read`.
+    pub fn from_sexpr(sexpr: &SExpr) -> Tree {
+        let mut tree = Tree::empty(true);
+        tree.root = tree.push_sexpr(sexpr, None, None);
+        tree.is_abstract = !tree.descendants(tree.root).into_iter().any(
+            |id| matches!(tree.kind(id), NodeKind::Token { name, .. } if is_ghost_token(&name)),
+        );
+        return tree;
+    }
+
+    fn empty(is_abstract: bool) -> Tree {
+        Tree {
+            is_abstract,
+            strings: Interner::default(),
+            nodes: Vec::new(),
+            root: NodeId(0),
+        }
+    }
+
+    fn push(&mut self, kind: NodeKind, label: Option<Symbol>, parent: Option<NodeId>) -> NodeId {
+        let id = NodeId(self.nodes.len() as u32);
+        self.nodes.push(TreeNode {
+            kind,
+            label,
+            parent,
+            children: Vec::new(),
+            span: None,
+        });
+        if let Some(p) = parent {
+            self.nodes[p.index()].children.push(id);
+        }
+        return id;
+    }
+
+    fn push_ast_node(&mut self, node: &ASTNode, parent: Option<NodeId>) -> NodeId {
+        let rule = self.strings.intern(node.matched_with);
+        let id = self.push(NodeKind::Node { rule }, None, parent);
+        for c in &node.content {
+            self.push_ast_content(c, None, id);
+        }
+        self.nodes[id.index()].span = node.span();
+        return id;
+    }
+
+    fn push_ast_content(
+        &mut self,
+        content: &ASTNodeContent,
+        label: Option<Symbol>,
+        parent: NodeId,
+    ) {
+        match content {
+            ASTNodeContent::None => {
+                self.push(NodeKind::None, label, Some(parent));
+            }
+            ASTNodeContent::Tok(t) => {
+                let text = self.strings.intern(t.str_content.unwrap_or(""));
+                let id = self.push(NodeKind::Token { name: t.name, text }, label, Some(parent));
+                self.nodes[id.index()].span = Some(t.span);
+            }
+            ASTNodeContent::Grouping(items) => {
+                let id = self.push(NodeKind::Grouping, label, Some(parent));
+                for c in items {
+                    self.push_ast_content(c, None, id);
+                }
+                self.nodes[id.index()].span = content.span();
+            }
+            ASTNodeContent::Node(n) => {
+                let id = self.push_ast_node(n, Some(parent));
+                self.nodes[id.index()].label = label;
+            }
+            ASTNodeContent::Labelled(l, inner) => {
+                let l = self.strings.intern(l);
+                self.push_ast_content(inner, Some(l), parent);
+            }
+        }
+    }
+
+    fn push_sexpr(
+        &mut self,
+        sexpr: &SExpr,
+        label: Option<Symbol>,
+        parent: Option<NodeId>,
+    ) -> NodeId {
+        let (id, span) = match sexpr {
+            SExpr::None => (self.push(NodeKind::None, label, parent), None),
+            SExpr::Token { name, text, span } => {
+                let text = self.strings.intern(text.as_deref().unwrap_or(""));
+                let kind = NodeKind::Token { name: *name, text };
+                (self.push(kind, label, parent), *span)
+            }
+            SExpr::Node {
+                name,
+                span,
+                content,
+            } => {
+                let rule = self.strings.intern(name);
+                let id = self.push(NodeKind::Node { rule }, label, parent);
+                for c in content {
+                    self.push_sexpr(c, None, Some(id));
+                }
+                (id, *span)
+            }
+            SExpr::Grouping(items) => {
+                let id = self.push(NodeKind::Grouping, label, parent);
+                for c in items {
+                    self.push_sexpr(c, None, Some(id));
+                }
+                (id, None)
+            }
+            SExpr::Labelled(l, inner) => {
+                let l = self.strings.intern(l);
+                return self.push_sexpr(inner, Some(l), parent);
+            }
+        };
+
+        // Spans left out of the text are recovered from the children, if they have any.
+        let span = span.or_else(|| {
+            self.children(id)
+                .iter()
+                .filter_map(|c| self.span(*c))
+                .reduce(Span::merge)
+        });
+        self.nodes[id.index()].span = span;
+        return id;
+    }
+
+    /// Converts the subtree at `id` back to its S-expression form.
+    pub fn to_sexpr(&self, id: NodeId, options: &SExprOptions) -> Option<SExpr> {
+        let span = self.span(id).filter(|_| options.spans);
+        let sexpr = match self.kind(id) {
+            NodeKind::None => SExpr::None,
+            NodeKind::Token { name, text } => {
+                if !options.ghost_tokens && is_ghost_token(&name) {
+                    return None;
+                }
+                SExpr::Token {
+                    name,
+                    text: match name {
+                        TokenName::EOF => None,
+                        _ => Some(self.strings.resolve(text).to_string()),
+                    },
+                    span,
+                }
+            }
+            NodeKind::Node { rule } => SExpr::Node {
+                name: self.strings.resolve(rule).to_string(),
+                span,
+                content: self.children_to_sexpr(id, options),
+            },
+            NodeKind::Grouping => SExpr::Grouping(self.children_to_sexpr(id, options)),
+        };
+
+        return Some(match self.label(id) {
+            None => sexpr,
+            Some(l) => SExpr::Labelled(l.to_string(), Box::new(sexpr)),
+        });
+    }
+
+    fn children_to_sexpr(&self, id: NodeId, options: &SExprOptions) -> Vec<SExpr> {
+        self.children(id)
+            .iter()
+            .filter_map(|c| self.to_sexpr(*c, options))
+            .collect()
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn node(&self, id: NodeId) -> &TreeNode {
+        &self.nodes[id.index()]
+    }
+
+    pub fn kind(&self, id: NodeId) -> NodeKind {
+        self.nodes[id.index()].kind
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.index()].parent
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.nodes[id.index()].children
+    }
+
+    pub fn span(&self, id: NodeId) -> Option<Span> {
+        self.nodes[id.index()].span
+    }
+
+    pub fn label(&self, id: NodeId) -> Option<&str> {
+        self.nodes[id.index()]
+            .label
+            .map(|l| self.strings.resolve(l))
+    }
+
+    /// The rule a node was matched with, `None` for tokens and groupings.
+    pub fn rule(&self, id: NodeId) -> Option<&str> {
+        match self.kind(id) {
+            NodeKind::Node { rule } => Some(self.strings.resolve(rule)),
+            _ => None,
+        }
+    }
+
+    /// Whether `id` is a node matched with `rule`.
+    pub fn is(&self, id: NodeId, rule: &str) -> bool {
+        self.rule(id) == Some(rule)
+    }
+
+    pub fn token(&self, id: NodeId) -> Option<(TokenName, &str)> {
+        match self.kind(id) {
+            NodeKind::Token { name, text } => Some((name, self.strings.resolve(text))),
+            _ => None,
+        }
+    }
+
+    /// The nodes right under `id`, looking through groupings.
+    pub fn child_nodes(&self, id: NodeId) -> Vec<NodeId> {
+        let mut out = Vec::new();
+        for c in self.children(id) {
+            match self.kind(*c) {
+                NodeKind::Node { .. } => out.push(*c),
+                NodeKind::Grouping => out.extend(self.child_nodes(*c)),
+                _ => {}
+            }
+        }
+        return out;
+    }
+
+    /// The first child of `id` captured under `label`.
+    pub fn get(&self, id: NodeId, label: &str) -> Option<NodeId> {
+        let label = self.strings.lookup(label)?;
+        self.children(id)
+            .iter()
+            .copied()
+            .find(|c| self.nodes[c.index()].label == Some(label))
+    }
+
+    /// Every child of `id` captured under `label`, in order.
+    pub fn get_all(&self, id: NodeId, label: &str) -> Vec<NodeId> {
+        let label = match self.strings.lookup(label) {
+            None => return Vec::new(),
+            Some(l) => l,
+        };
+        self.children(id)
+            .iter()
+            .copied()
+            .filter(|c| self.nodes[c.index()].label == Some(label))
+            .collect()
+    }
+
+    /// The text of the first token at or under `id`, `name: (t IDENTIFIER x)` -> "x".
+    pub fn text(&self, id: NodeId) -> Option<&str> {
+        if let Some((_, text)) = self.token(id) {
+            return Some(text);
+        }
+        self.children(id).iter().find_map(|c| self.text(*c))
+    }
+
+    /// The `FnDecl` a block item is, looking through `Expr` wrappers and scope annotations.
+    pub fn declared_fn(&self, item: NodeId) -> Option<NodeId> {
+        match self.rule(item)? {
+            "FnDecl" => Some(item),
+            "Expr" => self
+                .child_nodes(item)
+                .into_iter()
+                .find_map(|c| self.declared_fn(c)),
+            "ScopeAnnotation" => self.declared_fn(self.get(item, "value")?),
+            _ => None,
+        }
+    }
+
+    /// `id`'s parent, grandparent, and so on up to the root.
+    pub fn ancestors(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self.parent(id), move |p| self.parent(*p))
+    }
+
+    /// Every node under `id`, in pre-order, `id` included.
+    pub fn descendants(&self, id: NodeId) -> Vec<NodeId> {
+        let mut out = Vec::new();
+        let mut stack = vec![id];
+        while let Some(next) = stack.pop() {
+            out.push(next);
+            stack.extend(self.children(next).iter().rev());
+        }
+        return out;
+    }
+
+    /// The deepest node whose span contains the byte `offset`.
+    pub fn node_at(&self, offset: usize) -> Option<NodeId> {
+        let contains = |id: NodeId| match self.span(id) {
+            None => false,
+            Some(s) => s.start <= offset && offset < s.end,
+        };
+
+        let mut current = self.root;
+        if !contains(current) {
+            return None;
+        }
+        while let Some(child) = self
+            .children(current)
+            .iter()
+            .copied()
+            .find(|c| contains(*c))
+        {
+            current = child;
+        }
+        return Some(current);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{build_tree, tokenize};
+
+    /// The owned tree of `source`, concrete if `cst`.
+    fn tree(source: &str, cst: bool) -> Tree {
+        let tokens = tokenize(source);
+        let ast = build_tree(source, &tokens, "Program", cst, None)
+            .ok()
+            .unwrap();
+        return Tree::from_ast(&ast);
+    }
+
+    /// The first node under the root matched with `rule`.
+    fn first(tree: &Tree, rule: &str) -> NodeId {
+        return tree
+            .descendants(tree.root())
+            .into_iter()
+            .find(|id| tree.is(*id, rule))
+            .unwrap_or_else(|| panic!("no {}", rule));
+    }
+
+    #[test]
+    fn interned_strings_are_stored_once() {
+        let mut strings = Interner::default();
+        let a = strings.intern("Expr");
+        let b = strings.intern("Name");
+        assert_eq!(strings.intern("Expr"), a);
+        assert_ne!(a, b);
+        assert_eq!(strings.resolve(b), "Name");
+        assert_eq!(strings.lookup("Name"), Some(b));
+        assert_eq!(strings.lookup("Call"), None);
+    }
+
+    #[test]
+    fn trees_are_built_with_parents_and_shared_names() {
+        let tree = tree("fn add(a, b) -> a + b\nprint(add(1, 2))", false);
+        assert!(tree.is_abstract);
+        assert!(tree.is(tree.root(), "Program"));
+        assert_eq!(tree.parent(tree.root()), None);
+        for id in tree.descendants(tree.root()) {
+            for child in tree.children(id) {
+                assert_eq!(tree.parent(*child), Some(id));
+            }
+        }
+        // Every `Name` node refers to the same interned rule name.
+        let names: Vec<NodeId> = tree
+            .descendants(tree.root())
+            .into_iter()
+            .filter(|id| tree.is(*id, "Name"))
+            .collect();
+        assert!(names.len() > 1);
+        assert!(names.windows(2).all(|w| tree.kind(w[0]) == tree.kind(w[1])));
+    }
+
+    #[test]
+    fn walks_reach_every_node_in_order() {
+        let tree = tree("let x = [1, 2]", false);
+        let all = tree.descendants(tree.root());
+        assert_eq!(all.len(), tree.len());
+        assert_eq!(all[0], tree.root());
+        // Pre-order: parents come before their children.
+        for (i, id) in all.iter().enumerate() {
+            assert!(tree.ancestors(*id).all(|a| all[..i].contains(&a)));
+        }
+
+        let one = all
+            .iter()
+            .copied()
+            .find(|id| tree.token(*id) == Some((TokenName::IntLiteral, "1")))
+            .unwrap();
+        let rules: Vec<&str> = tree.ancestors(one).filter_map(|a| tree.rule(a)).collect();
+        assert_eq!(rules.last(), Some(&"Program"));
+        assert!(rules.contains(&"List"));
+    }
+
+    #[test]
+    fn labelled_children_are_found_by_label() {
+        let tree = tree("fn add(a, b) -> a + b\nprint(add(1, 2))", false);
+        let declaration = first(&tree, "FnDecl");
+        let name = tree.get(declaration, "name").unwrap();
+        assert_eq!(tree.text(name), Some("add"));
+        assert_eq!(tree.get(declaration, "nothing"), None);
+        assert!(tree.get_all(declaration, "nothing").is_empty());
+
+        let sum = first(&tree, "BinaryExpr");
+        let sides: Vec<&str> = ["lhs", "rhs"]
+            .iter()
+            .map(|l| tree.text(tree.get(sum, l).unwrap()).unwrap())
+            .collect();
+        assert_eq!(sides, ["a", "b"]);
+
+        let inner_call = tree
+            .descendants(tree.root())
+            .into_iter()
+            .rfind(|id| tree.is(*id, "Call"))
+            .unwrap();
+        let args = tree.child_nodes(tree.get(inner_call, "args").unwrap());
+        let texts: Vec<&str> = args.iter().map(|a| tree.text(*a).unwrap()).collect();
+        assert_eq!(texts, ["1", "2"]);
+    }
+
+    #[test]
+    fn node_at_finds_the_deepest_node() {
+        let source = "let total = sum + 1";
+        let tree = tree(source, false);
+        let at = tree.node_at(source.find("sum").unwrap() + 1).unwrap();
+        assert_eq!(tree.token(at), Some((TokenName::Identifier, "sum")));
+        assert_eq!(tree.node_at(source.len() + 10), None);
+    }
+
+    #[test]
+    fn trees_read_back_from_their_sexpr() {
+        let options = SExprOptions {
+            spans: true,
+            ghost_tokens: true,
+        };
+        for cst in [false, true] {
+            let tree = tree("fn f(x) -> x * 2 # doubled\nprint(f(3))", cst);
+            let sexpr = tree.to_sexpr(tree.root(), &options).unwrap();
+            let read = Tree::from_sexpr(&sexpr);
+            assert_eq!(read.is_abstract, !cst);
+            assert_eq!(read.len(), tree.len());
+            assert_eq!(read.to_sexpr(read.root(), &options), Some(sexpr));
+        }
+    }
+}
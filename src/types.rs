@@ -0,0 +1,996 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use crate::{
+    diagnostic::Diagnostic,
+    grammar::{Span, TokenName},
+    ir::Literal,
+    resolve::{BindingId, BindingKind, Resolution},
+    tree::{NodeId, Tree},
+};
+
+// Type checking, Hindley-Milner style.
+//
+// Every node gets a type, with unknowns as type variables that unification
+// fills in. `let`s and `fn`s are generalized, so `fn id(x) -> x` can be used
+// on ints and strings alike. Arithmetic works on ints and floats (and `+` on
+// strings too); operands whose type is still unknown at the end default to
+// `int`, and aren't generalized meanwhile, so they can't be both.
+
+pub type TypeVar = u32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Var(TypeVar),
+    // What expressions evaluated only for their effects, like `for`, return.
+    Unit,
+    Int,
+    Float,
+    Bool,
+    String,
+    List(Box<Type>),
+    Record(BTreeMap<String, Type>),
+    Fn(Vec<Type>, Box<Type>),
+}
+
+impl Type {
+    /// The type variables in `self`, in order of first appearance.
+    pub fn vars(&self) -> Vec<TypeVar> {
+        fn collect(ty: &Type, out: &mut Vec<TypeVar>) {
+            match ty {
+                Type::Var(v) if !out.contains(v) => out.push(*v),
+                Type::List(item) => collect(item, out),
+                Type::Record(fields) => fields.values().for_each(|t| collect(t, out)),
+                Type::Fn(params, ret) => {
+                    params.iter().for_each(|t| collect(t, out));
+                    collect(ret, out);
+                }
+                _ => {}
+            }
+        }
+
+        let mut out = Vec::new();
+        collect(self, &mut out);
+        return out;
+    }
+
+    fn write(&self, f: &mut fmt::Formatter, vars: &[TypeVar]) -> fmt::Result {
+        match self {
+            Type::Var(v) => {
+                let i = vars.iter().position(|x| x == v).unwrap_or(0);
+                let letter = (b'a' + (i % 26) as u8) as char;
+                match i / 26 {
+                    0 => write!(f, "'{}", letter),
+                    n => write!(f, "'{}{}", letter, n),
+                }
+            }
+            Type::Unit => write!(f, "unit"),
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::String => write!(f, "string"),
+            Type::List(item) => {
+                write!(f, "[")?;
+                item.write(f, vars)?;
+                write!(f, "]")
+            }
+            Type::Record(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, ty)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", key)?;
+                    ty.write(f, vars)?;
+                }
+                write!(f, "}}")
+            }
+            Type::Fn(params, ret) => {
+                write!(f, "(")?;
+                for (i, p) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    p.write(f, vars)?;
+                }
+                write!(f, ") -> ")?;
+                ret.write(f, vars)
+            }
+        }
+    }
+
+    /// Same as `to_string`, but with variables named after their place in `vars`,
+    /// so that related types (like a function's parameters) agree on names.
+    pub fn to_string_in(&self, vars: &[TypeVar]) -> String {
+        struct In<'a>(&'a Type, &'a [TypeVar]);
+        impl fmt::Display for In<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.write(f, self.1)
+            }
+        }
+        In(self, vars).to_string()
+    }
+}
+
+/// Shows types as they are written in nano, `(int, 'a) -> [string]`.
+/// Type variables are renamed `'a`, `'b`, ... in order of appearance.
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, &self.vars())
+    }
+}
+
+/// A type generalized over some of its variables, `forall 'a. ('a) -> 'a`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scheme {
+    pub vars: Vec<TypeVar>,
+    pub ty: Type,
+}
+
+impl Scheme {
+    pub fn mono(ty: Type) -> Scheme {
+        Scheme {
+            vars: Vec::new(),
+            ty,
+        }
+    }
+}
+
+/// The types of what a module exports, by name, see `Resolution::exports`.
+pub type Exports = BTreeMap<String, Scheme>;
+
+/// Replaces the variables in `fresh` by what they map to.
+pub fn substitute(ty: &Type, fresh: &HashMap<TypeVar, Type>) -> Type {
+    match ty {
+        Type::Var(v) => fresh.get(v).cloned().unwrap_or(Type::Var(*v)),
+        Type::List(item) => Type::List(Box::new(substitute(item, fresh))),
+        Type::Record(fields) => Type::Record(
+            fields
+                .iter()
+                .map(|(k, t)| (k.clone(), substitute(t, fresh)))
+                .collect(),
+        ),
+        Type::Fn(params, ret) => Type::Fn(
+            params.iter().map(|p| substitute(p, fresh)).collect(),
+            Box::new(substitute(ret, fresh)),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// The result of type checking a tree.
+#[derive(Debug, Clone, Default)]
+pub struct TypeInfo {
+    types: HashMap<NodeId, Type>,
+    schemes: HashMap<BindingId, Scheme>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl TypeInfo {
+    /// The inferred type of a node, `None` for nodes without a value (tokens, groupings).
+    pub fn type_of(&self, id: NodeId) -> Option<&Type> {
+        self.types.get(&id)
+    }
+
+    /// The (possibly generic) type of a binding.
+    pub fn scheme_of(&self, binding: BindingId) -> Option<&Scheme> {
+        self.schemes.get(&binding)
+    }
+
+    /// The types of the module's exports, generic over all of their variables,
+    /// since nothing outside the module can narrow them down.
+    pub fn exports(&self, tree: &Tree, resolution: &Resolution) -> Exports {
+        let mut out = Exports::new();
+        for b in resolution.exports(tree) {
+            let ty = match self.scheme_of(b) {
+                None => continue,
+                Some(scheme) => scheme.ty.clone(),
+            };
+            let scheme = Scheme {
+                vars: ty.vars(),
+                ty,
+            };
+            out.insert(resolution.binding(b).name.clone(), scheme);
+        }
+        return out;
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(Diagnostic::is_error)
+    }
+}
+
+pub fn check(tree: &Tree, resolution: &Resolution) -> TypeInfo {
+    return check_with_imports(tree, resolution, &HashMap::new());
+}
+
+/// Same as `check`, knowing what the modules behind some `Import` nodes export.
+pub fn check_with_imports(
+    tree: &Tree,
+    resolution: &Resolution,
+    imports: &HashMap<NodeId, Exports>,
+) -> TypeInfo {
+    let mut checker = Checker {
+        tree,
+        resolution,
+        imports,
+        vars: Vec::new(),
+        level: 0,
+        env: HashMap::new(),
+        types: HashMap::new(),
+        constraints: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+
+    checker.infer(tree.root());
+    checker.check_constraints();
+
+    // Every type is written out in full, now that all the variables that will be known are.
+    let types = checker
+        .types
+        .iter()
+        .map(|(id, ty)| (*id, checker.zonk(ty)))
+        .collect();
+    let schemes = checker
+        .env
+        .iter()
+        .map(|(b, s)| {
+            let ty = checker.zonk(&s.ty);
+            let vars = ty.vars().into_iter().filter(|v| s.vars.contains(v));
+            (
+                *b,
+                Scheme {
+                    vars: vars.collect(),
+                    ty,
+                },
+            )
+        })
+        .collect();
+
+    let mut diagnostics = checker.diagnostics;
+    diagnostics.sort_by_key(|d| d.span.map(|s| s.start));
+
+    return TypeInfo {
+        types,
+        schemes,
+        diagnostics,
+    };
+}
+
+#[derive(Debug, Clone)]
+enum VarState {
+    // Still unknown. Variables deeper than the current level can be generalized.
+    Unbound { level: u32 },
+    Bound(Type),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConstraintKind {
+    // int or float
+    Numeric,
+    // int, float or string
+    Addable,
+}
+
+#[derive(Debug, Clone)]
+struct Constraint {
+    ty: Type,
+    kind: ConstraintKind,
+    op: String,
+    span: Option<Span>,
+}
+
+struct Checker<'t> {
+    tree: &'t Tree,
+    resolution: &'t Resolution,
+    imports: &'t HashMap<NodeId, Exports>,
+    vars: Vec<VarState>,
+    level: u32,
+    env: HashMap<BindingId, Scheme>,
+    types: HashMap<NodeId, Type>,
+    constraints: Vec<Constraint>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'t> Checker<'t> {
+    fn fresh_at(&mut self, level: u32) -> Type {
+        self.vars.push(VarState::Unbound { level });
+        return Type::Var(self.vars.len() as TypeVar - 1);
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.fresh_at(self.level)
+    }
+
+    /// Follows bound variables until a type that isn't one.
+    fn shallow(&self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        while let Type::Var(v) = ty {
+            match &self.vars[v as usize] {
+                VarState::Bound(t) => ty = t.clone(),
+                VarState::Unbound { .. } => break,
+            }
+        }
+        return ty;
+    }
+
+    /// Replaces every bound variable in `ty`, all the way down.
+    fn zonk(&self, ty: &Type) -> Type {
+        match self.shallow(ty) {
+            Type::List(item) => Type::List(Box::new(self.zonk(&item))),
+            Type::Record(fields) => Type::Record(
+                fields
+                    .iter()
+                    .map(|(k, t)| (k.clone(), self.zonk(t)))
+                    .collect(),
+            ),
+            Type::Fn(params, ret) => Type::Fn(
+                params.iter().map(|p| self.zonk(p)).collect(),
+                Box::new(self.zonk(&ret)),
+            ),
+            other => other,
+        }
+    }
+
+    fn unify(&mut self, a: &Type, b: &Type) -> Result<(), ()> {
+        let a = self.shallow(a);
+        let b = self.shallow(b);
+
+        match (a, b) {
+            (Type::Var(x), Type::Var(y)) if x == y => Ok(()),
+            (Type::Var(v), t) | (t, Type::Var(v)) => self.bind(v, t),
+            (Type::List(x), Type::List(y)) => self.unify(&x, &y),
+            (Type::Record(x), Type::Record(y)) => {
+                if !x.keys().eq(y.keys()) {
+                    return Err(());
+                }
+                for (tx, ty) in x.values().zip(y.values()) {
+                    self.unify(tx, ty)?;
+                }
+                Ok(())
+            }
+            (Type::Fn(px, rx), Type::Fn(py, ry)) => {
+                if px.len() != py.len() {
+                    return Err(());
+                }
+                for (tx, ty) in px.iter().zip(py.iter()) {
+                    self.unify(tx, ty)?;
+                }
+                self.unify(&rx, &ry)
+            }
+            (a, b) if a == b => Ok(()),
+            _ => Err(()),
+        }
+    }
+
+    fn bind(&mut self, v: TypeVar, ty: Type) -> Result<(), ()> {
+        let level = match self.vars[v as usize] {
+            VarState::Unbound { level } => level,
+            VarState::Bound(_) => unreachable!("`shallow` only returns unbound variables"),
+        };
+
+        // No infinite types, like `'a = ['a]`.
+        let ty = self.zonk(&ty);
+        let vars = ty.vars();
+        if vars.contains(&v) {
+            return Err(());
+        }
+        // Whatever `v` is bound to is now as visible as `v` was.
+        for other in vars {
+            if let VarState::Unbound { level: l } = &mut self.vars[other as usize] {
+                *l = (*l).min(level);
+            }
+        }
+
+        self.vars[v as usize] = VarState::Bound(ty);
+        return Ok(());
+    }
+
+    /// Unifies `found` with `expected`, reporting a mismatch at both of their spans.
+    fn expect(
+        &mut self,
+        expected: &Type,
+        expected_at: Option<Span>,
+        found: &Type,
+        found_at: Option<Span>,
+    ) {
+        if self.unify(expected, found).is_ok() {
+            return;
+        }
+
+        let expected = self.zonk(expected);
+        let found = self.zonk(found);
+        let mut diagnostic = Diagnostic::error(
+            format!(
+                "Mismatched types: expected `{}`, found `{}`.",
+                expected, found
+            ),
+            found_at,
+        );
+        if expected_at.is_some() {
+            diagnostic = diagnostic.with_note(
+                expected_at,
+                format!("`{}` is expected because of this.", expected),
+            );
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let fresh: HashMap<TypeVar, Type> =
+            scheme.vars.iter().map(|v| (*v, self.fresh())).collect();
+        let ty = self.zonk(&scheme.ty);
+        return substitute(&ty, &fresh);
+    }
+
+    /// Instantiates a scheme checked by another `Checker`, whose variables mean nothing here.
+    fn instantiate_foreign(&mut self, scheme: &Scheme) -> Type {
+        let fresh: HashMap<TypeVar, Type> =
+            scheme.vars.iter().map(|v| (*v, self.fresh())).collect();
+        return substitute(&scheme.ty, &fresh);
+    }
+
+    fn generalize(&self, ty: &Type) -> Scheme {
+        let ty = self.zonk(ty);
+        let constrained: HashSet<TypeVar> = self
+            .constraints
+            .iter()
+            .flat_map(|c| self.zonk(&c.ty).vars())
+            .collect();
+
+        let vars = ty
+            .vars()
+            .into_iter()
+            .filter(|v| match self.vars[*v as usize] {
+                VarState::Unbound { level } => level > self.level && !constrained.contains(v),
+                VarState::Bound(_) => false,
+            })
+            .collect();
+
+        return Scheme { vars, ty };
+    }
+
+    fn binding_of_definition(&self, name: Option<NodeId>) -> Option<BindingId> {
+        name.and_then(|n| self.resolution.definitions.get(&n).copied())
+    }
+
+    /// Gives the `Name` node that introduces a binding its type.
+    fn define(&mut self, name: Option<NodeId>, scheme: Scheme) {
+        if let Some(n) = name {
+            self.types.insert(n, scheme.ty.clone());
+        }
+        if let Some(b) = self.binding_of_definition(name) {
+            self.env.insert(b, scheme);
+        }
+    }
+
+    /// The types of the names every program can use, see `resolve::PRELUDE`.
+    fn builtin(&mut self, name: &str) -> Type {
+        match name {
+            "print" => Type::Fn(vec![self.fresh()], Box::new(Type::Unit)),
+            _ => self.fresh(),
+        }
+    }
+
+    fn infer_opt(&mut self, id: Option<NodeId>) -> Type {
+        match id {
+            None => Type::Unit,
+            Some(id) => self.infer(id),
+        }
+    }
+
+    fn infer(&mut self, id: NodeId) -> Type {
+        let ty = self.infer_node(id);
+        self.types.insert(id, ty.clone());
+        return ty;
+    }
+
+    fn infer_node(&mut self, id: NodeId) -> Type {
+        let tree = self.tree;
+        match tree.rule(id).unwrap_or("") {
+            "Literal" => self.infer_literal(id),
+            "Name" => self.infer_name(id),
+            "BinaryExpr" => self.infer_binary(id),
+            "Range" => {
+                for bound in [tree.get(id, "start"), tree.get(id, "end")] {
+                    let ty = self.infer_opt(bound);
+                    self.expect(&Type::Int, None, &ty, bound.and_then(|b| tree.span(b)));
+                }
+                Type::List(Box::new(Type::Int))
+            }
+            "List" => {
+                let item = self.fresh();
+                let items = tree.get(id, "items").map(|i| tree.child_nodes(i));
+                let mut first_at = None;
+                for i in items.unwrap_or_default() {
+                    let ty = self.infer(i);
+                    self.expect(&item, first_at, &ty, tree.span(i));
+                    first_at = first_at.or(tree.span(i));
+                }
+                Type::List(Box::new(item))
+            }
+            "Record" => {
+                let mut fields = BTreeMap::new();
+                let mut seen: HashMap<String, Option<Span>> = HashMap::new();
+                let nodes = tree.get(id, "fields").map(|f| tree.child_nodes(f));
+                for field in nodes.unwrap_or_default() {
+                    let key = tree.get(field, "key");
+                    let name = key.and_then(|k| tree.text(k)).unwrap_or("").to_string();
+                    let ty = self.infer(field);
+
+                    let key_at = key.and_then(|k| tree.span(k));
+                    if let Some(first_at) = seen.get(&name) {
+                        let diagnostic =
+                            Diagnostic::error(format!("Duplicate field '{}'.", name), key_at)
+                                .with_note(*first_at, "First defined here.");
+                        self.diagnostics.push(diagnostic);
+                        continue;
+                    }
+                    seen.insert(name.clone(), key_at);
+                    fields.insert(name, ty);
+                }
+                Type::Record(fields)
+            }
+            "Field" => self.infer_opt(tree.get(id, "value")),
+            "Call" => self.infer_call(id),
+            "Parens" | "Exprs" => self.infer_block(tree.child_nodes(id)),
+            "Let" => {
+                self.level += 1;
+                let value = self.infer_opt(tree.get(id, "value"));
+                self.level -= 1;
+                let scheme = self.generalize(&value);
+                self.define(tree.get(id, "name"), scheme);
+                value
+            }
+            "FnDecl" => self.infer_fn(id),
+            "Lambda" => {
+                let param = self.fresh();
+                self.define(tree.get(id, "param"), Scheme::mono(param.clone()));
+                let body = self.infer_opt(tree.get(id, "body"));
+                Type::Fn(vec![param], Box::new(body))
+            }
+            "Import" => {
+                // An alias is a record of the module's exports. Without the module,
+                // what it exports isn't known, so the alias can be used as anything.
+                self.level += 1;
+                let module = match self.imports.get(&id) {
+                    None => self.fresh(),
+                    Some(exports) => Type::Record(
+                        exports
+                            .iter()
+                            .map(|(name, scheme)| (name.clone(), self.instantiate_foreign(scheme)))
+                            .collect(),
+                    ),
+                };
+                self.level -= 1;
+                let scheme = self.generalize(&module);
+                self.define(tree.get(id, "alias"), scheme);
+                Type::Unit
+            }
+            "Member" => self.infer_member(id),
+            "Annotation" => {
+                self.infer_opt(tree.get(id, "value"));
+                Type::Unit
+            }
+            // `%%t_eq(actual, expected)` compares two values of the same type.
+            "Assertion" => {
+                let args = tree.get(id, "args").map(|a| tree.child_nodes(a));
+                let args = args.unwrap_or_default();
+                let tys: Vec<Type> = args.iter().map(|a| self.infer(*a)).collect();
+                if let ([actual, expected], [actual_ty, expected_ty]) =
+                    (args.as_slice(), tys.as_slice())
+                {
+                    self.expect(
+                        actual_ty,
+                        tree.span(*actual),
+                        expected_ty,
+                        tree.span(*expected),
+                    );
+                }
+                Type::Bool
+            }
+            "For" | "Select" => {
+                let iterable = tree.get(id, "iterable");
+                let iterable_ty = self.infer_opt(iterable);
+                let item = self.fresh();
+                self.expect(
+                    &Type::List(Box::new(item.clone())),
+                    None,
+                    &iterable_ty,
+                    iterable.and_then(|i| tree.span(i)),
+                );
+                self.define(tree.get(id, "name"), Scheme::mono(item));
+
+                let body = self.infer_opt(tree.get(id, "body"));
+                match tree.is(id, "Select") {
+                    true => Type::List(Box::new(body)),
+                    false => Type::Unit,
+                }
+            }
+            // Wrappers, like `Expr` and `Program`, have the type of what they wrap.
+            _ => {
+                let mut ty = Type::Unit;
+                for child in tree.child_nodes(id) {
+                    ty = self.infer(child);
+                }
+                ty
+            }
+        }
+    }
+
+    fn infer_literal(&mut self, id: NodeId) -> Type {
+        let tree = self.tree;
+        let (name, text) = match tree.get(id, "value").and_then(|v| tree.token(v)) {
+            None => return self.fresh(),
+            Some(token) => token,
+        };
+        let negative = tree.get(id, "sign").is_some();
+        if Literal::parse(name, text, negative).is_none() {
+            let kind = match name {
+                TokenName::FloatLiteral => "Float",
+                _ => "Integer",
+            };
+            let message = format!("{} literal doesn't fit in 64 bits.", kind);
+            self.diagnostics
+                .push(Diagnostic::error(message, tree.span(id)));
+        }
+
+        match name {
+            TokenName::IntLiteral => Type::Int,
+            TokenName::FloatLiteral => Type::Float,
+            TokenName::StringLiteral => Type::String,
+            TokenName::BooleanLiteral => Type::Bool,
+            _ => self.fresh(),
+        }
+    }
+
+    fn infer_name(&mut self, id: NodeId) -> Type {
+        let binding = match self.resolution.uses.get(&id) {
+            // Undefined, which the resolver already reported.
+            None => return self.fresh(),
+            Some(b) => *b,
+        };
+
+        if let Some(scheme) = self.env.get(&binding).cloned() {
+            return self.instantiate(&scheme);
+        }
+        let binding = self.resolution.binding(binding);
+        return match binding.kind {
+            BindingKind::Builtin => self.builtin(&binding.name.clone()),
+            _ => self.fresh(),
+        };
+    }
+
+    /// The exports of the module `object` is the alias of, if it is one.
+    fn module_of(&self, object: Option<NodeId>) -> Option<(&'t str, &'t Exports)> {
+        let tree = self.tree;
+        let binding = self
+            .resolution
+            .binding(*self.resolution.uses.get(&object?)?);
+        if binding.kind != BindingKind::Import {
+            return None;
+        }
+        let import = tree.parent(binding.node?)?;
+        let alias = tree.text(binding.node?)?;
+        return Some((alias, self.imports.get(&import)?));
+    }
+
+    fn infer_member(&mut self, id: NodeId) -> Type {
+        let tree = self.tree;
+        let object = tree.get(id, "object");
+        let object_ty = self.infer_opt(object);
+        let field_node = tree.get(id, "field");
+        let field = field_node.and_then(|f| tree.text(f)).unwrap_or("");
+        let field_at = field_node.and_then(|f| tree.span(f));
+
+        // Exports are read from the module itself, so generic ones stay generic.
+        if let Some((alias, exports)) = self.module_of(object) {
+            return match exports.get(field) {
+                Some(scheme) => self.instantiate_foreign(scheme),
+                None => {
+                    let message = format!("Module '{}' has no export '{}'.", alias, field);
+                    self.diagnostics.push(Diagnostic::error(message, field_at));
+                    self.fresh()
+                }
+            };
+        }
+
+        let message = match self.shallow(&object_ty) {
+            Type::Record(fields) if fields.contains_key(field) => return fields[field].clone(),
+            Type::Var(_) => format!(
+                "The type of this value must be known before reading '.{}' from it.",
+                field
+            ),
+            other => format!("`{}` has no field '{}'.", self.zonk(&other), field),
+        };
+        self.diagnostics.push(Diagnostic::error(message, field_at));
+        return self.fresh();
+    }
+
+    fn infer_binary(&mut self, id: NodeId) -> Type {
+        let tree = self.tree;
+        let op = tree.get(id, "op").and_then(|o| tree.token(o));
+        let (lhs, rhs) = (tree.get(id, "lhs"), tree.get(id, "rhs"));
+        let lhs_ty = self.infer_opt(lhs);
+        let rhs_ty = self.infer_opt(rhs);
+
+        // `value |> function` is `function(value)`.
+        if let Some((TokenName::Pipe, _)) = op {
+            let ret = self.fresh();
+            let expected = Type::Fn(vec![lhs_ty], Box::new(ret.clone()));
+            self.expect(
+                &expected,
+                lhs.and_then(|l| tree.span(l)),
+                &rhs_ty,
+                rhs.and_then(|r| tree.span(r)),
+            );
+            return ret;
+        }
+
+        self.expect(
+            &lhs_ty,
+            lhs.and_then(|l| tree.span(l)),
+            &rhs_ty,
+            rhs.and_then(|r| tree.span(r)),
+        );
+
+        let (kind, text) = match op {
+            Some((TokenName::OpIs, _)) | Some((TokenName::OpXis, _)) => return Type::Bool,
+            Some((TokenName::OpPlus, text)) => (ConstraintKind::Addable, text),
+            Some((_, text)) => (ConstraintKind::Numeric, text),
+            None => return lhs_ty,
+        };
+        self.constraints.push(Constraint {
+            ty: lhs_ty.clone(),
+            kind,
+            op: text.to_string(),
+            span: tree.span(id),
+        });
+        return lhs_ty;
+    }
+
+    fn infer_call(&mut self, id: NodeId) -> Type {
+        let tree = self.tree;
+        let callee = tree.get(id, "callee");
+        let callee_ty = self.infer_opt(callee);
+        let args = tree
+            .get(id, "args")
+            .map(|a| tree.child_nodes(a))
+            .unwrap_or_default();
+        let arg_tys: Vec<Type> = args.iter().map(|a| self.infer(*a)).collect();
+        // `module.function` is named after the function.
+        let callee_name = callee
+            .map(|c| tree.get(c, "field").unwrap_or(c))
+            .and_then(|c| tree.text(c))
+            .unwrap_or("");
+        let callee_at = callee.and_then(|c| tree.span(c));
+
+        let (params, ret) = match self.shallow(&callee_ty) {
+            Type::Fn(params, ret) => (params, *ret),
+            // Not known yet, so it's whatever function takes these arguments.
+            // That fails for a value called with itself, like `x(x)`.
+            Type::Var(_) => {
+                let ret = self.fresh();
+                let fn_ty = Type::Fn(arg_tys, Box::new(ret.clone()));
+                self.expect(&fn_ty, tree.span(id), &callee_ty, callee_at);
+                return ret;
+            }
+            other => {
+                let message = format!(
+                    "'{}' is not a function, its type is `{}`.",
+                    callee_name,
+                    self.zonk(&other)
+                );
+                self.diagnostics
+                    .push(Diagnostic::error(message, tree.span(id)));
+                return self.fresh();
+            }
+        };
+
+        if params.len() != args.len() {
+            let message = format!(
+                "'{}' takes {} argument{} but {} {} given.",
+                callee_name,
+                params.len(),
+                if params.len() == 1 { "" } else { "s" },
+                args.len(),
+                if args.len() == 1 { "was" } else { "were" }
+            );
+            self.diagnostics
+                .push(Diagnostic::error(message, tree.span(id)));
+            return ret;
+        }
+
+        // Mismatched arguments point back at the parameter, when the function is declared here.
+        let param_spans = self.param_spans(callee);
+        for (i, (param, arg)) in params.iter().zip(arg_tys.iter()).enumerate() {
+            let expected_at = param_spans.get(i).copied().flatten().or(callee_at);
+            self.expect(param, expected_at, arg, tree.span(args[i]));
+        }
+        return ret;
+    }
+
+    fn param_spans(&self, callee: Option<NodeId>) -> Vec<Option<Span>> {
+        let tree = self.tree;
+        let decl = callee
+            .and_then(|c| self.resolution.uses.get(&c))
+            .map(|b| self.resolution.binding(*b))
+            .filter(|b| b.kind == BindingKind::Fn)
+            .and_then(|b| b.node)
+            .and_then(|n| tree.parent(n));
+
+        return match decl.and_then(|d| tree.get(d, "params")) {
+            None => Vec::new(),
+            Some(params) => tree
+                .child_nodes(params)
+                .into_iter()
+                .map(|p| tree.span(p))
+                .collect(),
+        };
+    }
+
+    fn infer_fn(&mut self, id: NodeId) -> Type {
+        let tree = self.tree;
+        let name = tree.get(id, "name");
+
+        self.level += 1;
+        // A hoisted `fn` was given a type variable by its block already.
+        let own = match self
+            .binding_of_definition(name)
+            .and_then(|b| self.env.get(&b))
+        {
+            Some(scheme) if scheme.vars.is_empty() => scheme.ty.clone(),
+            _ => self.fresh(),
+        };
+        self.define(name, Scheme::mono(own.clone()));
+
+        let params: Vec<Type> = tree
+            .get(id, "params")
+            .map(|p| tree.child_nodes(p))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|p| {
+                let ty = self.fresh();
+                self.define(Some(p), Scheme::mono(ty.clone()));
+                ty
+            })
+            .collect();
+        let body = self.infer_opt(tree.get(id, "body"));
+        let fn_ty = Type::Fn(params, Box::new(body));
+        let _ = self.unify(&own, &fn_ty);
+        self.level -= 1;
+
+        let scheme = self.generalize(&fn_ty);
+        self.define(name, scheme);
+        return fn_ty;
+    }
+
+    /// A sequence of expressions, typed as its last one. Its `fn`s are visible throughout.
+    fn infer_block(&mut self, items: Vec<NodeId>) -> Type {
+        let tree = self.tree;
+        for item in &items {
+            if let Some(decl) = tree.declared_fn(*item) {
+                let ty = self.fresh_at(self.level + 1);
+                self.define(tree.get(decl, "name"), Scheme::mono(ty));
+            }
+        }
+
+        let mut ty = Type::Unit;
+        for item in items {
+            ty = self.infer(item);
+        }
+        return ty;
+    }
+
+    fn check_constraints(&mut self) {
+        for c in std::mem::take(&mut self.constraints) {
+            let ty = self.zonk(&c.ty);
+            let ok = match (&ty, c.kind) {
+                // Nothing said what these are, so they're ints.
+                (Type::Var(v), _) => {
+                    let _ = self.bind(*v, Type::Int);
+                    true
+                }
+                (Type::Int, _) | (Type::Float, _) => true,
+                (Type::String, ConstraintKind::Addable) => true,
+                _ => false,
+            };
+
+            if !ok {
+                let message = format!("Operator '{}' can't be used on `{}`.", c.op, ty);
+                self.diagnostics.push(Diagnostic::error(message, c.span));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        parser::{build_tree, tokenize},
+        resolve::resolve,
+    };
+
+    /// The types `source` checks to.
+    fn checked(source: &str) -> (Resolution, TypeInfo) {
+        let tokens = tokenize(source);
+        let ast = build_tree(source, &tokens, "Program", false, None).expect("it doesn't parse");
+        let tree = Tree::from_ast(&ast);
+        let resolution = resolve(&tree);
+        assert!(!resolution.has_errors(), "{:?}", resolution.diagnostics);
+        let types = check(&tree, &resolution);
+        return (resolution, types);
+    }
+
+    /// The type of the last binding called `name` in `source`.
+    fn type_of(source: &str, name: &str) -> String {
+        let (resolution, types) = checked(source);
+        assert!(!types.has_errors(), "{:?}", types.diagnostics);
+        let binding = (0..resolution.bindings.len())
+            .rev()
+            .find(|b| resolution.binding(*b).name == name)
+            .expect("no such binding");
+        return types.scheme_of(binding).unwrap().ty.to_string();
+    }
+
+    /// What's wrong with `source`'s types.
+    fn errors(source: &str) -> Vec<String> {
+        let (_, types) = checked(source);
+        return types.diagnostics.into_iter().map(|d| d.message).collect();
+    }
+
+    #[test]
+    fn generic_functions_are_instantiated_at_each_use() {
+        let source = "fn id(x) -> x\nlet a = id(1)\nlet b = id('s')\nlet c = id([a])";
+        assert_eq!(type_of(source, "id"), "('a) -> 'a");
+        assert_eq!(type_of(source, "a"), "int");
+        assert_eq!(type_of(source, "b"), "string");
+        assert_eq!(type_of(source, "c"), "[int]");
+    }
+
+    #[test]
+    fn unknown_callees_are_typed_by_their_call() {
+        assert_eq!(
+            type_of("fn apply(f, x) -> f(x)", "apply"),
+            "(('a) -> 'b, 'a) -> 'b"
+        );
+        assert_eq!(
+            type_of("fn twice(f, x) -> f(f(x))", "twice"),
+            "(('a) -> 'a, 'a) -> 'a"
+        );
+    }
+
+    #[test]
+    fn values_called_with_themselves_are_refused() {
+        assert_eq!(
+            errors("fn f(x) -> x(x)"),
+            ["Mismatched types: expected `('a) -> 'b`, found `'a`."]
+        );
+        assert!(!errors("fn f(x) -> x(x)\nfn g(y) -> y\nprint(f(g) + 'a')").is_empty());
+    }
+
+    #[test]
+    fn unknown_callees_must_agree_with_every_call() {
+        assert_eq!(
+            errors("fn f(g) -> (\n    g(1)\n    g('a')\n)"),
+            ["Mismatched types: expected `int`, found `string`."]
+        );
+    }
+
+    #[test]
+    fn mismatches_are_reported() {
+        assert_eq!(
+            errors("let xs = [1, 'a']"),
+            ["Mismatched types: expected `int`, found `string`."]
+        );
+        assert_eq!(
+            errors("fn f(x) -> x\nf(1, 2)"),
+            ["'f' takes 1 argument but 2 were given."]
+        );
+        assert_eq!(
+            errors("let x = 1\nx(2)"),
+            ["'x' is not a function, its type is `int`."]
+        );
+    }
+}
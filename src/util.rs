@@ -0,0 +1,82 @@
+/// Quotes and escapes a string so it can be embedded in JSON output.
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    return out;
+}
+
+/// `BinaryExpr` -> `BINARY_EXPR`, `EOF` -> `EOF`.
+pub fn screaming_snake_case(name: &str) -> String {
+    let mut out = String::with_capacity(name.len() + 4);
+    let mut previous: Option<char> = None;
+    for c in name.chars() {
+        if let Some(p) = previous {
+            if c.is_uppercase() && (p.is_lowercase() || p.is_ascii_digit()) {
+                out.push('_');
+            }
+        }
+        out.extend(c.to_uppercase());
+        previous = Some(c);
+    }
+    return out;
+}
+
+/// `BINARY_EXPR` -> `BinaryExpr`, the inverse of `screaming_snake_case`.
+pub fn camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                None => String::new(),
+                Some(first) => first
+                    .to_uppercase()
+                    .chain(chars.flat_map(char::to_lowercase))
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// `text` without its colors, to compare rendered output in tests.
+#[cfg(test)]
+pub fn plain(text: &str) -> String {
+    let mut out = String::new();
+    let mut escaped = false;
+    for c in text.chars() {
+        match (escaped, c) {
+            (false, '\x1b') => escaped = true,
+            (false, c) => out.push(c),
+            (true, 'm') => escaped = false,
+            (true, _) => {}
+        }
+    }
+    return out;
+}
+
+/// The little-endian integers at `at`, to check written binaries in tests.
+#[cfg(test)]
+pub fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    return u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap());
+}
+
+#[cfg(test)]
+pub fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    return u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+}
+
+#[cfg(test)]
+pub fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    return u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+}
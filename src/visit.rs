@@ -0,0 +1,546 @@
+use std::collections::HashSet;
+
+use crate::{
+    grammar::{ASTNode, ASTNodeContent, Token, AST},
+    tree::{NodeId, Tree},
+};
+
+// Traversal of syntax trees, for the linter, the formatter, the name
+// resolver and the metaprogramming library.
+//
+// `Visitor` and `VisitorMut` walk a tree depth-first, each node's content in
+// order. Every method has a default that just keeps walking, so implementors
+// only override what they care about and call the matching `walk_*` function
+// to keep descending.
+// `Fold` rebuilds a tree bottom-up, the same way reduction actions do.
+// `Query` selects nodes by path, like `FnDecl Call` or `BinaryExpr > lhs:*`.
+
+pub trait Visitor<'a> {
+    fn visit_node(&mut self, node: &ASTNode<'a>) {
+        walk_node(self, node)
+    }
+
+    fn visit_content(&mut self, content: &ASTNodeContent<'a>) {
+        walk_content(self, content)
+    }
+
+    fn visit_labelled(&mut self, _label: &'static str, content: &ASTNodeContent<'a>) {
+        self.visit_content(content)
+    }
+
+    fn visit_token(&mut self, _token: &'a Token<'a>) {}
+}
+
+pub fn walk_ast<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, ast: &AST<'a>) {
+    visitor.visit_node(&ast.root)
+}
+
+pub fn walk_node<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &ASTNode<'a>) {
+    for c in &node.content {
+        visitor.visit_content(c);
+    }
+}
+
+pub fn walk_content<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, content: &ASTNodeContent<'a>) {
+    match content {
+        ASTNodeContent::None => {}
+        ASTNodeContent::Tok(t) => visitor.visit_token(t),
+        ASTNodeContent::Grouping(items) => {
+            for c in items {
+                visitor.visit_content(c);
+            }
+        }
+        ASTNodeContent::Node(n) => visitor.visit_node(n),
+        ASTNodeContent::Labelled(label, inner) => visitor.visit_labelled(label, inner),
+    }
+}
+
+pub trait VisitorMut<'a> {
+    fn visit_node_mut(&mut self, node: &mut ASTNode<'a>) {
+        walk_node_mut(self, node)
+    }
+
+    fn visit_content_mut(&mut self, content: &mut ASTNodeContent<'a>) {
+        walk_content_mut(self, content)
+    }
+
+    // Tokens are shared with the token pool, so they can be swapped but not edited.
+    fn visit_token_mut(&mut self, _token: &mut &'a Token<'a>) {}
+}
+
+pub fn walk_ast_mut<'a, V: VisitorMut<'a> + ?Sized>(visitor: &mut V, ast: &mut AST<'a>) {
+    visitor.visit_node_mut(&mut ast.root)
+}
+
+pub fn walk_node_mut<'a, V: VisitorMut<'a> + ?Sized>(visitor: &mut V, node: &mut ASTNode<'a>) {
+    for c in &mut node.content {
+        visitor.visit_content_mut(c);
+    }
+}
+
+pub fn walk_content_mut<'a, V: VisitorMut<'a> + ?Sized>(
+    visitor: &mut V,
+    content: &mut ASTNodeContent<'a>,
+) {
+    match content {
+        ASTNodeContent::None => {}
+        ASTNodeContent::Tok(t) => visitor.visit_token_mut(t),
+        ASTNodeContent::Grouping(items) => {
+            for c in items {
+                visitor.visit_content_mut(c);
+            }
+        }
+        ASTNodeContent::Node(n) => visitor.visit_node_mut(n),
+        ASTNodeContent::Labelled(_, inner) => visitor.visit_content_mut(inner),
+    }
+}
+
+/// Rebuilds a tree bottom-up: children are folded before their parent.
+pub trait Fold<'a> {
+    /// Called with a node whose children were already folded.
+    fn fold_node(&mut self, node: ASTNode<'a>) -> ASTNodeContent<'a> {
+        ASTNodeContent::Node(node)
+    }
+
+    fn fold_token(&mut self, token: &'a Token<'a>) -> ASTNodeContent<'a> {
+        ASTNodeContent::Tok(token)
+    }
+
+    fn fold_content(&mut self, content: ASTNodeContent<'a>) -> ASTNodeContent<'a> {
+        fold_children(self, content)
+    }
+}
+
+pub fn fold_children<'a, F: Fold<'a> + ?Sized>(
+    folder: &mut F,
+    content: ASTNodeContent<'a>,
+) -> ASTNodeContent<'a> {
+    match content {
+        ASTNodeContent::None => ASTNodeContent::None,
+        ASTNodeContent::Tok(t) => folder.fold_token(t),
+        ASTNodeContent::Grouping(items) => {
+            ASTNodeContent::Grouping(items.into_iter().map(|c| folder.fold_content(c)).collect())
+        }
+        ASTNodeContent::Node(n) => {
+            let node = ASTNode {
+                matched_with: n.matched_with,
+                consumed: n.consumed,
+                content: n
+                    .content
+                    .into_iter()
+                    .map(|c| folder.fold_content(c))
+                    .collect(),
+            };
+            folder.fold_node(node)
+        }
+        ASTNodeContent::Labelled(label, inner) => {
+            ASTNodeContent::Labelled(label, Box::new(folder.fold_content(*inner)))
+        }
+    }
+}
+
+/// Folds a whole tree. The root stays a node even if the folder replaces it.
+pub fn fold_ast<'a, F: Fold<'a> + ?Sized>(folder: &mut F, ast: AST<'a>) -> AST<'a> {
+    let name = ast.root.matched_with;
+    let root = match folder.fold_content(ASTNodeContent::Node(ast.root)) {
+        ASTNodeContent::Node(n) => n,
+        other => ASTNode {
+            matched_with: name,
+            consumed: other.span(),
+            content: vec![other],
+        },
+    };
+
+    return AST {
+        is_abstract: ast.is_abstract,
+        root,
+    };
+}
+
+/// A `Fold` out of a closure over nodes, with the same shape as a reduction action.
+pub struct FoldNodes<F>(pub F);
+
+impl<'a, F: FnMut(ASTNode<'a>) -> ASTNodeContent<'a>> Fold<'a> for FoldNodes<F> {
+    fn fold_node(&mut self, node: ASTNode<'a>) -> ASTNodeContent<'a> {
+        (self.0)(node)
+    }
+}
+
+/// Rewrites every node of a tree, bottom-up, with `f`.
+pub fn rewrite<'a>(ast: AST<'a>, f: impl FnMut(ASTNode<'a>) -> ASTNodeContent<'a>) -> AST<'a> {
+    fold_ast(&mut FoldNodes(f), ast)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Combinator {
+    // `A B`, B anywhere under A.
+    Descendant,
+    // `A > B`, B right under A (groupings don't count as a level).
+    Child,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    combinator: Combinator,
+    label: Option<String>,
+    // `None` for the `*` wildcard.
+    rule: Option<String>,
+}
+
+impl Step {
+    fn matches(&self, label: Option<&str>, rule: &str) -> bool {
+        let label_matches = match &self.label {
+            None => true,
+            Some(l) => label == Some(l.as_str()),
+        };
+        let rule_matches = match &self.rule {
+            None => true,
+            Some(r) => r == rule,
+        };
+        label_matches && rule_matches
+    }
+}
+
+/// A path query over nodes, in a CSS-like syntax:
+/// `FnDecl Call` selects every `Call` somewhere under a `FnDecl`,
+/// `BinaryExpr > lhs:*` the nodes captured as `lhs` right under a `BinaryExpr`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    steps: Vec<Step>,
+}
+
+impl Query {
+    pub fn parse(path: &str) -> Result<Query, String> {
+        let mut steps = Vec::new();
+        let mut combinator = Combinator::Descendant;
+
+        for word in path.split_whitespace() {
+            if word == ">" {
+                if steps.is_empty() || combinator == Combinator::Child {
+                    return Err(format!("Misplaced '>' in query '{}'", path));
+                }
+                combinator = Combinator::Child;
+                continue;
+            }
+
+            let (label, rule) = match word.split_once(':') {
+                None => (None, word),
+                Some((l, r)) => (Some(l.to_string()), r),
+            };
+            if rule.is_empty() {
+                return Err(format!("Missing rule name in query step '{}'", word));
+            }
+
+            steps.push(Step {
+                combinator,
+                label,
+                rule: match rule {
+                    "*" => None,
+                    r => Some(r.to_string()),
+                },
+            });
+            combinator = Combinator::Descendant;
+        }
+
+        if steps.is_empty() || combinator == Combinator::Child {
+            return Err(format!("Incomplete query '{}'", path));
+        }
+
+        return Ok(Query { steps });
+    }
+
+    /// Selects the matching nodes under (and including) `root`, in source order.
+    pub fn select<'b, 'a>(&self, root: &'b ASTNode<'a>) -> Vec<&'b ASTNode<'a>> {
+        // Every node once, in pre-order, which is source order.
+        let mut order = vec![(None, root)];
+        order.extend(ast_descendants(root));
+
+        let mut current: Vec<&'b ASTNode<'a>> = Vec::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            // The nodes this step may match, by address.
+            let mut reachable: HashSet<*const ASTNode<'a>> = HashSet::new();
+            for node in &current {
+                match step.combinator {
+                    // Whatever is under a node that's already reachable is too.
+                    Combinator::Descendant if reachable.contains(&(*node as *const _)) => {}
+                    Combinator::Descendant => reachable.extend(
+                        ast_descendants(node)
+                            .into_iter()
+                            .map(|(_, n)| n as *const _),
+                    ),
+                    Combinator::Child => {
+                        reachable.extend(ast_children(node).into_iter().map(|(_, n)| n as *const _))
+                    }
+                }
+            }
+
+            current = order
+                .iter()
+                .filter(|(_, node)| i == 0 || reachable.contains(&(*node as *const _)))
+                .filter(|(label, node)| step.matches(*label, node.matched_with))
+                .map(|(_, node)| *node)
+                .collect();
+        }
+
+        return current;
+    }
+
+    /// Same as `select`, over an owned tree.
+    pub fn select_in(&self, tree: &Tree, root: NodeId) -> Vec<NodeId> {
+        let mut current: Vec<NodeId> = Vec::new();
+
+        for (i, step) in self.steps.iter().enumerate() {
+            let candidates: Vec<NodeId> = if i == 0 {
+                tree.descendants(root)
+            } else {
+                current
+                    .iter()
+                    .flat_map(|n| match step.combinator {
+                        Combinator::Descendant => {
+                            tree.descendants(*n).into_iter().skip(1).collect()
+                        }
+                        Combinator::Child => tree.child_nodes(*n),
+                    })
+                    .collect()
+            };
+
+            let mut next: Vec<NodeId> = candidates
+                .into_iter()
+                .filter(|id| match tree.rule(*id) {
+                    None => false,
+                    Some(rule) => step.matches(tree.label(*id), rule),
+                })
+                .collect();
+            // NodeIds are handed out in pre-order, so sorting keeps source order.
+            next.sort();
+            next.dedup();
+            current = next;
+        }
+
+        return current;
+    }
+}
+
+/// The nodes right under `node`, looking through groupings and labels.
+fn ast_children<'b, 'a>(node: &'b ASTNode<'a>) -> Vec<(Option<&'static str>, &'b ASTNode<'a>)> {
+    fn collect<'b, 'a>(
+        content: &'b ASTNodeContent<'a>,
+        label: Option<&'static str>,
+        out: &mut Vec<(Option<&'static str>, &'b ASTNode<'a>)>,
+    ) {
+        match content {
+            ASTNodeContent::Node(n) => out.push((label, n)),
+            ASTNodeContent::Grouping(items) => {
+                for c in items {
+                    collect(c, None, out);
+                }
+            }
+            ASTNodeContent::Labelled(l, inner) => collect(inner, Some(l), out),
+            _ => {}
+        }
+    }
+
+    let mut out = Vec::new();
+    for c in &node.content {
+        collect(c, None, &mut out);
+    }
+    return out;
+}
+
+/// Every node under `node`, in pre-order, `node` excluded.
+fn ast_descendants<'b, 'a>(node: &'b ASTNode<'a>) -> Vec<(Option<&'static str>, &'b ASTNode<'a>)> {
+    let mut out = Vec::new();
+    for (label, child) in ast_children(node) {
+        out.push((label, child));
+        out.extend(ast_descendants(child));
+    }
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        grammar::{Span, TokenName},
+        parser::{build_tree, tokenize},
+        sexpr::{print_ast, SExprOptions},
+    };
+
+    const SOURCE: &str = "fn f(a) -> (g(a) + h(1))\nf(2) + k(3)";
+
+    /// Calls `check` with the abstract tree of `source`.
+    fn with_ast(source: &str, check: impl FnOnce(AST)) {
+        let tokens = tokenize(source);
+        check(build_tree(source, &tokens, "Program", false, None).unwrap());
+    }
+
+    /// The rule and start of each node, to compare selections.
+    fn described(nodes: &[&ASTNode]) -> Vec<(&'static str, usize)> {
+        nodes
+            .iter()
+            .map(|n| (n.matched_with, n.span().map_or(0, |s| s.start)))
+            .collect()
+    }
+
+    #[derive(Default)]
+    struct Collector {
+        nodes: Vec<&'static str>,
+        labels: Vec<&'static str>,
+        tokens: Vec<String>,
+    }
+
+    impl<'a> Visitor<'a> for Collector {
+        fn visit_node(&mut self, node: &ASTNode<'a>) {
+            self.nodes.push(node.matched_with);
+            walk_node(self, node)
+        }
+
+        fn visit_labelled(&mut self, label: &'static str, content: &ASTNodeContent<'a>) {
+            self.labels.push(label);
+            self.visit_content(content)
+        }
+
+        fn visit_token(&mut self, token: &'a Token<'a>) {
+            self.tokens
+                .push(token.str_content.unwrap_or("").to_string());
+        }
+    }
+
+    #[test]
+    fn visitor_walks_content_in_order() {
+        // A `BinaryExpr` holds its `op` before its operands.
+        with_ast("g(a) + 1", |ast| {
+            let mut collector = Collector::default();
+            walk_ast(&mut collector, &ast);
+
+            assert_eq!(collector.tokens, vec!["+", "g", "a", "1"]);
+            assert_eq!(
+                collector.labels,
+                vec!["op", "lhs", "callee", "name", "args", "name", "rhs", "value"]
+            );
+            assert_eq!(
+                collector.nodes,
+                vec![
+                    "Program",
+                    "Exprs",
+                    "Expr",
+                    "BinaryExpr",
+                    "Call",
+                    "Name",
+                    "Expr",
+                    "Name",
+                    "Literal"
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn visitor_mut_swaps_tokens() {
+        // Every integer becomes the last one in the source.
+        struct Swap<'a>(&'a Token<'a>);
+        impl<'a> VisitorMut<'a> for Swap<'a> {
+            fn visit_token_mut(&mut self, token: &mut &'a Token<'a>) {
+                if token.name == TokenName::IntLiteral {
+                    *token = self.0;
+                }
+            }
+        }
+
+        let source = "[1, 2, 3]";
+        let tokens = tokenize(source);
+        let mut ast = build_tree(source, &tokens, "Program", false, None).unwrap();
+        let last = tokens
+            .iter()
+            .rfind(|t| t.name == TokenName::IntLiteral)
+            .unwrap();
+        walk_ast_mut(&mut Swap(last), &mut ast);
+
+        let mut collector = Collector::default();
+        walk_ast(&mut collector, &ast);
+        assert_eq!(collector.tokens, vec!["3", "3", "3"]);
+    }
+
+    #[test]
+    fn fold_rewrites_bottom_up() {
+        with_ast("1 + 2 + 3", |ast| {
+            // Every sum is replaced by its left operand, innermost first,
+            // so the outer one sees `1` as its lhs.
+            let mut seen = Vec::new();
+            let ast = rewrite(ast, |node| match node.matched_with {
+                "BinaryExpr" => {
+                    seen.push(node.span());
+                    node.get("lhs").cloned().unwrap_or(ASTNodeContent::None)
+                }
+                _ => ASTNodeContent::Node(node),
+            });
+
+            assert_eq!(seen, vec![Some(Span::new(0, 5)), Some(Span::new(0, 9))]);
+            assert_eq!(
+                print_ast(&ast, &SExprOptions::default()),
+                "(n PROGRAM (n EXPRS (n EXPR (n LITERAL value: (t INT_LITERAL 1)))))"
+            );
+        });
+    }
+
+    #[test]
+    fn query_selects_descendants() {
+        with_ast(SOURCE, |ast| {
+            let query = Query::parse("FnDecl Call").unwrap();
+            let selected = query.select(&ast.root);
+            assert_eq!(described(&selected), vec![("Call", 12), ("Call", 19)]);
+
+            let all_calls = Query::parse("Call").unwrap().select(&ast.root);
+            assert_eq!(all_calls.len(), 4);
+        });
+    }
+
+    #[test]
+    fn query_selects_labelled_children() {
+        with_ast(SOURCE, |ast| {
+            let query = Query::parse("BinaryExpr > lhs:*").unwrap();
+            let selected = query.select(&ast.root);
+            assert_eq!(described(&selected), vec![("Call", 12), ("Call", 25)]);
+
+            let callees = Query::parse("FnDecl Call > callee:Name").unwrap();
+            assert_eq!(
+                described(&callees.select(&ast.root)),
+                vec![("Name", 12), ("Name", 19)]
+            );
+        });
+    }
+
+    #[test]
+    fn query_keeps_source_order_across_nested_matches() {
+        // The inner sum is the outer one's lhs, so its operands come first.
+        with_ast("1 + 2 + 3", |ast| {
+            let query = Query::parse("BinaryExpr > *").unwrap();
+            let selected = query.select(&ast.root);
+            assert_eq!(
+                described(&selected),
+                vec![
+                    ("BinaryExpr", 0),
+                    ("Literal", 0),
+                    ("Literal", 4),
+                    ("Literal", 8)
+                ]
+            );
+
+            // The owned tree agrees.
+            let tree = Tree::from_ast(&ast);
+            let ids = query.select_in(&tree, tree.root());
+            let spans: Vec<_> = ids.iter().map(|id| tree.span(*id)).collect();
+            let expected: Vec<_> = selected.iter().map(|n| n.span()).collect();
+            assert_eq!(spans, expected);
+        });
+    }
+
+    #[test]
+    fn query_rejects_incomplete_paths() {
+        assert!(Query::parse("").is_err());
+        assert!(Query::parse("> Call").is_err());
+        assert!(Query::parse("Call >").is_err());
+        assert!(Query::parse("Call > > Name").is_err());
+        assert!(Query::parse("lhs:").is_err());
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use crate::{
+    interp::{intrinsic, RuntimeError, TraceFrame, Value, INTRINSICS, MAX_CALL_DEPTH},
+    ir::INTRINSIC_PREFIX,
+    nbbc::{Constant, Instruction, Program},
+};
+
+// The virtual machine running `nbbc` programs.
+//
+// Calls don't recurse on the Rust stack: each one pushes a frame holding
+// its function, where it is in its code and its locals, and `Return` pops
+// it, leaving the result on the shared value stack. Values and intrinsics
+// are the interpreter's, so a program prints the same thing whichever of
+// the two runs it. There are no spans in bytecode, so errors only carry
+// the stack trace.
+
+struct Frame {
+    function: usize,
+    pc: usize,
+    locals: Vec<Value>,
+}
+
+pub struct Vm<'a> {
+    program: &'a Program,
+    // Function indices by symbol, for closures.
+    functions: HashMap<&'a str, usize>,
+    constants: Vec<Value>,
+    // `None` until the global's `let` runs.
+    globals: Vec<Option<Value>>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+}
+
+/// Runs a program's top-level code, returning its last value.
+pub fn run(program: &Program) -> Result<Value, RuntimeError> {
+    Vm::new(program).run()
+}
+
+impl Vm<'_> {
+    pub fn new(program: &Program) -> Vm<'_> {
+        let constants = program
+            .constants
+            .iter()
+            .map(|c| match c {
+                Constant::Int(i) => Value::Int(*i),
+                Constant::Float(x) => Value::Float(*x),
+                Constant::String(s) => Value::String(s.as_str().into()),
+            })
+            .collect();
+        Vm {
+            program,
+            functions: program
+                .functions
+                .iter()
+                .enumerate()
+                .map(|(i, f)| (f.name.as_str(), i))
+                .collect(),
+            constants,
+            globals: vec![None; program.globals.len()],
+            stack: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn run(&mut self) -> Result<Value, RuntimeError> {
+        self.stack.clear();
+        self.frames.clear();
+        self.enter(self.program.entry(), Vec::new())?;
+
+        loop {
+            let frame = self.frames.last_mut().expect("there's always a frame");
+            let code = &self.program.functions[frame.function].code;
+            let instruction = match code.get(frame.pc) {
+                Some(instruction) => *instruction,
+                None => return Err(self.error("Ran past the end of the function.")),
+            };
+            frame.pc += 1;
+
+            match instruction {
+                Instruction::Const(c) => {
+                    let value = self.constant(c)?;
+                    self.stack.push(value);
+                }
+                Instruction::Unit => self.stack.push(Value::Unit),
+                Instruction::Bool(x) => self.stack.push(Value::Bool(x)),
+                Instruction::Load(l) => {
+                    let value = self.local(l)?.clone();
+                    self.stack.push(value);
+                }
+                Instruction::Store(l) => {
+                    let value = self.pop()?;
+                    *self.local(l)? = value;
+                }
+                Instruction::LoadGlobal(g) => match self.globals.get(g as usize) {
+                    Some(Some(value)) => self.stack.push(value.clone()),
+                    Some(None) => {
+                        let symbol = &self.program.globals[g as usize];
+                        let message = format!("'{}' is used before its `let` ran.", symbol);
+                        return Err(self.error(message));
+                    }
+                    None => return Err(self.error(format!("There's no global @{}.", g))),
+                },
+                Instruction::StoreGlobal(g) => {
+                    let value = self.pop()?;
+                    match self.globals.get_mut(g as usize) {
+                        Some(global) => *global = Some(value),
+                        None => return Err(self.error(format!("There's no global @{}.", g))),
+                    }
+                }
+                Instruction::Dup => {
+                    let value = self.peek()?.clone();
+                    self.stack.push(value);
+                }
+                Instruction::Pop => {
+                    self.pop()?;
+                }
+                Instruction::Call { function, args } => {
+                    let args = self.pop_n(args as usize)?;
+                    self.enter(function as usize, args)?;
+                }
+                Instruction::Intrinsic { id, args } => {
+                    let args = self.pop_n(args as usize)?;
+                    let name = match INTRINSICS.get(id as usize) {
+                        Some(name) => name,
+                        None => return Err(self.error(format!("There's no intrinsic #{}.", id))),
+                    };
+                    let value = intrinsic(name, &args, true).map_err(|m| self.error(m))?;
+                    self.stack.push(value);
+                }
+                Instruction::Closure { symbol, captures } => {
+                    let captures = self.pop_n(captures as usize)?;
+                    let symbol = match self.program.constant_string(symbol) {
+                        Some(symbol) => symbol,
+                        None => return Err(self.error("A closure needs a string constant.")),
+                    };
+                    self.stack.push(Value::Closure {
+                        symbol: symbol.into(),
+                        captures: Rc::new(captures),
+                    });
+                }
+                Instruction::CallValue { args } => {
+                    let args = self.pop_n(args as usize)?;
+                    let callee = self.pop()?;
+                    self.call_value(&callee, args)?;
+                }
+                Instruction::List(n) => {
+                    let items = self.pop_n(n as usize)?;
+                    self.stack.push(Value::List(Rc::new(items)));
+                }
+                Instruction::Record(n) => {
+                    let pairs = self.pop_n(2 * n as usize)?;
+                    let mut fields = BTreeMap::new();
+                    for pair in pairs.chunks(2) {
+                        match &pair[0] {
+                            Value::String(key) => fields.insert(key.to_string(), pair[1].clone()),
+                            _ => return Err(self.error("Record keys must be strings.")),
+                        };
+                    }
+                    self.stack.push(Value::Record(Rc::new(fields)));
+                }
+                Instruction::GetField(key) => {
+                    let key = match self.program.constant_string(key) {
+                        Some(key) => key,
+                        None => return Err(self.error("A field name must be a string constant.")),
+                    };
+                    let value = match self.pop()? {
+                        Value::Record(fields) => match fields.get(key) {
+                            Some(value) => value.clone(),
+                            None => {
+                                let message = format!("No field '{}' in this record.", key);
+                                return Err(self.error(message));
+                            }
+                        },
+                        other => {
+                            let message =
+                                format!("Can't read '.{}' from a {}.", key, other.type_name());
+                            return Err(self.error(message));
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                Instruction::Push(l) => {
+                    let value = self.pop()?;
+                    match self.local(l)? {
+                        Value::List(items) => Rc::make_mut(items).push(value),
+                        _ => return Err(self.error("Can only push onto a list.")),
+                    }
+                }
+                Instruction::Next { list, index, done } => {
+                    let at = match self.local(index)? {
+                        Value::Int(i) => *i as usize,
+                        _ => return Err(self.error("A loop index must be an int.")),
+                    };
+                    let item = match self.local(list)? {
+                        Value::List(items) => items.get(at).cloned(),
+                        other => {
+                            let message =
+                                format!("Can't loop over a value of type {}.", other.type_name());
+                            return Err(self.error(message));
+                        }
+                    };
+                    match item {
+                        Some(item) => {
+                            *self.local(index)? = Value::Int(at as i64 + 1);
+                            self.stack.push(item);
+                        }
+                        None => self.jump(done),
+                    }
+                }
+                Instruction::Jump(to) => self.jump(to),
+                Instruction::Return => {
+                    let value = self.pop()?;
+                    self.frames.pop();
+                    if self.frames.is_empty() {
+                        return Ok(value);
+                    }
+                    self.stack.push(value);
+                }
+            }
+        }
+    }
+
+    /// Calls a closure, like `CallValue` does.
+    fn call_value(&mut self, callee: &Value, args: Vec<Value>) -> Result<(), RuntimeError> {
+        let (symbol, captures) = match callee {
+            Value::Closure { symbol, captures } => (symbol, captures),
+            other => {
+                let message = format!("Can't call a value of type {}.", other.type_name());
+                return Err(self.error(message));
+            }
+        };
+        let mut all = captures.as_ref().clone();
+        all.extend(args);
+
+        if let Some(name) = symbol.strip_prefix(INTRINSIC_PREFIX) {
+            let value = intrinsic(name, &all, true).map_err(|m| self.error(m))?;
+            self.stack.push(value);
+            return Ok(());
+        }
+        return match self.functions.get(symbol.as_ref()) {
+            Some(function) => self.enter(*function, all),
+            None => Err(self.error(format!("Unknown function '{}'.", symbol))),
+        };
+    }
+
+    /// Pushes a frame for `function`, its first locals being `args`.
+    fn enter(&mut self, function: usize, mut args: Vec<Value>) -> Result<(), RuntimeError> {
+        let code = match self.program.functions.get(function) {
+            Some(code) => code,
+            None => return Err(self.error(format!("There's no function #{}.", function))),
+        };
+        if args.len() != code.arity as usize {
+            let message = format!(
+                "'{}' takes {} arguments but got {}.",
+                code.name,
+                code.arity,
+                args.len()
+            );
+            return Err(self.error(message));
+        }
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            let message = format!("Stack overflow, more than {} calls deep.", MAX_CALL_DEPTH);
+            return Err(self.error(message));
+        }
+
+        args.resize(code.locals.max(code.arity) as usize, Value::Unit);
+        self.frames.push(Frame {
+            function,
+            pc: 0,
+            locals: args,
+        });
+        return Ok(());
+    }
+
+    fn jump(&mut self, to: u32) {
+        self.frames.last_mut().expect("there's always a frame").pc = to as usize;
+    }
+
+    fn constant(&self, c: u32) -> Result<Value, RuntimeError> {
+        match self.constants.get(c as usize) {
+            Some(value) => Ok(value.clone()),
+            None => Err(self.error(format!("There's no constant #{}.", c))),
+        }
+    }
+
+    fn local(&mut self, l: u16) -> Result<&mut Value, RuntimeError> {
+        let count = self.frames.last().map_or(0, |f| f.locals.len());
+        if l as usize >= count {
+            return Err(self.error(format!("There's no local {}.", l)));
+        }
+        let frame = self.frames.last_mut().expect("there's always a frame");
+        return Ok(&mut frame.locals[l as usize]);
+    }
+
+    fn pop(&mut self) -> Result<Value, RuntimeError> {
+        match self.stack.pop() {
+            Some(value) => Ok(value),
+            None => Err(self.error("The stack is empty.")),
+        }
+    }
+
+    fn peek(&self) -> Result<&Value, RuntimeError> {
+        match self.stack.last() {
+            Some(value) => Ok(value),
+            None => Err(self.error("The stack is empty.")),
+        }
+    }
+
+    /// The top `n` values, the deepest first.
+    fn pop_n(&mut self, n: usize) -> Result<Vec<Value>, RuntimeError> {
+        if n > self.stack.len() {
+            return Err(self.error("The stack is empty."));
+        }
+        let at = self.stack.len() - n;
+        return Ok(self.stack.split_off(at));
+    }
+
+    fn error(&self, message: impl Into<String>) -> RuntimeError {
+        RuntimeError {
+            message: message.into(),
+            span: None,
+            trace: self
+                .frames
+                .iter()
+                .rev()
+                .map(|f| TraceFrame {
+                    symbol: self.program.functions[f.function].name.clone(),
+                    call_site: None,
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,1735 @@
+use std::collections::HashMap;
+
+use wasm_encoder::{
+    BlockType, CodeSection, ConstExpr, DataSection, ElementSection, Elements, EntityType,
+    ExportKind, ExportSection, Function as WasmFunction, FunctionSection, GlobalSection,
+    GlobalType, ImportSection, Instruction as I, MemArg, MemorySection, MemoryType, NameMap,
+    NameSection, RefType, TableSection, TableType, TypeSection, ValType,
+};
+
+use crate::{
+    build::Backend,
+    ir::{intrinsic, type_symbol, Function, IRKind, IRNode, Literal, Module, INTRINSIC_PREFIX},
+    mono::{field_names, monomorphize},
+    types::Type,
+};
+
+// The WebAssembly backend: a `.wasm` module exporting `main` and its memory,
+// printing through WASI's `fd_write`.
+//
+// Every nano value is an `i64`: ints as they are, floats by their bits,
+// booleans as 0 or 1, unit as 0, and everything else as an address in the
+// linear memory, which a bump allocator hands out and never takes back.
+//
+//   string   [length][bytes...]
+//   list     [length][item]...
+//   record   [field]...          in the order of the field names
+//   closure  [table index][name][capture]...
+//
+// The module is monomorphized first, so printing and comparing values is
+// compiled for their exact types. Closures are called through a table of
+// "thunks" that take the closure itself first and unpack its captures, so
+// calling one only depends on how many arguments it takes. Unlike the
+// interpreter, integer overflow wraps, and floats print with 15 significant
+// digits at most.
+
+/// The `wasm` target.
+pub struct Wasm;
+
+impl Backend for Wasm {
+    fn emit(&self, module: &Module) -> Result<Vec<u8>, String> {
+        return compile(module);
+    }
+}
+
+/// The `wat` target, the same module in the text format.
+pub struct Wat;
+
+impl Backend for Wat {
+    fn emit(&self, module: &Module) -> Result<Vec<u8>, String> {
+        return Ok(to_wat(&compile(module)?)?.into_bytes());
+    }
+}
+
+/// The binary module for `module`, checked by a validator.
+pub fn compile(module: &Module) -> Result<Vec<u8>, String> {
+    let module = monomorphize(module);
+    let bytes = Compiler::new(&module).finish()?;
+    if let Err(e) = wasmparser::validate(&bytes) {
+        return Err(format!("The wasm backend made an invalid module: {}.", e));
+    }
+    return Ok(bytes);
+}
+
+/// A binary module in the text format.
+pub fn to_wat(bytes: &[u8]) -> Result<String, String> {
+    return wasmprinter::print_bytes(bytes)
+        .map_err(|e| format!("Couldn't print the module: {}.", e));
+}
+
+// `fd_write`'s arguments and result live at the start of the memory, then
+// what's printed, until a newline or until it's full.
+const IOVEC: i32 = 0;
+const NWRITTEN: i32 = 8;
+const OUT_BUFFER: u64 = 16;
+const OUT_CAPACITY: i64 = 1024;
+const DATA_START: u64 = OUT_BUFFER + OUT_CAPACITY as u64;
+
+// The functions before the module's own, and the globals before nano's.
+const FD_WRITE: u32 = 0;
+const HEAP: u32 = 0;
+const OUT_LENGTH: u32 = 1;
+
+/// The functions the generated code relies on.
+#[derive(Debug, Clone)]
+enum Runtime {
+    Alloc,
+    Flush,
+    OutByte,
+    OutString,
+    OutUint,
+    OutInt,
+    OutFloat,
+    Concat,
+    StringEq,
+    FloatEq,
+    Range,
+    // `Display` for values of a type, strings quoted.
+    Write(Type),
+    // `is` on two lists or records, item by item and field by field.
+    Eq(Type),
+    Print(Type),
+}
+
+impl Runtime {
+    fn name(&self) -> String {
+        match self {
+            Runtime::Alloc => intrinsic("alloc"),
+            Runtime::Flush => intrinsic("flush"),
+            Runtime::OutByte => intrinsic("out_byte"),
+            Runtime::OutString => intrinsic("out_string"),
+            Runtime::OutUint => intrinsic("out_uint"),
+            Runtime::OutInt => intrinsic("out_int"),
+            Runtime::OutFloat => intrinsic("out_float"),
+            Runtime::Concat => intrinsic("concat"),
+            Runtime::StringEq => intrinsic("string_eq"),
+            Runtime::FloatEq => intrinsic("float_eq"),
+            Runtime::Range => intrinsic("range"),
+            Runtime::Write(ty) => intrinsic(&format!("write<{}>", type_symbol(ty))),
+            Runtime::Eq(ty) => intrinsic(&format!("eq<{}>", type_symbol(ty))),
+            Runtime::Print(ty) => intrinsic(&format!("print<{}>", type_symbol(ty))),
+        }
+    }
+
+    fn arity(&self) -> usize {
+        match self {
+            Runtime::Flush => 0,
+            Runtime::Alloc
+            | Runtime::OutByte
+            | Runtime::OutString
+            | Runtime::OutUint
+            | Runtime::OutInt
+            | Runtime::OutFloat
+            | Runtime::Write(_)
+            | Runtime::Print(_) => 1,
+            Runtime::Concat
+            | Runtime::StringEq
+            | Runtime::FloatEq
+            | Runtime::Range
+            | Runtime::Eq(_) => 2,
+        }
+    }
+}
+
+/// A function whose index is known but whose body is still to be compiled.
+enum Pending<'a> {
+    User(&'a Function),
+    // The table entry `call_indirect` reaches for closures of `symbol` at type `ty`.
+    Thunk { symbol: String, ty: Type },
+    Runtime(Runtime),
+}
+
+/// The code of a function being compiled.
+struct Body {
+    params: u32,
+    locals: Vec<ValType>,
+    // The locals in scope by name, searched from the end.
+    scopes: Vec<(String, u32)>,
+    code: Vec<I<'static>>,
+}
+
+impl Body {
+    fn new(params: u32) -> Body {
+        Body {
+            params,
+            locals: Vec::new(),
+            scopes: Vec::new(),
+            code: Vec::new(),
+        }
+    }
+
+    fn local(&mut self, ty: ValType) -> u32 {
+        self.locals.push(ty);
+        return self.params + self.locals.len() as u32 - 1;
+    }
+
+    fn emit(&mut self, instruction: I<'static>) {
+        self.code.push(instruction);
+    }
+
+    fn finish(mut self) -> WasmFunction {
+        self.code.push(I::End);
+        let mut function = WasmFunction::new(self.locals.iter().map(|t| (1, *t)));
+        for instruction in &self.code {
+            function.instruction(instruction);
+        }
+        return function;
+    }
+}
+
+fn word(offset: u64) -> MemArg {
+    MemArg {
+        offset,
+        align: 3,
+        memory_index: 0,
+    }
+}
+
+fn byte(offset: u64) -> MemArg {
+    MemArg {
+        offset,
+        align: 0,
+        memory_index: 0,
+    }
+}
+
+fn int32(offset: u64) -> MemArg {
+    MemArg {
+        offset,
+        align: 2,
+        memory_index: 0,
+    }
+}
+
+struct Compiler<'a> {
+    module: &'a Module,
+    signatures: Vec<(Vec<ValType>, Vec<ValType>)>,
+    // Name and signature of each function after the imports.
+    functions: Vec<(String, u32)>,
+    bodies: Vec<Option<WasmFunction>>,
+    by_name: HashMap<String, u32>,
+    pending: Vec<(u32, Pending<'a>)>,
+    // The functions closures call, by table index.
+    table: Vec<u32>,
+    data: Vec<u8>,
+    strings: HashMap<String, i64>,
+    globals: HashMap<String, u32>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(module: &'a Module) -> Compiler<'a> {
+        let mut compiler = Compiler {
+            module,
+            signatures: Vec::new(),
+            functions: Vec::new(),
+            bodies: Vec::new(),
+            by_name: HashMap::new(),
+            pending: Vec::new(),
+            table: Vec::new(),
+            data: Vec::new(),
+            strings: HashMap::new(),
+            globals: HashMap::new(),
+        };
+        for (i, (symbol, _)) in module.globals.iter().enumerate() {
+            compiler
+                .globals
+                .insert(symbol.clone(), OUT_LENGTH + 1 + i as u32);
+        }
+        return compiler;
+    }
+
+    fn signature(&mut self, params: Vec<ValType>, results: Vec<ValType>) -> u32 {
+        let signature = (params, results);
+        if let Some(i) = self.signatures.iter().position(|s| *s == signature) {
+            return i as u32;
+        }
+        self.signatures.push(signature);
+        return self.signatures.len() as u32 - 1;
+    }
+
+    /// The signature of functions taking `arity` values.
+    fn values(&mut self, arity: usize) -> u32 {
+        return self.signature(vec![ValType::I64; arity], vec![ValType::I64]);
+    }
+
+    /// The index of the function called `name`, to be compiled from `pending` if it's new.
+    fn function(
+        &mut self,
+        name: String,
+        arity: usize,
+        pending: impl FnOnce() -> Pending<'a>,
+    ) -> u32 {
+        if let Some(index) = self.by_name.get(&name) {
+            return *index;
+        }
+        let signature = self.values(arity);
+        let index = 1 + self.functions.len() as u32;
+        self.functions.push((name.clone(), signature));
+        self.bodies.push(None);
+        self.by_name.insert(name, index);
+        self.pending.push((index, pending()));
+        return index;
+    }
+
+    fn runtime(&mut self, runtime: Runtime) -> u32 {
+        return self.function(runtime.name(), runtime.arity(), || {
+            Pending::Runtime(runtime)
+        });
+    }
+
+    fn user(&mut self, symbol: &str) -> Result<u32, String> {
+        let function = match self.module.function(symbol) {
+            None => return Err(format!("The wasm backend can't find '{}'.", symbol)),
+            Some(f) => f,
+        };
+        let arity = function.captures.len() + function.params.len();
+        return Ok(self.function(symbol.to_string(), arity, || Pending::User(function)));
+    }
+
+    /// The table index of the thunk closures of `symbol` are called through.
+    fn thunk(&mut self, symbol: &str, ty: &Type) -> u32 {
+        let arity = match ty {
+            Type::Fn(params, _) => params.len(),
+            _ => 0,
+        };
+        let name = format!("{}$closure<{}>", symbol, type_symbol(ty));
+        let index = self.function(name, arity + 1, || Pending::Thunk {
+            symbol: symbol.to_string(),
+            ty: ty.clone(),
+        });
+        if let Some(slot) = self.table.iter().position(|f| *f == index) {
+            return slot as u32;
+        }
+        self.table.push(index);
+        return self.table.len() as u32 - 1;
+    }
+
+    /// The address of a string with `text`, in the data section.
+    fn string(&mut self, text: &str) -> i64 {
+        if let Some(address) = self.strings.get(text) {
+            return *address;
+        }
+        while !self.data.len().is_multiple_of(8) {
+            self.data.push(0);
+        }
+        let address = (DATA_START + self.data.len() as u64) as i64;
+        self.data
+            .extend_from_slice(&(text.len() as i64).to_le_bytes());
+        self.data.extend_from_slice(text.as_bytes());
+        self.strings.insert(text.to_string(), address);
+        return address;
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, String> {
+        let main_signature = self.signature(Vec::new(), Vec::new());
+        let main = 1 + self.functions.len() as u32;
+        self.functions.push(("main".to_string(), main_signature));
+        self.bodies.push(None);
+
+        let mut body = Body::new(0);
+        for node in &self.module.body {
+            self.expr(&mut body, node)?;
+            body.emit(I::Drop);
+        }
+        let flush = self.runtime(Runtime::Flush);
+        body.emit(I::Call(flush));
+        body.emit(I::Drop);
+        self.bodies[(main - 1) as usize] = Some(body.finish());
+
+        while let Some((index, pending)) = self.pending.pop() {
+            let function = match pending {
+                Pending::User(function) => self.user_body(function)?,
+                Pending::Thunk { symbol, ty } => self.thunk_body(&symbol, &ty)?,
+                Pending::Runtime(runtime) => self.runtime_body(&runtime),
+            };
+            self.bodies[(index - 1) as usize] = Some(function);
+        }
+
+        return Ok(self.assemble(main));
+    }
+
+    fn assemble(mut self, main: u32) -> Vec<u8> {
+        let fd_write = self.signature(vec![ValType::I32; 4], vec![ValType::I32]);
+
+        let mut types = TypeSection::new();
+        for (params, results) in &self.signatures {
+            types
+                .ty()
+                .function(params.iter().copied(), results.iter().copied());
+        }
+
+        let mut imports = ImportSection::new();
+        imports.import(
+            "wasi_snapshot_preview1",
+            "fd_write",
+            EntityType::Function(fd_write),
+        );
+
+        let mut functions = FunctionSection::new();
+        let mut code = CodeSection::new();
+        let mut names = NameMap::new();
+        names.append(FD_WRITE, "fd_write");
+        for (i, ((name, signature), body)) in self.functions.iter().zip(&self.bodies).enumerate() {
+            functions.function(*signature);
+            if let Some(body) = body {
+                code.function(body);
+            }
+            names.append(1 + i as u32, name);
+        }
+
+        let mut tables = TableSection::new();
+        tables.table(TableType {
+            element_type: RefType::FUNCREF,
+            table64: false,
+            minimum: self.table.len() as u64,
+            maximum: None,
+            shared: false,
+        });
+
+        let heap_start = (DATA_START + self.data.len() as u64 + 7) & !7;
+        let mut memories = MemorySection::new();
+        memories.memory(MemoryType {
+            minimum: heap_start / 65536 + 1,
+            maximum: None,
+            memory64: false,
+            shared: false,
+            page_size_log2: None,
+        });
+
+        let mut globals = GlobalSection::new();
+        let variable = GlobalType {
+            val_type: ValType::I64,
+            mutable: true,
+            shared: false,
+        };
+        globals.global(variable, &ConstExpr::i64_const(heap_start as i64));
+        globals.global(variable, &ConstExpr::i64_const(0));
+        for _ in &self.module.globals {
+            globals.global(variable, &ConstExpr::i64_const(0));
+        }
+
+        let mut exports = ExportSection::new();
+        exports.export("main", ExportKind::Func, main);
+        exports.export("memory", ExportKind::Memory, 0);
+
+        let mut elements = ElementSection::new();
+        elements.active(
+            Some(0),
+            &ConstExpr::i32_const(0),
+            Elements::Functions(std::mem::take(&mut self.table).into()),
+        );
+
+        let mut data = DataSection::new();
+        data.active(
+            0,
+            &ConstExpr::i32_const(DATA_START as i32),
+            self.data.iter().copied(),
+        );
+
+        let mut name_section = NameSection::new();
+        name_section.functions(&names);
+
+        let mut module = wasm_encoder::Module::new();
+        module
+            .section(&types)
+            .section(&imports)
+            .section(&functions)
+            .section(&tables)
+            .section(&memories)
+            .section(&globals)
+            .section(&exports)
+            .section(&elements)
+            .section(&code)
+            .section(&data)
+            .section(&name_section);
+        return module.finish();
+    }
+
+    fn user_body(&mut self, function: &Function) -> Result<WasmFunction, String> {
+        let names = function.captures.iter().chain(function.params.iter());
+        let mut body = Body::new(names.clone().count() as u32);
+        for (i, (name, _)) in names.enumerate() {
+            body.scopes.push((name.clone(), i as u32));
+        }
+        self.expr(&mut body, &function.body)?;
+        return Ok(body.finish());
+    }
+
+    /// `(closure, args...)`, unpacking the closure's captures before `args`.
+    fn thunk_body(&mut self, symbol: &str, ty: &Type) -> Result<WasmFunction, String> {
+        let (params, ret) = match ty {
+            Type::Fn(params, ret) => (params.clone(), ret.as_ref().clone()),
+            _ => (Vec::new(), Type::Unit),
+        };
+        let mut body = Body::new(1 + params.len() as u32);
+
+        if let Some(name) = symbol.strip_prefix(INTRINSIC_PREFIX) {
+            for i in 0..params.len() {
+                body.emit(I::LocalGet(1 + i as u32));
+            }
+            self.intrinsic(&mut body, name, &params, &ret)?;
+            return Ok(body.finish());
+        }
+
+        let function = self.user(symbol)?;
+        let captures = self
+            .module
+            .function(symbol)
+            .map(|f| f.captures.len())
+            .unwrap_or(0);
+        for i in 0..captures {
+            body.emit(I::LocalGet(0));
+            body.emit(I::I32WrapI64);
+            body.emit(I::I64Load(word(16 + 8 * i as u64)));
+        }
+        for i in 0..params.len() {
+            body.emit(I::LocalGet(1 + i as u32));
+        }
+        body.emit(I::Call(function));
+        return Ok(body.finish());
+    }
+
+    /// Compiles `node`, leaving its value on the stack.
+    fn expr(&mut self, b: &mut Body, node: &IRNode) -> Result<(), String> {
+        match &node.kind {
+            IRKind::Literal(literal) => {
+                let value = match literal {
+                    Literal::Unit => 0,
+                    Literal::Int(i) => *i,
+                    Literal::Float(x) => x.to_bits() as i64,
+                    Literal::Bool(x) => *x as i64,
+                    Literal::String(s) => self.string(s),
+                };
+                b.emit(I::I64Const(value));
+            }
+            IRKind::Local(name) => {
+                let local = b.scopes.iter().rev().find(|(n, _)| n == name);
+                match local {
+                    Some((_, index)) => b.emit(I::LocalGet(*index)),
+                    None => return Err(format!("The wasm backend can't find local '{}'.", name)),
+                }
+            }
+            IRKind::Global(symbol) => {
+                let global = self.global(symbol)?;
+                b.emit(I::GlobalGet(global));
+            }
+            IRKind::SetGlobal { symbol, value } => {
+                let global = self.global(symbol)?;
+                self.expr(b, value)?;
+                b.emit(I::GlobalSet(global));
+                b.emit(I::GlobalGet(global));
+            }
+            IRKind::Let { name, value } => {
+                self.expr(b, value)?;
+                let local = b.local(ValType::I64);
+                b.emit(I::LocalTee(local));
+                b.scopes.push((name.clone(), local));
+            }
+            IRKind::Block(items) => {
+                let depth = b.scopes.len();
+                if items.is_empty() {
+                    b.emit(I::I64Const(0));
+                }
+                for (i, item) in items.iter().enumerate() {
+                    self.expr(b, item)?;
+                    if i + 1 < items.len() {
+                        b.emit(I::Drop);
+                    }
+                }
+                b.scopes.truncate(depth);
+            }
+            IRKind::FnRef { symbol, captures } => {
+                let table_index = self.thunk(symbol, &node.ty);
+                let name = symbol.split('<').next().unwrap_or(symbol).to_string();
+                let name = self.string(&name);
+
+                let closure = self.alloc(b, 16 + 8 * captures.len() as i64);
+                self.store(b, closure, 0, |_, b| {
+                    b.emit(I::I64Const(table_index as i64));
+                    Ok(())
+                })?;
+                self.store(b, closure, 8, |_, b| {
+                    b.emit(I::I64Const(name));
+                    Ok(())
+                })?;
+                for (i, capture) in captures.iter().enumerate() {
+                    self.store(b, closure, 16 + 8 * i as u64, |c, b| c.expr(b, capture))?;
+                }
+                b.emit(I::LocalGet(closure));
+            }
+            IRKind::SymbolCall { symbol, args } => {
+                for arg in args {
+                    self.expr(b, arg)?;
+                }
+                match symbol.strip_prefix(INTRINSIC_PREFIX) {
+                    Some(name) => {
+                        let types: Vec<Type> = args.iter().map(|a| a.ty.clone()).collect();
+                        self.intrinsic(b, name, &types, &node.ty)?;
+                    }
+                    None => {
+                        let function = self.user(symbol)?;
+                        b.emit(I::Call(function));
+                    }
+                }
+            }
+            IRKind::Call { callee, args } => {
+                self.expr(b, callee)?;
+                let closure = b.local(ValType::I64);
+                b.emit(I::LocalTee(closure));
+                for arg in args {
+                    self.expr(b, arg)?;
+                }
+                b.emit(I::LocalGet(closure));
+                b.emit(I::I32WrapI64);
+                b.emit(I::I64Load(word(0)));
+                b.emit(I::I32WrapI64);
+                let signature = self.values(args.len() + 1);
+                b.emit(I::CallIndirect {
+                    type_index: signature,
+                    table_index: 0,
+                });
+            }
+            IRKind::List(items) => {
+                let list = self.alloc(b, 8 + 8 * items.len() as i64);
+                self.store(b, list, 0, |_, b| {
+                    b.emit(I::I64Const(items.len() as i64));
+                    Ok(())
+                })?;
+                for (i, item) in items.iter().enumerate() {
+                    self.store(b, list, 8 + 8 * i as u64, |c, b| c.expr(b, item))?;
+                }
+                b.emit(I::LocalGet(list));
+            }
+            IRKind::Record(fields) => {
+                let keys = field_names(&node.ty, fields.iter().map(|(k, _)| k.clone()));
+                let record = self.alloc(b, 8 * keys.len().max(1) as i64);
+                for (key, value) in fields {
+                    let slot = match keys.iter().position(|k| k == key) {
+                        None => {
+                            return Err(format!("The wasm backend can't find field '{}'.", key))
+                        }
+                        Some(slot) => slot,
+                    };
+                    self.store(b, record, 8 * slot as u64, |c, b| c.expr(b, value))?;
+                }
+                b.emit(I::LocalGet(record));
+            }
+            IRKind::GetField { record, key } => {
+                let keys = field_names(&record.ty, std::iter::empty());
+                let slot = match keys.iter().position(|k| k == key) {
+                    None => return Err(format!("The wasm backend can't find field '{}'.", key)),
+                    Some(slot) => slot,
+                };
+                self.expr(b, record)?;
+                b.emit(I::I32WrapI64);
+                b.emit(I::I64Load(word(8 * slot as u64)));
+            }
+            IRKind::Loop {
+                var,
+                iterable,
+                body,
+                collect,
+            } => self.for_loop(b, var, iterable, body, *collect)?,
+            IRKind::Comptime(value) => self.expr(b, value)?,
+            IRKind::Import { path } => {
+                return Err(format!(
+                    "The wasm backend can't compile the import of '{}'.",
+                    path
+                ));
+            }
+        }
+        return Ok(());
+    }
+
+    fn global(&self, symbol: &str) -> Result<u32, String> {
+        match self.globals.get(symbol) {
+            Some(global) => Ok(*global),
+            None => Err(format!("The wasm backend can't find global '{}'.", symbol)),
+        }
+    }
+
+    /// Allocates `size` bytes, returning the local holding their address.
+    fn alloc(&mut self, b: &mut Body, size: i64) -> u32 {
+        let alloc = self.runtime(Runtime::Alloc);
+        let local = b.local(ValType::I64);
+        b.emit(I::I64Const(size));
+        b.emit(I::Call(alloc));
+        b.emit(I::LocalSet(local));
+        return local;
+    }
+
+    /// Stores what `value` compiles to at `offset` from the address in `local`.
+    fn store(
+        &mut self,
+        b: &mut Body,
+        local: u32,
+        offset: u64,
+        value: impl FnOnce(&mut Self, &mut Body) -> Result<(), String>,
+    ) -> Result<(), String> {
+        b.emit(I::LocalGet(local));
+        b.emit(I::I32WrapI64);
+        value(self, b)?;
+        b.emit(I::I64Store(word(offset)));
+        return Ok(());
+    }
+
+    fn for_loop(
+        &mut self,
+        b: &mut Body,
+        var: &str,
+        iterable: &IRNode,
+        body: &IRNode,
+        collect: bool,
+    ) -> Result<(), String> {
+        let list = b.local(ValType::I64);
+        let length = b.local(ValType::I64);
+        let i = b.local(ValType::I64);
+        let item = b.local(ValType::I64);
+        self.expr(b, iterable)?;
+        b.emit(I::LocalTee(list));
+        b.emit(I::I32WrapI64);
+        b.emit(I::I64Load(word(0)));
+        b.emit(I::LocalSet(length));
+        b.emit(I::I64Const(0));
+        b.emit(I::LocalSet(i));
+
+        let results = b.local(ValType::I64);
+        if collect {
+            let alloc = self.runtime(Runtime::Alloc);
+            b.emit(I::LocalGet(length));
+            b.emit(I::I64Const(8));
+            b.emit(I::I64Mul);
+            b.emit(I::I64Const(8));
+            b.emit(I::I64Add);
+            b.emit(I::Call(alloc));
+            b.emit(I::LocalTee(results));
+            b.emit(I::I32WrapI64);
+            b.emit(I::LocalGet(length));
+            b.emit(I::I64Store(word(0)));
+        }
+
+        b.emit(I::Block(BlockType::Empty));
+        b.emit(I::Loop(BlockType::Empty));
+        b.emit(I::LocalGet(i));
+        b.emit(I::LocalGet(length));
+        b.emit(I::I64GeU);
+        b.emit(I::BrIf(1));
+
+        emit_item_address(b, list, i);
+        b.emit(I::I64Load(word(8)));
+        b.emit(I::LocalSet(item));
+        let depth = b.scopes.len();
+        b.scopes.push((var.to_string(), item));
+        if collect {
+            emit_item_address(b, results, i);
+            self.expr(b, body)?;
+            b.emit(I::I64Store(word(8)));
+        } else {
+            self.expr(b, body)?;
+            b.emit(I::Drop);
+        }
+        b.scopes.truncate(depth);
+
+        b.emit(I::LocalGet(i));
+        b.emit(I::I64Const(1));
+        b.emit(I::I64Add);
+        b.emit(I::LocalSet(i));
+        b.emit(I::Br(0));
+        b.emit(I::End);
+        b.emit(I::End);
+
+        match collect {
+            true => b.emit(I::LocalGet(results)),
+            false => b.emit(I::I64Const(0)),
+        }
+        return Ok(());
+    }
+
+    /// Compiles an intrinsic, whose arguments are already on the stack.
+    fn intrinsic(
+        &mut self,
+        b: &mut Body,
+        name: &str,
+        args: &[Type],
+        ret: &Type,
+    ) -> Result<(), String> {
+        let first = args.first().cloned().unwrap_or(Type::Unit);
+        match (name, &first) {
+            ("print", ty) => {
+                let print = self.runtime(Runtime::Print(ty.clone()));
+                b.emit(I::Call(print));
+            }
+            ("range", _) => {
+                let range = self.runtime(Runtime::Range);
+                b.emit(I::Call(range));
+            }
+            ("eq", ty) => self.eq(b, ty),
+            ("ne", ty) => {
+                self.eq(b, ty);
+                b.emit(I::I64Eqz);
+                b.emit(I::I64ExtendI32U);
+            }
+            ("sum", Type::String) => {
+                let concat = self.runtime(Runtime::Concat);
+                b.emit(I::Call(concat));
+            }
+            ("sum", Type::Int) => b.emit(I::I64Add),
+            ("sub", Type::Int) => b.emit(I::I64Sub),
+            ("mul", Type::Int) => b.emit(I::I64Mul),
+            ("div", Type::Int) => b.emit(I::I64DivS),
+            ("rem", Type::Int) => b.emit(I::I64RemS),
+            (op @ ("sum" | "sub" | "mul" | "div" | "rem"), Type::Float) => {
+                let lhs = b.local(ValType::F64);
+                let rhs = b.local(ValType::F64);
+                b.emit(I::F64ReinterpretI64);
+                b.emit(I::LocalSet(rhs));
+                b.emit(I::F64ReinterpretI64);
+                b.emit(I::LocalTee(lhs));
+                b.emit(I::LocalGet(rhs));
+                match op {
+                    "sum" => b.emit(I::F64Add),
+                    "sub" => b.emit(I::F64Sub),
+                    "mul" => b.emit(I::F64Mul),
+                    "div" => b.emit(I::F64Div),
+                    // lhs - trunc(lhs / rhs) * rhs, like Rust's `%`.
+                    _ => {
+                        b.emit(I::F64Div);
+                        b.emit(I::F64Trunc);
+                        b.emit(I::LocalGet(rhs));
+                        b.emit(I::F64Mul);
+                        b.emit(I::LocalSet(rhs));
+                        b.emit(I::LocalGet(lhs));
+                        b.emit(I::LocalGet(rhs));
+                        b.emit(I::F64Sub);
+                    }
+                }
+                b.emit(I::I64ReinterpretF64);
+            }
+            _ => {
+                let types: Vec<String> = args.iter().map(|t| t.to_string()).collect();
+                return Err(format!(
+                    "The wasm backend has no '{}{}' for ({}) -> {}.",
+                    INTRINSIC_PREFIX,
+                    name,
+                    types.join(", "),
+                    ret
+                ));
+            }
+        }
+        return Ok(());
+    }
+
+    /// Compares the two values of type `ty` on the stack, leaving 1 if they're equal.
+    fn eq(&mut self, b: &mut Body, ty: &Type) {
+        let helper = match ty {
+            Type::Float => Runtime::FloatEq,
+            Type::String => Runtime::StringEq,
+            Type::List(_) | Type::Record(_) => Runtime::Eq(ty.clone()),
+            _ => {
+                b.emit(I::I64Eq);
+                b.emit(I::I64ExtendI32U);
+                return;
+            }
+        };
+        let helper = self.runtime(helper);
+        b.emit(I::Call(helper));
+    }
+
+    /// Writes the value of type `ty` on the stack, as nano shows it.
+    fn write(&mut self, b: &mut Body, ty: &Type) {
+        let helper = match ty {
+            Type::Int => Runtime::OutInt,
+            Type::Float => Runtime::OutFloat,
+            _ => Runtime::Write(ty.clone()),
+        };
+        let helper = self.runtime(helper);
+        b.emit(I::Call(helper));
+        b.emit(I::Drop);
+    }
+
+    fn out_text(&mut self, b: &mut Body, text: &str) {
+        let out_string = self.runtime(Runtime::OutString);
+        let text = self.string(text);
+        b.emit(I::I64Const(text));
+        b.emit(I::Call(out_string));
+        b.emit(I::Drop);
+    }
+
+    fn out_byte(&mut self, b: &mut Body, c: u8) {
+        let out_byte = self.runtime(Runtime::OutByte);
+        b.emit(I::I64Const(c as i64));
+        b.emit(I::Call(out_byte));
+        b.emit(I::Drop);
+    }
+
+    fn runtime_body(&mut self, runtime: &Runtime) -> WasmFunction {
+        let mut b = Body::new(runtime.arity() as u32);
+        match runtime {
+            Runtime::Alloc => self.alloc_body(&mut b),
+            Runtime::Flush => flush_body(&mut b),
+            Runtime::OutByte => self.out_byte_body(&mut b),
+            Runtime::OutString => self.out_string_body(&mut b),
+            Runtime::OutUint => self.out_uint_body(&mut b),
+            Runtime::OutInt => self.out_int_body(&mut b),
+            Runtime::OutFloat => self.out_float_body(&mut b),
+            Runtime::Concat => self.concat_body(&mut b),
+            Runtime::StringEq => string_eq_body(&mut b),
+            Runtime::FloatEq => {
+                b.emit(I::LocalGet(0));
+                b.emit(I::F64ReinterpretI64);
+                b.emit(I::LocalGet(1));
+                b.emit(I::F64ReinterpretI64);
+                b.emit(I::F64Eq);
+                b.emit(I::I64ExtendI32U);
+            }
+            Runtime::Range => self.range_body(&mut b),
+            Runtime::Write(ty) => {
+                self.write_body(&mut b, ty);
+                b.emit(I::I64Const(0));
+            }
+            Runtime::Eq(ty) => self.eq_body(&mut b, ty),
+            Runtime::Print(ty) => {
+                b.emit(I::LocalGet(0));
+                match ty {
+                    Type::String => {
+                        let out_string = self.runtime(Runtime::OutString);
+                        b.emit(I::Call(out_string));
+                        b.emit(I::Drop);
+                    }
+                    ty => self.write(&mut b, ty),
+                }
+                self.out_byte(&mut b, b'\n');
+                let flush = self.runtime(Runtime::Flush);
+                b.emit(I::Call(flush));
+            }
+        }
+        return b.finish();
+    }
+
+    /// alloc(size): bumps the heap, growing the memory when it's too small.
+    fn alloc_body(&mut self, b: &mut Body) {
+        let address = b.local(ValType::I64);
+        b.emit(I::GlobalGet(HEAP));
+        b.emit(I::LocalTee(address));
+        b.emit(I::LocalGet(0));
+        b.emit(I::I64Const(7));
+        b.emit(I::I64Add);
+        b.emit(I::I64Const(-8));
+        b.emit(I::I64And);
+        b.emit(I::I64Add);
+        b.emit(I::GlobalSet(HEAP));
+
+        // The pages missing, rounded up.
+        let missing = b.local(ValType::I64);
+        b.emit(I::GlobalGet(HEAP));
+        b.emit(I::MemorySize(0));
+        b.emit(I::I64ExtendI32U);
+        b.emit(I::I64Const(16));
+        b.emit(I::I64Shl);
+        b.emit(I::I64Sub);
+        b.emit(I::LocalTee(missing));
+        b.emit(I::I64Const(0));
+        b.emit(I::I64GtS);
+        b.emit(I::If(BlockType::Empty));
+        b.emit(I::LocalGet(missing));
+        b.emit(I::I64Const(65535));
+        b.emit(I::I64Add);
+        b.emit(I::I64Const(16));
+        b.emit(I::I64ShrU);
+        b.emit(I::I32WrapI64);
+        b.emit(I::MemoryGrow(0));
+        b.emit(I::I32Const(-1));
+        b.emit(I::I32Eq);
+        b.emit(I::If(BlockType::Empty));
+        b.emit(I::Unreachable);
+        b.emit(I::End);
+        b.emit(I::End);
+
+        b.emit(I::LocalGet(address));
+    }
+
+    /// out_byte(c): adds a byte to what's printed, flushing it first if it's full.
+    fn out_byte_body(&mut self, b: &mut Body) {
+        let flush = self.runtime(Runtime::Flush);
+        b.emit(I::GlobalGet(OUT_LENGTH));
+        b.emit(I::I64Const(OUT_CAPACITY));
+        b.emit(I::I64GeU);
+        b.emit(I::If(BlockType::Empty));
+        b.emit(I::Call(flush));
+        b.emit(I::Drop);
+        b.emit(I::End);
+
+        b.emit(I::GlobalGet(OUT_LENGTH));
+        b.emit(I::I32WrapI64);
+        b.emit(I::LocalGet(0));
+        b.emit(I::I32WrapI64);
+        b.emit(I::I32Store8(byte(OUT_BUFFER)));
+        b.emit(I::GlobalGet(OUT_LENGTH));
+        b.emit(I::I64Const(1));
+        b.emit(I::I64Add);
+        b.emit(I::GlobalSet(OUT_LENGTH));
+        b.emit(I::I64Const(0));
+    }
+
+    /// out_string(s): the bytes of `s`, without quotes.
+    fn out_string_body(&mut self, b: &mut Body) {
+        let out_byte = self.runtime(Runtime::OutByte);
+        let length = b.local(ValType::I64);
+        let i = b.local(ValType::I64);
+        b.emit(I::LocalGet(0));
+        b.emit(I::I32WrapI64);
+        b.emit(I::I64Load(word(0)));
+        b.emit(I::LocalSet(length));
+        counted_loop(b, i, length, |b| {
+            b.emit(I::LocalGet(0));
+            b.emit(I::LocalGet(i));
+            b.emit(I::I64Add);
+            b.emit(I::I32WrapI64);
+            b.emit(I::I64Load8U(byte(8)));
+            b.emit(I::Call(out_byte));
+            b.emit(I::Drop);
+        });
+        b.emit(I::I64Const(0));
+    }
+
+    /// out_uint(n): `n` in decimal, as an unsigned number.
+    fn out_uint_body(&mut self, b: &mut Body) {
+        let out_uint = self.runtime(Runtime::OutUint);
+        let out_byte = self.runtime(Runtime::OutByte);
+        b.emit(I::LocalGet(0));
+        b.emit(I::I64Const(10));
+        b.emit(I::I64GeU);
+        b.emit(I::If(BlockType::Empty));
+        b.emit(I::LocalGet(0));
+        b.emit(I::I64Const(10));
+        b.emit(I::I64DivU);
+        b.emit(I::Call(out_uint));
+        b.emit(I::Drop);
+        b.emit(I::End);
+        b.emit(I::LocalGet(0));
+        b.emit(I::I64Const(10));
+        b.emit(I::I64RemU);
+        b.emit(I::I64Const(b'0' as i64));
+        b.emit(I::I64Add);
+        b.emit(I::Call(out_byte));
+    }
+
+    /// out_int(n): `n` in decimal.
+    fn out_int_body(&mut self, b: &mut Body) {
+        let out_uint = self.runtime(Runtime::OutUint);
+        b.emit(I::LocalGet(0));
+        b.emit(I::I64Const(0));
+        b.emit(I::I64LtS);
+        b.emit(I::If(BlockType::Empty));
+        self.out_byte(b, b'-');
+        // Negating the smallest int gives itself, which is right as unsigned.
+        b.emit(I::I64Const(0));
+        b.emit(I::LocalGet(0));
+        b.emit(I::I64Sub);
+        b.emit(I::LocalSet(0));
+        b.emit(I::End);
+        b.emit(I::LocalGet(0));
+        b.emit(I::Call(out_uint));
+    }
+
+    /// out_float(bits): like Rust's `{:?}`, `1.0`, `0.25`, `1e16`, with up to 15 significant digits.
+    fn out_float_body(&mut self, b: &mut Body) {
+        let out_uint = self.runtime(Runtime::OutUint);
+        let out_int = self.runtime(Runtime::OutInt);
+        let x = b.local(ValType::F64);
+        let exponent = b.local(ValType::I64);
+        let scientific = b.local(ValType::I32);
+        let whole = b.local(ValType::I64);
+        let fraction = b.local(ValType::I64);
+        let digits = b.local(ValType::I64);
+        let scale = b.local(ValType::F64);
+        let t = b.local(ValType::I64);
+
+        b.emit(I::LocalGet(0));
+        b.emit(I::F64ReinterpretI64);
+        b.emit(I::LocalSet(x));
+
+        // NaN isn't equal to itself.
+        b.emit(I::LocalGet(x));
+        b.emit(I::LocalGet(x));
+        b.emit(I::F64Ne);
+        b.emit(I::If(BlockType::Empty));
+        self.out_text(b, "NaN");
+        b.emit(I::I64Const(0));
+        b.emit(I::Return);
+        b.emit(I::End);
+
+        b.emit(I::LocalGet(0));
+        b.emit(I::I64Const(0));
+        b.emit(I::I64LtS);
+        b.emit(I::If(BlockType::Empty));
+        self.out_byte(b, b'-');
+        b.emit(I::LocalGet(x));
+        b.emit(I::F64Abs);
+        b.emit(I::LocalSet(x));
+        b.emit(I::End);
+
+        b.emit(I::LocalGet(x));
+        b.emit(I::F64Const(f64::INFINITY.into()));
+        b.emit(I::F64Eq);
+        b.emit(I::If(BlockType::Empty));
+        self.out_text(b, "inf");
+        b.emit(I::I64Const(0));
+        b.emit(I::Return);
+        b.emit(I::End);
+
+        // Too big or too small numbers are shown as `mantissa e exponent`.
+        b.emit(I::LocalGet(x));
+        b.emit(I::F64Const(0.0.into()));
+        b.emit(I::F64Ne);
+        b.emit(I::LocalGet(x));
+        b.emit(I::F64Const(1e16.into()));
+        b.emit(I::F64Ge);
+        b.emit(I::LocalGet(x));
+        b.emit(I::F64Const(1e-4.into()));
+        b.emit(I::F64Lt);
+        b.emit(I::I32Or);
+        b.emit(I::I32And);
+        b.emit(I::LocalTee(scientific));
+        b.emit(I::If(BlockType::Empty));
+        for (compare, by, step) in [(I::F64Ge, I::F64Div, 1), (I::F64Lt, I::F64Mul, -1)] {
+            let bound = match step {
+                1 => 10.0,
+                _ => 1.0,
+            };
+            b.emit(I::Block(BlockType::Empty));
+            b.emit(I::Loop(BlockType::Empty));
+            b.emit(I::LocalGet(x));
+            b.emit(I::F64Const(f64::into(bound)));
+            b.emit(compare);
+            b.emit(I::I32Eqz);
+            b.emit(I::BrIf(1));
+            b.emit(I::LocalGet(x));
+            b.emit(I::F64Const(10.0.into()));
+            b.emit(by);
+            b.emit(I::LocalSet(x));
+            b.emit(I::LocalGet(exponent));
+            b.emit(I::I64Const(step));
+            b.emit(I::I64Add);
+            b.emit(I::LocalSet(exponent));
+            b.emit(I::Br(0));
+            b.emit(I::End);
+            b.emit(I::End);
+        }
+        b.emit(I::End);
+
+        // The whole part, and how many digits are left for the fraction.
+        b.emit(I::LocalGet(x));
+        b.emit(I::I64TruncSatF64U);
+        b.emit(I::LocalTee(whole));
+        b.emit(I::LocalSet(t));
+        b.emit(I::I64Const(15));
+        b.emit(I::LocalSet(digits));
+        b.emit(I::Block(BlockType::Empty));
+        b.emit(I::Loop(BlockType::Empty));
+        b.emit(I::LocalGet(t));
+        b.emit(I::I64Eqz);
+        b.emit(I::BrIf(1));
+        b.emit(I::LocalGet(t));
+        b.emit(I::I64Const(10));
+        b.emit(I::I64DivU);
+        b.emit(I::LocalSet(t));
+        b.emit(I::LocalGet(digits));
+        b.emit(I::I64Const(1));
+        b.emit(I::I64Sub);
+        b.emit(I::LocalSet(digits));
+        b.emit(I::Br(0));
+        b.emit(I::End);
+        b.emit(I::End);
+        b.emit(I::LocalGet(digits));
+        b.emit(I::I64Const(1));
+        b.emit(I::I64LtS);
+        b.emit(I::If(BlockType::Empty));
+        b.emit(I::I64Const(1));
+        b.emit(I::LocalSet(digits));
+        b.emit(I::End);
+
+        b.emit(I::F64Const(1.0.into()));
+        b.emit(I::LocalSet(scale));
+        b.emit(I::LocalGet(digits));
+        b.emit(I::LocalSet(t));
+        b.emit(I::Block(BlockType::Empty));
+        b.emit(I::Loop(BlockType::Empty));
+        b.emit(I::LocalGet(t));
+        b.emit(I::I64Eqz);
+        b.emit(I::BrIf(1));
+        b.emit(I::LocalGet(scale));
+        b.emit(I::F64Const(10.0.into()));
+        b.emit(I::F64Mul);
+        b.emit(I::LocalSet(scale));
+        b.emit(I::LocalGet(t));
+        b.emit(I::I64Const(1));
+        b.emit(I::I64Sub);
+        b.emit(I::LocalSet(t));
+        b.emit(I::Br(0));
+        b.emit(I::End);
+        b.emit(I::End);
+
+        // The fraction, rounded to those digits, may round up into the whole part.
+        b.emit(I::LocalGet(x));
+        b.emit(I::LocalGet(whole));
+        b.emit(I::F64ConvertI64U);
+        b.emit(I::F64Sub);
+        b.emit(I::LocalGet(scale));
+        b.emit(I::F64Mul);
+        b.emit(I::F64Nearest);
+        b.emit(I::I64TruncSatF64U);
+        b.emit(I::LocalTee(fraction));
+        b.emit(I::LocalGet(scale));
+        b.emit(I::I64TruncSatF64U);
+        b.emit(I::I64GeU);
+        b.emit(I::If(BlockType::Empty));
+        b.emit(I::LocalGet(whole));
+        b.emit(I::I64Const(1));
+        b.emit(I::I64Add);
+        b.emit(I::LocalSet(whole));
+        b.emit(I::I64Const(0));
+        b.emit(I::LocalSet(fraction));
+        b.emit(I::End);
+
+        b.emit(I::LocalGet(whole));
+        b.emit(I::Call(out_uint));
+        b.emit(I::Drop);
+
+        // `1e16` has no fraction, `1.0` does.
+        b.emit(I::LocalGet(scientific));
+        b.emit(I::I32Eqz);
+        b.emit(I::LocalGet(fraction));
+        b.emit(I::I64Const(0));
+        b.emit(I::I64Ne);
+        b.emit(I::I32Or);
+        b.emit(I::If(BlockType::Empty));
+        self.out_byte(b, b'.');
+        b.emit(I::LocalGet(fraction));
+        b.emit(I::I64Eqz);
+        b.emit(I::If(BlockType::Empty));
+        self.out_byte(b, b'0');
+        b.emit(I::Else);
+        // Trailing zeros go, leading ones stay.
+        b.emit(I::Block(BlockType::Empty));
+        b.emit(I::Loop(BlockType::Empty));
+        b.emit(I::LocalGet(fraction));
+        b.emit(I::I64Const(10));
+        b.emit(I::I64RemU);
+        b.emit(I::I64Const(0));
+        b.emit(I::I64Ne);
+        b.emit(I::BrIf(1));
+        b.emit(I::LocalGet(fraction));
+        b.emit(I::I64Const(10));
+        b.emit(I::I64DivU);
+        b.emit(I::LocalSet(fraction));
+        b.emit(I::LocalGet(digits));
+        b.emit(I::I64Const(1));
+        b.emit(I::I64Sub);
+        b.emit(I::LocalSet(digits));
+        b.emit(I::Br(0));
+        b.emit(I::End);
+        b.emit(I::End);
+        b.emit(I::LocalGet(fraction));
+        b.emit(I::LocalSet(t));
+        b.emit(I::Block(BlockType::Empty));
+        b.emit(I::Loop(BlockType::Empty));
+        b.emit(I::LocalGet(t));
+        b.emit(I::I64Eqz);
+        b.emit(I::BrIf(1));
+        b.emit(I::LocalGet(t));
+        b.emit(I::I64Const(10));
+        b.emit(I::I64DivU);
+        b.emit(I::LocalSet(t));
+        b.emit(I::LocalGet(digits));
+        b.emit(I::I64Const(1));
+        b.emit(I::I64Sub);
+        b.emit(I::LocalSet(digits));
+        b.emit(I::Br(0));
+        b.emit(I::End);
+        b.emit(I::End);
+        b.emit(I::Block(BlockType::Empty));
+        b.emit(I::Loop(BlockType::Empty));
+        b.emit(I::LocalGet(digits));
+        b.emit(I::I64Const(0));
+        b.emit(I::I64LeS);
+        b.emit(I::BrIf(1));
+        self.out_byte(b, b'0');
+        b.emit(I::LocalGet(digits));
+        b.emit(I::I64Const(1));
+        b.emit(I::I64Sub);
+        b.emit(I::LocalSet(digits));
+        b.emit(I::Br(0));
+        b.emit(I::End);
+        b.emit(I::End);
+        b.emit(I::LocalGet(fraction));
+        b.emit(I::Call(out_uint));
+        b.emit(I::Drop);
+        b.emit(I::End);
+        b.emit(I::End);
+
+        b.emit(I::LocalGet(scientific));
+        b.emit(I::If(BlockType::Empty));
+        self.out_byte(b, b'e');
+        b.emit(I::LocalGet(exponent));
+        b.emit(I::Call(out_int));
+        b.emit(I::Drop);
+        b.emit(I::End);
+        b.emit(I::I64Const(0));
+    }
+
+    /// concat(a, b): a new string with the bytes of both.
+    fn concat_body(&mut self, b: &mut Body) {
+        let alloc = self.runtime(Runtime::Alloc);
+        let (left, right, result) = (
+            b.local(ValType::I64),
+            b.local(ValType::I64),
+            b.local(ValType::I64),
+        );
+        for (string, length) in [(0, left), (1, right)] {
+            b.emit(I::LocalGet(string));
+            b.emit(I::I32WrapI64);
+            b.emit(I::I64Load(word(0)));
+            b.emit(I::LocalSet(length));
+        }
+        b.emit(I::LocalGet(left));
+        b.emit(I::LocalGet(right));
+        b.emit(I::I64Add);
+        b.emit(I::I64Const(8));
+        b.emit(I::I64Add);
+        b.emit(I::Call(alloc));
+        b.emit(I::LocalTee(result));
+        b.emit(I::I32WrapI64);
+        b.emit(I::LocalGet(left));
+        b.emit(I::LocalGet(right));
+        b.emit(I::I64Add);
+        b.emit(I::I64Store(word(0)));
+
+        // Both copied after the length, `b` after `a`.
+        for (string, at, length) in [(0, None, left), (1, Some(left), right)] {
+            b.emit(I::LocalGet(result));
+            b.emit(I::I64Const(8));
+            b.emit(I::I64Add);
+            if let Some(at) = at {
+                b.emit(I::LocalGet(at));
+                b.emit(I::I64Add);
+            }
+            b.emit(I::I32WrapI64);
+            b.emit(I::LocalGet(string));
+            b.emit(I::I64Const(8));
+            b.emit(I::I64Add);
+            b.emit(I::I32WrapI64);
+            b.emit(I::LocalGet(length));
+            b.emit(I::I32WrapI64);
+            b.emit(I::MemoryCopy {
+                src_mem: 0,
+                dst_mem: 0,
+            });
+        }
+        b.emit(I::LocalGet(result));
+    }
+
+    /// range(start, end): `[start, start + 1, ..., end - 1]`.
+    fn range_body(&mut self, b: &mut Body) {
+        let alloc = self.runtime(Runtime::Alloc);
+        let (length, list, i) = (
+            b.local(ValType::I64),
+            b.local(ValType::I64),
+            b.local(ValType::I64),
+        );
+        b.emit(I::LocalGet(1));
+        b.emit(I::LocalGet(0));
+        b.emit(I::I64Sub);
+        b.emit(I::LocalTee(length));
+        b.emit(I::I64Const(0));
+        b.emit(I::I64LtS);
+        b.emit(I::If(BlockType::Empty));
+        b.emit(I::I64Const(0));
+        b.emit(I::LocalSet(length));
+        b.emit(I::End);
+
+        b.emit(I::LocalGet(length));
+        b.emit(I::I64Const(8));
+        b.emit(I::I64Mul);
+        b.emit(I::I64Const(8));
+        b.emit(I::I64Add);
+        b.emit(I::Call(alloc));
+        b.emit(I::LocalTee(list));
+        b.emit(I::I32WrapI64);
+        b.emit(I::LocalGet(length));
+        b.emit(I::I64Store(word(0)));
+        counted_loop(b, i, length, |b| {
+            emit_item_address(b, list, i);
+            b.emit(I::LocalGet(0));
+            b.emit(I::LocalGet(i));
+            b.emit(I::I64Add);
+            b.emit(I::I64Store(word(8)));
+        });
+        b.emit(I::LocalGet(list));
+    }
+
+    /// write<T>(value), for everything but ints and floats, which have their own.
+    fn write_body(&mut self, b: &mut Body, ty: &Type) {
+        match ty {
+            Type::String => {
+                let out_string = self.runtime(Runtime::OutString);
+                self.out_byte(b, b'\'');
+                b.emit(I::LocalGet(0));
+                b.emit(I::Call(out_string));
+                b.emit(I::Drop);
+                self.out_byte(b, b'\'');
+            }
+            Type::Bool => {
+                b.emit(I::LocalGet(0));
+                b.emit(I::I32WrapI64);
+                b.emit(I::If(BlockType::Empty));
+                self.out_text(b, "yes");
+                b.emit(I::Else);
+                self.out_text(b, "no");
+                b.emit(I::End);
+            }
+            Type::List(item) => {
+                let (length, i) = (b.local(ValType::I64), b.local(ValType::I64));
+                self.out_byte(b, b'[');
+                b.emit(I::LocalGet(0));
+                b.emit(I::I32WrapI64);
+                b.emit(I::I64Load(word(0)));
+                b.emit(I::LocalSet(length));
+                b.emit(I::I64Const(0));
+                b.emit(I::LocalSet(i));
+                b.emit(I::Block(BlockType::Empty));
+                b.emit(I::Loop(BlockType::Empty));
+                b.emit(I::LocalGet(i));
+                b.emit(I::LocalGet(length));
+                b.emit(I::I64GeU);
+                b.emit(I::BrIf(1));
+                b.emit(I::LocalGet(i));
+                b.emit(I::I64Const(0));
+                b.emit(I::I64Ne);
+                b.emit(I::If(BlockType::Empty));
+                self.out_text(b, ", ");
+                b.emit(I::End);
+                emit_item_address(b, 0, i);
+                b.emit(I::I64Load(word(8)));
+                self.write(b, item);
+                b.emit(I::LocalGet(i));
+                b.emit(I::I64Const(1));
+                b.emit(I::I64Add);
+                b.emit(I::LocalSet(i));
+                b.emit(I::Br(0));
+                b.emit(I::End);
+                b.emit(I::End);
+                self.out_byte(b, b']');
+            }
+            Type::Record(fields) => {
+                self.out_byte(b, b'{');
+                for (i, (key, field)) in fields.iter().enumerate() {
+                    let separator = match i {
+                        0 => "",
+                        _ => ", ",
+                    };
+                    self.out_text(b, &format!("{}{}: ", separator, key));
+                    b.emit(I::LocalGet(0));
+                    b.emit(I::I32WrapI64);
+                    b.emit(I::I64Load(word(8 * i as u64)));
+                    self.write(b, field);
+                }
+                self.out_byte(b, b'}');
+            }
+            Type::Fn(_, _) => {
+                let out_string = self.runtime(Runtime::OutString);
+                self.out_text(b, "<fn ");
+                b.emit(I::LocalGet(0));
+                b.emit(I::I32WrapI64);
+                b.emit(I::I64Load(word(8)));
+                b.emit(I::Call(out_string));
+                b.emit(I::Drop);
+                self.out_byte(b, b'>');
+            }
+            Type::Int => {
+                b.emit(I::LocalGet(0));
+                self.write(b, &Type::Int);
+            }
+            Type::Float => {
+                b.emit(I::LocalGet(0));
+                self.write(b, &Type::Float);
+            }
+            Type::Unit | Type::Var(_) => self.out_text(b, "()"),
+        }
+    }
+
+    /// eq<T>(a, b), for lists and records.
+    fn eq_body(&mut self, b: &mut Body, ty: &Type) {
+        match ty {
+            Type::List(item) => {
+                let (length, i) = (b.local(ValType::I64), b.local(ValType::I64));
+                b.emit(I::LocalGet(0));
+                b.emit(I::I32WrapI64);
+                b.emit(I::I64Load(word(0)));
+                b.emit(I::LocalTee(length));
+                b.emit(I::LocalGet(1));
+                b.emit(I::I32WrapI64);
+                b.emit(I::I64Load(word(0)));
+                b.emit(I::I64Ne);
+                b.emit(I::If(BlockType::Empty));
+                b.emit(I::I64Const(0));
+                b.emit(I::Return);
+                b.emit(I::End);
+                counted_loop(b, i, length, |b| {
+                    for list in [0, 1] {
+                        emit_item_address(b, list, i);
+                        b.emit(I::I64Load(word(8)));
+                    }
+                    self.eq(b, item);
+                    b.emit(I::I64Eqz);
+                    b.emit(I::If(BlockType::Empty));
+                    b.emit(I::I64Const(0));
+                    b.emit(I::Return);
+                    b.emit(I::End);
+                });
+            }
+            Type::Record(fields) => {
+                for (i, field) in fields.values().enumerate() {
+                    for record in [0, 1] {
+                        b.emit(I::LocalGet(record));
+                        b.emit(I::I32WrapI64);
+                        b.emit(I::I64Load(word(8 * i as u64)));
+                    }
+                    self.eq(b, field);
+                    b.emit(I::I64Eqz);
+                    b.emit(I::If(BlockType::Empty));
+                    b.emit(I::I64Const(0));
+                    b.emit(I::Return);
+                    b.emit(I::End);
+                }
+            }
+            _ => {}
+        }
+        b.emit(I::I64Const(1));
+    }
+}
+
+/// flush(): prints what `out_byte` gathered.
+fn flush_body(b: &mut Body) {
+    b.emit(I::GlobalGet(OUT_LENGTH));
+    b.emit(I::I64Eqz);
+    b.emit(I::I32Eqz);
+    b.emit(I::If(BlockType::Empty));
+    b.emit(I::I32Const(IOVEC));
+    b.emit(I::I32Const(OUT_BUFFER as i32));
+    b.emit(I::I32Store(int32(0)));
+    b.emit(I::I32Const(IOVEC));
+    b.emit(I::GlobalGet(OUT_LENGTH));
+    b.emit(I::I32WrapI64);
+    b.emit(I::I32Store(int32(4)));
+    // Standard output.
+    b.emit(I::I32Const(1));
+    b.emit(I::I32Const(IOVEC));
+    b.emit(I::I32Const(1));
+    b.emit(I::I32Const(NWRITTEN));
+    b.emit(I::Call(FD_WRITE));
+    b.emit(I::Drop);
+    b.emit(I::I64Const(0));
+    b.emit(I::GlobalSet(OUT_LENGTH));
+    b.emit(I::End);
+    b.emit(I::I64Const(0));
+}
+
+/// string_eq(a, b): whether both have the same bytes.
+fn string_eq_body(b: &mut Body) {
+    let (length, i) = (b.local(ValType::I64), b.local(ValType::I64));
+    b.emit(I::LocalGet(0));
+    b.emit(I::I32WrapI64);
+    b.emit(I::I64Load(word(0)));
+    b.emit(I::LocalTee(length));
+    b.emit(I::LocalGet(1));
+    b.emit(I::I32WrapI64);
+    b.emit(I::I64Load(word(0)));
+    b.emit(I::I64Ne);
+    b.emit(I::If(BlockType::Empty));
+    b.emit(I::I64Const(0));
+    b.emit(I::Return);
+    b.emit(I::End);
+    counted_loop(b, i, length, |b| {
+        for string in [0, 1] {
+            b.emit(I::LocalGet(string));
+            b.emit(I::LocalGet(i));
+            b.emit(I::I64Add);
+            b.emit(I::I32WrapI64);
+            b.emit(I::I64Load8U(byte(8)));
+        }
+        b.emit(I::I64Ne);
+        b.emit(I::If(BlockType::Empty));
+        b.emit(I::I64Const(0));
+        b.emit(I::Return);
+        b.emit(I::End);
+    });
+    b.emit(I::I64Const(1));
+}
+
+/// Runs what `body` emits for `i` from 0 to the value of `length`.
+fn counted_loop(b: &mut Body, i: u32, length: u32, body: impl FnOnce(&mut Body)) {
+    b.emit(I::I64Const(0));
+    b.emit(I::LocalSet(i));
+    b.emit(I::Block(BlockType::Empty));
+    b.emit(I::Loop(BlockType::Empty));
+    b.emit(I::LocalGet(i));
+    b.emit(I::LocalGet(length));
+    b.emit(I::I64GeU);
+    b.emit(I::BrIf(1));
+    body(b);
+    b.emit(I::LocalGet(i));
+    b.emit(I::I64Const(1));
+    b.emit(I::I64Add);
+    b.emit(I::LocalSet(i));
+    b.emit(I::Br(0));
+    b.emit(I::End);
+    b.emit(I::End);
+}
+
+/// The address of item `i` of the list in `list`, minus the 8 bytes of its length.
+fn emit_item_address(b: &mut Body, list: u32, i: u32) {
+    b.emit(I::LocalGet(list));
+    b.emit(I::LocalGet(i));
+    b.emit(I::I64Const(3));
+    b.emit(I::I64Shl);
+    b.emit(I::I64Add);
+    b.emit(I::I32WrapI64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{front_end, interpreted};
+    use wasmi::{Caller, Engine, Extern, Linker, Module as WasmModule, Store};
+
+    /// What `main` prints, run by wasmi, with an `fd_write` that only knows stdout.
+    fn executed(bytes: &[u8]) -> String {
+        let engine = Engine::default();
+        let module = WasmModule::new(&engine, bytes).expect("wasmi rejected the module");
+        let mut store = Store::new(&engine, Vec::<u8>::new());
+        let mut linker = Linker::new(&engine);
+        let fd_write = |mut caller: Caller<'_, Vec<u8>>,
+                        fd: i32,
+                        iovs: i32,
+                        count: i32,
+                        nwritten: i32|
+         -> i32 {
+            assert_eq!(fd, 1, "only stdout is written to");
+            let memory = caller
+                .get_export("memory")
+                .and_then(Extern::into_memory)
+                .expect("the memory isn't exported");
+            let word = |caller: &Caller<'_, Vec<u8>>, at: i32| {
+                let mut bytes = [0; 4];
+                memory.read(caller, at as usize, &mut bytes).unwrap();
+                return u32::from_le_bytes(bytes) as usize;
+            };
+            let mut written = 0;
+            for i in 0..count {
+                let start = word(&caller, iovs + 8 * i);
+                let length = word(&caller, iovs + 8 * i + 4);
+                let mut text = vec![0; length];
+                memory.read(&caller, start, &mut text).unwrap();
+                caller.data_mut().extend(text);
+                written += length as u32;
+            }
+            memory
+                .write(&mut caller, nwritten as usize, &written.to_le_bytes())
+                .unwrap();
+            return 0;
+        };
+        linker
+            .func_wrap("wasi_snapshot_preview1", "fd_write", fd_write)
+            .unwrap();
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .and_then(|pre| pre.start(&mut store))
+            .expect("the module didn't instantiate");
+        let main = instance
+            .get_typed_func::<(), ()>(&store, "main")
+            .expect("`main` isn't exported");
+        main.call(&mut store, ()).expect("`main` trapped");
+        return String::from_utf8(store.into_data()).expect("the output isn't UTF-8");
+    }
+
+    /// Compiles `source`, validates the module, and checks that `main` prints what the interpreter does.
+    fn prints_like_the_interpreter(source: &str) {
+        let module = front_end(source);
+        let bytes = compile(&module).expect("the backend failed");
+        wasmparser::validate(&bytes).expect("the module is invalid");
+        assert_eq!(executed(&bytes), interpreted(&module));
+    }
+
+    #[test]
+    fn values_print_like_the_interpreter() {
+        prints_like_the_interpreter(
+            "fn id(x) -> x
+            fn square(x) -> x * x
+            print(id(3))
+            print(id('hi'))
+            print(square(12))
+            print(1.5 + 2.25)
+            print('a' + 'b')
+            print(select n * n for n in 0..5)
+            print({name: 'nano', age: 3, tags: ['a', 'b']})
+            print([])
+            print(0.1)
+            print(1.0)
+            print(0.0 - 2.5)
+            print(7 % 3)
+            print(print('inner'))
+            for i in 0..3 -> print(i)",
+        );
+    }
+
+    #[test]
+    fn comparisons_print_like_the_interpreter() {
+        prints_like_the_interpreter(
+            "print([1, 2] is [1, 2])
+            print('ab' xis 'ab')
+            print({a: 1, b: 'x'} is {a: 1, b: 'x'})
+            print({a: 1, b: 'x'} is {a: 2, b: 'x'})
+            let big = select [n, n * 2] for n in 0..2000
+            print(big is big)",
+        );
+    }
+
+    #[test]
+    fn closures_print_like_the_interpreter() {
+        prints_like_the_interpreter(
+            "fn id(x) -> x
+            let g = id
+            print(g(1.5))
+            print(g('s'))
+            let k = 10
+            let addk = x -> x + k
+            print(addk(5))
+            print(3 |> addk)
+            fn twice(f, x) -> f(f(x))
+            print(twice((s -> s + '!'), 'hey'))
+            print(twice((n -> n * 3), 2))
+            fn make(n) -> (m -> (k -> n + m + k))
+            let f1 = make(1)
+            let f2 = f1(2)
+            print(f2(3))
+            let adders = select (x -> x + n) for n in 0..3
+            for f in adders -> print(f(100))
+            fn outer(n) -> (
+                fn inner(m) -> m + n
+                inner(1) + inner(2)
+            )
+            print(outer(10))",
+        );
+    }
+}
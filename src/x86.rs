@@ -0,0 +1,427 @@
+// A small x86-64 assembler, just the instructions the native backend uses.
+//
+// Instructions are encoded as soon as they're emitted. Jumps, calls and
+// `lea`s of code go to labels, patched once every label is bound, and
+// references to data are left as relocations for the object writers to
+// resolve. Both are always a 32-bit displacement from the end of the
+// instruction, which is also where it ends: nothing follows the
+// displacement. Memory operands are `[base + displacement]` only.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg {
+    Rax,
+    Rcx,
+    Rdx,
+    Rbx,
+    Rsp,
+    Rbp,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+impl Reg {
+    fn number(self) -> u8 {
+        self as u8
+    }
+}
+
+/// An SSE register, `xmm0` to `xmm15`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Xmm(pub u8);
+
+/// Condition codes, as `jcc` and `setcc` encode them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cond {
+    Below = 0x2,
+    AboveEqual = 0x3,
+    Equal = 0x4,
+    NotEqual = 0x5,
+    BelowEqual = 0x6,
+    Above = 0x7,
+    Parity = 0xa,
+    NoParity = 0xb,
+    Less = 0xc,
+    GreaterEqual = 0xd,
+    LessEqual = 0xe,
+    Greater = 0xf,
+}
+
+/// The two-operand integer instructions, by their `/digit` in the `0x81` group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alu {
+    Add = 0,
+    Or = 1,
+    And = 4,
+    Sub = 5,
+    Xor = 6,
+    Cmp = 7,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shift {
+    Shl = 4,
+    Shr = 5,
+    Sar = 7,
+}
+
+/// Scalar double arithmetic, by opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sse {
+    Add = 0x58,
+    Mul = 0x59,
+    Sub = 0x5c,
+    Div = 0x5e,
+}
+
+/// What a relocation points at, an offset in a section or an imported function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Data {
+    Rodata(u32),
+    Bss(u32),
+    // An index into the importer's table.
+    Import(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label(usize);
+
+#[derive(Default)]
+pub struct Asm {
+    pub code: Vec<u8>,
+    labels: Vec<Option<usize>>,
+    // Where a displacement to a label goes.
+    fixups: Vec<(usize, Label)>,
+    // Where a displacement to data goes.
+    pub relocations: Vec<(usize, Data)>,
+}
+
+impl Asm {
+    pub fn new() -> Asm {
+        Asm::default()
+    }
+
+    pub fn here(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn label(&mut self) -> Label {
+        self.labels.push(None);
+        return Label(self.labels.len() - 1);
+    }
+
+    pub fn bind(&mut self, label: Label) {
+        self.labels[label.0] = Some(self.code.len());
+    }
+
+    /// Where a bound label is.
+    pub fn offset(&self, label: Label) -> Option<usize> {
+        self.labels[label.0]
+    }
+
+    /// Patches every jump and call, failing if one goes to a label that was never bound.
+    pub fn finish(&mut self) -> Result<(), String> {
+        for (at, label) in &self.fixups {
+            let target = match self.labels[label.0] {
+                Some(target) => target,
+                None => return Err("The x86 backend jumps to a label it never placed.".to_string()),
+            };
+            let displacement = target as i64 - (*at as i64 + 4);
+            self.code[*at..*at + 4].copy_from_slice(&(displacement as i32).to_le_bytes());
+        }
+        self.fixups.clear();
+        return Ok(());
+    }
+
+    /// Overwrites 4 bytes at `at`, like a frame size only known later.
+    pub fn patch_u32(&mut self, at: usize, value: u32) {
+        self.code[at..at + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn rex(&mut self, w: bool, reg: u8, rm: u8, force: bool) {
+        let rex = 0x40 | (w as u8) << 3 | (reg >> 3 & 1) << 2 | (rm >> 3 & 1);
+        if rex != 0x40 || force {
+            self.code.push(rex);
+        }
+    }
+
+    /// `opcode reg, rm` with both operands registers.
+    fn op_rr(&mut self, prefix: &[u8], w: bool, opcode: &[u8], reg: u8, rm: u8, force: bool) {
+        self.code.extend_from_slice(prefix);
+        self.rex(w, reg, rm, force);
+        self.code.extend_from_slice(opcode);
+        self.code.push(0xc0 | (reg & 7) << 3 | (rm & 7));
+    }
+
+    /// `opcode reg, [base + displacement]`.
+    fn op_mem(
+        &mut self,
+        w: bool,
+        opcode: &[u8],
+        reg: u8,
+        base: Reg,
+        displacement: i32,
+        force: bool,
+    ) {
+        let base = base.number();
+        self.rex(w, reg, base, force);
+        self.code.extend_from_slice(opcode);
+        let short = (-128..128).contains(&displacement);
+        let mode = if short { 0x40 } else { 0x80 };
+        self.code.push(mode | (reg & 7) << 3 | (base & 7));
+        // `rsp` and `r12` as a base need a SIB byte.
+        if base & 7 == 4 {
+            self.code.push(0x24);
+        }
+        match short {
+            true => self.code.push(displacement as i8 as u8),
+            false => self.code.extend_from_slice(&displacement.to_le_bytes()),
+        }
+    }
+
+    /// `opcode reg, [rip + displacement]`, returning where the displacement goes.
+    fn op_rip(&mut self, w: bool, opcode: &[u8], reg: u8) -> usize {
+        self.rex(w, reg, 0, false);
+        self.code.extend_from_slice(opcode);
+        self.code.push((reg & 7) << 3 | 0b101);
+        self.code.extend_from_slice(&[0; 4]);
+        return self.code.len() - 4;
+    }
+
+    // Where the rel32 at `at` points, patched by `finish` or the object writer.
+    fn refer_label(&mut self, at: usize, label: Label) {
+        self.fixups.push((at, label));
+    }
+
+    fn refer_data(&mut self, at: usize, data: Data) {
+        self.relocations.push((at, data));
+    }
+
+    /// mov dst, src
+    pub fn mov(&mut self, dst: Reg, src: Reg) {
+        self.op_rr(&[], true, &[0x89], src.number(), dst.number(), false);
+    }
+
+    /// mov dst, value, as short as the value allows.
+    pub fn mov_imm(&mut self, dst: Reg, value: i64) {
+        match i32::try_from(value) {
+            Ok(value) => {
+                self.op_rr(&[], true, &[0xc7], 0, dst.number(), false);
+                self.code.extend_from_slice(&value.to_le_bytes());
+            }
+            Err(_) => {
+                self.rex(true, 0, dst.number(), false);
+                self.code.push(0xb8 + (dst.number() & 7));
+                self.code.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+
+    /// mov dst, [base + displacement]
+    pub fn load(&mut self, dst: Reg, base: Reg, displacement: i32) {
+        self.op_mem(true, &[0x8b], dst.number(), base, displacement, false);
+    }
+
+    /// mov [base + displacement], src
+    pub fn store(&mut self, base: Reg, displacement: i32, src: Reg) {
+        self.op_mem(true, &[0x89], src.number(), base, displacement, false);
+    }
+
+    /// movzx dst, byte [base + displacement]
+    pub fn load_byte(&mut self, dst: Reg, base: Reg, displacement: i32) {
+        self.op_mem(true, &[0x0f, 0xb6], dst.number(), base, displacement, false);
+    }
+
+    /// mov byte [base + displacement], src
+    pub fn store_byte(&mut self, base: Reg, displacement: i32, src: Reg) {
+        self.op_mem(false, &[0x88], src.number(), base, displacement, true);
+    }
+
+    /// lea dst, [base + displacement]
+    pub fn lea(&mut self, dst: Reg, base: Reg, displacement: i32) {
+        self.op_mem(true, &[0x8d], dst.number(), base, displacement, false);
+    }
+
+    /// lea dst, [rip + label], the address of some code.
+    pub fn lea_label(&mut self, dst: Reg, label: Label) {
+        let at = self.op_rip(true, &[0x8d], dst.number());
+        self.refer_label(at, label);
+    }
+
+    /// lea dst, [rip + data]
+    pub fn lea_data(&mut self, dst: Reg, data: Data) {
+        let at = self.op_rip(true, &[0x8d], dst.number());
+        self.refer_data(at, data);
+    }
+
+    /// mov dst, [rip + data]
+    pub fn load_data(&mut self, dst: Reg, data: Data) {
+        let at = self.op_rip(true, &[0x8b], dst.number());
+        self.refer_data(at, data);
+    }
+
+    /// mov [rip + data], src
+    pub fn store_data(&mut self, data: Data, src: Reg) {
+        let at = self.op_rip(true, &[0x89], src.number());
+        self.refer_data(at, data);
+    }
+
+    /// add, or, and, sub, xor or cmp of two registers.
+    pub fn alu(&mut self, op: Alu, dst: Reg, src: Reg) {
+        let opcode = (op as u8) << 3 | 1;
+        self.op_rr(&[], true, &[opcode], src.number(), dst.number(), false);
+    }
+
+    /// add, or, and, sub, xor or cmp with an immediate.
+    pub fn alu_imm(&mut self, op: Alu, dst: Reg, value: i32) {
+        self.op_rr(&[], true, &[0x81], op as u8, dst.number(), false);
+        self.code.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// imul dst, src
+    pub fn imul(&mut self, dst: Reg, src: Reg) {
+        self.op_rr(&[], true, &[0x0f, 0xaf], dst.number(), src.number(), false);
+    }
+
+    pub fn shift(&mut self, op: Shift, dst: Reg, by: u8) {
+        self.op_rr(&[], true, &[0xc1], op as u8, dst.number(), false);
+        self.code.push(by);
+    }
+
+    pub fn neg(&mut self, reg: Reg) {
+        self.op_rr(&[], true, &[0xf7], 3, reg.number(), false);
+    }
+
+    /// Unsigned `rdx:rax / divisor`, the quotient in `rax` and the remainder in `rdx`.
+    pub fn div(&mut self, divisor: Reg) {
+        self.op_rr(&[], true, &[0xf7], 6, divisor.number(), false);
+    }
+
+    /// Signed `rdx:rax / divisor`, after a `cqo`.
+    pub fn idiv(&mut self, divisor: Reg) {
+        self.op_rr(&[], true, &[0xf7], 7, divisor.number(), false);
+    }
+
+    /// Sign-extends `rax` into `rdx`.
+    pub fn cqo(&mut self) {
+        self.code.extend_from_slice(&[0x48, 0x99]);
+    }
+
+    /// test a, b
+    pub fn test(&mut self, a: Reg, b: Reg) {
+        self.op_rr(&[], true, &[0x85], b.number(), a.number(), false);
+    }
+
+    /// `dst` becomes 1 if `cond` holds, 0 otherwise.
+    pub fn set(&mut self, cond: Cond, dst: Reg) {
+        self.op_rr(
+            &[],
+            false,
+            &[0x0f, 0x90 | cond as u8],
+            0,
+            dst.number(),
+            true,
+        );
+        self.op_rr(&[], true, &[0x0f, 0xb6], dst.number(), dst.number(), false);
+    }
+
+    pub fn push(&mut self, reg: Reg) {
+        self.rex(false, 0, reg.number(), false);
+        self.code.push(0x50 + (reg.number() & 7));
+    }
+
+    pub fn pop(&mut self, reg: Reg) {
+        self.rex(false, 0, reg.number(), false);
+        self.code.push(0x58 + (reg.number() & 7));
+    }
+
+    pub fn jmp(&mut self, label: Label) {
+        self.code.push(0xe9);
+        self.code.extend_from_slice(&[0; 4]);
+        self.refer_label(self.code.len() - 4, label);
+    }
+
+    pub fn jcc(&mut self, cond: Cond, label: Label) {
+        self.code.extend_from_slice(&[0x0f, 0x80 | cond as u8]);
+        self.code.extend_from_slice(&[0; 4]);
+        self.refer_label(self.code.len() - 4, label);
+    }
+
+    pub fn call(&mut self, label: Label) {
+        self.code.push(0xe8);
+        self.code.extend_from_slice(&[0; 4]);
+        self.refer_label(self.code.len() - 4, label);
+    }
+
+    /// call [base + displacement], like a closure's code address.
+    pub fn call_mem(&mut self, base: Reg, displacement: i32) {
+        self.op_mem(false, &[0xff], 2, base, displacement, false);
+    }
+
+    /// call [rip + data], like an entry of an import address table.
+    pub fn call_data(&mut self, data: Data) {
+        let at = self.op_rip(false, &[0xff], 2);
+        self.refer_data(at, data);
+    }
+
+    pub fn ret(&mut self) {
+        self.code.push(0xc3);
+    }
+
+    /// mov rsp, rbp; pop rbp
+    pub fn leave(&mut self) {
+        self.code.push(0xc9);
+    }
+
+    pub fn syscall(&mut self) {
+        self.code.extend_from_slice(&[0x0f, 0x05]);
+    }
+
+    /// Copies `rcx` bytes from `[rsi]` to `[rdi]`.
+    pub fn rep_movsb(&mut self) {
+        self.code.extend_from_slice(&[0xf3, 0xa4]);
+    }
+
+    /// movq dst, src, the bits of a general register into an SSE one.
+    pub fn movq_to_xmm(&mut self, dst: Xmm, src: Reg) {
+        self.op_rr(&[0x66], true, &[0x0f, 0x6e], dst.0, src.number(), false);
+    }
+
+    /// movq dst, src, the bits of an SSE register into a general one.
+    pub fn movq_from_xmm(&mut self, dst: Reg, src: Xmm) {
+        self.op_rr(&[0x66], true, &[0x0f, 0x7e], src.0, dst.number(), false);
+    }
+
+    /// addsd, subsd, mulsd or divsd.
+    pub fn sse(&mut self, op: Sse, dst: Xmm, src: Xmm) {
+        self.op_rr(&[0xf2], false, &[0x0f, op as u8], dst.0, src.0, false);
+    }
+
+    /// Compares two doubles, unordered ones setting the parity flag.
+    pub fn ucomisd(&mut self, a: Xmm, b: Xmm) {
+        self.op_rr(&[0x66], false, &[0x0f, 0x2e], a.0, b.0, false);
+    }
+
+    /// Rounds to an integral double: mode 0 to nearest, even on ties, 3 towards zero.
+    pub fn roundsd(&mut self, dst: Xmm, src: Xmm, mode: u8) {
+        self.op_rr(&[0x66], false, &[0x0f, 0x3a, 0x0b], dst.0, src.0, false);
+        self.code.push(mode);
+    }
+
+    /// cvttsd2si dst, src, truncating.
+    pub fn cvttsd2si(&mut self, dst: Reg, src: Xmm) {
+        self.op_rr(&[0xf2], true, &[0x0f, 0x2c], dst.number(), src.0, false);
+    }
+
+    /// cvtsi2sd dst, src
+    pub fn cvtsi2sd(&mut self, dst: Xmm, src: Reg) {
+        self.op_rr(&[0xf2], true, &[0x0f, 0x2a], dst.0, src.number(), false);
+    }
+}
@@ -0,0 +1,66 @@
+// What the `nnc` binary itself does: its exit status.
+
+// Explicit `return`s at the end of functions are the house style.
+#![allow(clippy::needless_return)]
+
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Writes `source` to a file of its own for `name`.
+fn program(name: &str, source: &str) -> PathBuf {
+    let folder = std::env::temp_dir().join(format!("nnc-cli-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&folder).unwrap();
+    let path = folder.join("main.nano");
+    std::fs::write(&path, source).unwrap();
+    return path;
+}
+
+/// The exit status of `nnc <command> <source's file>`.
+fn status(command: &str, name: &str, source: &str) -> Option<i32> {
+    let path = program(name, source);
+    let output = Command::new(env!("CARGO_BIN_EXE_nnc"))
+        .arg(command)
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    return output.status.code();
+}
+
+#[test]
+fn run_succeeds_on_programs_that_run() {
+    assert_eq!(status("run", "ok", "print(1)"), Some(0));
+}
+
+#[test]
+fn run_fails_on_errors() {
+    assert_eq!(status("run", "runtime", "let x = 0\nprint(1 / x)"), Some(1));
+    assert_eq!(status("run", "types", "print(1 + 'a')"), Some(1));
+    assert_eq!(status("run", "undefined", "print(x)"), Some(1));
+    assert_eq!(status("run", "parse", "print(1"), Some(1));
+}
+
+#[test]
+fn run_fails_on_import_cycles() {
+    let path = program("cycle", "import './other.nano' as other\nprint(1)");
+    std::fs::write(
+        path.with_file_name("other.nano"),
+        "import './main.nano' as main\nlet x = 1",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_nnc"))
+        .arg("run")
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_fails_when_a_test_does() {
+    let passing = "%%test fn ok -> (\n    %%t_eq(1 + 1, 2)\n)";
+    let failing = "%%test fn wrong -> (\n    %%t_eq(1 + 1, 3)\n)";
+    assert_eq!(status("test", "passing", passing), Some(0));
+    assert_eq!(status("test", "failing", failing), Some(1));
+}